@@ -0,0 +1,412 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::{read_json_file, title_case_term};
+use crate::plan::MigrationPlan;
+
+// the schema.org types and properties `suggest-alignment` matches the classes and properties against
+const SCHEMA_ORG_INDEX: &str = include_str!("schema_org.tsv");
+
+/// The prefixes of the ontologies an alignment can name terms of without declaring them
+pub const KNOWN_PREFIXES: [(&str, &str); 4] = [
+    ("schema", "http://schema.org/"),
+    ("foaf", "http://xmlns.com/foaf/0.1/"),
+    ("dcterms", "http://purl.org/dc/terms/"),
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+];
+
+pub const OWL_NAMESPACE: &str = "http://www.w3.org/2002/07/owl#";
+
+/// How a generated class or property is aligned to the term of an external ontology
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AlignMode {
+    /// The external term replaces the generated one, in the vocab and the data
+    Reuse,
+    /// The generated term is kept, and linked to the external one with `rdfs:subClassOf` (a class) or
+    /// `owl:equivalentProperty` (a property)
+    #[default]
+    Link,
+}
+
+/// An external term, given as its IRI (e.g. "schema:Person"), or with the mode it's aligned by
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AlignedTerm {
+    Iri(String),
+    Rule {
+        iri: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<AlignMode>,
+        /// How closely a term suggested by `suggest-alignment` matched, from 0 to 1, for review. It isn't used
+        /// when aligning
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        score: Option<f64>,
+    },
+}
+
+/// An alignment file (from `--align`) that maps v2 collections and predicates to the terms of external ontologies,
+/// e.g. schema.org, FOAF, or Dublin Core. Predicates are given as "collection/predicate", or as a bare "predicate"
+/// for every collection
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Alignment {
+    /// The mode of the terms that aren't given one
+    pub mode: AlignMode,
+    /// Prefixes of the IRIs other than the known ones, e.g. {"ex": "http://example.org/ontology#"}
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub prefixes: BTreeMap<String, String>,
+    pub classes: BTreeMap<String, AlignedTerm>,
+    pub properties: BTreeMap<String, AlignedTerm>,
+}
+
+impl AlignedTerm {
+    pub fn iri(&self) -> &str {
+        match self {
+            AlignedTerm::Iri(iri) | AlignedTerm::Rule { iri, .. } => iri,
+        }
+    }
+
+    fn mode(&self, default_mode: AlignMode) -> AlignMode {
+        match self {
+            AlignedTerm::Rule {
+                mode: Some(mode), ..
+            } => *mode,
+            _ => default_mode,
+        }
+    }
+}
+
+impl Alignment {
+    pub fn load(path: &Path) -> Self {
+        let json = read_json_file(path, "alignment");
+        let alignment = match serde_json::from_value::<Self>(json) {
+            Ok(alignment) => alignment,
+            Err(e) => {
+                pretty_print(
+                    &format!("Invalid alignment file {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        };
+        let terms = alignment
+            .classes
+            .values()
+            .chain(alignment.properties.values());
+        for term in terms {
+            if let Some(prefix) = term.iri().split_once(':').map(|(prefix, _)| prefix) {
+                if !alignment.is_full_iri(term.iri()) && alignment.namespace(prefix).is_none() {
+                    pretty_print(
+                        &format!(
+                            "Invalid alignment file {}: the prefix of {} isn't known, so it needs to be declared \
+                             in its \"prefixes\"",
+                            path.display(),
+                            term.iri()
+                        ),
+                        ERROR_COLOR,
+                        true,
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        alignment
+    }
+
+    fn is_full_iri(&self, iri: &str) -> bool {
+        iri.contains("://")
+    }
+
+    fn namespace(&self, prefix: &str) -> Option<String> {
+        self.prefixes.get(prefix).cloned().or_else(|| {
+            KNOWN_PREFIXES
+                .iter()
+                .find(|(known_prefix, _)| *known_prefix == prefix)
+                .map(|(_, namespace)| namespace.to_string())
+        })
+    }
+
+    pub fn write(&self, path: &Path) {
+        let contents = serde_json::to_string_pretty(self).unwrap();
+        if let Err(e) = std::fs::write(path, contents) {
+            pretty_print(
+                &format!("Could not write alignment to {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+
+    /// The external term a v2 collection is aligned to, and how
+    pub fn class(&self, orig_class_name: &str) -> Option<(&str, AlignMode)> {
+        self.classes
+            .get(orig_class_name)
+            .map(|term| (term.iri(), term.mode(self.mode)))
+    }
+
+    /// The external term a property is aligned to, and how, by the key it's tracked under: "collection/predicate"
+    /// when it's renamed in a collection, and otherwise the predicate, whose term every collection shares, so it's
+    /// aligned by an entry of any of them
+    pub fn property(&self, property_key: &str) -> Option<(&str, AlignMode)> {
+        let predicate = property_key.rsplit('/').next().unwrap_or(property_key);
+        self.properties
+            .get(property_key)
+            .or_else(|| {
+                self.properties
+                    .iter()
+                    .find(|(key, _)| key.rsplit('/').next() == Some(predicate))
+                    .map(|(_, term)| term)
+            })
+            .map(|term| (term.iri(), term.mode(self.mode)))
+    }
+
+    // the v2 names of the terms that are replaced by external ones, and the external terms
+    fn reused(&self, terms: &BTreeMap<String, AlignedTerm>) -> Vec<(String, String)> {
+        terms
+            .iter()
+            .filter(|(_, term)| term.mode(self.mode) == AlignMode::Reuse)
+            .map(|(name, term)| (name.to_owned(), term.iri().to_string()))
+            .collect()
+    }
+
+    /// The v2 collections whose classes are replaced by external ones, as class renames
+    pub fn reused_classes(&self) -> Vec<(String, String)> {
+        self.reused(&self.classes)
+    }
+
+    /// The v2 predicates whose properties are replaced by external ones, as property renames
+    pub fn reused_properties(&self) -> Vec<(String, String)> {
+        self.reused(&self.properties)
+    }
+
+    /// The prefixes of the aligned terms, which the vocab and data @contexts need to expand them, and `owl` when a
+    /// property is linked with `owl:equivalentProperty`
+    pub fn context(&self) -> BTreeMap<String, String> {
+        let terms = self.classes.values().chain(self.properties.values());
+        let mut context = terms
+            .filter(|term| !self.is_full_iri(term.iri()))
+            .filter_map(|term| {
+                let (prefix, _) = term.iri().split_once(':')?;
+                Some((prefix.to_string(), self.namespace(prefix)?))
+            })
+            .collect::<BTreeMap<String, String>>();
+        if self
+            .properties
+            .values()
+            .any(|term| term.mode(self.mode) == AlignMode::Link)
+        {
+            context.insert("owl".to_string(), OWL_NAMESPACE.to_string());
+        }
+        context
+    }
+}
+
+/// A schema.org type or property of the index
+struct IndexedTerm {
+    name: &'static str,
+    /// A type's parent type, or the types a property is expected on
+    related: Vec<&'static str>,
+    aliases: Vec<&'static str>,
+}
+
+// the types and the properties of the schema.org index
+fn schema_org_index() -> (Vec<IndexedTerm>, Vec<IndexedTerm>) {
+    let (mut types, mut properties) = (vec![], vec![]);
+    for line in SCHEMA_ORG_INDEX.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(kind), Some(name)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let mut words = || {
+            fields
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter(|word| *word != "-")
+                .collect::<Vec<&str>>()
+        };
+        let term = IndexedTerm {
+            name,
+            related: words(),
+            aliases: words(),
+        };
+        match kind {
+            "class" => types.push(term),
+            _ => properties.push(term),
+        }
+    }
+    (types, properties)
+}
+
+// the lowercased words of a name, e.g. ["tax", "id"] for "taxID" or "tax_id"
+fn words(name: &str) -> Vec<String> {
+    title_case_term(name)
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// how alike two names are, from 0 to 1: 1 when they have the same words, and otherwise the closer of how few
+// edits turn one into the other, and how many words they share
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a_words, b_words) = (words(a), words(b));
+    let (a_joined, b_joined) = (
+        a_words.concat().chars().collect::<Vec<char>>(),
+        b_words.concat().chars().collect::<Vec<char>>(),
+    );
+    if a_joined.is_empty() || b_joined.is_empty() {
+        return 0.0;
+    }
+    if a_joined == b_joined {
+        return 1.0;
+    }
+    let longest = a_joined.len().max(b_joined.len()) as f64;
+    let edits = 1.0 - edit_distance(&a_joined, &b_joined) as f64 / longest;
+    let shared = a_words.iter().filter(|word| b_words.contains(word)).count() as f64;
+    let dice = 2.0 * shared / (a_words.len() + b_words.len()) as f64;
+    edits.max(0.9 * dice)
+}
+
+// how well any of the names of a class or property matches a schema.org term, or one of its aliases
+fn match_score(names: &[&str], term: &IndexedTerm) -> f64 {
+    names
+        .iter()
+        .flat_map(|name| {
+            std::iter::once(similarity(name, term.name)).chain(
+                term.aliases
+                    .iter()
+                    .map(|alias| 0.95 * similarity(name, alias)),
+            )
+        })
+        .fold(0.0, f64::max)
+}
+
+// the type, and the types it's a subtype of
+fn ancestors<'a>(types: &'a [IndexedTerm], name: &'a str) -> Vec<&'a str> {
+    let mut ancestors = vec![name];
+    let mut current = name;
+    while let Some(parent) = types
+        .iter()
+        .find(|term| term.name == current)
+        .and_then(|term| term.related.first())
+    {
+        if ancestors.contains(parent) {
+            break;
+        }
+        ancestors.push(parent);
+        current = parent;
+    }
+    ancestors
+}
+
+// the best-matching term, and its score, if it's at least the minimum
+fn best_match(
+    terms: &[IndexedTerm],
+    min_score: f64,
+    score: impl Fn(&IndexedTerm) -> f64,
+) -> Option<(&IndexedTerm, f64)> {
+    terms
+        .iter()
+        .map(|term| (term, score(term)))
+        .fold(
+            None,
+            |best: Option<(&IndexedTerm, f64)>, (term, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((term, score)),
+            },
+        )
+        .filter(|(_, score)| *score >= min_score)
+}
+
+fn suggested(name: &str, score: f64) -> AlignedTerm {
+    AlignedTerm::Rule {
+        iri: format!("schema:{}", name),
+        mode: None,
+        score: Some((score * 100.0).round() / 100.0),
+    }
+}
+
+/// Suggests a schema.org type for each class of a plan, and a schema.org property for each of its properties,
+/// by fuzzy-matching their v2 names and terms against a bundled index of schema.org. A property is more likely
+/// matched with a property schema.org expects on its class's suggested type. Only the matches scoring at least
+/// `min_score` are suggested, as links, for review before they're used with `--align`
+pub fn suggest(plan: &MigrationPlan, min_score: f64) -> Alignment {
+    let (types, properties) = schema_org_index();
+    let mut alignment = Alignment::default();
+    for class in &plan.classes {
+        let names = [class.collection.as_str(), class.class.as_str()];
+        let class_match = best_match(&types, min_score, |term| match_score(&names, term));
+        if let Some((term, score)) = class_match {
+            alignment
+                .classes
+                .insert(class.collection.to_owned(), suggested(term.name, score));
+        }
+        let class_types = class_match.map(|(term, _)| ancestors(&types, term.name));
+
+        for property in &class.properties {
+            let names = [property.predicate.as_str(), property.term.as_str()];
+            let property_match = best_match(&properties, min_score, |term| {
+                let score = match_score(&names, term);
+                let is_expected = term.related.iter().any(|domain| match &class_types {
+                    Some(class_types) => class_types.contains(domain),
+                    None => *domain == "Thing",
+                });
+                match (is_expected, &class_types) {
+                    (true, Some(_)) => (score + 0.05).min(1.0),
+                    (true, None) => score,
+                    (false, Some(_)) => 0.9 * score,
+                    (false, None) => 0.95 * score,
+                }
+            });
+            if let Some((term, score)) = property_match {
+                alignment.properties.insert(
+                    format!("{}/{}", class.collection, property.predicate),
+                    suggested(term.name, score),
+                );
+            }
+        }
+    }
+    alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // with serde_json's arbitrary_precision, an untagged enum holding a number only deserializes from a Value
+    #[test]
+    fn reads_scored_terms_through_a_value() {
+        let json = r#"{"classes": {"person": {"iri": "schema:Person", "score": 0.87}}}"#;
+        let alignment =
+            serde_json::from_value::<Alignment>(serde_json::from_str(json).unwrap()).unwrap();
+        match &alignment.classes["person"] {
+            AlignedTerm::Rule { iri, score, .. } => {
+                assert_eq!(iri, "schema:Person");
+                assert_eq!(*score, Some(0.87));
+            }
+            term => panic!("not a rule: {:?}", term),
+        }
+    }
+}
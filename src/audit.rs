@@ -0,0 +1,442 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use dialoguer::console::Style;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+use crate::functions::read_json_file;
+use crate::redact::redact;
+use crate::shapes::INTEGER_DATATYPES;
+use crate::state;
+use crate::verify::without_reverse_terms;
+
+const AUDIT_PAGE_SIZE: usize = 10_000;
+
+// the violations of each class, property, and constraint that are printed; the report lists all of them
+const MAX_PRINTED_EXAMPLES: usize = 3;
+
+/// A value (or missing value) of an instance that doesn't conform to its class's shape
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Violation {
+    pub focus_node: String,
+    pub class: String,
+    pub path: String,
+    /// The SHACL constraint that isn't met, e.g. "sh:datatype"
+    pub constraint: String,
+    pub message: String,
+}
+
+/// The violations `audit` found, written as JSON when `--report` is given
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditReport {
+    pub ledger: String,
+    pub checked_instances: u64,
+    pub violations: Vec<Violation>,
+}
+
+#[derive(Debug, Default)]
+struct PropertyShape {
+    path: String,
+    datatype: Option<String>,
+    class: Option<String>,
+    node_kind: Option<String>,
+    min_count: Option<u64>,
+    max_count: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct NodeShape {
+    target_class: String,
+    properties: Vec<PropertyShape>,
+    closed: bool,
+    ignored_properties: HashSet<String>,
+}
+
+fn id(value: &Value) -> Option<String> {
+    match value {
+        Value::Array(values) => values.first().and_then(id),
+        Value::Object(object) => object
+            .get("@id")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        Value::String(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn ids(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().filter_map(id).collect(),
+        value => id(value).into_iter().collect(),
+    }
+}
+
+fn count(value: &Value) -> Option<u64> {
+    value.as_u64().or(value["@value"].as_u64())
+}
+
+fn node_shapes(document: &Value) -> Vec<NodeShape> {
+    let nodes = match document {
+        Value::Array(nodes) => nodes.as_slice(),
+        document => document
+            .get("insert")
+            .or(document.get("@graph"))
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+    };
+    nodes
+        .iter()
+        .filter(|node| {
+            ids(&node["@type"])
+                .iter()
+                .any(|type_| type_ == "sh:NodeShape")
+        })
+        .filter_map(|node| {
+            Some(NodeShape {
+                target_class: id(&node["sh:targetClass"])?,
+                properties: node["sh:property"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|property| {
+                        Some(PropertyShape {
+                            path: id(&property["sh:path"])?,
+                            datatype: id(&property["sh:datatype"]),
+                            class: id(&property["sh:class"]),
+                            node_kind: id(&property["sh:nodeKind"]),
+                            min_count: count(&property["sh:minCount"]),
+                            max_count: count(&property["sh:maxCount"]),
+                        })
+                    })
+                    .collect(),
+                closed: node["sh:closed"] == json!(true),
+                ignored_properties: ids(&node["sh:ignoredProperties"]).into_iter().collect(),
+            })
+        })
+        .collect()
+}
+
+fn datatype_matches(expected: &str, actual: &str) -> bool {
+    expected == actual
+        || (INTEGER_DATATYPES.contains(&expected) && INTEGER_DATATYPES.contains(&actual))
+}
+
+/// Runs the SHACL shapes of a vocab (or a `shapes` file) against the instances of the --target ledger
+/// and reports the values that violate them, e.g. data that was transacted outside the migration
+pub async fn audit(opt: &Opt, shapes_path: &Path) {
+    let green_bold = Style::new().green().bold();
+    let red_bold = Style::new().red().bold();
+    let yellow_bold = Style::new().yellow().bold();
+    let document = read_json_file(shapes_path, "shapes");
+    let shapes = node_shapes(&document);
+    if shapes.is_empty() {
+        pretty_print(
+            &format!(
+                "{} holds no SHACL shapes (migrate with --shacl, or write them with the shapes command)",
+                shapes_path.display()
+            ),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    }
+    let ledger = match opt
+        .ledger_name
+        .clone()
+        .or(document["ledger"].as_str().map(str::to_string))
+    {
+        Some(ledger) => ledger,
+        None => {
+            pretty_print(
+                "Auditing needs the --ledger-name of the v3 ledger",
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    };
+    let context = without_reverse_terms(&document["@context"]);
+    let mut target_instance = FlureeInstance::new_target(opt);
+    let mut report = AuditReport {
+        ledger: ledger.to_owned(),
+        ..Default::default()
+    };
+    let mut class_instances: HashMap<String, HashSet<String>> = HashMap::new();
+
+    opt.pb.set_length(shapes.len() as u64);
+    opt.pb.set_prefix("Auditing");
+    for shape in &shapes {
+        opt.pb.inc(1);
+        opt.pb.set_message(shape.target_class.to_owned());
+        let class = &shape.target_class;
+        // every value of every instance, as the datatype and refs of each subject's values per property
+        let mut values: BTreeMap<String, HashMap<String, Vec<(Value, String)>>> = BTreeMap::new();
+        let mut offset = 0;
+        loop {
+            let query = json!({
+                "@context": context,
+                "from": ledger,
+                "select": ["?s", "?p", "?o", "?dt"],
+                "where": [
+                    { "@id": "?s", "@type": class },
+                    { "@id": "?s", "?p": "?o" },
+                    ["bind", "?dt", "(datatype ?o)"]
+                ],
+                "orderBy": ["?s", "?p"],
+                "limit": AUDIT_PAGE_SIZE,
+                "offset": offset
+            });
+            let rows = target_instance
+                .v3_query_array(&query)
+                .await
+                .unwrap_or_else(|e| state::abort(opt, &e));
+            for row in &rows {
+                let [subject, property, object, datatype] =
+                    row.as_array().map(Vec::as_slice).unwrap_or_default()
+                else {
+                    continue;
+                };
+                let (Some(subject), Some(property)) = (subject.as_str(), property.as_str()) else {
+                    continue;
+                };
+                let subject_values = values.entry(subject.to_string()).or_default();
+                if ["@type", "rdf:type"].contains(&property) {
+                    continue;
+                }
+                subject_values
+                    .entry(property.to_string())
+                    .or_default()
+                    .push((
+                        object.to_owned(),
+                        datatype.as_str().unwrap_or_default().to_string(),
+                    ));
+            }
+            if rows.len() < AUDIT_PAGE_SIZE {
+                break;
+            }
+            offset += AUDIT_PAGE_SIZE;
+        }
+        report.checked_instances += values.len() as u64;
+
+        for property in &shape.properties {
+            let ref_instances = match &property.class {
+                Some(ref_class) => Some(
+                    instances_of(
+                        &mut target_instance,
+                        &mut class_instances,
+                        &context,
+                        &ledger,
+                        ref_class,
+                    )
+                    .await,
+                ),
+                None => None,
+            };
+            for (subject, subject_values) in &values {
+                let property_values = subject_values
+                    .get(&property.path)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let mut violation = |constraint: &str, message: String| {
+                    report.violations.push(Violation {
+                        focus_node: subject.to_owned(),
+                        class: class.to_owned(),
+                        path: property.path.to_owned(),
+                        constraint: constraint.to_string(),
+                        message,
+                    })
+                };
+                let value_count = property_values.len() as u64;
+                if let Some(min_count) = property.min_count.filter(|min| value_count < *min) {
+                    violation(
+                        "sh:minCount",
+                        format!(
+                            "{} values, but at least {} are required",
+                            value_count, min_count
+                        ),
+                    );
+                }
+                if let Some(max_count) = property.max_count.filter(|max| value_count > *max) {
+                    violation(
+                        "sh:maxCount",
+                        format!(
+                            "{} values, but at most {} are allowed",
+                            value_count, max_count
+                        ),
+                    );
+                }
+                for (object, datatype) in property_values {
+                    let is_ref = datatype == "@id" || datatype == "id";
+                    match (&property.datatype, is_ref) {
+                        (Some(expected), true) => violation(
+                            "sh:datatype",
+                            format!("{} is a ref, but values must be {}", object, expected),
+                        ),
+                        (Some(expected), false) if !datatype_matches(expected, datatype) => {
+                            violation(
+                                "sh:datatype",
+                                format!(
+                                    "{} is {}, but values must be {}",
+                                    object, datatype, expected
+                                ),
+                            )
+                        }
+                        _ => {}
+                    }
+                    if property.node_kind.as_deref() == Some("sh:IRI") && !is_ref {
+                        violation(
+                            "sh:nodeKind",
+                            format!("{} is a literal, but values must be IRIs", object),
+                        );
+                    }
+                    if let (Some(ref_class), Some(ref_instances)) =
+                        (&property.class, &ref_instances)
+                    {
+                        let is_instance = object
+                            .as_str()
+                            .is_some_and(|object| is_ref && ref_instances.contains(object));
+                        if !is_instance {
+                            violation(
+                                "sh:class",
+                                format!("{} is not an instance of {}", object, ref_class),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if shape.closed {
+            let paths = shape
+                .properties
+                .iter()
+                .map(|property| property.path.as_str())
+                .collect::<HashSet<&str>>();
+            for (subject, subject_values) in &values {
+                for property in subject_values.keys() {
+                    if !paths.contains(property.as_str())
+                        && !shape.ignored_properties.contains(property)
+                    {
+                        report.violations.push(Violation {
+                            focus_node: subject.to_owned(),
+                            class: class.to_owned(),
+                            path: property.to_owned(),
+                            constraint: "sh:closed".to_string(),
+                            message: format!("{} is not a property of the closed shape", property),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    opt.pb.finish_and_clear();
+
+    if let Some(report_path) = &opt.report {
+        if let Err(e) = std::fs::write(
+            report_path,
+            redact(&serde_json::to_string_pretty(&report).unwrap()),
+        ) {
+            pretty_print(
+                &format!("Could not write report to {}: {}", report_path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+        }
+    }
+    if report.violations.is_empty() {
+        println!(
+            "{:>12} {} instances of {} classes in {} conform to their shapes",
+            green_bold.apply_to("Audited"),
+            report.checked_instances,
+            shapes.len(),
+            ledger
+        );
+        return;
+    }
+    let mut groups: BTreeMap<(&str, &str, &str), Vec<&Violation>> = BTreeMap::new();
+    for violation in &report.violations {
+        groups
+            .entry((
+                violation.class.as_str(),
+                violation.path.as_str(),
+                violation.constraint.as_str(),
+            ))
+            .or_default()
+            .push(violation);
+    }
+    for ((class, path, constraint), violations) in &groups {
+        println!(
+            "{:>12} {} instances of {} violate {} of {}",
+            yellow_bold.apply_to("VIOLATION"),
+            violations.len(),
+            class,
+            constraint,
+            path
+        );
+        for violation in violations.iter().take(MAX_PRINTED_EXAMPLES) {
+            println!("{:>12} {}: {}", "", violation.focus_node, violation.message);
+        }
+    }
+    println!(
+        "{:>12} {} violations in {} of {} checked instances of {}",
+        red_bold.apply_to("ERROR"),
+        report.violations.len(),
+        report
+            .violations
+            .iter()
+            .map(|violation| &violation.focus_node)
+            .collect::<HashSet<&String>>()
+            .len(),
+        report.checked_instances,
+        ledger
+    );
+    std::process::exit(1);
+}
+
+// the instances of a class, queried once per class
+async fn instances_of(
+    target_instance: &mut FlureeInstance,
+    class_instances: &mut HashMap<String, HashSet<String>>,
+    context: &Value,
+    ledger: &str,
+    class: &str,
+) -> HashSet<String> {
+    if let Some(instances) = class_instances.get(class) {
+        return instances.to_owned();
+    }
+    let mut instances = HashSet::new();
+    let mut offset = 0;
+    loop {
+        let query = json!({
+            "@context": context,
+            "from": ledger,
+            "select": ["?s"],
+            "where": [{ "@id": "?s", "@type": class }],
+            "orderBy": ["?s"],
+            "limit": AUDIT_PAGE_SIZE,
+            "offset": offset
+        });
+        let rows = target_instance
+            .v3_query_array(&query)
+            .await
+            .unwrap_or_else(|e| state::abort(&target_instance.opt, &e));
+        instances.extend(rows.iter().filter_map(|row| {
+            row.as_str()
+                .or(row.get(0).and_then(Value::as_str))
+                .map(str::to_string)
+        }));
+        if rows.len() < AUDIT_PAGE_SIZE {
+            break;
+        }
+        offset += AUDIT_PAGE_SIZE;
+    }
+    class_instances.insert(class.to_string(), instances.to_owned());
+    instances
+}
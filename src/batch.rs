@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use dialoguer::console::Style;
+use indicatif::HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::opt::Opt;
+use crate::cli::source::Migrate;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+use crate::functions::read_json_file;
+use crate::redact::redact;
+use crate::report::MigrationReport;
+use crate::state::run_file_path;
+use crate::trace;
+
+/// The file, in the output directory (or the working directory), where a batch writes its combined report
+/// when --report isn't given
+pub const BATCH_REPORT_FILE: &str = "batch-report.json";
+
+/// The source ledgers of a batch migration, e.g.
+/// {"ledgers": [{"source": "http://localhost:8090/fdb/acme/crm", "ledger": "acme/crm", "excludeClasses": ["auditLog"]}]}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BatchManifest {
+    pub ledgers: Vec<BatchLedger>,
+}
+
+/// One source ledger of a batch, with the options that override the command line's for it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BatchLedger {
+    /// The v2 ledger URL
+    pub source: String,
+    /// The target ledger name (otherwise derived with --ledger-map, or the v2 ledger name)
+    pub ledger: Option<String>,
+    pub base: Option<String>,
+    pub vocab: Option<String>,
+    #[serde(default)]
+    pub classes: Vec<String>,
+    #[serde(default)]
+    pub exclude_classes: Vec<String>,
+    /// The output directory (otherwise a subdirectory of --output named for the target ledger)
+    pub output: Option<PathBuf>,
+    /// v2 collections whose entities are migrated into another ledger, like --ref-ledger. A ledger of the
+    /// batch (by target or v2 ledger name) stands for the base IRI its subjects are migrated with
+    #[serde(default)]
+    pub ref_ledgers: BTreeMap<String, String>,
+}
+
+/// What a batch did with one source ledger
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchLedgerReport {
+    pub source: String,
+    pub ledger: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    pub duration_seconds: f64,
+    #[serde(flatten)]
+    pub report: MigrationReport,
+}
+
+/// The findings of every ledger of a batch, written as one JSON report
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchReport {
+    pub ledgers: Vec<BatchLedgerReport>,
+}
+
+impl BatchManifest {
+    pub fn load(path: &Path) -> Self {
+        let json = read_json_file(path, "batch manifest");
+        match serde_json::from_value::<BatchManifest>(json) {
+            Ok(manifest) if !manifest.ledgers.is_empty() => manifest,
+            Ok(_) => {
+                pretty_print(
+                    &format!("The batch manifest {} lists no ledgers", path.display()),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                pretty_print(
+                    &format!("Invalid batch manifest {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+impl BatchLedger {
+    /// The options of the command line, with this ledger's overrides applied
+    fn options(&self, opt: &Opt, batch: &[BatchLedger]) -> Opt {
+        let mut ledger_opt = opt.clone();
+        ledger_opt.command = None;
+        ledger_opt.report = None;
+        ledger_opt.source = Some(self.source.to_owned());
+        ledger_opt.ledger_name = self.ledger.clone();
+        if self.base.is_some() {
+            ledger_opt.base = self.base.clone();
+            ledger_opt.no_base = false;
+        }
+        if self.vocab.is_some() {
+            ledger_opt.vocab = self.vocab.clone();
+            ledger_opt.no_vocab = false;
+        }
+        if !self.classes.is_empty() {
+            ledger_opt.classes = self.classes.clone();
+        }
+        if !self.exclude_classes.is_empty() {
+            ledger_opt.exclude_classes = self.exclude_classes.clone();
+        }
+        for (collection, ledger) in &self.ref_ledgers {
+            let ledger = match batch.iter().find(|other| other.is_named(ledger)) {
+                Some(other) => other.subject_base(opt),
+                None => ledger.to_owned(),
+            };
+            ledger_opt.ref_ledgers.push((collection.to_owned(), ledger));
+        }
+        ledger_opt.output = match (&self.output, &opt.output) {
+            (Some(output), _) => Some(output.to_owned()),
+            (None, Some(output)) => Some(output.join(self.target_ledger(&ledger_opt))),
+            (None, None) => None,
+        };
+        ledger_opt
+    }
+
+    fn v2_ledger(&self) -> String {
+        let (network_name, db_name) = FlureeInstance::get_db_name(&self.source);
+        format!("{}/{}", network_name, db_name)
+    }
+
+    fn target_ledger(&self, ledger_opt: &Opt) -> String {
+        ledger_opt.target_ledger_name(&self.v2_ledger())
+    }
+
+    fn is_named(&self, ledger: &str) -> bool {
+        self.ledger.as_deref() == Some(ledger) || self.v2_ledger() == ledger
+    }
+
+    // the base IRI this ledger's subjects are migrated with: its own base, --base, or the default one for its source
+    fn subject_base(&self, opt: &Opt) -> String {
+        match self.base.as_ref().or(opt.base.as_ref()) {
+            Some(base) => base.to_owned(),
+            None => format!("{}/ids/", self.source.trim_end_matches('/')),
+        }
+    }
+}
+
+/// Migrates the ledgers of a batch manifest one after the other, then writes their combined report.
+/// A ledger that fails stops the batch, so the ledgers after it can be migrated by rerunning with a trimmed manifest
+pub async fn migrate_batch(opt: &Opt, manifest_path: &Path) {
+    let green_bold = Style::new().green().bold();
+    let manifest = BatchManifest::load(manifest_path);
+    let start = Instant::now();
+    let mut batch_report = BatchReport::default();
+    for (index, ledger) in manifest.ledgers.iter().enumerate() {
+        let ledger_opt = ledger.options(opt, &manifest.ledgers);
+        let target_ledger = ledger.target_ledger(&ledger_opt);
+        println!(
+            "{:>12} {} to {} ({} of {})",
+            green_bold.apply_to("Migrating"),
+            ledger.source,
+            target_ledger,
+            index + 1,
+            manifest.ledgers.len()
+        );
+        let ledger_start = Instant::now();
+        let mut source_instance = FlureeInstance::new_source(&ledger_opt);
+        let report = source_instance.migrate().await;
+        batch_report.ledgers.push(BatchLedgerReport {
+            source: ledger.source.to_owned(),
+            ledger: target_ledger,
+            output: ledger_opt
+                .output
+                .filter(|_| !opt.print && opt.target.is_none()),
+            duration_seconds: ledger_start.elapsed().as_secs_f64(),
+            report,
+        });
+    }
+
+    let report_path = opt
+        .report
+        .clone()
+        .unwrap_or_else(|| run_file_path(opt, BATCH_REPORT_FILE));
+    if let Err(e) = std::fs::write(
+        &report_path,
+        redact(&serde_json::to_string_pretty(&batch_report).unwrap()),
+    ) {
+        pretty_print(
+            &format!("Could not write report to {}: {}", report_path.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+    }
+    println!(
+        "{:>12} Batch of {} ledgers in {} (report written to {}, run {})",
+        green_bold.apply_to("Finished"),
+        batch_report.ledgers.len(),
+        HumanDuration(start.elapsed()),
+        report_path.display(),
+        trace::correlation_id()
+    );
+}
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{Error, Response, StatusCode};
+// responses faster than this grow the batches they answered, and slower ones shrink them
+const FAST_RESPONSE: Duration = Duration::from_secs(2);
+const SLOW_RESPONSE: Duration = Duration::from_secs(30);
+
+// how far --adaptive-batching can take a batch from its base size
+const MIN_SCALE: f64 = 1.0 / 16.0;
+const MAX_SCALE: f64 = 4.0;
+const GROWTH: f64 = 1.25;
+
+/// The factor `--adaptive-batching` applies to a batch size (the source page size, or the target data chunk size),
+/// shared by the tasks using it. It halves on a failed or slow response and grows on fast ones, and stays 1
+/// without the flag
+#[derive(Debug, Clone)]
+pub struct BatchScale(Arc<AtomicU64>);
+
+impl Default for BatchScale {
+    fn default() -> Self {
+        BatchScale(Arc::new(AtomicU64::new(1f64.to_bits())))
+    }
+}
+
+impl BatchScale {
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, scale: f64) {
+        self.0.store(
+            scale.clamp(MIN_SCALE, MAX_SCALE).to_bits(),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// `base` at the current scale, at least 1
+    pub fn scale(&self, base: u64) -> u64 {
+        ((base as f64 * self.get()).round() as u64).max(1)
+    }
+
+    /// Adjusts the scale to how long a successful request took
+    pub fn observe(&self, elapsed: Duration) {
+        if elapsed < FAST_RESPONSE {
+            self.set(self.get() * GROWTH);
+        } else if elapsed > SLOW_RESPONSE {
+            self.shrink();
+        }
+    }
+
+    pub fn shrink(&self) {
+        self.set(self.get() / 2.0);
+    }
+}
+
+/// Whether a request failed in a way a smaller batch could avoid: a 413, or a timeout
+pub fn is_batch_too_large(result: &Result<Response, Error>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+                | StatusCode::REQUEST_TIMEOUT
+                | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(e) => e.is_timeout(),
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+
+use reqwest::{Error, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::cassette;
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::{read_json_file, response_error_message, sha256_hex};
+
+/// The file, in the cache directory, that records which source snapshot its responses are from
+pub const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// The source and --block (or --as-of) of the queries in a cache, which `--from-cache` runs reuse
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<String>,
+}
+
+impl Snapshot {
+    pub fn read(directory: &Path) -> Self {
+        serde_json::from_value(read_json_file(
+            &directory.join(SNAPSHOT_FILE),
+            "cache snapshot",
+        ))
+        .unwrap_or_else(|e| {
+            pretty_print(
+                &format!("Invalid cache snapshot in {}: {}", directory.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        })
+    }
+}
+
+/// The `--cache` directory, where the responses to source queries are kept, each in `<key>.json` next to
+/// its query in `<key>.query.json`. The key is the SHA-256 of the source URL and query, which holds its `block`
+#[derive(Debug, Clone)]
+pub struct QueryCache {
+    directory: PathBuf,
+    /// With --from-cache, a query that isn't cached is an error rather than a request to the source
+    pub is_offline: bool,
+}
+
+impl QueryCache {
+    /// The cache of the queries to the source at `url`, whose snapshot is recorded unless reading --from-cache
+    pub fn new(opt: &Opt, url: &str) -> Option<Self> {
+        if let Some(directory) = &opt.from_cache {
+            return Some(QueryCache {
+                directory: directory.to_owned(),
+                is_offline: true,
+            });
+        }
+        let directory = opt.cache.to_owned()?;
+        let snapshot = Snapshot {
+            source: url.to_string(),
+            block: opt.block,
+            as_of: opt.as_of.to_owned(),
+        };
+        let created = std::fs::create_dir_all(&directory).and_then(|_| {
+            std::fs::write(
+                directory.join(SNAPSHOT_FILE),
+                serde_json::to_string_pretty(&snapshot).unwrap(),
+            )
+        });
+        if let Err(e) = created {
+            pretty_print(
+                &format!(
+                    "Could not create cache directory {}: {}",
+                    directory.display(),
+                    e
+                ),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+        Some(QueryCache {
+            directory,
+            is_offline: false,
+        })
+    }
+
+    fn path(&self, url: &str, query: &str, extension: &str) -> PathBuf {
+        let key = sha256_hex(format!("{}\n{}", url, query).as_bytes());
+        self.directory.join(format!("{}.{}", key, extension))
+    }
+
+    /// The cached response to a query, if it was answered in an earlier run
+    pub fn get(&self, url: &str, query: &str) -> Option<Response> {
+        let body = std::fs::read_to_string(self.path(url, query, "json")).ok()?;
+        Some(cassette::response(200, body))
+    }
+
+    /// Caches the response to a query, returning an equivalent response. Only successful responses are cached,
+    /// so a failed query is issued again by the next run
+    pub async fn put(
+        &self,
+        url: &str,
+        query: &str,
+        result: Result<Response, Error>,
+    ) -> Result<Response, Error> {
+        let response = result?;
+        if !response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+        if response_error_message(&body).is_none() {
+            // the response is written last and renamed into place, so an interrupted run never leaves a partial
+            // response, or one without its query, to be read by the next
+            let path = self.path(url, query, "json");
+            let partial_path = self.path(url, query, "json.partial");
+            let written = std::fs::write(self.path(url, query, "query.json"), query)
+                .and_then(|_| std::fs::write(&partial_path, &body))
+                .and_then(|_| std::fs::rename(&partial_path, &path));
+            if let Err(e) = written {
+                pretty_print(
+                    &format!("Could not cache the response to a query: {}", e),
+                    ERROR_COLOR,
+                    true,
+                );
+            }
+        }
+        Ok(cassette::response(status, body))
+    }
+}
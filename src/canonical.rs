@@ -0,0 +1,786 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::functions::sha256_hex;
+
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+pub const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_LANG_STRING: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    Iri(String),
+    BlankNode(String),
+    Literal {
+        value: String,
+        datatype: String,
+        language: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Quad {
+    pub subject: Term,
+    pub predicate: String,
+    pub object: Term,
+    pub graph: Option<Term>,
+}
+
+impl Quad {
+    pub fn has_blank_nodes(&self) -> bool {
+        self.blank_nodes().next().is_some()
+    }
+
+    fn blank_nodes(&self) -> impl Iterator<Item = &str> {
+        [Some(&self.subject), Some(&self.object), self.graph.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|term| match term {
+                Term::BlankNode(label) => Some(label.as_str()),
+                _ => None,
+            })
+    }
+
+    // one line of N-Quads, with each blank node written as `label` names it (e.g. "_:c14n0")
+    pub fn to_nquad(&self, label: &dyn Fn(&str) -> String) -> String {
+        let term = |term: &Term| match term {
+            Term::Iri(iri) => format!("<{}>", iri),
+            Term::BlankNode(blank_node) => label(blank_node),
+            Term::Literal {
+                value,
+                datatype,
+                language,
+            } => match (language, datatype.as_str()) {
+                (Some(language), _) => format!("\"{}\"@{}", escape(value), language),
+                (None, datatype) if datatype == format!("{}string", XSD) => {
+                    format!("\"{}\"", escape(value))
+                }
+                (None, datatype) => format!("\"{}\"^^<{}>", escape(value), datatype),
+            },
+        };
+        let graph = match &self.graph {
+            Some(graph) => format!(" {}", term(graph)),
+            None => String::new(),
+        };
+        format!(
+            "{} <{}> {}{} .\n",
+            term(&self.subject),
+            self.predicate,
+            term(&self.object),
+            graph
+        )
+    }
+}
+
+// the escaping of canonical N-Quads: ECHAR for the characters that have one, UCHAR for other control characters
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '\u{0}'..='\u{1f}' | '\u{7f}' => {
+                escaped.push_str(&format!("\\u{:04X}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// The parts of a JSON-LD @context this tool generates: @base, @vocab, prefixes/terms, and @reverse terms
+#[derive(Debug, Default)]
+struct Context {
+    base: Option<String>,
+    vocab: Option<String>,
+    terms: HashMap<String, String>,
+    reverse: HashMap<String, String>,
+}
+
+impl Context {
+    fn new(context: Option<&Value>) -> Self {
+        let mut parsed = Context::default();
+        let contexts = match context {
+            Some(Value::Array(contexts)) => contexts.iter().collect(),
+            Some(context) => vec![context],
+            None => vec![],
+        };
+        for (key, value) in contexts.into_iter().filter_map(Value::as_object).flatten() {
+            match (key.as_str(), value) {
+                ("@base", Value::String(base)) => parsed.base = Some(base.to_string()),
+                ("@vocab", Value::String(vocab)) => parsed.vocab = Some(vocab.to_string()),
+                (term, Value::String(iri)) => {
+                    parsed.terms.insert(term.to_string(), iri.to_string());
+                }
+                (term, Value::Object(definition)) => {
+                    if let Some(Value::String(inverse)) = definition.get("@reverse") {
+                        parsed.reverse.insert(term.to_string(), inverse.to_string());
+                    } else if let Some(Value::String(iri)) = definition.get("@id") {
+                        parsed.terms.insert(term.to_string(), iri.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    /// Expands a compact IRI, term, or relative IRI; `vocab` resolves against @vocab (properties and
+    /// types) rather than @base (ids)
+    fn expand(&self, value: &str, vocab: bool) -> String {
+        if vocab {
+            if let Some(iri) = self.terms.get(value) {
+                return self.expand(iri, false);
+            }
+        }
+        if let Some((prefix, suffix)) = value.split_once(':') {
+            if let Some(namespace) = self.terms.get(prefix) {
+                return format!("{}{}", namespace, suffix);
+            }
+            return value.to_string();
+        }
+        let namespace = match vocab {
+            true => &self.vocab,
+            false => &self.base,
+        };
+        format!("{}{}", namespace.as_deref().unwrap_or_default(), value)
+    }
+}
+
+// flattens nested arrays of values and @set objects, as JSON-LD does
+fn values(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(array) => array.iter().flat_map(values).collect(),
+        Value::Object(object) if object.contains_key("@set") => values(&object["@set"]),
+        value => vec![value],
+    }
+}
+
+struct Converter {
+    context: Context,
+    graph: Option<Term>,
+    quads: Vec<Quad>,
+    blank_nodes: HashMap<String, String>,
+    blank_node_count: usize,
+}
+
+impl Converter {
+    // blank nodes are relabeled by canonicalization anyway, so the document's own labels are only kept apart
+    fn blank_node(&mut self, label: Option<&str>) -> Term {
+        if let Some(existing) = label.and_then(|label| self.blank_nodes.get(label)) {
+            return Term::BlankNode(existing.to_owned());
+        }
+        let fresh = format!("n{}", self.blank_node_count);
+        self.blank_node_count += 1;
+        if let Some(label) = label {
+            self.blank_nodes.insert(label.to_string(), fresh.to_owned());
+        }
+        Term::BlankNode(fresh)
+    }
+
+    fn node(&mut self, node: &Map<String, Value>) -> Term {
+        let subject = match node.get("@id").and_then(Value::as_str) {
+            Some(id) if id.starts_with("_:") => self.blank_node(Some(id)),
+            Some(id) => Term::Iri(self.context.expand(id, false)),
+            None => self.blank_node(None),
+        };
+        for (key, value) in node {
+            match key.as_str() {
+                "@type" => {
+                    for class in values(value).into_iter().filter_map(Value::as_str) {
+                        let object = Term::Iri(self.context.expand(class, true));
+                        self.push(subject.clone(), RDF_TYPE.to_string(), object);
+                    }
+                }
+                "@reverse" => {
+                    for (term, value) in value.as_object().into_iter().flatten() {
+                        let predicate = self.context.expand(term, true);
+                        self.reverse(&subject, predicate, value);
+                    }
+                }
+                key if key.starts_with('@') => {}
+                key if self.context.reverse.contains_key(key) => {
+                    let predicate = self.context.expand(&self.context.reverse[key], true);
+                    self.reverse(&subject, predicate, value);
+                }
+                key => {
+                    let predicate = self.context.expand(key, true);
+                    for value in values(value) {
+                        if let Some(object) = self.object(value) {
+                            self.push(subject.clone(), predicate.to_owned(), object);
+                        }
+                    }
+                }
+            }
+        }
+        subject
+    }
+
+    fn reverse(&mut self, subject: &Term, predicate: String, value: &Value) {
+        for value in values(value) {
+            if let Value::Object(node) = value {
+                let reverse_subject = self.node(node);
+                self.push(reverse_subject, predicate.to_owned(), subject.clone());
+            }
+        }
+    }
+
+    fn object(&mut self, value: &Value) -> Option<Term> {
+        let literal = |value: String, datatype: &str| Term::Literal {
+            value,
+            datatype: datatype.to_string(),
+            language: None,
+        };
+        match value {
+            Value::Null | Value::Array(_) => None,
+            Value::Bool(boolean) => Some(literal(boolean.to_string(), &format!("{}boolean", XSD))),
+            Value::Number(number) => Some(number_literal(&number.to_string())),
+            Value::String(string) => Some(literal(string.to_string(), &format!("{}string", XSD))),
+            Value::Object(object) => match object.get("@value") {
+                Some(Value::Null) => None,
+                Some(inner) => {
+                    let lexical = match inner {
+                        Value::String(string) => string.to_string(),
+                        inner => inner.to_string(),
+                    };
+                    if let Some(Value::String(language)) = object.get("@language") {
+                        return Some(Term::Literal {
+                            value: lexical,
+                            datatype: RDF_LANG_STRING.to_string(),
+                            language: Some(language.to_lowercase()),
+                        });
+                    }
+                    match (object.get("@type").and_then(Value::as_str), inner) {
+                        (Some(datatype), _) => {
+                            Some(literal(lexical, &self.context.expand(datatype, true)))
+                        }
+                        (None, Value::Number(number)) => Some(number_literal(&number.to_string())),
+                        (None, Value::Bool(_)) => {
+                            Some(literal(lexical, &format!("{}boolean", XSD)))
+                        }
+                        (None, _) => Some(literal(lexical, &format!("{}string", XSD))),
+                    }
+                }
+                None => match object.get("@list") {
+                    Some(list) => Some(self.list(list)),
+                    None => Some(self.node(object)),
+                },
+            },
+        }
+    }
+
+    // an @list as an RDF collection: a blank node per item, linked by rdf:first and rdf:rest and ended by rdf:nil
+    fn list(&mut self, list: &Value) -> Term {
+        let items = values(list)
+            .into_iter()
+            .filter_map(|item| self.object(item))
+            .collect::<Vec<Term>>();
+        let mut rest = Term::Iri(RDF_NIL.to_string());
+        for item in items.into_iter().rev() {
+            let node = self.blank_node(None);
+            self.push(node.clone(), RDF_FIRST.to_string(), item);
+            self.push(node.clone(), RDF_REST.to_string(), rest);
+            rest = node;
+        }
+        rest
+    }
+
+    fn push(&mut self, subject: Term, predicate: String, object: Term) {
+        self.quads.push(Quad {
+            subject,
+            predicate,
+            object,
+            graph: self.graph.clone(),
+        });
+    }
+}
+
+// JSON-LD's conversion of a JSON number to RDF: a number with a fractional part, or of at least 10^21, is an
+// xsd:double in canonical form (e.g. "1.5E0"), and any other (e.g. 5 or 5.0) is an xsd:integer. Integers too large
+// for a double keep their digits
+fn number_literal(number: &str) -> Term {
+    let double = number.parse::<f64>().unwrap_or_default();
+    let (value, datatype) = match number.contains(['.', 'e', 'E']) {
+        false => (number.to_string(), "integer"),
+        true if double.fract() == 0.0 && double.abs() < 1e21 => {
+            (format!("{}", double as i128), "integer")
+        }
+        true => {
+            let double = format!("{:E}", double);
+            let double = match double.split_once('E') {
+                Some((mantissa, exponent)) if !mantissa.contains('.') => {
+                    format!("{}.0E{}", mantissa, exponent)
+                }
+                _ => double,
+            };
+            (double, "double")
+        }
+    };
+    Term::Literal {
+        value,
+        datatype: format!("{}{}", XSD, datatype),
+        language: None,
+    }
+}
+
+/// The RDF quads of an output document: the nodes of its `insert` (or `@graph`), under its @context
+pub fn document_quads(document: &Map<String, Value>) -> Vec<Quad> {
+    let context = Context::new(document.get("@context"));
+    // a document-level @id names the graph its @graph is in
+    let graph = match (
+        document.get("@id").and_then(Value::as_str),
+        document.get("@graph"),
+    ) {
+        (Some(id), Some(_)) => Some(Term::Iri(context.expand(id, false))),
+        _ => None,
+    };
+    let mut converter = Converter {
+        context,
+        graph,
+        quads: vec![],
+        blank_nodes: HashMap::new(),
+        blank_node_count: 0,
+    };
+    let nodes = match (document.get("insert"), document.get("@graph")) {
+        (Some(insert), _) => values(insert),
+        (None, Some(graph)) => values(graph),
+        (None, None) => vec![],
+    };
+    for node in nodes.into_iter().filter_map(Value::as_object) {
+        converter.node(node);
+    }
+    converter.quads
+}
+
+#[derive(Debug, Clone)]
+struct IdentifierIssuer {
+    prefix: &'static str,
+    issued: Vec<String>,
+    identifiers: HashMap<String, String>,
+}
+
+impl IdentifierIssuer {
+    fn new(prefix: &'static str) -> Self {
+        IdentifierIssuer {
+            prefix,
+            issued: vec![],
+            identifiers: HashMap::new(),
+        }
+    }
+
+    fn issue(&mut self, blank_node: &str) -> String {
+        if let Some(identifier) = self.identifiers.get(blank_node) {
+            return identifier.to_owned();
+        }
+        let identifier = format!("_:{}{}", self.prefix, self.issued.len());
+        self.issued.push(blank_node.to_string());
+        self.identifiers
+            .insert(blank_node.to_string(), identifier.to_owned());
+        identifier
+    }
+
+    fn get(&self, blank_node: &str) -> Option<&String> {
+        self.identifiers.get(blank_node)
+    }
+}
+
+/// Canonicalizes a dataset with the URDNA2015 algorithm (https://www.w3.org/TR/rdf-canon/), i.e. labels its blank
+/// nodes by their position in the graph rather than by the order they were written in, and returns it as
+/// sorted N-Quads, so the same data always serializes (and hashes) to the same bytes
+pub fn canonical_nquads(quads: &[Quad]) -> String {
+    let mut seen = HashSet::new();
+    let quads: Vec<&Quad> = quads.iter().filter(|quad| seen.insert(*quad)).collect();
+    let mut canonicalizer = Canonicalizer {
+        blank_node_quads: HashMap::new(),
+        first_degree_hashes: HashMap::new(),
+        canonical_issuer: IdentifierIssuer::new("c14n"),
+    };
+    for quad in &quads {
+        for blank_node in quad.blank_nodes() {
+            canonicalizer
+                .blank_node_quads
+                .entry(blank_node.to_string())
+                .or_default()
+                .push((*quad).clone());
+        }
+    }
+
+    let mut hash_to_blank_nodes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut blank_nodes = canonicalizer
+        .blank_node_quads
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+    blank_nodes.sort();
+    for blank_node in blank_nodes {
+        let hash = canonicalizer.first_degree_hash(&blank_node);
+        hash_to_blank_nodes
+            .entry(hash)
+            .or_default()
+            .push(blank_node);
+    }
+
+    let mut shared_hashes = vec![];
+    for blank_nodes in hash_to_blank_nodes.into_values() {
+        match blank_nodes.as_slice() {
+            [blank_node] => {
+                canonicalizer.canonical_issuer.issue(blank_node);
+            }
+            _ => shared_hashes.push(blank_nodes),
+        }
+    }
+    for blank_nodes in shared_hashes {
+        let mut hash_path_list = vec![];
+        for blank_node in blank_nodes {
+            if canonicalizer.canonical_issuer.get(&blank_node).is_some() {
+                continue;
+            }
+            let mut issuer = IdentifierIssuer::new("b");
+            issuer.issue(&blank_node);
+            hash_path_list.push(canonicalizer.n_degree_hash(&blank_node, issuer));
+        }
+        hash_path_list.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, issuer) in hash_path_list {
+            for blank_node in issuer.issued {
+                canonicalizer.canonical_issuer.issue(&blank_node);
+            }
+        }
+    }
+
+    let label = |blank_node: &str| {
+        canonicalizer
+            .canonical_issuer
+            .get(blank_node)
+            .cloned()
+            .unwrap_or_default()
+    };
+    let mut lines = quads
+        .iter()
+        .map(|quad| quad.to_nquad(&label))
+        .collect::<Vec<String>>();
+    lines.sort();
+    lines.dedup();
+    lines.concat()
+}
+
+struct Canonicalizer {
+    blank_node_quads: HashMap<String, Vec<Quad>>,
+    first_degree_hashes: HashMap<String, String>,
+    canonical_issuer: IdentifierIssuer,
+}
+
+impl Canonicalizer {
+    fn first_degree_hash(&mut self, blank_node: &str) -> String {
+        if let Some(hash) = self.first_degree_hashes.get(blank_node) {
+            return hash.to_owned();
+        }
+        let label = |label: &str| match label == blank_node {
+            true => "_:a".to_string(),
+            false => "_:z".to_string(),
+        };
+        let mut lines = self.blank_node_quads[blank_node]
+            .iter()
+            .map(|quad| quad.to_nquad(&label))
+            .collect::<Vec<String>>();
+        lines.sort();
+        let hash = sha256_hex(lines.concat().as_bytes());
+        self.first_degree_hashes
+            .insert(blank_node.to_string(), hash.to_owned());
+        hash
+    }
+
+    fn related_hash(
+        &mut self,
+        related: &str,
+        quad: &Quad,
+        issuer: &IdentifierIssuer,
+        position: &str,
+    ) -> String {
+        let identifier = match (self.canonical_issuer.get(related), issuer.get(related)) {
+            (Some(identifier), _) | (None, Some(identifier)) => identifier.to_owned(),
+            (None, None) => self.first_degree_hash(related),
+        };
+        let predicate = match position {
+            "g" => String::new(),
+            _ => format!("<{}>", quad.predicate),
+        };
+        sha256_hex(format!("{}{}{}", position, predicate, identifier).as_bytes())
+    }
+
+    fn n_degree_hash(
+        &mut self,
+        blank_node: &str,
+        mut issuer: IdentifierIssuer,
+    ) -> (String, IdentifierIssuer) {
+        let mut related_blank_nodes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for quad in self.blank_node_quads[blank_node].clone() {
+            let components = [
+                ("s", Some(&quad.subject)),
+                ("o", Some(&quad.object)),
+                ("g", quad.graph.as_ref()),
+            ];
+            for (position, component) in components {
+                if let Some(Term::BlankNode(related)) = component {
+                    if related != blank_node {
+                        let hash = self.related_hash(related, &quad, &issuer, position);
+                        related_blank_nodes
+                            .entry(hash)
+                            .or_default()
+                            .push(related.to_owned());
+                    }
+                }
+            }
+        }
+
+        let mut data_to_hash = String::new();
+        for (related_hash, blank_nodes) in related_blank_nodes {
+            data_to_hash.push_str(&related_hash);
+            let mut chosen: Option<(String, IdentifierIssuer)> = None;
+            'permutations: for permutation in permutations(&blank_nodes) {
+                let mut issuer_copy = issuer.clone();
+                let mut path = String::new();
+                let mut recursion_list = vec![];
+                let is_worse = |path: &String, chosen: &Option<(String, IdentifierIssuer)>| matches!(chosen, Some((chosen_path, _)) if path.len() >= chosen_path.len() && path > chosen_path);
+                for related in &permutation {
+                    match self.canonical_issuer.get(related) {
+                        Some(identifier) => path.push_str(identifier),
+                        None => {
+                            if issuer_copy.get(related).is_none() {
+                                recursion_list.push(related.to_owned());
+                            }
+                            path.push_str(&issuer_copy.issue(related));
+                        }
+                    }
+                    if is_worse(&path, &chosen) {
+                        continue 'permutations;
+                    }
+                }
+                for related in recursion_list {
+                    let (hash, result_issuer) = self.n_degree_hash(&related, issuer_copy.clone());
+                    path.push_str(&issuer_copy.issue(&related));
+                    path.push_str(&format!("<{}>", hash));
+                    issuer_copy = result_issuer;
+                    if is_worse(&path, &chosen) {
+                        continue 'permutations;
+                    }
+                }
+                if chosen
+                    .as_ref()
+                    .is_none_or(|(chosen_path, _)| path < *chosen_path)
+                {
+                    chosen = Some((path, issuer_copy));
+                }
+            }
+            if let Some((chosen_path, chosen_issuer)) = chosen {
+                data_to_hash.push_str(&chosen_path);
+                issuer = chosen_issuer;
+            }
+        }
+        (sha256_hex(data_to_hash.as_bytes()), issuer)
+    }
+}
+
+fn permutations(items: &[String]) -> Vec<Vec<String>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+    let mut result = vec![];
+    for (index, item) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(index);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, item.to_owned());
+            result.push(permutation);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // a quad of the default graph, where a term starting with "_:" is a blank node and any other an IRI
+    fn quad(subject: &str, predicate: &str, object: &str) -> Quad {
+        let term = |term: &str| match term.strip_prefix("_:") {
+            Some(label) => Term::BlankNode(label.to_string()),
+            None => Term::Iri(term.to_string()),
+        };
+        Quad {
+            subject: term(subject),
+            predicate: predicate.to_string(),
+            object: term(object),
+            graph: None,
+        }
+    }
+
+    fn document(insert: Value) -> Map<String, Value> {
+        json!({
+            "@context": {"ex": "http://example.com/#"},
+            "insert": insert,
+        })
+        .as_object()
+        .unwrap()
+        .to_owned()
+    }
+
+    // the "unique hashes" example of https://www.w3.org/TR/rdf-canon/, whose blank nodes are told apart by their
+    // first-degree hashes
+    #[test]
+    fn canonicalizes_unique_hashes() {
+        let quads = [
+            quad("http://example.com/#p", "http://example.com/#q", "_:e0"),
+            quad("http://example.com/#p", "http://example.com/#r", "_:e1"),
+            quad("_:e0", "http://example.com/#s", "http://example.com/#u"),
+            quad("_:e1", "http://example.com/#t", "http://example.com/#u"),
+        ];
+        assert_eq!(
+            canonical_nquads(&quads),
+            "<http://example.com/#p> <http://example.com/#q> _:c14n0 .\n\
+             <http://example.com/#p> <http://example.com/#r> _:c14n1 .\n\
+             _:c14n0 <http://example.com/#s> <http://example.com/#u> .\n\
+             _:c14n1 <http://example.com/#t> <http://example.com/#u> .\n"
+        );
+    }
+
+    // the "shared hashes" example of https://www.w3.org/TR/rdf-canon/, where e0 and e1 share a first-degree hash
+    // and are told apart by the hash of their n-degree paths
+    #[test]
+    fn canonicalizes_shared_hashes() {
+        let quads = [
+            quad("http://example.com/#p", "http://example.com/#q", "_:e0"),
+            quad("http://example.com/#p", "http://example.com/#q", "_:e1"),
+            quad("_:e0", "http://example.com/#p", "_:e2"),
+            quad("_:e1", "http://example.com/#p", "_:e3"),
+            quad("_:e2", "http://example.com/#r", "_:e3"),
+        ];
+        assert_eq!(
+            canonical_nquads(&quads),
+            "<http://example.com/#p> <http://example.com/#q> _:c14n2 .\n\
+             <http://example.com/#p> <http://example.com/#q> _:c14n3 .\n\
+             _:c14n0 <http://example.com/#r> _:c14n1 .\n\
+             _:c14n2 <http://example.com/#p> _:c14n1 .\n\
+             _:c14n3 <http://example.com/#p> _:c14n0 .\n"
+        );
+    }
+
+    #[test]
+    fn canonical_labels_dont_depend_on_the_input_labels() {
+        let quads = [
+            quad("_:x", "http://example.com/#next", "_:y"),
+            quad("_:y", "http://example.com/#next", "_:x"),
+        ];
+        let relabeled = [
+            quad("_:b", "http://example.com/#next", "_:a"),
+            quad("_:a", "http://example.com/#next", "_:b"),
+        ];
+        assert_eq!(canonical_nquads(&quads), canonical_nquads(&relabeled));
+    }
+
+    #[test]
+    fn escapes_literals() {
+        let quad = Quad {
+            subject: Term::Iri("http://example.com/#s".to_string()),
+            predicate: "http://example.com/#p".to_string(),
+            object: Term::Literal {
+                value: "say \"hi\"\n\\\u{1}".to_string(),
+                datatype: format!("{}string", XSD),
+                language: None,
+            },
+            graph: None,
+        };
+        assert_eq!(
+            canonical_nquads(&[quad]),
+            "<http://example.com/#s> <http://example.com/#p> \"say \\\"hi\\\"\\n\\\\\\u0001\" .\n"
+        );
+    }
+
+    #[test]
+    fn converts_numbers_as_json_ld_does() {
+        let number = |number: &str| match number_literal(number) {
+            Term::Literal {
+                value, datatype, ..
+            } => (value, datatype.trim_start_matches(XSD).to_string()),
+            term => panic!("not a literal: {:?}", term),
+        };
+        assert_eq!(number("5"), ("5".to_string(), "integer".to_string()));
+        assert_eq!(number("5.0"), ("5".to_string(), "integer".to_string()));
+        assert_eq!(number("-2e3"), ("-2000".to_string(), "integer".to_string()));
+        assert_eq!(number("1.5"), ("1.5E0".to_string(), "double".to_string()));
+        assert_eq!(
+            number("-0.25"),
+            ("-2.5E-1".to_string(), "double".to_string())
+        );
+        assert_eq!(number("1e21"), ("1.0E21".to_string(), "double".to_string()));
+        assert_eq!(
+            number("123456789012345678901234567890"),
+            (
+                "123456789012345678901234567890".to_string(),
+                "integer".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn converts_lists_to_rdf_collections() {
+        let quads = document_quads(&document(json!([
+            {"@id": "ex:s", "ex:p": {"@list": ["a", {"@id": "ex:o"}]}}
+        ])));
+        assert_eq!(
+            canonical_nquads(&quads),
+            "<http://example.com/#s> <http://example.com/#p> _:c14n1 .\n\
+             _:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#first> <http://example.com/#o> .\n\
+             _:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#rest> <http://www.w3.org/1999/02/22-rdf-syntax-ns#nil> .\n\
+             _:c14n1 <http://www.w3.org/1999/02/22-rdf-syntax-ns#first> \"a\" .\n\
+             _:c14n1 <http://www.w3.org/1999/02/22-rdf-syntax-ns#rest> _:c14n0 .\n"
+        );
+    }
+
+    #[test]
+    fn converts_an_empty_list_to_rdf_nil() {
+        let quads = document_quads(&document(json!([
+            {"@id": "ex:s", "ex:p": {"@list": []}}
+        ])));
+        assert_eq!(
+            quads,
+            vec![quad(
+                "http://example.com/#s",
+                "http://example.com/#p",
+                RDF_NIL
+            )]
+        );
+    }
+
+    #[test]
+    fn converts_sets_to_their_values() {
+        let quads = document_quads(&document(json!([
+            {"@id": "ex:s", "ex:p": {"@set": [{"@id": "ex:a"}, {"@id": "ex:b"}]}}
+        ])));
+        assert_eq!(
+            quads,
+            vec![
+                quad(
+                    "http://example.com/#s",
+                    "http://example.com/#p",
+                    "http://example.com/#a"
+                ),
+                quad(
+                    "http://example.com/#s",
+                    "http://example.com/#p",
+                    "http://example.com/#b"
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+use reqwest::{Error, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::redact::redact;
+
+/// An HTTP request to the source or target and the response it got, as written to a `--record` cassette
+/// (one interaction per line). Request headers, which hold API keys, are left out, and secrets in bodies redacted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub url: String,
+    pub request: String,
+    pub status: u16,
+    pub response: String,
+}
+
+static RECORDING: Mutex<Option<File>> = Mutex::new(None);
+
+// the interactions of the --replay cassette, each taken once it has answered a request
+static REPLAYING: Mutex<Option<Vec<Option<Interaction>>>> = Mutex::new(None);
+
+/// Opens the `--record` cassette, or loads the `--replay` one, exiting if it can't be
+pub fn start(opt: &Opt) {
+    if let Some(path) = &opt.record {
+        match File::create(path) {
+            Ok(file) => *RECORDING.lock().unwrap() = Some(file),
+            Err(e) => {
+                pretty_print(
+                    &format!("Could not create cassette {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &opt.replay {
+        let interactions = File::open(path).map(|file| {
+            BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str::<Interaction>(&line))
+                .collect::<Result<Vec<Interaction>, serde_json::Error>>()
+        });
+        match interactions {
+            Ok(Ok(interactions)) => {
+                *REPLAYING.lock().unwrap() = Some(interactions.into_iter().map(Some).collect())
+            }
+            Ok(Err(e)) => {
+                pretty_print(
+                    &format!("Invalid cassette {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                pretty_print(
+                    &format!("Could not read cassette {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// With `--replay`, the recorded response to a request: the first unused interaction with the same URL and body,
+/// or failing that (e.g. a vocab whose nodes were ordered differently), the first unused one with the same URL.
+/// Exits when the cassette has no response left for the URL
+pub fn replayed(url: &str, body: &str) -> Option<Response> {
+    // the cassette holds the requests redacted
+    let (url, body) = (redact(url), redact(body));
+    let mut replaying = REPLAYING.lock().unwrap();
+    let interactions = replaying.as_mut()?;
+    let position = interactions
+        .iter()
+        .position(|interaction| {
+            interaction
+                .as_ref()
+                .is_some_and(|interaction| interaction.url == url && interaction.request == body)
+        })
+        .or_else(|| {
+            interactions.iter().position(|interaction| {
+                interaction
+                    .as_ref()
+                    .is_some_and(|interaction| interaction.url == url)
+            })
+        });
+    match position.and_then(|position| interactions[position].take()) {
+        Some(interaction) => Some(response(interaction.status, interaction.response)),
+        None => {
+            pretty_print(
+                &format!(
+                    "The cassette has no recorded response left for a request to {}",
+                    url
+                ),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// With `--record`, writes a request and its response to the cassette, returning an equivalent response.
+/// Requests that got no response (e.g. a refused connection) aren't recorded
+pub async fn record(
+    url: &str,
+    body: &str,
+    result: Result<Response, Error>,
+) -> Result<Response, Error> {
+    if RECORDING.lock().unwrap().is_none() {
+        return result;
+    }
+    let recorded = result?;
+    let status = recorded.status().as_u16();
+    let text = recorded.text().await?;
+    let interaction = Interaction {
+        url: redact(url),
+        request: redact(body),
+        status,
+        response: redact(&text),
+    };
+    if let Some(file) = RECORDING.lock().unwrap().as_mut() {
+        let line = serde_json::to_string(&interaction).unwrap();
+        let _ = writeln!(file, "{}", line);
+    }
+    Ok(response(status, text))
+}
+
+/// A response with the given status and body, as if it came from the server
+pub fn response(status: u16, body: String) -> Response {
+    Response::from(
+        http::Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap(),
+    )
+}
@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::content_hash;
+use crate::store;
+
+/// The file, in the working directory, where `--changed-only` runs record the content hash of every entity they
+/// migrated to each destination (unless they're recorded in the --state-db)
+pub const ENTITY_HASHES_FILE: &str = ".fluree-migrate-entity-hashes.json";
+
+// the content hashes of the entities migrated to each destination, keyed by the destination and then by `@id`
+type EntityHashFile = BTreeMap<String, BTreeMap<String, String>>;
+
+/// The content hashes of the entities the previous run migrated to the destination, and of those this run
+/// migrates, so only the entities that changed since are written (or transacted). With a --state-db, the hashes
+/// are looked up and recorded there, an entity at a time, rather than held in memory
+#[derive(Debug, Default)]
+pub struct EntityHashes {
+    destination: String,
+    is_stored: bool,
+    previous: HashMap<String, String>,
+    // the changed entities hashed since the last chunk was cut, which aren't written yet
+    pending: HashMap<String, String>,
+    // the unchanged entities, and the changed ones whose chunk was written
+    written: HashMap<String, String>,
+    unchanged: u64,
+}
+
+// the --target (or --output) and ledger the entities are migrated to, and the --shard migrating them, so the
+// shards don't forget each other's entities
+fn destination(opt: &Opt, ledger: &str) -> String {
+    let destination = match (&opt.target, &opt.output) {
+        (Some(target), _) => target.trim_end_matches('/').to_string(),
+        (None, Some(output)) => output.display().to_string(),
+        (None, None) => String::new(),
+    };
+    match opt.shard {
+        Some(shard) => format!("{} {} (shard {})", destination, ledger, shard),
+        None => format!("{} {}", destination, ledger),
+    }
+}
+
+// the hashes are kept out of the --output directory, which a run without --append deletes. The shards of a
+// --shard run each keep their own file
+fn path(opt: &Opt) -> PathBuf {
+    match opt.shard {
+        Some(shard) => shard.file_name(Path::new(ENTITY_HASHES_FILE)),
+        None => PathBuf::from(ENTITY_HASHES_FILE),
+    }
+}
+
+fn read(opt: &Opt) -> EntityHashFile {
+    std::fs::read_to_string(path(opt))
+        .ok()
+        .and_then(|hashes| serde_json::from_str(&hashes).ok())
+        .unwrap_or_default()
+}
+
+/// The hashes of the entities of the chunks the writer wrote: upserted into the --state-db as each chunk is
+/// written, or kept for `EntityHashes::record_written`
+#[derive(Debug)]
+pub enum WrittenHashes {
+    Stored(String),
+    Kept(HashMap<String, String>),
+}
+
+impl WrittenHashes {
+    /// Records the hashes of the entities of a chunk that was written (or transacted)
+    pub fn record(&mut self, hashes: HashMap<String, String>) {
+        match self {
+            WrittenHashes::Stored(destination) => store::save_entity_hashes(destination, &hashes),
+            WrittenHashes::Kept(written) => written.extend(hashes),
+        }
+    }
+}
+
+impl EntityHashes {
+    /// The hashes of the entities the previous run migrated to the ledger, if any
+    pub fn load(opt: &Opt, ledger: &str) -> Self {
+        let destination = destination(opt, ledger);
+        let is_stored = store::is_started();
+        let previous = match is_stored {
+            true => HashMap::new(),
+            false => read(opt)
+                .remove(&destination)
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        };
+        EntityHashes {
+            destination,
+            is_stored,
+            previous,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the transformed entity is new or differs from the one the previous run migrated. The hash of a
+    /// changed entity is pending until its chunk is written (see `take_pending`). An entity without an `@id` can't
+    /// be matched with the previous run's, so it always counts as changed
+    pub fn is_changed(&mut self, entity: &Value) -> bool {
+        let Some(id) = entity["@id"].as_str() else {
+            return true;
+        };
+        // the keys of a JSON object are serialized in the order of their names, so the same entity always has the
+        // same hash
+        let hash = content_hash(entity.to_string().as_bytes());
+        let previous = match self.is_stored {
+            true => store::entity_hash(&self.destination, id),
+            false => self.previous.get(id).cloned(),
+        };
+        match previous == Some(hash.clone()) {
+            true => {
+                self.unchanged += 1;
+                match self.is_stored {
+                    true => store::see_entity(&self.destination, id),
+                    false => {
+                        self.written.insert(id.to_string(), hash);
+                    }
+                }
+                false
+            }
+            false => {
+                self.pending.insert(id.to_string(), hash);
+                true
+            }
+        }
+    }
+
+    /// The hashes of the changed entities since the last chunk was cut, which go with the chunk to its writer
+    pub fn take_pending(&mut self) -> HashMap<String, String> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Where the chunk writer records the hashes of the entities of the chunks it writes
+    pub fn written_hashes(&self) -> WrittenHashes {
+        match self.is_stored {
+            true => WrittenHashes::Stored(self.destination.to_owned()),
+            false => WrittenHashes::Kept(HashMap::new()),
+        }
+    }
+
+    /// Records the hashes of the entities of chunks that were written (or transacted)
+    pub fn record_written(&mut self, hashes: WrittenHashes) {
+        if let WrittenHashes::Kept(hashes) = hashes {
+            self.written.extend(hashes);
+        }
+    }
+
+    /// The number of entities that hadn't changed since the previous run, so weren't written (or transacted)
+    pub fn unchanged(&self) -> u64 {
+        self.unchanged
+    }
+
+    /// The number of entities the previous run migrated that this one didn't, e.g. because they were deleted
+    /// from the source. They aren't retracted from the target
+    pub fn missing(&self) -> usize {
+        if self.is_stored {
+            return store::missing_entities(&self.destination);
+        }
+        self.previous
+            .keys()
+            .filter(|id| !self.written.contains_key(*id))
+            .count()
+    }
+
+    /// Records the hashes of the entities this run migrated, in place of the previous run's, for the next run. An
+    /// entity whose chunk wasn't written has no hash, so the next run writes it. The --state-db already holds the
+    /// hashes of every written chunk, alongside those of the entities this run didn't migrate
+    pub fn save(&self, opt: &Opt) {
+        if self.is_stored {
+            return;
+        }
+        let mut hashes = read(opt);
+        hashes.insert(
+            self.destination.to_owned(),
+            self.written
+                .iter()
+                .map(|(id, hash)| (id.to_owned(), hash.to_owned()))
+                .collect(),
+        );
+        let path = path(opt);
+        if let Err(e) = std::fs::write(&path, serde_json::to_string(&hashes).unwrap()) {
+            pretty_print(
+                &format!(
+                    "Could not record the entity hashes in {}: {}",
+                    path.display(),
+                    e
+                ),
+                ERROR_COLOR,
+                true,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // the hashes of the entities as the previous run migrated them
+    fn migrated(entities: &[Value]) -> EntityHashes {
+        EntityHashes {
+            previous: entities
+                .iter()
+                .map(|entity| {
+                    let id = entity["@id"].as_str().unwrap().to_string();
+                    (id, content_hash(entity.to_string().as_bytes()))
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn skips_unchanged_entities() {
+        let person = json!({"@id": "person/1", "name": "Ada"});
+        let mut hashes = migrated(std::slice::from_ref(&person));
+        assert!(!hashes.is_changed(&person));
+        assert_eq!(hashes.unchanged(), 1);
+        assert!(hashes.take_pending().is_empty());
+        assert_eq!(hashes.missing(), 0);
+    }
+
+    #[test]
+    fn records_changed_entities_once_their_chunk_is_written() {
+        let mut hashes = migrated(&[json!({"@id": "person/1", "name": "Ada"})]);
+        let person = json!({"@id": "person/1", "name": "Ada Lovelace"});
+        let new_person = json!({"@id": "person/2", "name": "Grace"});
+        assert!(hashes.is_changed(&person));
+        assert!(hashes.is_changed(&new_person));
+        assert!(hashes.is_changed(&json!({"name": "Anonymous"})));
+        assert_eq!(hashes.unchanged(), 0);
+
+        let mut written = hashes.written_hashes();
+        written.record(hashes.take_pending());
+        hashes.record_written(written);
+        assert_eq!(
+            hashes.written["person/1"],
+            content_hash(person.to_string().as_bytes())
+        );
+        assert!(hashes.written.contains_key("person/2"));
+        assert_eq!(hashes.missing(), 0);
+    }
+
+    // an entity whose chunk wasn't written keeps no hash, so the next run writes it again
+    #[test]
+    fn forgets_entities_of_unwritten_chunks() {
+        let mut hashes = migrated(&[json!({"@id": "person/1", "name": "Ada"})]);
+        assert!(hashes.is_changed(&json!({"@id": "person/1", "name": "Ada Lovelace"})));
+        assert_eq!(hashes.take_pending().len(), 1);
+        hashes.record_written(hashes.written_hashes());
+        assert!(hashes.written.is_empty());
+        assert_eq!(hashes.missing(), 1);
+    }
+
+    #[test]
+    fn counts_entities_this_run_didnt_migrate() {
+        let person = json!({"@id": "person/1", "name": "Ada"});
+        let mut hashes = migrated(&[
+            person.clone(),
+            json!({"@id": "person/2", "name": "Grace"}),
+            json!({"@id": "person/3", "name": "Hedy"}),
+        ]);
+        assert!(!hashes.is_changed(&person));
+        assert_eq!(hashes.missing(), 2);
+    }
+}
@@ -1,19 +1,38 @@
 pub mod opt {
-    use clap::Parser;
+    use clap::{Parser, Subcommand, ValueEnum};
     use crossterm::{
         execute,
         style::{Print, ResetColor},
     };
-    use dialoguer::{console::Style, theme::ColorfulTheme, Input};
+    use dialoguer::{console::Style, theme::ColorfulTheme, Confirm, Input};
     use indicatif::ProgressBar;
-    use serde_json::Value;
+    use log::Level;
+    use serde_json::{Map, Value};
     use std::{
+        collections::VecDeque,
         fs::File,
-        io::{self, stdout, Write},
+        io::{self, stdout, IsTerminal, Write},
         path::PathBuf,
+        time::Instant,
     };
 
+    use crate::batching::{is_batch_too_large, BatchScale};
+    use crate::cache::Snapshot;
+    use crate::canonical::{canonical_nquads, document_quads};
+    use crate::cli::file_template::{FileTemplate, HASH_PLACEHOLDER};
+    use crate::diagram::DiagramFormat;
+    use crate::extract::ExtractManifest;
     use crate::fluree::FlureeInstance;
+    use crate::functions::{content_hash, format_bytes, glob_match, pretty_log};
+    use crate::keys::{read_secret_file, SigningKey};
+    use crate::mapping::LedgerMap;
+    use crate::mock::MOCK_SOURCE;
+    use crate::parquet;
+    use crate::shard::Shard;
+    use crate::state as run_state;
+    use crate::trace::LogRotation;
+    use crate::transaction::{merge_txn_opts, split_transaction};
+    use crate::uploads;
 
     // #[structopt(
     //     name = "fluree-migrate",
@@ -27,23 +46,45 @@ pub mod opt {
     )]
     pub struct Opt {
         /// Accessible URL for v2 Fluree DB. This will be used to fetch the schema and data state
-        #[arg(short, long, conflicts_with = "input")]
+        #[arg(global = true, short, long, conflicts_with = "input")]
         pub source: Option<String>,
 
         /// Path to the input directory containing v3 Fluree Txn (JSON-LD) data
         /// For example, data written to local files by this tool
-        #[arg(short, long, value_hint = clap::ValueHint::DirPath, conflicts_with = "source")]
+        #[arg(global = true, short, long, value_hint = clap::ValueHint::DirPath, conflicts_with = "source")]
         pub input: Option<PathBuf>,
 
         /// Authorization token for Nexus ledgers.
         /// e.g. 796b******854d
-        #[arg(long, conflicts_with = "input", requires = "source")]
+        #[arg(global = true, long, conflicts_with = "input", requires = "source")]
         pub source_auth: Option<String>,
 
+        /// Username and password for a basic-auth reverse proxy in front of the v2 source, as "user:password".
+        /// Sent as the Authorization header, or as Proxy-Authorization when --source-auth is also given
+        #[arg(
+            global = true,
+            long,
+            requires = "source",
+            value_parser = parse_basic_auth
+        )]
+        pub source_basic_auth: Option<String>,
+
+        /// If set, then the v2 --source is read through its GraphQL endpoint rather than the FlureeQL `/query` one,
+        /// for deployments that only expose GraphQL. Its schema is introspected from the `_predicate` and
+        /// `_collection` types, and each collection is paged through its own type
+        #[arg(
+            global = true,
+            long,
+            requires = "source",
+            conflicts_with_all = ["block", "as_of", "sample_seed"]
+        )]
+        pub graphql: bool,
+
         /// If writing the output to local files,
         /// then this is the relative path to the directory where the files will be written.
         /// [Conflicts with --target & --print]
         #[arg(
+            global = true,
             short,
             long,
             value_hint = clap::ValueHint::DirPath,
@@ -56,6 +97,7 @@ pub mod opt {
         /// e.g. http://localhost:58090
         /// [Conflicts with --output & --print]
         #[arg(
+            global = true,
             short,
             long = "target",
             conflicts_with = "output",
@@ -65,61 +107,852 @@ pub mod opt {
 
         /// Authorization token for the target v3 instance (if hosted on Nexus).
         /// Only useful if transacting the output to a target v3 Fluree instance.
-        #[arg(long, requires = "target")]
+        #[arg(global = true, long, requires = "target")]
         pub target_auth: Option<String>,
 
+        /// Username and password for a basic-auth reverse proxy in front of the target v3 instance, as
+        /// "user:password". Sent as the Authorization header, or as Proxy-Authorization when --target-auth is also given
+        #[arg(
+            global = true,
+            long,
+            requires = "target",
+            value_parser = parse_basic_auth
+        )]
+        pub target_basic_auth: Option<String>,
+
+        /// Path to a file holding the authorization token for the target v3 instance, read instead of --target-auth
+        /// so the token isn't in the shell history or process list
+        #[arg(
+            global = true,
+            long = "auth-file",
+            value_hint = clap::ValueHint::FilePath,
+            requires = "target",
+            conflicts_with = "target_auth"
+        )]
+        pub auth_file: Option<PathBuf>,
+
+        /// Path to a file holding the Ed25519 private key (as 64 hex digits) that every transaction to the target is
+        /// signed with, sending it as a JWS
+        #[arg(
+            global = true,
+            long = "signing-key-file",
+            value_hint = clap::ValueHint::FilePath,
+            requires = "target"
+        )]
+        pub signing_key_file: Option<PathBuf>,
+
         /// If set, then the output will be printed to stdout instead of written to local files or to a target v3 instance.
         /// [Conflicts with --output & --target]
-        #[arg(long, conflicts_with = "output", conflicts_with = "target")]
+        #[arg(
+            global = true,
+            long,
+            conflicts_with = "output",
+            conflicts_with = "target"
+        )]
         pub print: bool,
 
         /// @base value for @context.
         /// This will be used as a default IRI prefix for all data entities.
         /// e.g. http://example.org/ids/
-        #[arg(short, long, conflicts_with = "no_base")]
+        #[arg(global = true, short, long, conflicts_with = "no_base")]
         pub base: Option<String>,
 
         /// @vocab value for @context.
         /// This will be used as a default IRI prefix for all vocab entities.
         /// e.g. http://example.org/terms/
-        #[arg(short, long, conflicts_with = "no_vocab")]
+        #[arg(global = true, long, conflicts_with = "no_vocab")]
         pub vocab: Option<String>,
 
         /// If set, then the result vocab JSON-LD will include SHACL shapes for each class.
-        #[arg(long)]
+        #[arg(global = true, long)]
         pub shacl: bool,
 
         /// This depends on the --shacl flag being used.
         /// If set, then the resulting SHACL shapes will be "closed" (i.e. no additional properties can be added to instances of the class).
-        #[arg(long = "closed-shapes", requires = "shacl")]
+        #[arg(global = true, long = "closed-shapes", requires = "shacl")]
         pub closed_shapes: bool,
 
+        /// How the classes and properties of the vocab are labeled. "term" labels them with their term, without its
+        /// prefix (e.g. "fullName"), "title" with the term's words, title-cased (e.g. "Full Name"), and "none"
+        /// leaves out rdfs:label
+        #[arg(global = true, long, value_enum, default_value_t = Labels::Term)]
+        pub labels: Labels,
+
+        /// A language tag, e.g. "en", the rdfs:label of each class and property is tagged with
+        #[arg(global = true, long, value_parser = parse_language_tag)]
+        pub label_language: Option<String>,
+
         /// This depends on the --target flag being used.
         /// If set, then the first transaction issued against the target will attempt to create the ledger
-        #[arg(long = "create-ledger", requires = "target")]
+        #[arg(global = true, long = "create-ledger", requires = "target")]
         pub is_create_ledger: bool,
 
+        /// This depends on the --create-ledger flag being used.
+        /// Path to a JSON-LD policy document that will be transacted immediately after the ledger is created (before any data)
+        #[arg(
+            global = true,
+            long = "initial-policy",
+            value_hint = clap::ValueHint::FilePath,
+            requires = "is_create_ledger"
+        )]
+        pub initial_policy: Option<PathBuf>,
+
+        /// This depends on the --create-ledger flag being used.
+        /// Path to a file holding the authorization token the create-ledger transaction is issued with, rather than
+        /// the one of --target-auth (or --auth-file), which the later transactions are still issued with
+        #[arg(
+            global = true,
+            long = "create-auth-file",
+            value_hint = clap::ValueHint::FilePath,
+            requires = "is_create_ledger"
+        )]
+        pub create_auth_file: Option<PathBuf>,
+
+        /// This depends on the --create-ledger flag being used.
+        /// Path to a file holding the Ed25519 private key the create-ledger transaction is signed with, rather than
+        /// the one of --signing-key-file, which the later transactions are still signed with
+        #[arg(
+            global = true,
+            long = "create-signing-key-file",
+            value_hint = clap::ValueHint::FilePath,
+            requires = "is_create_ledger"
+        )]
+        pub create_signing_key_file: Option<PathBuf>,
+
+        /// This depends on the --target flag being used.
+        /// If set, then the target's dataset is created through the Nexus management API (--nexus-api) before the
+        /// first transaction, rather than having to exist already or being created with --create-ledger
+        #[arg(
+            global = true,
+            long = "create-dataset",
+            requires = "target",
+            conflicts_with = "is_create_ledger"
+        )]
+        pub create_dataset: bool,
+
+        /// The name of the Nexus dataset --create-dataset creates. By default, the last segment of the ledger name
+        #[arg(global = true, long = "dataset-name", requires = "create_dataset")]
+        pub dataset_name: Option<String>,
+
+        /// Who can see the Nexus dataset --create-dataset creates
+        #[arg(
+            global = true,
+            long = "dataset-visibility",
+            value_enum,
+            default_value_t = DatasetVisibility::Private,
+            requires = "create_dataset"
+        )]
+        pub dataset_visibility: DatasetVisibility,
+
+        /// The description of the Nexus dataset --create-dataset creates
+        #[arg(
+            global = true,
+            long = "dataset-description",
+            requires = "create_dataset"
+        )]
+        pub dataset_description: Option<String>,
+
+        /// The base URL of the Nexus management API that --create-dataset creates the dataset with
+        #[arg(
+            global = true,
+            long = "nexus-api",
+            default_value = "https://data.flur.ee/api"
+        )]
+        pub nexus_api: String,
+
         /// If set, then the @context will not include a @base value.
         /// Expanded IRIs for data entities may not be valid fully-qualified IRIs, so use this at your own risk.
-        #[arg(long = "no-base", conflicts_with = "base")]
+        #[arg(global = true, long = "no-base", conflicts_with = "base")]
         pub no_base: bool,
 
         /// If set, then the @context will not include a @vocab value.
         /// Expanded IRIs for vocab entities may not be valid fully-qualified IRIs, so use this at your own risk.
-        #[arg(long = "no-vocab", conflicts_with = "vocab")]
+        #[arg(global = true, long = "no-vocab", conflicts_with = "vocab")]
         pub no_vocab: bool,
 
+        /// Path to a JSON mapping config that customizes how v2 entities are migrated.
+        /// e.g. "sameAs" rules that link entities to canonical external IRIs
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub mapping: Option<PathBuf>,
+
+        /// Path to a JSON file that renames v2 collections to v3 class names, e.g. {"purchaseOrder": "Order"}
+        #[arg(global = true, long = "rename-classes")]
+        pub rename_classes: Option<PathBuf>,
+
+        /// Path to a JSON file that renames v2 predicates in the migrated vocab, SHACL shapes, and data.
+        /// e.g. {"person/handle": "username"}. A bare "predicate" key renames the predicate in every collection
+        #[arg(global = true, long = "rename-properties")]
+        pub rename_properties: Option<PathBuf>,
+
+        /// If set, then v2 collection and predicate names are kept as they're spelled as the v3 class names and terms,
+        /// rather than being standardized to e.g. "PurchaseOrder" and "fullName". Renames are still applied
+        #[arg(global = true, long = "preserve-names")]
+        pub preserve_names: bool,
+
+        /// Path to a JSON alignment file that maps v2 collections and predicates to the terms of external
+        /// ontologies (e.g. schema.org, FOAF, or Dublin Core), which are either reused in place of the generated
+        /// terms, or linked to them with rdfs:subClassOf and owl:equivalentProperty
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub align: Option<PathBuf>,
+
+        /// Comma-separated v2 predicates that are removed from the vocab, the SHACL shapes, and every migrated entity,
+        /// for data that must not leave the source system. Predicates are given as "collection/predicate",
+        /// or as a bare "predicate" to remove it from every collection.
+        /// e.g. "person/ssn,account/internalNotes"
+        #[arg(global = true, long = "drop-properties", value_delimiter = ',')]
+        pub drop_properties: Vec<String>,
+
+        /// Comma-separated v2 collections to migrate, as names or patterns with "*" wildcards.
+        /// Other collections are left out of the vocab, the SHACL shapes, and the data.
+        /// e.g. "person,order*"
+        #[arg(global = true, long = "classes", value_delimiter = ',')]
+        pub classes: Vec<String>,
+
+        /// Comma-separated v2 collections that are not migrated, as names or patterns with "*" wildcards.
+        /// e.g. "auditLog,tmp*"
+        #[arg(global = true, long = "exclude-classes", value_delimiter = ',')]
+        pub exclude_classes: Vec<String>,
+
+        /// A JSON object of v2 predicates and values that the entities of their collections must hold to be migrated,
+        /// e.g. to leave out soft-deleted records. Collections without a predicate in it are migrated in full.
+        /// e.g. '{"person/status": "active", "person/deleted": false}'
+        #[arg(global = true, long = "where", value_parser = parse_where)]
+        pub where_filters: Option<Map<String, Value>>,
+
+        /// The v2 block every source query is issued at, so the migration reflects a consistent snapshot of the
+        /// ledger while writes continue
+        #[arg(global = true, long = "block", value_parser = clap::value_parser!(u64).range(1..))]
+        pub block: Option<u64>,
+
+        /// The point in time (ISO-8601) every source query is issued at, like --block.
+        /// e.g. "2024-03-01T00:00:00Z"
+        #[arg(global = true, long = "as-of", conflicts_with = "block", value_parser = parse_as_of)]
+        pub as_of: Option<String>,
+
+        /// How to handle references to entities that are never migrated as subjects (e.g. deleted entities).
+        /// "keep" leaves them as-is, "drop" removes them, "stub" emits minimal subjects for them,
+        /// and "fail" aborts the run before any data is written
+        #[arg(global = true, long = "dangling-refs", value_enum, default_value_t = DanglingRefs::Keep)]
+        pub dangling_refs: DanglingRefs,
+
+        /// If set, then an entity fetched under several collections (the same `_id`) is migrated as one subject
+        /// with the class of each, rather than as one competing subject per collection
+        #[arg(global = true, long = "merge-collections")]
+        pub merge_collections: bool,
+
+        /// If set, then a sample of each class's data is used to infer the real cardinality of its properties,
+        /// tightening or loosening "sh:maxCount" where the data disagrees with the v2 "multi" setting
+        #[arg(global = true, long = "infer-cardinality", requires = "shacl")]
+        pub infer_cardinality: bool,
+
+        /// If set, then properties whose v2 type has no xsd equivalent (e.g. "tag" or "json") get a datatype
+        /// inferred from a sample of their values, which is used in SHACL shapes and as the @type of the migrated values
+        #[arg(global = true, long = "infer-datatypes")]
+        pub infer_datatypes: bool,
+
+        /// If set, then instant properties whose sampled values are always at midnight UTC are migrated as xsd:date
+        /// (e.g. "2023-08-30") instead of xsd:dateTime
+        #[arg(global = true, long = "detect-dates")]
+        pub detect_dates: bool,
+
+        /// Comma-separated glob patterns of instant properties that are migrated as xsd:date instead of xsd:dateTime.
+        /// Patterns match either "collection/predicate" or "predicate" names.
+        /// e.g. "*Date,person/birthday"
+        #[arg(global = true, long = "date-pattern", value_delimiter = ',')]
+        pub date_pattern: Vec<String>,
+
+        /// If set, then values of v2 "float", "double", and "bigdec" properties are migrated as xsd:decimal typed literals
+        /// that keep the exact lexical form from the source response (no binary floating point noise)
+        #[arg(global = true, long = "preserve-decimals")]
+        pub preserve_decimals: bool,
+
+        /// If set, then string values are trimmed of leading and trailing whitespace,
+        /// and runs of control characters (other than newlines and tabs) are collapsed into a single space
+        #[arg(global = true, long = "clean-strings")]
+        pub clean_strings: bool,
+
+        /// If set, then migrated values are scanned for PII (emails, phone numbers, national IDs, and credit card numbers),
+        /// and properties whose names or values look like PII are listed in the console output and the --report
+        #[arg(global = true, long = "scan-pii")]
+        pub scan_pii: bool,
+
+        /// Path to a JSON file where a profile of the transformed data will be written: for each property of each class,
+        /// its null rate, number of distinct values, shortest and longest string values, and a few sample values
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub profile: Option<PathBuf>,
+
+        /// How to handle property values that are null or empty strings, which v2 data is full of.
+        /// "keep" migrates them as-is, "drop" removes them, and "sentinel" replaces them with --empty-sentinel
+        #[arg(global = true, long = "empty-values", value_enum, default_value_t = EmptyValues::Keep)]
+        pub empty_values: EmptyValues,
+
+        /// The value that null and empty-string values are replaced with when --empty-values is "sentinel"
+        #[arg(
+            global = true,
+            long = "empty-sentinel",
+            required_if_eq("empty_values", "sentinel")
+        )]
+        pub empty_sentinel: Option<String>,
+
+        /// If set, then the v2 `_tag` collection is migrated as a skos:ConceptScheme of skos:Concept individuals,
+        /// and the values of tag properties become references to those concepts
+        #[arg(global = true, long = "tags-as-concepts")]
+        pub tags_as_concepts: bool,
+
+        /// If set, then the v2 `_auth` records are migrated as identities named by their DIDs (`did:fluree:<auth id>`),
+        /// labeled with the usernames of their `_user`s, and the values of refs to `_user` or `_auth` become refs to those DIDs
+        #[arg(global = true, long = "identities")]
+        pub identities: bool,
+
+        /// If set, then only the first N entities of each class are migrated (along with the full vocab),
+        /// for validating the whole pipeline against a real target before a full run
+        #[arg(global = true, long = "sample", value_parser = clap::value_parser!(u64).range(1..))]
+        pub sample: Option<u64>,
+
+        /// If set, then --sample selects a reproducible pseudorandom subset of each class using this seed,
+        /// rather than the first N entities
+        #[arg(global = true, long = "sample-seed", requires = "sample")]
+        pub sample_seed: Option<u64>,
+
+        /// If set, then the entities of each class are counted and a few are measured, and the projected output size,
+        /// transaction count, and duration are printed, without transferring or writing the data
+        #[arg(global = true, long = "estimate")]
+        pub estimate: bool,
+
+        /// If set, then only the vocab (and, with --shacl, the shapes) is written or transacted, skipping the data,
+        /// for iterating quickly on modeling decisions
+        #[arg(global = true, long = "vocab-only", conflicts_with = "estimate")]
+        pub vocab_only: bool,
+
+        /// If set, then only the data is written or transacted, transformed with the classes, property names,
+        /// and SHACL datatypes of the vocab given with --vocab-file rather than ones re-derived from the source
+        #[arg(
+            global = true,
+            long = "data-only",
+            requires = "vocab_file",
+            conflicts_with_all = ["vocab_only", "estimate"]
+        )]
+        pub data_only: bool,
+
+        /// This depends on the --data-only flag being used.
+        /// Path to a vocab written by an earlier run (e.g. 0_vocab.jsonld), possibly edited by hand
+        #[arg(
+            global = true,
+            long = "vocab-file",
+            value_hint = clap::ValueHint::FilePath,
+            requires = "data_only"
+        )]
+        pub vocab_file: Option<PathBuf>,
+
+        /// The number of entities per class that are sampled when inferring schema details from the data
+        #[arg(global = true, long = "inference-sample", default_value_t = 1000)]
+        pub inference_sample: usize,
+
+        /// Path to a JSON file where a report of the run's findings (e.g. dangling references) will be written
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub report: Option<PathBuf>,
+
+        /// Path to a SQLite database (created if needed) where the run keeps its state: the files it wrote or
+        /// transacted, the content hashes of --content-addressed uploads, an extract's checkpoints and the ids it
+        /// fetched, and the report's findings, instead of the JSON state files, so a very large migration can be
+        /// resumed and inspected with standard SQLite tools
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub state_db: Option<PathBuf>,
+
+        /// Path to a directory where static HTML documentation of the vocab (and its SHACL shapes) will be written
+        #[arg(global = true, long, value_hint = clap::ValueHint::DirPath)]
+        pub docs: Option<PathBuf>,
+
         /// If set, then the resulting transactions will target the specified ledger name.
         /// This is useful if the target instance is an existing, already-named ledger.
         /// e.g. "example/dataset-one"
-        #[arg(long = "ledger-name")]
+        #[arg(global = true, long = "ledger-name")]
         pub ledger_name: Option<String>,
 
+        /// A rule deriving the target ledger from the v2 ledger, e.g. "old-network/old-db=new/alias" or "old-network/*=new/*",
+        /// or the path to a JSON file of such rules. Can be given several times; the first matching rule applies.
+        /// [--ledger-name takes precedence]
+        #[arg(global = true, long = "ledger-map")]
+        pub ledger_map: Vec<LedgerMap>,
+
+        /// A v2 collection whose entities are migrated into another ledger, given as "collection=ledger", so refs to them
+        /// are written as fully-qualified IRIs in that ledger rather than bare ids. The ledger is either a v2 ledger
+        /// on the --source server (whose subjects get its default base IRI), or the base IRI of the other ledger's subjects.
+        /// e.g. "org=acme/hr" or "org=http://hr.example.com/ids/". Can be given several times
+        #[arg(global = true, long = "ref-ledger", value_parser = parse_ref_ledger)]
+        pub ref_ledgers: Vec<(String, String)>,
+
+        /// If set, then the lock files are removed before they're taken, e.g. on a file system whose locks outlive
+        /// the run that took them. Only use this when no other migration into the same output directory or target
+        /// ledger is active
+        #[arg(global = true, long = "force-unlock")]
+        pub force_unlock: bool,
+
+        /// If set, then an existing, non-empty output directory is deleted without asking.
+        /// Otherwise, the tool asks first (or refuses, when not run interactively)
+        #[arg(global = true, long)]
+        pub force: bool,
+
+        /// If set, then the confirmations asked before destructive operations (deleting a non-empty output
+        /// directory, creating the target ledger or its dataset) are answered yes. Otherwise, the tool asks first
+        /// (or refuses, when not run interactively)
+        #[arg(global = true, short, long)]
+        pub yes: bool,
+
+        /// If set, then an existing output directory is kept and this run's data files are numbered
+        /// after the ones already in it, so incremental runs can accumulate into one directory
+        #[arg(global = true, long, conflicts_with = "force")]
+        pub append: bool,
+
+        /// If set, then each run writes into its own timestamped subdirectory of --output,
+        /// e.g. output/2024-05-01T12-00-00/, keeping a history of exports
+        #[arg(
+            global = true,
+            long = "output-per-run",
+            requires = "output",
+            conflicts_with = "append"
+        )]
+        pub output_per_run: bool,
+
+        /// The names of the files written to --output, with the placeholders {seq} (or e.g. {seq:04}), {class} and {ledger}.
+        /// e.g. "{seq:04}_{class}_{ledger}.jsonld". The vocab file is rendered with seq 0 and class "vocab"
+        #[arg(global = true, long = "file-template")]
+        pub file_template: Option<FileTemplate>,
+
+        /// If set, then each output document is written as canonical (URDNA2015) N-Quads, with stable blank node labels
+        /// and sorted statements, so outputs can be hashed and compared across runs and machines.
+        /// [Conflicts with --target]
+        #[arg(global = true, long, conflicts_with = "target")]
+        pub canonicalize: bool,
+
+        /// Path to a JSON-LD frame that is applied to the entities of each data file before it's written,
+        /// e.g. to embed referenced entities in the entities that reference them
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub frame: Option<PathBuf>,
+
+        /// The shape of the output documents: Fluree v3 transactions, or standard JSON-LD documents whose
+        /// entities are in a top-level @graph with a document-level @id per file.
+        /// [Conflicts with --target]
+        #[arg(
+            global = true,
+            long = "output-format",
+            visible_alias = "format",
+            value_enum,
+            default_value_t = OutputFormat::Transaction,
+            conflicts_with = "target"
+        )]
+        pub output_format: OutputFormat,
+
+        /// The form of the data transactions: bare inserts, or where/delete/insert transactions
+        /// that replace the values of each written property (e.g. when re-running into a populated ledger)
+        #[arg(global = true, long = "txn-mode", value_enum, default_value_t = TxnMode::Insert)]
+        pub txn_mode: TxnMode,
+
+        /// A JSON object merged into the `opts` of every transaction, so the target's commits carry e.g. a message,
+        /// tag, or author identifying the migration run.
+        /// e.g. '{"message": "Migrated from v2", "tag": "migration-1"}'
+        #[arg(global = true, long = "txn-opts", value_parser = parse_txn_opts)]
+        pub txn_opts: Option<Map<String, Value>>,
+
+        /// If set, then the migration runs against built-in fixture responses (a small v2 schema and a few collections)
+        /// instead of a v2 --source, writing its output locally with no network, e.g. to see what the tool generates
+        #[arg(global = true, long, conflicts_with_all = ["source", "input", "target"])]
+        pub mock: bool,
+
+        /// Path to a file where every HTTP request to the source and target and its response are recorded
+        /// (without their headers, which hold API keys), to reproduce the run later with --replay
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath, conflicts_with_all = ["replay", "mock"])]
+        pub record: Option<PathBuf>,
+
+        /// Path to a file written with --record whose recorded responses answer the run's HTTP requests,
+        /// instead of the source and target, e.g. to reproduce a failed migration without access to its instances
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath, conflicts_with = "mock")]
+        pub replay: Option<PathBuf>,
+
+        /// Log every HTTP request to the source and target (with Authorization headers redacted and long bodies
+        /// truncated) and the response it got to the log file, e.g. to see why the target rejects a transaction
+        #[arg(global = true, long)]
+        pub trace_http: bool,
+
+        /// Directory where the responses to source queries are cached, keyed by their query (and --block), so a
+        /// rerun, e.g. after a failed transform or load, reads them from disk instead of downloading them again
+        #[arg(global = true, long, value_hint = clap::ValueHint::DirPath, conflicts_with = "mock")]
+        pub cache: Option<PathBuf>,
+
+        /// Directory of a --cache whose responses answer every source query, so the transform and output run
+        /// offline, e.g. to iterate on a mapping config. Its source and --block are used unless given
+        #[arg(global = true, long, value_hint = clap::ValueHint::DirPath, conflicts_with_all = ["cache", "mock", "input"])]
+        pub from_cache: Option<PathBuf>,
+
+        /// Show more detail in the console: -v for INFO (every file written or transacted), -vv for DEBUG
+        /// (also the body of every request), -vvv for TRACE (also the logs of the HTTP client). Overrides RUST_LOG
+        #[arg(global = true, short, long, action = clap::ArgAction::Count)]
+        pub verbose: u8,
+
+        /// Path to the log file --trace-http appends to [default: fluree-migrate.log]
+        #[arg(global = true, long, value_hint = clap::ValueHint::FilePath)]
+        pub log_file: Option<PathBuf>,
+
+        /// Start a new log file once the log file reaches a size (e.g. 100MB) or on a new day ("daily"),
+        /// renaming the previous one to <log file>.1, so a long migration's log doesn't grow unbounded
+        #[arg(global = true, long)]
+        pub log_rotate: Option<LogRotation>,
+
+        /// Number of rotated log files kept by --log-rotate; older ones are deleted
+        #[arg(global = true, long, default_value_t = 5)]
+        pub log_keep: usize,
+
+        /// Maximum number of classes fetched from the source at once. Lower it to spare a small source server
+        #[arg(global = true, long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+        pub fetch_concurrency: u64,
+
+        /// Adjust the source page size and the target transaction size to how the servers respond: shrink them
+        /// on timeouts, 413s, and slow responses (splitting a rejected transaction), and grow them when fast
+        #[arg(global = true, long, conflicts_with = "from_cache")]
+        pub adaptive_batching: bool,
+
+        /// Name the output and temp files by the hash of their contents, and skip transacting a file whose
+        /// contents the target ledger already got from an earlier run, e.g. when rerunning after a failed load
+        #[arg(global = true, long)]
+        pub content_addressed: bool,
+
+        /// Only write (or transact) the entities that changed since the previous run to the same destination, by
+        /// comparing the content hash of each transformed entity with the one that run recorded, for cheap
+        /// incremental syncs even without v2 block metadata. Requires --txn-mode replace
+        #[arg(global = true, long, conflicts_with = "input")]
+        pub changed_only: bool,
+
+        /// How many times a file of --input (or `load`) is sent again after the target times out or can't be
+        /// reached, before --on-upload-failure decides what becomes of it
+        #[arg(global = true, long, default_value_t = 5)]
+        pub upload_retries: u32,
+
+        /// Seconds to wait before sending a file of --input (or `load`) again after the target timed out
+        #[arg(global = true, long, value_name = "SECONDS", default_value_t = 15)]
+        pub upload_retry_delay: u64,
+
+        /// What to do with a file of --input (or `load`) the target still hasn't answered after --upload-retries:
+        /// "skip" moves on to the next file (listing it in the --report), and "abort" stops the load
+        #[arg(global = true, long, value_enum, default_value_t = UploadFailure::Skip)]
+        pub on_upload_failure: UploadFailure,
+
+        /// Migrate only a deterministic slice of the source, e.g. "2/8" for the second of eight, so several
+        /// machines (or processes, each in its own working directory) can migrate a ledger into the same target
+        /// at once. Only the first shard writes (or transacts) the vocab; the others only write their data
+        #[arg(
+            global = true,
+            long,
+            conflicts_with_all = ["input", "is_create_ledger", "create_dataset"]
+        )]
+        pub shard: Option<Shard>,
+
+        /// How --shard slices the source: by classes, each migrated whole by one shard, or by ranges of `_id`s,
+        /// which splits a large class between the shards
+        #[arg(global = true, long, value_enum, default_value_t = ShardBy::Classes, requires = "shard")]
+        pub shard_by: ShardBy,
+
+        #[command(subcommand)]
+        pub command: Option<Command>,
+
         #[arg(skip = ProgressBar::new(2))]
         pub pb: ProgressBar,
+
+        /// The --adaptive-batching scale of the source page size, shared by the fetch tasks
+        #[arg(skip)]
+        pub source_batch: BatchScale,
+
+        /// The --adaptive-batching scale of the data chunk size, shared by the transform and the writer
+        #[arg(skip)]
+        pub target_batch: BatchScale,
+
+        /// The token read from --create-auth-file
+        #[arg(skip)]
+        pub create_auth: Option<String>,
+
+        /// The keys read from --signing-key-file and --create-signing-key-file
+        #[arg(skip)]
+        pub signing_key: Option<SigningKey>,
+
+        #[arg(skip)]
+        pub create_signing_key: Option<SigningKey>,
+    }
+
+    #[derive(Subcommand, Debug, Clone)]
+    pub enum Command {
+        /// Migrates the v2 schema and data (the default when no command is given)
+        Migrate {
+            /// Path to a plan written by `fluree-migrate plan`, which is executed exactly as written
+            #[arg(long, value_hint = clap::ValueHint::FilePath)]
+            plan: Option<PathBuf>,
+        },
+        /// Fetches the schema and entities of the v2 --source and writes them, untransformed, to the --output
+        /// directory (default raw/): schema.json, one <collection>.jsonl per collection, and manifest.json
+        Extract,
+        /// Transforms a v2 snapshot written by extract into v3 JSON-LD files in the --output directory, offline,
+        /// exactly as a migration from its source would
+        Transform {
+            /// Path to the directory written by extract
+            #[arg(default_value = "raw", value_hint = clap::ValueHint::DirPath)]
+            directory: PathBuf,
+        },
+        /// Transforms one v2 entity of a collection, read as JSON from stdin, as a migration would (with its --mapping,
+        /// renames, and --vocab-file), and prints the v3 JSON-LD to stdout, e.g. to test mapping rules or debug an odd
+        /// record. The schema is read from the --source (or --mock), or from a directory written by extract
+        TransformOne {
+            /// The v2 collection the entity belongs to
+            collection: String,
+            /// Path to a directory written by extract to read the schema from, instead of the --source
+            #[arg(long, value_hint = clap::ValueHint::DirPath)]
+            extract: Option<PathBuf>,
+        },
+        /// Transacts the v3 JSON-LD files of a directory written by transform (or a migration) to the --target,
+        /// like --input
+        Load {
+            /// Path to the directory of v3 JSON-LD files
+            #[arg(default_value = "output", value_hint = clap::ValueHint::DirPath)]
+            directory: PathBuf,
+        },
+        /// Reads every statement of the v3 --target ledger (given with --ledger-name) and writes it to a directory as
+        /// the vocab and data files a migration writes, e.g. as a backup to load again
+        Export {
+            /// Path to the directory the JSON-LD files are written to
+            #[arg(default_value = "export", value_hint = clap::ValueHint::DirPath)]
+            directory: PathBuf,
+        },
+        /// Writes a reviewable migration plan (classes, counts, datatypes, renames, warnings, and chunking)
+        /// without migrating any data
+        Plan {
+            /// Path to the JSON file the plan is written to
+            #[arg(default_value = "plan.json", value_hint = clap::ValueHint::FilePath)]
+            path: PathBuf,
+        },
+        /// Writes a diagram of the classes to be migrated, their properties, and the refs between them, without
+        /// migrating any data
+        Diagram {
+            /// Path to the file the diagram is written to
+            #[arg(default_value = "schema.mmd", value_hint = clap::ValueHint::FilePath)]
+            path: PathBuf,
+            /// The diagram's format
+            #[arg(long = "diagram-format", value_enum, default_value_t = DiagramFormat::Mermaid)]
+            format: DiagramFormat,
+        },
+        /// Suggests schema.org types and properties for the classes and properties to be migrated, by fuzzy-matching
+        /// their names against a bundled schema.org index, and writes them as an alignment file to review and use
+        /// with --align, without migrating any data
+        SuggestAlignment {
+            /// Path to the JSON alignment file the suggestions are written to
+            #[arg(default_value = "alignment.json", value_hint = clap::ValueHint::FilePath)]
+            path: PathBuf,
+            /// The lowest score, from 0 to 1, of a match that's suggested
+            #[arg(long = "min-score", default_value_t = 0.8)]
+            min_score: f64,
+        },
+        /// Combines the --report files of the shards of a --shard run into one report, written to --report
+        MergeReports {
+            /// Paths to the shards' reports, e.g. report.shard-1-of-8.json
+            #[arg(required = true, value_hint = clap::ValueHint::FilePath)]
+            reports: Vec<PathBuf>,
+        },
+        /// Migrates every source ledger listed in a batch manifest, one after the other, and writes a combined report
+        Batch {
+            /// Path to the JSON manifest listing the source ledgers, their target ledger names, and per-ledger overrides
+            #[arg(value_hint = clap::ValueHint::FilePath)]
+            manifest: PathBuf,
+        },
+        /// Writes SHACL shapes for the classes of an already-migrated --target ledger (given with --ledger-name),
+        /// inferred from a sample of its data and its vocab
+        Shapes {
+            /// Path to the JSON-LD file the shapes are written to, as a transaction for the ledger
+            #[arg(default_value = "shapes.jsonld", value_hint = clap::ValueHint::FilePath)]
+            path: PathBuf,
+        },
+        /// Validates the instances of the --target ledger against SHACL shapes and reports the violations,
+        /// e.g. data transacted outside the migration
+        Audit {
+            /// Path to the JSON-LD file holding the shapes: a vocab migrated with --shacl, or the output of shapes
+            #[arg(default_value = "0_vocab.jsonld", value_hint = clap::ValueHint::FilePath)]
+            shapes: PathBuf,
+        },
+        /// Compares two migrated ledgers, or a ledger and an output directory, entity by entity, and reports
+        /// the added, deleted, and changed entities
+        Diff {
+            /// The v3 server URL or output directory compared against, e.g. a previous migration
+            #[arg(long = "target-a")]
+            target_a: String,
+            /// The v3 server URL or output directory compared, e.g. a re-run
+            #[arg(long = "target-b")]
+            target_b: String,
+            /// The ledger compared on the server of --target-a (otherwise --ledger-name)
+            #[arg(long = "ledger-a")]
+            ledger_a: Option<String>,
+            /// The ledger compared on the server of --target-b (otherwise --ledger-name)
+            #[arg(long = "ledger-b")]
+            ledger_b: Option<String>,
+        },
+        /// Checks that the --target ledger holds exactly the data a migration produced, by hashing its
+        /// statements and comparing the hash with the one the migration recorded
+        Verify {
+            /// Path to the dataset hash written by the migration
+            #[arg(default_value = "dataset-hash.json", value_hint = clap::ValueHint::FilePath)]
+            manifest: PathBuf,
+        },
+        /// Checks, before committing to a long run, that the --source and --target can be reached and accept the
+        /// run's credentials, that they speak the v2 and v3 APIs, their round-trip latency, and the disk space free
+        /// for .tmp
+        Doctor,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DanglingRefs {
+        Keep,
+        Drop,
+        Stub,
+        Fail,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DatasetVisibility {
+        /// Only the members of the dataset's organization can see it
+        Private,
+        /// Anyone can see and query it
+        Public,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum OutputFormat {
+        /// Fluree v3 transactions, i.e. {"ledger": ..., "insert": [...]}
+        Transaction,
+        /// Standard JSON-LD documents, i.e. {"@id": ..., "@graph": [...]}
+        Graph,
+        /// A Parquet file per class, with a column per property (the vocab is still written as JSON-LD)
+        Parquet,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TxnMode {
+        /// Bare `insert` transactions, which add to any values the target already holds
+        Insert,
+        /// `where`/`delete`/`insert` transactions, which replace the values the target holds for each written property
+        Replace,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Labels {
+        /// The term, without its prefix, e.g. "fullName"
+        Term,
+        /// The term's words, title-cased, e.g. "Full Name"
+        Title,
+        /// No rdfs:label
+        None,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum UploadFailure {
+        /// Move on to the next file, listing the skipped one in the --report
+        Skip,
+        /// Stop the load; a rerun skips the files already transacted
+        Abort,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ShardBy {
+        /// Each class is migrated whole by one shard: the classes, in the order of their collections' names, are
+        /// dealt to the shards in turn
+        Classes,
+        /// The entities of every class are dealt to the shards in blocks of consecutive `_id`s
+        Ids,
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum EmptyValues {
+        Keep,
+        Drop,
+        Sentinel,
+    }
+
+    // a BCP 47 language tag: subtags of 1 to 8 letters and digits, joined by "-", the first of them letters
+    fn parse_language_tag(language: &str) -> Result<String, String> {
+        let is_tag = language.split('-').enumerate().all(|(index, subtag)| {
+            (1..=8).contains(&subtag.len())
+                && match index {
+                    0 => subtag.chars().all(|c| c.is_ascii_alphabetic()),
+                    _ => subtag.chars().all(|c| c.is_ascii_alphanumeric()),
+                }
+        });
+        match is_tag {
+            true => Ok(language.to_string()),
+            false => Err("Expected a language tag, e.g. \"en\" or \"pt-BR\"".to_string()),
+        }
+    }
+
+    fn parse_ref_ledger(ref_ledger: &str) -> Result<(String, String), String> {
+        match ref_ledger.split_once('=') {
+            Some((collection, ledger))
+                if !collection.trim().is_empty() && !ledger.trim().is_empty() =>
+            {
+                Ok((collection.trim().to_string(), ledger.trim().to_string()))
+            }
+            _ => Err("Expected \"collection=ledger\", e.g. \"org=acme/hr\"".to_string()),
+        }
+    }
+
+    fn parse_basic_auth(credentials: &str) -> Result<String, String> {
+        match credentials.split_once(':') {
+            Some((user, _)) if !user.is_empty() => Ok(credentials.to_string()),
+            _ => Err("Expected \"user:password\", e.g. \"admin:s3cret\"".to_string()),
+        }
+    }
+
+    fn parse_where(filters: &str) -> Result<Map<String, Value>, String> {
+        let filters = match serde_json::from_str::<Value>(filters) {
+            Ok(Value::Object(filters)) => filters,
+            Ok(_) => return Err("The filters must be a JSON object".to_string()),
+            Err(e) => return Err(format!("The filters are not valid JSON: {}", e)),
+        };
+        for (predicate, value) in &filters {
+            if !predicate.contains('/') {
+                return Err(format!(
+                    "\"{}\" is not a v2 predicate, e.g. \"person/status\"",
+                    predicate
+                ));
+            }
+            if value.is_array() || value.is_object() || value.is_null() {
+                return Err(format!(
+                    "The value of \"{}\" must be a string, number, or boolean",
+                    predicate
+                ));
+            }
+        }
+        Ok(filters)
+    }
+
+    fn parse_as_of(as_of: &str) -> Result<String, String> {
+        match chrono::DateTime::parse_from_rfc3339(as_of) {
+            Ok(_) => Ok(as_of.to_string()),
+            Err(e) => Err(format!(
+                "Expected an ISO-8601 time, e.g. \"2024-03-01T00:00:00Z\": {}",
+                e
+            )),
+        }
+    }
+
+    fn parse_txn_opts(txn_opts: &str) -> Result<Map<String, Value>, String> {
+        match serde_json::from_str::<Value>(txn_opts) {
+            Ok(Value::Object(txn_opts)) => Ok(txn_opts),
+            Ok(_) => Err("The transaction opts must be a JSON object".to_string()),
+            Err(e) => Err(format!("The transaction opts are not valid JSON: {}", e)),
+        }
     }
 
     impl Opt {
+        /// The plan given with `migrate --plan`, if any
+        pub fn plan_input(&self) -> Option<&PathBuf> {
+            match &self.command {
+                Some(Command::Migrate { plan }) => plan.as_ref(),
+                _ => None,
+            }
+        }
+
         pub fn check_url(&self, is_source: bool) -> String {
             let url = if is_source {
                 self.source.clone()
@@ -134,7 +967,7 @@ pub mod opt {
                     .show_default(true)
                     .validate_with({
                         move |input: &String| -> Result<(), &str> {
-                            if let Ok(_) = reqwest::Url::parse(input) {
+                            if reqwest::Url::parse(input).is_ok() {
                                 Ok(())
                             } else {
                                 Err("Please provide a valid URL")
@@ -146,95 +979,616 @@ pub mod opt {
             }
         }
 
-        pub async fn write_or_print<P>(
-            &self,
-            file_name: P,
-            data: String,
-            target_instance: Option<FlureeInstance>,
-        ) -> Option<FlureeInstance>
-        where
-            P: AsRef<std::path::Path>,
-        {
-            if self.print {
-                let mut stdout = stdout();
-                execute!(stdout, Print(data), ResetColor).unwrap();
-                None
-            } else if self.target.is_some() {
-                let mut target_instance = match target_instance {
-                    None => FlureeInstance::new_target(&self),
-                    Some(fi) => fi,
-                };
+        /// Whether a v2 collection is migrated, given --classes and --exclude-classes
+        pub fn is_migrated_class(&self, orig_class_name: &str) -> bool {
+            let is_included = self.classes.is_empty()
+                || self
+                    .classes
+                    .iter()
+                    .any(|pattern| glob_match(pattern, orig_class_name));
+            is_included
+                && !self
+                    .exclude_classes
+                    .iter()
+                    .any(|pattern| glob_match(pattern, orig_class_name))
+        }
 
-                let response_string: Option<Value> = None;
+        /// The `where` clause of the v2 queries for a collection's entities, ANDing the --where filters on its
+        /// predicates, e.g. `person/status = "active" AND person/deleted = false`
+        pub fn where_clause(&self, orig_class_name: &str) -> Option<String> {
+            let prefix = format!("{}/", orig_class_name);
+            let conditions = self
+                .where_filters
+                .iter()
+                .flatten()
+                .filter(|(predicate, _)| predicate.starts_with(&prefix))
+                .map(|(predicate, value)| format!("{} = {}", predicate, value))
+                .collect::<Vec<String>>();
+            (!conditions.is_empty()).then(|| conditions.join(" AND "))
+        }
 
-                let green_bold = Style::new().green().bold();
-                let red_bold = Style::new().red().bold();
+        /// The `block` of every source query: --block, or the --as-of time
+        pub fn source_block(&self) -> Option<Value> {
+            match (self.block, &self.as_of) {
+                (Some(block), _) => Some(Value::from(block)),
+                (None, Some(as_of)) => Some(Value::String(as_of.to_owned())),
+                (None, None) => None,
+            }
+        }
 
-                while !target_instance.is_available
-                    || !target_instance.is_authorized
-                    || response_string.is_none()
-                {
-                    if !target_instance.is_available {
-                        target_instance.prompt_fix_url();
-                    }
+        /// Exits with a pointer to --vocab when -v is given an IRI (e.g. `-v http://example.org/terms/`), as scripts
+        /// written when -v was short for --vocab do, rather than running verbosely with a stray argument
+        pub fn check_short_vocab(args: &[String]) {
+            let short_vocab = args.iter().enumerate().find_map(|(index, arg)| {
+                let value = match arg.strip_prefix("-v")? {
+                    "" => args.get(index + 1).filter(|next| !next.starts_with('-'))?,
+                    attached => attached,
+                };
+                value.contains(':').then_some(value)
+            });
+            if let Some(vocab) = short_vocab {
+                println!(
+                    "{:>12} -v is short for --verbose, and no longer for --vocab. Use --vocab {}",
+                    Style::new().red().bold().apply_to("ERROR"),
+                    vocab
+                );
+                std::process::exit(1);
+            }
+        }
 
-                    if !target_instance.is_authorized {
-                        target_instance.prompt_api_key();
-                    }
-                    if self.pb.is_finished() {
-                        self.pb.reset();
-                    }
+        /// Sets up the logger for the --verbose level; without -v, RUST_LOG still applies
+        pub fn init_logger(&self) {
+            let mut builder = env_logger::Builder::from_default_env();
+            match self.verbose {
+                0 => {}
+                1 => {
+                    builder.filter_module("fluree_migrate", log::LevelFilter::Info);
+                }
+                2 => {
+                    builder.filter_module("fluree_migrate", log::LevelFilter::Debug);
+                }
+                _ => {
+                    builder.filter_level(log::LevelFilter::Trace);
+                }
+            }
+            builder.init();
+        }
 
-                    let is_vocab_file = file_name.as_ref().to_str().unwrap().contains("vocab");
+        /// Reads the tokens and keys of --auth-file, --create-auth-file, and the signing key files, before the run's
+        /// secrets are registered for redaction
+        pub fn use_key_files(&mut self) {
+            if let Some(path) = &self.auth_file {
+                self.target_auth = Some(read_secret_file(path, "auth"));
+            }
+            self.create_auth = self
+                .create_auth_file
+                .as_deref()
+                .map(|path| read_secret_file(path, "create auth"));
+            self.signing_key = self.signing_key_file.as_deref().map(SigningKey::read);
+            self.create_signing_key = self
+                .create_signing_key_file
+                .as_deref()
+                .map(SigningKey::read);
+        }
 
-                    if is_vocab_file {
-                        self.pb.println(format!(
-                            "{:>12} Vocab Data to v3 Ledger",
-                            green_bold.apply_to("Transacting")
-                        ));
-                    };
+        /// With --mock, points --source at the mock ledger, so the run doesn't ask for one
+        pub fn use_mock_source(&mut self) {
+            if self.mock {
+                self.source = Some(MOCK_SOURCE.to_string());
+            }
+        }
 
-                    // let response_result = target_instance.issue_initial_query().await;
-                    let response_result = target_instance.v3_transact(data.clone()).await;
+        /// The directory of the v2 snapshot `transform` (or `transform-one --extract`) reads instead of querying the
+        /// source
+        pub fn extract_directory(&self) -> Option<&PathBuf> {
+            match &self.command {
+                Some(Command::Transform { directory }) => Some(directory),
+                Some(Command::TransformOne {
+                    extract: Some(directory),
+                    ..
+                }) => Some(directory),
+                _ => None,
+            }
+        }
 
-                    let validate_attempt = target_instance.validate_result(&response_result);
+        /// With `transform`, takes --source and --block (or --as-of) from the manifest of the snapshot, so the
+        /// output is named and shaped as a migration from the source would be
+        pub fn use_extracted_source(&mut self) {
+            let Some(directory) = self.extract_directory() else {
+                return;
+            };
+            let manifest = ExtractManifest::read(directory);
+            if self.target.is_some() {
+                let red_bold = Style::new().red().bold();
+                println!(
+                    "{:>12} transform writes to --output; transact its files to the --target with load",
+                    red_bold.apply_to("ERROR")
+                );
+                std::process::exit(1);
+            }
+            if self.source.is_none() {
+                self.source = Some(manifest.source);
+            }
+            if self.block.is_none() && self.as_of.is_none() {
+                self.block = manifest.block;
+                self.as_of = manifest.as_of;
+            }
+        }
 
-                    if let Err(e) = validate_attempt {
-                        self.pb
-                            .println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
-                    }
+        /// With --from-cache, takes --source and --block (or --as-of) from the snapshot of the cache when not given,
+        /// so the run issues the cached queries
+        pub fn use_cached_source(&mut self) {
+            let Some(directory) = &self.from_cache else {
+                return;
+            };
+            let snapshot = Snapshot::read(directory);
+            if self.source.is_none() {
+                self.source = Some(snapshot.source);
+            }
+            if self.block.is_none() && self.as_of.is_none() {
+                self.block = snapshot.block;
+                self.as_of = snapshot.as_of;
+            }
+        }
 
-                    // let awaited_response = response_result.unwrap().text().await.unwrap();
-                    let awaited_response = match response_result {
-                        Ok(response) => response.text().await.unwrap(),
-                        Err(_) => {
-                            self.pb.finish_and_clear();
-                            continue;
-                        }
-                    };
+        /// Exits if --output-format parquet is used with --print, as Parquet files can't be printed
+        pub fn check_output_format(&self) {
+            if self.output_format == OutputFormat::Parquet && self.print {
+                println!(
+                    "{:>12} --output-format parquet writes files to --output, and can't be used with --print",
+                    Style::new().red().bold().apply_to("ERROR")
+                );
+                std::process::exit(1);
+            }
+        }
 
-                    if target_instance.is_available && target_instance.is_authorized {
-                        // let awaited_response = response_result.unwrap().text().await.unwrap();
-                        // response_string = serde_json::from_str(&awaited_response).unwrap();
-                        // println!("Response: {:?}", response_string);
-                        break;
-                    } else {
-                        let error = serde_json::from_str::<Value>(&awaited_response);
-                        if let Ok(error) = error {
-                            if let Some(error) = error["error"].as_str() {
-                                self.pb.println(format!(
-                                    "{:>12} {}",
-                                    red_bold.apply_to("ERROR"),
-                                    error
+        /// Exits if --changed-only is given without --txn-mode replace: a changed entity sent again as a bare insert
+        /// would keep its old values alongside the new ones
+        pub fn check_changed_only(&self) {
+            if self.changed_only && self.txn_mode != TxnMode::Replace {
+                println!(
+                    "{:>12} --changed-only sends the changed entities again, which needs --txn-mode replace to replace their old values",
+                    Style::new().red().bold().apply_to("ERROR")
+                );
+                std::process::exit(1);
+            }
+        }
+
+        /// With --output-per-run, points --output at a new subdirectory named for the run's start time
+        pub fn use_run_directory(&mut self) {
+            if let (Some(output), true) = (&self.output, self.output_per_run) {
+                let run_directory = chrono::Local::now().format("%Y-%m-%dT%H-%M-%S").to_string();
+                self.output = Some(output.join(run_directory));
+            }
+        }
+
+        /// Whether the command writes `.tmp`, the --output directory, or the target, so must hold the run's locks.
+        /// Commands that only read (e.g. `doctor` or `verify`) or write a single file (e.g. `plan`) don't
+        pub fn takes_run_lock(&self) -> bool {
+            matches!(
+                self.command,
+                None | Some(Command::Migrate { .. })
+                    | Some(Command::Extract)
+                    | Some(Command::Transform { .. })
+                    | Some(Command::Load { .. })
+                    | Some(Command::Export { .. })
+                    | Some(Command::Batch { .. })
+            )
+        }
+
+        /// With --shard, points --output at the shard's own subdirectory (e.g. shard-2-of-8) and names the --report
+        /// for the shard, so shards sharing a file system don't clobber each other's files. Exits if --shard is given
+        /// to a command other than `migrate` or `transform`, which it doesn't slice
+        pub fn use_shard(&mut self) {
+            let Some(shard) = self.shard else {
+                return;
+            };
+            if !matches!(
+                self.command,
+                None | Some(Command::Migrate { .. }) | Some(Command::Transform { .. })
+            ) {
+                println!(
+                    "{:>12} --shard slices a migration from the v2 source (or a transform), and can't be used with this command",
+                    Style::new().red().bold().apply_to("ERROR")
+                );
+                std::process::exit(1);
+            }
+            if let (Some(output), false) = (&self.output, self.print) {
+                self.output = Some(output.join(shard.name()));
+            }
+            self.report = self.report.as_ref().map(|report| shard.file_name(report));
+        }
+
+        /// The fetched entities of a page this run migrates: with --shard-by ids, those whose `_id` is the shard's
+        pub fn shard_slice(&self, entities: Vec<Value>) -> Vec<Value> {
+            match (self.shard, self.shard_by) {
+                (Some(shard), ShardBy::Ids) => entities
+                    .into_iter()
+                    .filter(|entity| entity["_id"].as_i64().is_none_or(|id| shard.owns_id(id)))
+                    .collect(),
+                _ => entities,
+            }
+        }
+
+        /// Whether this run writes (or transacts) what the shards of a --shard run share, e.g. the vocab
+        pub fn is_first_shard(&self) -> bool {
+            self.shard.is_none_or(|shard| shard.is_first())
+        }
+
+        /// Deletes the output directory of a previous run, so its files aren't mixed with this run's.
+        /// A non-empty directory is only deleted with --force or after confirming
+        pub fn clear_output_directory(&self) {
+            let output = match (&self.output, self.print, self.append) {
+                (Some(output), false, false) => output,
+                _ => return,
+            };
+            let is_empty = match std::fs::read_dir(output) {
+                Ok(mut entries) => entries.next().is_none(),
+                Err(why) if why.kind() == io::ErrorKind::NotFound => return,
+                Err(why) => panic!("Unable to read existing output directory: {}", why),
+            };
+            if !is_empty && !self.force {
+                let entries = std::fs::read_dir(output).map_or(0, |entries| entries.count());
+                let confirmed = self.confirm(&format!(
+                    "{} holds {} files and directories, which will be deleted. Delete them?",
+                    output.display(),
+                    entries
+                ));
+                if !confirmed {
+                    self.pb.finish_and_clear();
+                    let red_bold = Style::new().red().bold();
+                    println!(
+                        "{:>12} The output directory {} is not empty. Rerun with --force (or --yes) to delete it, or choose another --output",
+                        red_bold.apply_to("ERROR"),
+                        output.display()
+                    );
+                    std::process::exit(1);
+                }
+            }
+            std::fs::remove_dir_all(output).unwrap_or_else(|why| {
+                if why.kind() != io::ErrorKind::NotFound {
+                    panic!("Unable to remove existing output directory: {}", why);
+                }
+            });
+        }
+
+        /// Asks whether to go ahead with a destructive operation, unless --yes answered for the user. When not
+        /// run interactively, there's no one to ask, so it's not confirmed
+        pub fn confirm(&self, prompt: &str) -> bool {
+            self.yes
+                || (io::stdin().is_terminal()
+                    && self.pb.suspend(|| {
+                        Confirm::with_theme(&ColorfulTheme::default())
+                            .with_prompt(prompt)
+                            .default(false)
+                            .interact()
+                            .unwrap_or(false)
+                    }))
+        }
+
+        /// Asks before a run creates the target ledger (with --create-ledger) or its Nexus dataset (with
+        /// --create-dataset), exiting unless it's confirmed
+        pub fn confirm_create(&self, ledger: &str) {
+            let target = match (&self.target, self.print) {
+                (Some(target), false) => target,
+                _ => return,
+            };
+            let creation = match (self.is_create_ledger, self.create_dataset) {
+                (true, _) => format!("create the ledger {} on {}", ledger, target),
+                (_, true) => format!(
+                    "create a Nexus dataset for the ledger {} through {}",
+                    ledger, self.nexus_api
+                ),
+                _ => return,
+            };
+            let confirmed = self.confirm(&format!(
+                "This will {}, and transact the migrated data to it. Continue?",
+                creation
+            ));
+            if !confirmed {
+                self.pb.finish_and_clear();
+                let red_bold = Style::new().red().bold();
+                println!(
+                    "{:>12} Not creating the ledger {}. Rerun with --yes to create it without asking",
+                    red_bold.apply_to("ERROR"),
+                    ledger
+                );
+                std::process::exit(1);
+            }
+        }
+
+        /// The ledger the output is written for: --ledger-name, or the v2 ledger (e.g. "network/db")
+        /// as mapped by the first matching --ledger-map rule
+        pub fn target_ledger_name(&self, source_ledger: &str) -> String {
+            match &self.ledger_name {
+                Some(ledger_name) => ledger_name.to_string(),
+                None => self
+                    .ledger_map
+                    .iter()
+                    .find_map(|ledger_map| ledger_map.target_ledger(source_ledger))
+                    .unwrap_or(source_ledger.to_string()),
+            }
+        }
+
+        /// The name of the file holding the vocab (and, with --shacl, the shapes)
+        pub fn vocab_file_name(&self, ledger_name: &str) -> String {
+            match &self.file_template {
+                Some(template) => template.render(0, "vocab", ledger_name),
+                None if self.content_addressed => format!("0_vocab_{}.jsonld", HASH_PLACEHOLDER),
+                None => "0_vocab.jsonld".to_string(),
+            }
+        }
+
+        /// The name of the `seq`th data file, which holds entities of `classes`
+        pub fn data_file_name(&self, seq: u64, classes: &[String], ledger_name: &str) -> String {
+            match &self.file_template {
+                Some(template) => template.render(seq, &classes.join("-"), ledger_name),
+                None if self.content_addressed => {
+                    format!("{}_data_{}.jsonld", seq, HASH_PLACEHOLDER)
+                }
+                None => format!("{}_data.jsonld", seq),
+            }
+        }
+
+        /// The number of the first data file this run writes, i.e. 1, or with --append,
+        /// the number after the last data file already in the output directory
+        pub fn first_data_file_number(&self, ledger_name: &str) -> u64 {
+            let output = match (&self.output, self.print, self.append) {
+                (Some(output), false, true) => output,
+                _ => return 1,
+            };
+            let last_file_number = std::fs::read_dir(output)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| {
+                    let file_name = PathBuf::from(entry.ok()?.file_name());
+                    // with --canonicalize, the files were written with an .nq extension instead of the template's
+                    let file_name = match (&self.file_template, self.canonicalize) {
+                        (Some(template), true) => template.with_template_extension(&file_name),
+                        _ => file_name,
+                    };
+                    let file_name = file_name.to_str()?;
+                    match &self.file_template {
+                        Some(template) => template.seq_of(file_name, ledger_name),
+                        None => {
+                            let file_name = file_name
+                                .strip_suffix(".jsonld")
+                                .or(file_name.strip_suffix(".nq"))?;
+                            // with --content-addressed, the name ends with the hash of the file
+                            let file_name = match file_name.rsplit_once("_data_") {
+                                Some((seq, hash))
+                                    if hash.chars().all(|c| c.is_ascii_hexdigit()) =>
+                                {
+                                    seq
+                                }
+                                _ => file_name.strip_suffix("_data")?,
+                            };
+                            file_name.parse::<u64>().ok()
+                        }
+                    }
+                })
+                .max()
+                .unwrap_or(0);
+            last_file_number + 1
+        }
+
+        /// The file name and contents an output document is written with, in the --output-format
+        /// (and, with --canonicalize, as canonical N-Quads)
+        fn output_document(&self, file_name: &std::path::Path, data: String) -> (PathBuf, String) {
+            // transactions sent to a target get their --txn-opts as they're transacted
+            let has_txn_opts = self.txn_opts.is_some() && self.target.is_none();
+            if self.output_format != OutputFormat::Graph && !self.canonicalize && !has_txn_opts {
+                return (file_name.to_path_buf(), data);
+            }
+            let mut document = serde_json::from_str::<Map<String, Value>>(&data).unwrap();
+            if let (Some(txn_opts), true) = (&self.txn_opts, has_txn_opts) {
+                merge_txn_opts(&mut document, txn_opts);
+            }
+            if self.output_format == OutputFormat::Graph {
+                let ledger = document
+                    .remove("ledger")
+                    .and_then(|ledger| ledger.as_str().map(String::from))
+                    .unwrap_or_default();
+                let document_id = format!(
+                    "urn:fluree:{}:{}",
+                    ledger,
+                    file_name.file_stem().unwrap_or_default().to_string_lossy()
+                );
+                let nodes = document.remove("insert").unwrap_or(Value::Array(vec![]));
+                for key in ["f:defaultContext", "where", "delete", "opts"] {
+                    document.remove(key);
+                }
+                document.insert("@id".to_string(), Value::String(document_id));
+                document.insert("@graph".to_string(), nodes);
+            }
+            match self.canonicalize {
+                true => {
+                    let nquads = canonical_nquads(&document_quads(&document));
+                    (file_name.with_extension("nq"), nquads)
+                }
+                false => (
+                    file_name.to_path_buf(),
+                    serde_json::to_string_pretty(&document).unwrap(),
+                ),
+            }
+        }
+
+        /// Writes a document to --output, prints it, or transacts it to --target, returning the target instance
+        /// for the next. Gives up with an error once the target has failed it too many times in a row
+        pub async fn write_or_print<P>(
+            &self,
+            file_name: P,
+            data: String,
+            target_instance: Option<FlureeInstance>,
+        ) -> Result<Option<FlureeInstance>, String>
+        where
+            P: AsRef<std::path::Path>,
+        {
+            let (file_name, data) = self.output_document(file_name.as_ref(), data);
+            let is_hash_named = file_name.to_string_lossy().contains(HASH_PLACEHOLDER);
+            let hash =
+                (self.content_addressed || is_hash_named).then(|| content_hash(data.as_bytes()));
+            let file_name = match (&hash, is_hash_named) {
+                (Some(hash), true) => {
+                    PathBuf::from(file_name.to_string_lossy().replace(HASH_PLACEHOLDER, hash))
+                }
+                _ => file_name,
+            };
+            let run_file_name = file_name.display().to_string();
+            run_state::begin(&run_file_name);
+            if self.print {
+                let mut stdout = stdout();
+                execute!(stdout, Print(data), ResetColor).unwrap();
+                run_state::complete(&run_file_name);
+                Ok(None)
+            } else if self.target.is_some() {
+                let mut target_instance = match target_instance {
+                    None => FlureeInstance::new_target(self),
+                    Some(fi) => fi,
+                };
+
+                if !target_instance.is_dataset_created {
+                    let ledger = serde_json::from_str::<Value>(&data)
+                        .ok()
+                        .and_then(|txn| txn["ledger"].as_str().map(String::from))
+                        .unwrap_or_default();
+                    target_instance.create_dataset(&ledger, &self.pb).await?;
+                }
+
+                let response_string: Option<Value> = None;
+                let is_creating_ledger = !target_instance.is_created;
+
+                let green_bold = Style::new().green().bold();
+                let red_bold = Style::new().red().bold();
+                let yellow_bold = Style::new().yellow().bold();
+
+                // with --content-addressed, a file the ledger already got from an earlier run isn't sent again
+                let uploaded = match (&hash, self.content_addressed) {
+                    (Some(hash), true) => {
+                        let ledger = serde_json::from_str::<Value>(&data)
+                            .ok()
+                            .and_then(|txn| txn["ledger"].as_str().map(String::from))
+                            .unwrap_or_default();
+                        if is_creating_ledger {
+                            uploads::forget(self, &ledger);
+                        } else if uploads::is_uploaded(self, &ledger, hash) {
+                            self.pb.println(format!(
+                                "{:>12} {} (already transacted)",
+                                yellow_bold.apply_to("Skipping"),
+                                file_name.display()
+                            ));
+                            run_state::complete(&run_file_name);
+                            return Ok(Some(target_instance));
+                        }
+                        Some((ledger, hash))
+                    }
+                    _ => None,
+                };
+
+                // with --adaptive-batching, a transaction the target rejects as too large is split into halves,
+                // transacted in order
+                let mut pending = VecDeque::from([data.clone()]);
+                while let Some(body) = pending.pop_front() {
+                    while !target_instance.is_available
+                        || !target_instance.is_authorized
+                        || response_string.is_none()
+                    {
+                        if !target_instance.is_available {
+                            target_instance.prompt_fix_url();
+                        }
+
+                        if !target_instance.is_authorized {
+                            target_instance.prompt_api_key();
+                        }
+                        if self.pb.is_finished() {
+                            self.pb.reset();
+                        }
+
+                        let is_vocab_file = file_name.to_str().unwrap().contains("vocab");
+
+                        if is_vocab_file {
+                            self.pb.println(format!(
+                                "{:>12} Vocab Data to v3 Ledger",
+                                green_bold.apply_to("Transacting")
+                            ));
+                        };
+
+                        run_state::wait_while_paused(&self.pb).await;
+                        // let response_result = target_instance.issue_initial_query().await;
+                        let sent = Instant::now();
+                        let response_result = target_instance.v3_transact(body.clone()).await;
+                        let is_too_large =
+                            self.adaptive_batching && is_batch_too_large(&response_result);
+
+                        match target_instance.validate_result(response_result).await {
+                            Ok(_) => {
+                                if self.adaptive_batching {
+                                    self.target_batch.observe(sent.elapsed());
+                                }
+                                pretty_log(
+                                    Level::Info,
+                                    &mut self.pb.clone(),
+                                    &format!(
+                                        "Transacted {} ({})",
+                                        file_name.display(),
+                                        format_bytes(body.len())
+                                    ),
+                                );
+                                break;
+                            }
+                            Err(e) => {
+                                self.pb.println(format!(
+                                    "{:>12} {}",
+                                    red_bold.apply_to("ERROR"),
+                                    e
                                 ));
+                                if is_too_large {
+                                    self.target_batch.shrink();
+                                    // the target answered, so it's still there
+                                    target_instance.is_available = true;
+                                    let halves = match serde_json::from_str::<Value>(&body) {
+                                        Ok(Value::Object(transaction)) => {
+                                            split_transaction(&transaction)
+                                        }
+                                        _ => None,
+                                    };
+                                    if let Some((first, second)) = halves {
+                                        self.pb.println(format!(
+                                            "{:>12} {} into two transactions",
+                                            yellow_bold.apply_to("Splitting"),
+                                            file_name.display()
+                                        ));
+                                        for half in [second, first] {
+                                            pending.push_front(
+                                                serde_json::to_string_pretty(&half).unwrap(),
+                                            );
+                                        }
+                                        break;
+                                    }
+                                }
+                                if target_instance.is_available && target_instance.is_authorized {
+                                    target_instance.wait_to_retry(&self.pb).await?;
+                                }
+                                self.pb.finish_and_clear();
+                                continue;
                             }
                         }
-                        self.pb.finish_and_clear();
-                        continue;
                     }
                 }
+                run_state::complete(&run_file_name);
+                if let Some((ledger, hash)) = uploaded {
+                    uploads::record(self, &ledger, hash);
+                }
+
+                if is_creating_ledger && target_instance.initial_policy.is_some() {
+                    let txn = serde_json::from_str::<Value>(&data).unwrap();
+                    let ledger_name = txn["ledger"].as_str().unwrap_or_default();
+                    target_instance
+                        .transact_initial_policy(ledger_name, &self.pb)
+                        .await?;
+                }
 
-                Some(target_instance)
+                Ok(Some(target_instance))
             } else {
                 let base_path = self.output.clone().unwrap();
                 std::fs::create_dir_all(&base_path).unwrap_or_else(|why| {
@@ -243,16 +1597,264 @@ pub mod opt {
                     }
                 });
 
-                let mut file =
-                    File::create(&base_path.join(file_name)).expect("Unable to create file");
+                // with --output-format parquet, the data goes to the Parquet files of its classes once it's all written
+                let is_vocab_file = file_name.to_string_lossy().contains("vocab");
+                if self.output_format == OutputFormat::Parquet && !is_vocab_file {
+                    let document = serde_json::from_str::<Map<String, Value>>(&data).unwrap();
+                    parquet::spool_rows(&base_path, &document);
+                    run_state::complete(&run_file_name);
+                    return Ok(None);
+                }
+
+                let path = base_path.join(file_name);
+                let mut file = File::create(&path).expect("Unable to create file");
                 let mut data_writer = io::BufWriter::new(&mut file);
                 data_writer
                     .write_all(data.as_bytes())
                     .expect("Unable to write data");
-                None
+                data_writer.flush().expect("Unable to write data");
+                run_state::complete(&run_file_name);
+                pretty_log(
+                    Level::Info,
+                    &mut self.pb.clone(),
+                    &format!("Wrote {} ({})", path.display(), format_bytes(data.len())),
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+pub mod file_template {
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    /// What `{hash}` renders as in a file name, until the file is written with the hash of its contents
+    pub const HASH_PLACEHOLDER: &str = "{hash}";
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Literal(String),
+        Seq { width: usize },
+        Class,
+        Ledger,
+        Hash,
+    }
+
+    /// Names the files written to --output, e.g. "{seq:04}_{class}_{ledger}.jsonld".
+    /// `{seq}` is the file's number (zero-padded with `{seq:04}`), `{class}` the class(es) in the file,
+    /// and `{ledger}` the target ledger name
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct FileTemplate {
+        tokens: Vec<Token>,
+    }
+
+    impl FromStr for FileTemplate {
+        type Err = String;
+
+        fn from_str(template: &str) -> Result<Self, Self::Err> {
+            let mut tokens = vec![];
+            let mut rest = template;
+            while let Some(start) = rest.find('{') {
+                if start > 0 {
+                    tokens.push(Token::Literal(rest[..start].to_string()));
+                }
+                let end = rest[start..]
+                    .find('}')
+                    .ok_or_else(|| format!("Unclosed placeholder in \"{}\"", template))?
+                    + start;
+                let placeholder = &rest[start + 1..end];
+                let token = match placeholder.split_once(':') {
+                    None if placeholder == "seq" => Token::Seq { width: 0 },
+                    Some(("seq", width)) => Token::Seq {
+                        width: width
+                            .parse()
+                            .map_err(|_| format!("Invalid width in {{{}}}", placeholder))?,
+                    },
+                    None if placeholder == "class" => Token::Class,
+                    None if placeholder == "ledger" => Token::Ledger,
+                    None if placeholder == "hash" => Token::Hash,
+                    _ => {
+                        return Err(format!(
+                        "Unknown placeholder {{{}}} (expected {{seq}}, {{class}}, {{ledger}} or {{hash}})",
+                        placeholder
+                    ))
+                    }
+                };
+                tokens.push(token);
+                rest = &rest[end + 1..];
+            }
+            if !rest.is_empty() {
+                tokens.push(Token::Literal(rest.to_string()));
+            }
+            if !tokens
+                .iter()
+                .any(|token| matches!(token, Token::Seq { .. }))
+            {
+                return Err(
+                    "The template needs a {seq} placeholder, or every file would get the same name"
+                        .to_string(),
+                );
+            }
+            Ok(FileTemplate { tokens })
+        }
+    }
+
+    // class and ledger names can hold characters (e.g. "/" or ":") that don't belong in file names
+    pub fn file_name_safe(name: &str) -> String {
+        name.chars()
+            .map(
+                |c| match c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                    true => c,
+                    false => '-',
+                },
+            )
+            .collect()
+    }
+
+    impl FileTemplate {
+        pub fn render(&self, seq: u64, class: &str, ledger: &str) -> String {
+            self.tokens
+                .iter()
+                .map(|token| match token {
+                    Token::Literal(literal) => literal.to_string(),
+                    Token::Seq { width } => format!("{:0width$}", seq, width = width),
+                    Token::Class => file_name_safe(class),
+                    Token::Ledger => file_name_safe(ledger),
+                    // the contents aren't known until the file is written, which fills this in
+                    Token::Hash => HASH_PLACEHOLDER.to_string(),
+                })
+                .collect()
+        }
+
+        /// A file name with the template's extension in place of its own, e.g. for a file written with --canonicalize
+        pub fn with_template_extension(&self, file_name: &Path) -> PathBuf {
+            let template_file_name = PathBuf::from(self.render(0, "", ""));
+            match template_file_name.extension() {
+                Some(extension) => file_name.with_extension(extension),
+                None => file_name.with_extension(""),
+            }
+        }
+
+        /// The `{seq}` of a file name rendered from this template, if it was
+        pub fn seq_of(&self, file_name: &str, ledger: &str) -> Option<u64> {
+            Self::match_tokens(&self.tokens, file_name, &file_name_safe(ledger))
+        }
+
+        fn match_tokens(tokens: &[Token], file_name: &str, ledger: &str) -> Option<u64> {
+            let Some((token, rest)) = tokens.split_first() else {
+                return file_name.is_empty().then_some(0);
+            };
+            match token {
+                Token::Literal(literal) => {
+                    Self::match_tokens(rest, file_name.strip_prefix(literal.as_str())?, ledger)
+                }
+                Token::Ledger => Self::match_tokens(rest, file_name.strip_prefix(ledger)?, ledger),
+                Token::Seq { .. } => {
+                    let digits = file_name
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(file_name.len());
+                    let seq = file_name[..digits].parse().ok()?;
+                    Self::match_tokens(rest, &file_name[digits..], ledger).map(|_| seq)
+                }
+                Token::Hash => {
+                    let digits = file_name
+                        .find(|c: char| !c.is_ascii_hexdigit())
+                        .unwrap_or(file_name.len());
+                    Self::match_tokens(rest, &file_name[digits..], ledger)
+                }
+                Token::Class => (0..=file_name.len())
+                    .filter(|&end| file_name.is_char_boundary(end))
+                    .find_map(|end| Self::match_tokens(rest, &file_name[end..], ledger)),
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse(template: &str) -> FileTemplate {
+            template.parse().unwrap()
+        }
+
+        #[test]
+        fn renders_a_zero_padded_seq() {
+            let template = parse("{seq:04}_{class}_{ledger}.jsonld");
+            assert_eq!(
+                template.render(7, "Person", "demo/test"),
+                "0007_Person_demo-test.jsonld"
+            );
+            assert_eq!(parse("{seq}.jsonld").render(12, "", ""), "12.jsonld");
+        }
+
+        #[test]
+        fn reads_the_seq_of_a_rendered_name() {
+            let template = parse("{seq:04}_{class}_{ledger}.jsonld");
+            let file_name = template.render(12, "Person", "demo/test");
+            assert_eq!(template.seq_of(&file_name, "demo/test"), Some(12));
+            assert_eq!(template.seq_of("0012_Person.jsonld", "demo/test"), None);
+            assert_eq!(template.seq_of("vocab.jsonld", "demo/test"), None);
+        }
+
+        // the class is matched by backtracking, so it may hold the characters that follow it in the template
+        #[test]
+        fn reads_the_seq_past_a_class_holding_the_template_separator() {
+            let template = parse("{class}_{seq}.jsonld");
+            let file_name = template.render(3, "Person_Address", "");
+            assert_eq!(file_name, "Person_Address_3.jsonld");
+            assert_eq!(template.seq_of(&file_name, ""), Some(3));
+            assert_eq!(template.seq_of("Person_Address_x.jsonld", ""), None);
+        }
+
+        #[test]
+        fn renders_and_reads_past_a_hash() {
+            let template = parse("{seq}_{hash}.jsonld");
+            assert_eq!(template.render(2, "", ""), "2_{hash}.jsonld");
+            assert_eq!(template.seq_of("2_9f86d081884c7d65.jsonld", ""), Some(2));
+            assert_eq!(template.seq_of("2_not-a-hash.jsonld", ""), None);
+        }
+
+        #[test]
+        fn swaps_in_the_template_extension() {
+            let file_name = Path::new("0001_Person.nq");
+            assert_eq!(
+                parse("{seq:04}_{class}.jsonld").with_template_extension(file_name),
+                PathBuf::from("0001_Person.jsonld")
+            );
+            assert_eq!(
+                parse("{seq:04}_{class}").with_template_extension(file_name),
+                PathBuf::from("0001_Person")
+            );
+        }
+
+        #[test]
+        fn rejects_invalid_templates() {
+            assert!("{seq"
+                .parse::<FileTemplate>()
+                .unwrap_err()
+                .contains("Unclosed"));
+            assert!("{seq:wide}"
+                .parse::<FileTemplate>()
+                .unwrap_err()
+                .contains("Invalid width"));
+            assert!("{seq}_{name}"
+                .parse::<FileTemplate>()
+                .unwrap_err()
+                .contains("Unknown placeholder {name}"));
+            assert!("{class}.jsonld"
+                .parse::<FileTemplate>()
+                .unwrap_err()
+                .contains("{seq}"));
+        }
+
+        #[test]
+        fn replaces_characters_that_dont_belong_in_file_names() {
+            assert_eq!(file_name_safe("demo/test"), "demo-test");
+            assert_eq!(file_name_safe("ex:Person Record"), "ex-Person-Record");
+            assert_eq!(file_name_safe("person_v1.2-b"), "person_v1.2-b");
+        }
+    }
 }
 
 pub mod temp_files {
@@ -262,12 +1864,18 @@ pub mod temp_files {
 
     use serde_json::Value;
 
+    use crate::functions::content_hash;
+
+    /// Where fetched v2 entities are kept until they are transformed
+    pub const TEMP_DIRECTORY: &str = ".tmp";
+
     #[derive(Debug)]
     pub struct TempFile {
         directory: PathBuf,
         current_file: Option<File>,
         current_file_size: u64,
         file_counter: u32,
+        is_content_addressed: bool,
     }
 
     impl TempFile {
@@ -281,13 +1889,24 @@ pub mod temp_files {
                 current_file: None,
                 current_file_size: 0,
                 file_counter: 0,
+                is_content_addressed: false,
             })
         }
 
+        /// With --content-addressed, temp files are named `<n>__<hash>__<collection>` rather than `<n>__<collection>`
+        pub fn content_addressed(mut self, is_content_addressed: bool) -> Self {
+            self.is_content_addressed = is_content_addressed;
+            self
+        }
+
         pub fn write(&mut self, collection_name: &str, data: &Vec<Value>) -> io::Result<()> {
             let pretty_string = serde_json::to_string_pretty(data).unwrap();
             let bytes_data = pretty_string.as_bytes();
-            self.create_new_file(collection_name)?;
+            let file_name = match self.is_content_addressed {
+                true => format!("{}__{}", content_hash(bytes_data), collection_name),
+                false => collection_name.to_string(),
+            };
+            self.create_new_file(&file_name)?;
             if let Some(file) = &mut self.current_file {
                 file.write_all(bytes_data)?;
                 self.current_file_size += data.len() as u64;
@@ -295,8 +1914,8 @@ pub mod temp_files {
             Ok(())
         }
 
-        fn create_new_file(&mut self, collection_name: &str) -> io::Result<()> {
-            let file_name = format!("{}__{}", self.file_counter, collection_name);
+        fn create_new_file(&mut self, name: &str) -> io::Result<()> {
+            let file_name = format!("{}__{}", self.file_counter, name);
             let file_path = self.directory.join(&file_name);
             self.file_counter += 1;
             self.current_file_size = 0;
@@ -304,11 +1923,29 @@ pub mod temp_files {
                 OpenOptions::new()
                     .write(true)
                     .create(true)
+                    .truncate(true)
                     .open(file_path)?,
             );
             Ok(())
         }
 
+        /// The v2 collection whose entities are stored in the given temp file
+        pub fn collection_name(file: &Path) -> String {
+            file.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .split("__")
+                .last()
+                .unwrap()
+                .to_string()
+        }
+
+        pub fn read(file: &Path) -> Vec<Value> {
+            let file_bytes = fs::read(file).expect("Could not read file");
+            serde_json::from_slice(&file_bytes).expect("Could not parse JSON")
+        }
+
         pub fn get_files(&self) -> io::Result<Vec<PathBuf>> {
             let mut files: Vec<PathBuf> = fs::read_dir(&self.directory)?
                 .filter_map(|entry| {
@@ -329,130 +1966,1994 @@ pub mod temp_files {
 
             Ok(files.to_owned())
         }
+
+        /// The temp files, grouped by collection and in the order each collection's were written, so the order
+        /// doesn't depend on how the fetching of the collections interleaved
+        pub fn get_files_by_collection(&self) -> io::Result<Vec<PathBuf>> {
+            let mut files = self.get_files()?;
+            files.sort_by_cached_key(|file| {
+                let counter = file
+                    .file_name()
+                    .and_then(|name| name.to_str()?.split("__").next()?.parse::<u32>().ok());
+                (Self::collection_name(file), counter)
+            });
+            Ok(files)
+        }
     }
 }
 
 pub mod parser {
-    use std::collections::HashMap;
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        path::PathBuf,
+    };
 
-    use serde_json::{Map, Value};
+    use serde_json::{json, Map, Value};
 
     use crate::{
-        fluree::FlureeInstance,
-        functions::{create_data_context, create_vocab_context, standardize_class_name},
+        align::{AlignMode, Alignment},
+        fluree::{FlureeInstance, DATA_CHUNK_SIZE},
+        functions::{
+            class_term, clean_strings, create_data_context, create_vocab_context,
+            default_property_term, glob_match, infer_datatype, instant_to_iso_date,
+            instant_to_iso_string, map_instants, nfc_strings, parse_for_class_and_property_name,
+            ref_ledger_base, remove_namespace, represent_fluree_value, restrict_collections,
+            scrub_empty_values, standardize_class_name, standardize_property_name, title_case_term,
+            typed_literal,
+        },
+        hints::MIGRATE_NAMESPACE,
+        mapping::{load_renames, MappingConfig, MergeKey, MergeRule},
+        plan::{MigrationPlan, PlannedClass, PlannedProperty},
+        report::{CardinalityChange, ClassStatistics, DatatypeInference, UnsupportedFeature},
+        shard::Shard,
+        spec::{collection_requirement, spec_constraints, spec_functions, Requirement},
     };
 
-    use self::jsonld::{Class, Property, ShaclShape};
+    use super::temp_files::TempFile;
+
+    use self::jsonld::{Class, Property, ShaclProperty, ShaclShape};
+
+    use super::opt::{EmptyValues, Labels, Opt, ShardBy};
+
+    // the low bits of a v2 subject `_id`, above which it carries the index of its collection (which is the low bits
+    // of the `_id` of the collection's `_collection` record)
+    const SUBJECT_ID_BITS: u32 = 44;
+
+    // links a vocab class or property to the external term it's aligned to, alongside any links it already has
+    fn link_aligned_term(node: &mut Value, link: &str, iri: &str) {
+        let aligned = json!({ "@id": iri });
+        match &mut node[link] {
+            Value::Array(links) => links.push(aligned),
+            Value::Null => node[link] = json!([aligned]),
+            existing => *existing = json!([existing.take(), aligned]),
+        }
+    }
+
+    // the rdfs:label of a vocab class or property, which is its term without its prefix, as --labels and
+    // --label-language shape it
+    fn set_label(node: &mut Value, opt: &Opt) {
+        let Value::Object(node) = node else {
+            return;
+        };
+        let Some(Value::String(term)) = node.remove("rdfs:label") else {
+            return;
+        };
+        let label = match opt.labels {
+            Labels::Term => term,
+            Labels::Title => title_case_term(&term),
+            Labels::None => return,
+        };
+        let label = match &opt.label_language {
+            Some(language) => json!({ "@value": label, "@language": language }),
+            None => json!(label),
+        };
+        node.insert("rdfs:label".to_string(), label);
+    }
+
+    fn resolve_alias(entity_aliases: &HashMap<String, String>, id: &str) -> String {
+        let mut id = id;
+        while let Some(alias) = entity_aliases.get(id) {
+            id = alias;
+        }
+        id.to_string()
+    }
+
+    /// Aliases the subjects of a group of entities sharing a `mergeEntities` key to the first's, joining the
+    /// subjects they're already aliased to (by the other keys they share), so no earlier alias is overwritten
+    fn alias_group(entity_aliases: &mut HashMap<String, String>, ids: &[String]) {
+        let Some(first) = ids.first() else {
+            return;
+        };
+        let canonical_id = resolve_alias(entity_aliases, first);
+        for id in &ids[1..] {
+            let root = resolve_alias(entity_aliases, id);
+            if root != canonical_id {
+                entity_aliases.insert(root, canonical_id.clone());
+            }
+        }
+    }
+
+    pub struct Parser {
+        pub classes: HashMap<String, Class>,
+        pub properties: HashMap<String, Property>,
+        pub shacl_shapes: HashMap<String, ShaclShape>,
+        pub vocab_context: HashMap<String, String>,
+        pub data_context: HashMap<String, String>,
+        pub network_name: String,
+        pub db_name: String,
+        pub mapping: MappingConfig,
+        pub entity_aliases: HashMap<String, String>,
+        pub inferred_datatypes: HashMap<String, HashMap<String, String>>,
+        pub tag_concepts: HashMap<String, String>,
+        /// v2 `_user` and `_auth` ids mapped to the identities they're migrated as
+        pub identities: HashMap<String, String>,
+        pub clean_strings: bool,
+        pub empty_values: EmptyValues,
+        pub empty_sentinel: Option<String>,
+        pub property_renames: HashMap<String, String>,
+        pub class_renames: HashMap<String, String>,
+        /// Whether v2 collection and predicate names are kept as they're spelled, with `--preserve-names`
+        pub preserve_names: bool,
+        pub dropped_properties: HashSet<String>,
+        pub plan: Option<MigrationPlan>,
+        /// v2 ref predicates (keyed like `properties`) mapped to the collection they're restricted to
+        pub ref_collections: HashMap<String, String>,
+        /// v2 ref predicates (keyed like `properties`) restricted to several collections, mapped to them
+        pub multi_ref_collections: HashMap<String, Vec<String>>,
+        /// v2 collections migrated into other ledgers, mapped to the base IRI of their subjects there
+        pub external_refs: HashMap<String, String>,
+        /// v2 collections mapped to their predicates marked `upsert`, whose values are natural keys
+        pub upsert_keys: BTreeMap<String, Vec<String>>,
+        /// The external terms the classes and properties are aligned to, with `--align`
+        pub alignment: Alignment,
+        /// The indexes of the v2 collections with a `base` of their own in the mapping's `namespaces`, mapped to
+        /// the prefix their subjects' ids are written with
+        pub id_prefixes: HashMap<i64, String>,
+        /// The indexes of the v2 collections mapped to their names, so the collection of a referenced `_id` is known
+        pub collection_indexes: HashMap<i64, String>,
+        /// The slice of the source this run migrates, with `--shard`
+        pub shard: Option<Shard>,
+        pub shard_by: ShardBy,
+        /// With `--shard` by classes, the v2 collections this shard migrates
+        pub shard_classes: HashSet<String>,
+    }
+
+    impl Parser {
+        /// Creates a parser for the source; a plan (e.g. from `migrate --plan` or `--vocab-file`) decides
+        /// which classes, names, and predicates are migrated
+        pub fn new(
+            opt: &Opt,
+            source_instance: &FlureeInstance,
+            plan: Option<MigrationPlan>,
+        ) -> Self {
+            let mut mapping = match &opt.mapping {
+                Some(path) => MappingConfig::load(path),
+                None => MappingConfig::default(),
+            };
+            let mut property_renames = match &opt.rename_properties {
+                Some(path) => load_renames(path, "property rename"),
+                None => HashMap::new(),
+            };
+            let mut class_renames = match &opt.rename_classes {
+                Some(path) => load_renames(path, "class rename"),
+                None => HashMap::new(),
+            };
+            // the external terms an alignment reuses are renames, unless the collection or predicate is renamed
+            let alignment = match &opt.align {
+                Some(path) => Alignment::load(path),
+                None => Alignment::default(),
+            };
+            for (orig_class_name, class_name) in alignment.reused_classes() {
+                class_renames.entry(orig_class_name).or_insert(class_name);
+            }
+            for (orig_property_name, term) in alignment.reused_properties() {
+                property_renames.entry(orig_property_name).or_insert(term);
+            }
+            // a collection with a vocab of its own names its class with its prefix, unless the class is renamed
+            for orig_class_name in mapping.namespaces.keys() {
+                if let Some(prefix) = mapping.vocab_prefix(orig_class_name) {
+                    class_renames
+                        .entry(orig_class_name.to_owned())
+                        .or_insert_with(|| {
+                            let class_name =
+                                class_term(orig_class_name, &HashMap::new(), opt.preserve_names);
+                            format!("{}:{}", prefix, class_name)
+                        });
+                }
+            }
+            let plan = plan.or_else(|| opt.plan_input().map(|path| MigrationPlan::load(path)));
+            // a plan lists exactly the classes, names, and predicates that are migrated
+            for planned_class in plan.iter().flat_map(|plan| plan.classes.iter()) {
+                class_renames.insert(
+                    planned_class.collection.to_owned(),
+                    planned_class.class.to_owned(),
+                );
+                let mut predicates = vec![];
+                for planned_property in &planned_class.properties {
+                    if planned_property.term
+                        != default_property_term(&planned_property.predicate, opt.preserve_names)
+                    {
+                        property_renames.insert(
+                            format!(
+                                "{}/{}",
+                                planned_class.collection, planned_property.predicate
+                            ),
+                            planned_property.term.to_owned(),
+                        );
+                    }
+                    predicates.push(planned_property.predicate.to_owned());
+                }
+                mapping
+                    .allow_properties
+                    .insert(planned_class.collection.to_owned(), predicates);
+            }
+            let mut data_context = create_data_context(opt, source_instance);
+            if !mapping.same_as.is_empty() {
+                data_context.insert(
+                    "owl".to_string(),
+                    "http://www.w3.org/2002/07/owl#".to_string(),
+                );
+            }
+            let mut vocab_context = create_vocab_context(opt, source_instance);
+            for (prefix, namespace) in alignment
+                .context()
+                .into_iter()
+                .chain(mapping.vocab_prefixes())
+            {
+                vocab_context.insert(prefix.to_owned(), namespace.to_owned());
+                data_context.insert(prefix, namespace);
+            }
+            data_context.extend(mapping.base_prefixes());
+            Parser {
+                classes: HashMap::new(),
+                properties: HashMap::new(),
+                shacl_shapes: HashMap::new(),
+                vocab_context,
+                data_context,
+                network_name: source_instance.network_name.to_owned(),
+                db_name: source_instance.db_name.to_owned(),
+                mapping,
+                entity_aliases: HashMap::new(),
+                inferred_datatypes: HashMap::new(),
+                tag_concepts: HashMap::new(),
+                identities: HashMap::new(),
+                clean_strings: opt.clean_strings,
+                empty_values: opt.empty_values,
+                empty_sentinel: opt.empty_sentinel.clone(),
+                property_renames,
+                class_renames,
+                preserve_names: opt.preserve_names,
+                dropped_properties: opt.drop_properties.iter().cloned().collect(),
+                plan,
+                ref_collections: HashMap::new(),
+                multi_ref_collections: HashMap::new(),
+                external_refs: opt
+                    .ref_ledgers
+                    .iter()
+                    .map(|(collection, ledger)| {
+                        (
+                            collection.to_owned(),
+                            ref_ledger_base(&source_instance.url, ledger),
+                        )
+                    })
+                    .collect(),
+                upsert_keys: BTreeMap::new(),
+                alignment,
+                id_prefixes: HashMap::new(),
+                collection_indexes: HashMap::new(),
+                shard: opt.shard,
+                shard_by: opt.shard_by,
+                shard_classes: HashSet::new(),
+            }
+        }
+
+        pub fn get_vocab_json(&self, opt: &Opt) -> Map<String, Value> {
+            let classes = self.classes.iter().map(|(orig_class_name, class)| {
+                let mut class_json = serde_json::to_value(class).unwrap();
+                if let Some((iri, AlignMode::Link)) = self.alignment.class(orig_class_name) {
+                    link_aligned_term(&mut class_json, "rdfs:subClassOf", iri);
+                }
+                set_label(&mut class_json, opt);
+                class_json
+            });
+
+            let reversed_terms = self.reversed_terms();
+
+            let properties: Vec<Value> = self
+                .properties
+                .iter()
+                .map(|(property_key, property)| {
+                    let mut property_json = serde_json::to_value(property).unwrap();
+                    if let Some(inverse) = reversed_terms.get(&property.id) {
+                        self.reverse_property_json(&mut property_json, &property.id, inverse);
+                    }
+                    if let Some((iri, AlignMode::Link)) = self.alignment.property(property_key) {
+                        link_aligned_term(&mut property_json, "owl:equivalentProperty", iri);
+                    }
+                    set_label(&mut property_json, opt);
+                    property_json
+                })
+                .collect();
+
+            let class_shacl_shapes: Vec<Value> = self
+                .shacl_shapes
+                .values()
+                .map(|shape| {
+                    let mut shape_json = serde_json::to_value(shape).unwrap();
+                    if let Some(Value::Array(shacl_properties)) = shape_json.get_mut("sh:property")
+                    {
+                        for shacl_property in shacl_properties {
+                            let path = shacl_property["sh:path"]["@id"].as_str().unwrap_or("");
+                            if let Some(inverse) = reversed_terms.get(path) {
+                                shacl_property["sh:path"] =
+                                    json!({ "sh:inversePath": { "@id": inverse } });
+                            }
+                        }
+                    }
+                    shape_json
+                })
+                .collect();
+
+            // the nodes of each kind are sorted, so the same schema always gives the same vocab
+            let by_id = |mut nodes: Vec<Value>| {
+                nodes.sort_by(|a, b| a["@id"].to_string().cmp(&b["@id"].to_string()));
+                nodes
+            };
+            let results = match opt.shacl {
+                true => by_id(classes.collect())
+                    .into_iter()
+                    .chain(by_id(properties))
+                    .chain(by_id(class_shacl_shapes))
+                    .collect(),
+                false => by_id(classes.collect())
+                    .into_iter()
+                    .chain(by_id(properties))
+                    .collect(),
+            };
+
+            let mut vocab_results_map = serde_json::Map::new();
+
+            let ledger_name =
+                opt.target_ledger_name(&format!("{}/{}", self.network_name, self.db_name));
+
+            vocab_results_map.insert("ledger".to_string(), serde_json::json!(ledger_name));
+
+            vocab_results_map.insert(
+                "@context".to_string(),
+                Value::Object(
+                    self.vocab_context
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                        .collect(),
+                ),
+            );
+
+            // the create txn also sets the data @context as the ledger's default context
+            if opt.is_create_ledger {
+                vocab_results_map.insert("f:defaultContext".to_string(), self.data_context_json());
+            }
+
+            vocab_results_map.insert("insert".to_string(), Value::Array(results));
+
+            vocab_results_map
+        }
+
+        /// The data @context, including `@reverse` term definitions for reversed properties
+        pub fn data_context_json(&self) -> Value {
+            let mut context: Map<String, Value> = self
+                .data_context
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect();
+            for (term, inverse) in self.reversed_terms() {
+                context.insert(term, json!({ "@reverse": inverse }));
+            }
+            Value::Object(context)
+        }
+
+        /// Maps the data term of each property declared in the mapping's `reverse` rules to its inverse term
+        fn reversed_terms(&self) -> HashMap<String, String> {
+            self.mapping
+                .reverse
+                .iter()
+                .filter_map(|rule| {
+                    let property = match rule.property.split_once('/') {
+                        Some((orig_class_name, orig_property_name)) => self
+                            .properties
+                            .get(&self.property_key(orig_class_name, orig_property_name)),
+                        None => self.properties.get(rule.predicate()),
+                    }?;
+                    Some((property.id.to_owned(), rule.inverse.to_owned()))
+                })
+                .collect()
+        }
+
+        // the inverse property points from the ref's target class back to the classes that held the v2 predicate
+        fn reverse_property_json(&self, property_json: &mut Value, term: &str, inverse: &str) {
+            let target_classes = self
+                .shacl_shapes
+                .values()
+                .flat_map(|shape| shape.property.iter())
+                .filter(|shacl_property| {
+                    shacl_property.path.get("@id").map(String::as_str) == Some(term)
+                })
+                .filter_map(|shacl_property| shacl_property.class.clone())
+                .collect::<Vec<HashMap<String, String>>>();
+            property_json["rdfs:range"] = property_json["rdfs:domain"].take();
+            property_json["rdfs:domain"] = json!(target_classes);
+            property_json["@id"] = json!(inverse);
+            property_json["rdfs:label"] = json!(remove_namespace(inverse));
+        }
+
+        /// The v3 class name of a v2 collection
+        pub fn class_name(&self, orig_class_name: &str) -> String {
+            class_term(orig_class_name, &self.class_renames, self.preserve_names)
+        }
+
+        pub fn get_or_create_class(&self, orig_class_name: &str) -> Class {
+            let class_name = &self.class_name(orig_class_name);
+            let class_object = self.classes.get(orig_class_name);
+            match class_object {
+                Some(class_object) => class_object.to_owned(),
+                None => Class::new(class_name),
+            }
+        }
+
+        pub fn get_or_create_property(&self, property_name: &str, type_value: &str) -> Property {
+            let property_object = self.properties.get(property_name);
+            match property_object {
+                Some(property_object) => property_object.update_types_and_own(type_value),
+                None => {
+                    let mut property_object = Property::new(property_name, type_value);
+                    // renamed properties keep their term exactly as given, and so do all of them with --preserve-names
+                    let term = match self.preserve_names {
+                        true => Some(property_name.to_string()),
+                        false => None,
+                    };
+                    // a predicate of a collection with a vocab of its own is named with its prefix, unless the
+                    // predicate is renamed in the collection or in all of them
+                    let (renamed, namespaced) = match property_name.split_once('/') {
+                        Some((orig_class_name, orig_property_name)) => (
+                            self.property_renames
+                                .get(property_name)
+                                .or(self.property_renames.get(orig_property_name)),
+                            self.mapping.vocab_prefix(orig_class_name).map(|prefix| {
+                                let term =
+                                    default_property_term(orig_property_name, self.preserve_names);
+                                format!("{}:{}", prefix, term)
+                            }),
+                        ),
+                        None => (self.property_renames.get(property_name), None),
+                    };
+                    if let Some(term) = renamed.cloned().or(namespaced).or(term) {
+                        property_object.label = remove_namespace(&term);
+                        property_object.id = term;
+                    }
+                    property_object
+                }
+            }
+        }
+
+        /// Whether the v2 predicate was excluded from the migration, with `--drop-properties`
+        /// or by being left out of its collection's `allowProperties` in the mapping config
+        pub fn is_dropped_property(&self, orig_class_name: &str, orig_property_name: &str) -> bool {
+            !self
+                .mapping
+                .is_allowed_property(orig_class_name, orig_property_name)
+                || self.dropped_properties.contains(orig_property_name)
+                || self
+                    .dropped_properties
+                    .contains(&format!("{}/{}", orig_class_name, orig_property_name))
+        }
+
+        /// The key that a v2 predicate's property is tracked under in `properties`.
+        /// Predicates are shared across collections by name, unless a collection's predicate is renamed, or the
+        /// collection has a vocab of its own, in which case it is tracked separately under its full
+        /// "collection/predicate" name
+        pub fn property_key(&self, orig_class_name: &str, orig_property_name: &str) -> String {
+            let full_name = format!("{}/{}", orig_class_name, orig_property_name);
+            match self.property_renames.contains_key(&full_name)
+                || self.mapping.vocab_prefix(orig_class_name).is_some()
+            {
+                true => full_name,
+                false => orig_property_name.to_string(),
+            }
+        }
+
+        pub fn get_or_create_shacl_shape(
+            &self,
+            class_name: &str,
+            closed_shapes: bool,
+        ) -> ShaclShape {
+            let shacl_shape = self.shacl_shapes.get(class_name);
+            match shacl_shape {
+                Some(shacl_shape) => shacl_shape.to_owned(),
+                None => ShaclShape::new(class_name, closed_shapes),
+            }
+        }
+
+        /// Scans the fetched entities for the mapping's `mergeEntities` keys and aliases
+        /// every duplicate to one canonical subject. Returns the number of aliased entities
+        pub fn collect_entity_aliases(&mut self, files: &[PathBuf]) -> usize {
+            if self.mapping.merge_entities.is_empty() {
+                return 0;
+            }
+            let mut candidates: HashMap<String, Vec<(usize, i64)>> = HashMap::new();
+            for file in files {
+                let orig_class_name = TempFile::collection_name(file);
+                for entity in TempFile::read(file) {
+                    let id = match entity["_id"].as_i64() {
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    for (merge_key, priority) in self.mapping.merge_keys(&orig_class_name, &entity)
+                    {
+                        candidates
+                            .entry(merge_key)
+                            .or_default()
+                            .push((priority, id));
+                    }
+                }
+            }
+            for mut group in candidates.into_values() {
+                // prefer the entity from the first listed key, then the lowest _id, so reruns are stable
+                group.sort();
+                let ids: Vec<String> = group.into_iter().map(|(_, id)| id.to_string()).collect();
+                alias_group(&mut self.entity_aliases, &ids);
+            }
+            self.entity_aliases.len()
+        }
+
+        /// Removes the refs to the given dangling ids from a transformed entity
+        pub fn drop_references(entity: &mut Value, dangling_ids: &HashSet<String>) {
+            let is_dangling = |value: &Value| match value["@id"].as_str() {
+                Some(id) => dangling_ids.contains(id),
+                None => false,
+            };
+            if let Some(object) = entity.as_object_mut() {
+                object.retain(|key, value| {
+                    if key.starts_with('@') {
+                        return true;
+                    }
+                    match value {
+                        Value::Array(array) => {
+                            array.retain(|value| !is_dangling(value));
+                            !array.is_empty()
+                        }
+                        value => !is_dangling(value),
+                    }
+                });
+            }
+        }
+
+        fn resolve_alias(&self, id: &str) -> String {
+            resolve_alias(&self.entity_aliases, id)
+        }
+
+        fn apply_entity_aliases(&self, value: &mut Value) {
+            match value {
+                Value::Object(object) => {
+                    if let Some(Value::String(id)) = object.get("@id") {
+                        let canonical_id = self.resolve_alias(id);
+                        object.insert("@id".to_string(), Value::String(canonical_id));
+                    }
+                    for (key, value) in object.iter_mut() {
+                        if key != "@id" {
+                            self.apply_entity_aliases(value);
+                        }
+                    }
+                }
+                Value::Array(array) => {
+                    for value in array {
+                        self.apply_entity_aliases(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        /// With `--shard` by classes, deals the classes to the shards in turn, in the order of their collections'
+        /// names, so every shard of a migration picks its classes alike
+        pub fn deal_shard_classes(&mut self) {
+            let (Some(shard), ShardBy::Classes) = (self.shard, self.shard_by) else {
+                return;
+            };
+            let mut collections = self.classes.keys().cloned().collect::<Vec<String>>();
+            collections.sort();
+            self.shard_classes = collections
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| shard.owns(*index as u64))
+                .map(|(_, collection)| collection)
+                .collect();
+        }
+
+        /// Whether the v2 collection is migrated by this run: with `--shard` by classes, only the shard's own
+        pub fn is_shard_class(&self, collection: &str) -> bool {
+            match (self.shard, self.shard_by) {
+                (Some(_), ShardBy::Classes) => self.shard_classes.contains(collection),
+                _ => true,
+            }
+        }
+
+        /// Whether, with `--shard`, the subject (written with its `_id`, or under the prefix of its collection's own
+        /// base) is migrated by another shard, so a ref to it isn't dangling though this shard never migrates it
+        pub fn is_other_shards_subject(&self, id: &str) -> bool {
+            let Some(shard) = self.shard else {
+                return false;
+            };
+            let Some(id) = id.rsplit(':').next().and_then(|id| id.parse::<i64>().ok()) else {
+                return false;
+            };
+            match self.collection_indexes.get(&(id >> SUBJECT_ID_BITS)) {
+                Some(collection) => {
+                    self.classes.contains_key(collection)
+                        && match self.shard_by {
+                            ShardBy::Classes => !self.shard_classes.contains(collection),
+                            ShardBy::Ids => !shard.owns_id(id),
+                        }
+                }
+                None => false,
+            }
+        }
+
+        /// The id a v2 subject is written with: its `_id`, relative to the @base, or under the prefix of its
+        /// collection's own `base`
+        fn subject_id(&self, id: &str) -> String {
+            let prefix = id
+                .parse::<i64>()
+                .ok()
+                .and_then(|id| self.id_prefixes.get(&(id >> SUBJECT_ID_BITS)));
+            match prefix {
+                Some(prefix) => format!("{}:{}", prefix, id),
+                None => id.to_string(),
+            }
+        }
+
+        // writes the ids of the subjects, and of their refs, in collections with a base of their own under its prefix
+        fn apply_id_prefixes(&self, value: &mut Value) {
+            match value {
+                Value::Object(object) => {
+                    if let Some(Value::String(id)) = object.get("@id") {
+                        let id = self.subject_id(id);
+                        object.insert("@id".to_string(), Value::String(id));
+                    }
+                    for (key, value) in object.iter_mut() {
+                        if key != "@id" {
+                            self.apply_id_prefixes(value);
+                        }
+                    }
+                }
+                Value::Array(array) => {
+                    for value in array {
+                        self.apply_id_prefixes(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        /// The specs of a v2 predicate that aren't recognizable as SHACL constraints
+        pub fn unsupported_specs(
+            &self,
+            orig_class_name: &str,
+            orig_property_name: &str,
+            item: &Value,
+        ) -> Vec<UnsupportedFeature> {
+            spec_functions(&item["spec"])
+                .iter()
+                .filter(|spec| spec["code"].as_str().and_then(spec_constraints).is_none())
+                .map(|spec| UnsupportedFeature {
+                    feature: "predicateSpec".to_string(),
+                    class: orig_class_name.to_string(),
+                    property: Some(orig_property_name.to_string()),
+                    name: spec["name"].as_str().unwrap_or_default().to_string(),
+                    code: spec["code"].as_str().map(str::to_string),
+                })
+                .collect()
+        }
+
+        /// The migrated properties of each class whose v2 predicate sets a boolean `flag` (e.g. `fullText`),
+        /// as class => [(property, v2 predicate)]
+        pub fn flagged_properties(
+            &self,
+            predicates: &[Value],
+            flag: &str,
+        ) -> BTreeMap<String, Vec<(String, String)>> {
+            let mut flagged: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+            for item in predicates {
+                if item[flag].as_bool() != Some(true) {
+                    continue;
+                }
+                let (orig_class_name, orig_property_name) = parse_for_class_and_property_name(item);
+                if self.is_dropped_property(&orig_class_name, &orig_property_name) {
+                    continue;
+                }
+                let property_key = self.property_key(&orig_class_name, &orig_property_name);
+                let (Some(class), Some(property)) = (
+                    self.classes.get(&orig_class_name),
+                    self.properties.get(&property_key),
+                ) else {
+                    continue;
+                };
+                flagged.entry(class.id.to_owned()).or_default().push((
+                    property.id.to_owned(),
+                    format!("{}/{}", orig_class_name, orig_property_name),
+                ));
+            }
+            flagged
+        }
+
+        /// Annotates the vocab's properties of v2 predicates marked `index` with `fm:indexed`, given the
+        /// `flagged_properties` of the `index` flag
+        pub fn set_indexed_properties(
+            &mut self,
+            indexed: &BTreeMap<String, Vec<(String, String)>>,
+        ) {
+            let indexed_properties = indexed
+                .values()
+                .flatten()
+                .map(|(property, _)| property.to_owned())
+                .collect::<HashSet<String>>();
+            if indexed_properties.is_empty() {
+                return;
+            }
+            for property in self.properties.values_mut() {
+                if indexed_properties.contains(&property.id) {
+                    property.indexed = Some(true);
+                }
+            }
+            self.vocab_context
+                .insert("fm".to_string(), MIGRATE_NAMESPACE.to_string());
+        }
+
+        /// Records a v2 predicate marked `upsert` as a natural key of its collection: entities sharing its value
+        /// are merged into one subject, like the mapping's `mergeEntities` rules
+        pub fn set_upsert_key(
+            &mut self,
+            orig_class_name: &str,
+            orig_property_name: &str,
+            item: &Value,
+        ) {
+            if item["upsert"].as_bool() != Some(true) {
+                return;
+            }
+            self.upsert_keys
+                .entry(orig_class_name.to_string())
+                .or_default()
+                .push(orig_property_name.to_string());
+            self.mapping.merge_entities.push(MergeRule {
+                keys: vec![MergeKey {
+                    class: orig_class_name.to_string(),
+                    property: orig_property_name.to_string(),
+                }],
+            });
+        }
+
+        /// The migrated classes with `upsert` keys, whose data is written as upserts
+        pub fn upsert_classes(&self) -> HashSet<String> {
+            self.upsert_keys
+                .keys()
+                .filter_map(|orig_class_name| self.classes.get(orig_class_name))
+                .map(|class| class.id.to_owned())
+                .collect()
+        }
+
+        /// Records the collection a v2 ref predicate is restricted to, so refs into collections
+        /// migrated into other ledgers can be told apart. Refs restricted to several collections are
+        /// recorded apart, in `multi_ref_collections`
+        pub fn set_ref_collection(&mut self, orig_property_name: &str, item: &Value) {
+            match restrict_collections(item).as_slice() {
+                [] => {}
+                [collection] => {
+                    self.ref_collections
+                        .insert(orig_property_name.to_string(), collection.to_string());
+                }
+                collections => {
+                    self.multi_ref_collections
+                        .insert(orig_property_name.to_string(), collections.to_vec());
+                }
+            }
+        }
+
+        /// Loosens the `sh:or` of classes on refs restricted to several collections to `sh:nodeKind sh:IRI` when
+        /// one of the collections isn't migrated into this ledger (it's left out, or given with --ref-ledger),
+        /// since its subjects can't satisfy an `sh:class` here. Returns a warning for each loosened ref
+        pub fn apply_multi_class_refs(&mut self) -> Vec<String> {
+            let mut warnings = vec![];
+            let mut loosened_properties = HashSet::new();
+            let mut multi_ref_collections = self.multi_ref_collections.iter().collect::<Vec<_>>();
+            multi_ref_collections.sort();
+            for (property_key, collections) in multi_ref_collections {
+                let Some(property) = self.properties.get(property_key) else {
+                    continue;
+                };
+                let unmigrated = collections
+                    .iter()
+                    .filter(|collection| {
+                        !self.classes.contains_key(*collection)
+                            || self.external_refs.contains_key(*collection)
+                    })
+                    .map(String::as_str)
+                    .collect::<Vec<&str>>();
+                if unmigrated.is_empty() {
+                    continue;
+                }
+                warnings.push(format!(
+                    "Property, \"{}\", refs the collections [{}], of which [{}] aren't migrated into this ledger, so its refs are only required to be IRIs (sh:nodeKind sh:IRI)",
+                    property.id,
+                    collections.join(", "),
+                    unmigrated.join(", ")
+                ));
+                loosened_properties.insert(property.id.to_owned());
+            }
+            for shacl_shape in self.shacl_shapes.values_mut() {
+                for shacl_property in shacl_shape.property.iter_mut() {
+                    let is_loosened = shacl_property
+                        .path
+                        .get("@id")
+                        .is_some_and(|path| loosened_properties.contains(path));
+                    if is_loosened && shacl_property.or.is_some() {
+                        shacl_property.or = None;
+                        shacl_property.node_kind = "sh:IRI".to_string();
+                    }
+                }
+            }
+            warnings
+        }
+
+        /// The base IRI of the other ledger holding the entities a v2 predicate refers to, if it's one
+        /// given with --ref-ledger
+        pub fn external_ref_base(
+            &self,
+            orig_class_name: &str,
+            orig_property_name: &str,
+        ) -> Option<&String> {
+            self.ref_collections
+                .get(&self.property_key(orig_class_name, orig_property_name))
+                .and_then(|collection| self.external_refs.get(collection))
+        }
+
+        /// Loosens the shapes of refs into collections migrated into other ledgers from `sh:class` to `sh:nodeKind sh:IRI`,
+        /// since their subjects (and types) are only in the other ledger
+        pub fn apply_external_refs(&mut self) {
+            let external_properties = self
+                .ref_collections
+                .iter()
+                .filter(|(_, collection)| self.external_refs.contains_key(*collection))
+                .filter_map(|(property_key, _)| self.properties.get(property_key))
+                .map(|property| property.id.to_owned())
+                .collect::<HashSet<String>>();
+            for shacl_shape in self.shacl_shapes.values_mut() {
+                for shacl_property in shacl_shape.property.iter_mut() {
+                    let is_external = shacl_property
+                        .path
+                        .get("@id")
+                        .is_some_and(|path| external_properties.contains(path));
+                    if is_external && shacl_property.class.is_some() {
+                        shacl_property.class = None;
+                        shacl_property.node_kind = "sh:IRI".to_string();
+                    }
+                }
+            }
+        }
+
+        // turns refs into fully-qualified IRIs under the base IRI of the other ledger; the ref's type
+        // is left to that ledger
+        fn qualify_refs(value: &Value, base: &str) -> Value {
+            match value {
+                Value::Object(object) => match object.get("@id").and_then(Value::as_str) {
+                    Some(id) => json!({ "@id": format!("{}{}", base, id) }),
+                    None => value.to_owned(),
+                },
+                Value::Array(array) => Value::Array(
+                    array
+                        .iter()
+                        .map(|value| Self::qualify_refs(value, base))
+                        .collect(),
+                ),
+                value => value.to_owned(),
+            }
+        }
+
+        /// Transforms a single v2 entity of the given collection into a v3 JSON-LD subject
+        pub fn transform_entity(&self, orig_class_name: &str, result: &Value) -> Value {
+            let mut parsed_result: HashMap<String, Value> = HashMap::new();
+            let string_id: String = result["_id"].to_string();
+            parsed_result.insert("@id".to_string(), json!(string_id));
+
+            let class_name = match self.classes.get(orig_class_name) {
+                Some(class) => class.id.to_owned(),
+                None => panic!("Could not find class {}", orig_class_name),
+            };
+
+            parsed_result.insert("@type".to_string(), json!(&class_name));
+            for (orig_property_name, value) in result.as_object().unwrap() {
+                if self.is_dropped_property(orig_class_name, orig_property_name) {
+                    continue;
+                }
+                let property_key = self.property_key(orig_class_name, orig_property_name);
+                if let Some(canonical_property) = self.properties.get(&property_key) {
+                    let key = canonical_property.id.to_owned();
+                    let shacl_shape = self.shacl_shapes.get(&class_name).unwrap();
+                    let shacl_properties = &shacl_shape.property;
+                    let shacl_datatype = shacl_properties
+                        .iter()
+                        .find(|&x| x.path.get("@id") == Some(&key))
+                        .and_then(|x| x.datatype.as_ref())
+                        .and_then(|datatype| datatype.get("@id"))
+                        .map(String::as_str);
+                    let value = match shacl_datatype {
+                        Some("xsd:dateTime") => map_instants(value, instant_to_iso_string),
+                        Some("xsd:date") => map_instants(value, instant_to_iso_date),
+                        _ => value.to_owned(),
+                    };
+                    let ref_type = shacl_properties
+                        .iter()
+                        .find(|&x| {
+                            let shacl_path = x.path.get("@id").unwrap();
+                            let shacl_class = x.class.is_some();
+                            (shacl_path == &key) && shacl_class
+                        })
+                        .map(|x| x.class.clone().unwrap().get("@id").unwrap().to_string());
+                    let value = nfc_strings(&represent_fluree_value(&value, ref_type));
+                    let value = match self.external_ref_base(orig_class_name, orig_property_name) {
+                        Some(base) => Self::qualify_refs(&value, base),
+                        None => value,
+                    };
+                    let value = match self
+                        .inferred_datatypes
+                        .get(&class_name)
+                        .and_then(|datatypes| datatypes.get(&key))
+                    {
+                        // strings and booleans are already typed by their JSON representation
+                        Some(datatype)
+                            if datatype == "xsd:dateTime" || datatype == "xsd:decimal" =>
+                        {
+                            typed_literal(&value, datatype)
+                        }
+                        _ => value,
+                    };
+                    let value = match canonical_property.untyped_v2_types.contains("tag") {
+                        true => self.tag_concept_refs(
+                            &format!("{}/{}", orig_class_name, orig_property_name),
+                            &value,
+                        ),
+                        false => value,
+                    };
+                    let value = match self.ref_collections.get(&property_key) {
+                        Some(collection) if ["_user", "_auth"].contains(&collection.as_str()) => {
+                            self.identity_refs(&value)
+                        }
+                        _ => value,
+                    };
+                    let value = match self.clean_strings {
+                        true => clean_strings(&value),
+                        false => value,
+                    };
+                    let value = match self.empty_values {
+                        EmptyValues::Keep => value,
+                        EmptyValues::Drop => match scrub_empty_values(value, None) {
+                            Some(value) => value,
+                            None => continue,
+                        },
+                        EmptyValues::Sentinel => {
+                            let sentinel = json!(self.empty_sentinel.clone().unwrap_or_default());
+                            match scrub_empty_values(value, Some(&sentinel)) {
+                                Some(value) => value,
+                                None => continue,
+                            }
+                        }
+                    };
+                    parsed_result.insert(key, value);
+                }
+            }
+
+            let same_as = self.mapping.same_as_iris(orig_class_name, result);
+            match same_as.len() {
+                0 => {}
+                1 => {
+                    parsed_result.insert("owl:sameAs".to_string(), json!({"@id": same_as[0]}));
+                }
+                _ => {
+                    let same_as = same_as
+                        .iter()
+                        .map(|iri| json!({ "@id": iri }))
+                        .collect::<Vec<Value>>();
+                    parsed_result.insert("owl:sameAs".to_string(), Value::Array(same_as));
+                }
+            }
+
+            let mut parsed_result = json!(parsed_result);
+            if !self.entity_aliases.is_empty() {
+                self.apply_entity_aliases(&mut parsed_result);
+            }
+            if !self.id_prefixes.is_empty() {
+                self.apply_id_prefixes(&mut parsed_result);
+            }
+            parsed_result
+        }
+
+        /// Sets `sh:maxCount` on the class's property shapes from the most values any sampled entity holds
+        pub fn infer_cardinality(
+            &mut self,
+            orig_class_name: &str,
+            samples: &[Value],
+        ) -> Vec<CardinalityChange> {
+            let mut changes = vec![];
+            let class_name = match self.classes.get(orig_class_name) {
+                Some(class) => class.id.to_owned(),
+                None => return changes,
+            };
+            let mut observed_max_values: HashMap<String, usize> = HashMap::new();
+            for sample in samples {
+                for (key, value) in sample.as_object().into_iter().flatten() {
+                    let property = match self
+                        .properties
+                        .get(&self.property_key(orig_class_name, key))
+                    {
+                        Some(property) => property,
+                        None => continue,
+                    };
+                    let value_count = match value {
+                        Value::Array(values) => values.len(),
+                        Value::Null => 0,
+                        _ => 1,
+                    };
+                    let max_values = observed_max_values
+                        .entry(property.id.to_owned())
+                        .or_default();
+                    *max_values = (*max_values).max(value_count);
+                }
+            }
+            let shacl_shape = match self.shacl_shapes.get_mut(&class_name) {
+                Some(shacl_shape) => shacl_shape,
+                None => return changes,
+            };
+            for shacl_property in shacl_shape.property.iter_mut() {
+                let path = shacl_property.path.get("@id").cloned().unwrap_or_default();
+                let observed = match observed_max_values.get(&path) {
+                    Some(observed) if *observed > 0 => *observed,
+                    _ => continue,
+                };
+                let inferred_max_count = match observed {
+                    1 => Some(1),
+                    _ => None,
+                };
+                if inferred_max_count != shacl_property.max_count {
+                    changes.push(CardinalityChange {
+                        class: class_name.to_owned(),
+                        property: path,
+                        declared_max_count: shacl_property.max_count,
+                        inferred_max_count,
+                        observed_max_values: observed,
+                        sampled_entities: samples.len(),
+                    });
+                    shacl_property.max_count = inferred_max_count;
+                }
+            }
+            changes
+        }
+
+        /// Infers a datatype for each of the class's properties whose v2 type has no xsd equivalent,
+        /// applying it to the class's property shape and to the migrated values
+        pub fn infer_datatypes(
+            &mut self,
+            orig_class_name: &str,
+            samples: &[Value],
+        ) -> Vec<DatatypeInference> {
+            let mut inferences = vec![];
+            let class_name = match self.classes.get(orig_class_name) {
+                Some(class) => class.id.to_owned(),
+                None => return inferences,
+            };
+            let mut sampled_values: HashMap<String, Vec<&Value>> = HashMap::new();
+            for sample in samples {
+                for (key, value) in sample.as_object().into_iter().flatten() {
+                    let key = self.property_key(orig_class_name, key);
+                    match self.properties.get(&key) {
+                        Some(property) if !property.untyped_v2_types.is_empty() => {}
+                        _ => continue,
+                    };
+                    let values = sampled_values.entry(key).or_default();
+                    match value {
+                        Value::Array(array) => values.extend(array.iter()),
+                        Value::Null => {}
+                        value => values.push(value),
+                    }
+                }
+            }
+            let mut keys = sampled_values.keys().cloned().collect::<Vec<String>>();
+            keys.sort();
+            for key in keys {
+                let values = &sampled_values[&key];
+                let datatype = match infer_datatype(values) {
+                    Some(datatype) => datatype,
+                    None => continue,
+                };
+                let property = &self.properties[&key];
+                let mut v2_types = property
+                    .untyped_v2_types
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<String>>();
+                v2_types.sort();
+                inferences.push(DatatypeInference {
+                    class: class_name.to_owned(),
+                    property: property.id.to_owned(),
+                    v2_types,
+                    datatype: datatype.to_owned(),
+                    sampled_values: values.len(),
+                });
+                if let Some(shacl_shape) = self.shacl_shapes.get_mut(&class_name) {
+                    for shacl_property in shacl_shape.property.iter_mut() {
+                        if shacl_property.path.get("@id") == Some(&property.id)
+                            && shacl_property.datatype.is_none()
+                        {
+                            shacl_property.datatype =
+                                Some(HashMap::from([("@id".to_string(), datatype.to_owned())]));
+                        }
+                    }
+                }
+                self.inferred_datatypes
+                    .entry(class_name.to_owned())
+                    .or_default()
+                    .insert(property.id.to_owned(), datatype);
+            }
+            if !inferences.is_empty() {
+                self.data_context.insert(
+                    "xsd".to_string(),
+                    "http://www.w3.org/2001/XMLSchema#".to_string(),
+                );
+            }
+            inferences
+        }
+
+        /// Switches the class's instant properties whose names match one of the given glob patterns
+        /// (e.g. "*Date" or "person/birthday") to xsd:date
+        pub fn apply_date_patterns(
+            &mut self,
+            orig_class_name: &str,
+            patterns: &[String],
+        ) -> Vec<DatatypeInference> {
+            let instant_properties = self.instant_properties(orig_class_name);
+            let mut inferences = vec![];
+            for (orig_property_name, property_id) in instant_properties {
+                let full_name = format!("{}/{}", orig_class_name, orig_property_name);
+                let is_match = patterns.iter().any(|pattern| {
+                    glob_match(pattern, &full_name) || glob_match(pattern, &orig_property_name)
+                });
+                if is_match {
+                    inferences.extend(self.set_date_property(orig_class_name, &property_id, 0));
+                }
+            }
+            inferences
+        }
+
+        /// Switches the class's instant properties whose sampled values are all at midnight UTC to xsd:date
+        pub fn detect_dates(
+            &mut self,
+            orig_class_name: &str,
+            samples: &[Value],
+        ) -> Vec<DatatypeInference> {
+            let instant_properties = self.instant_properties(orig_class_name);
+            let mut inferences = vec![];
+            for (orig_property_name, property_id) in instant_properties {
+                let values = samples
+                    .iter()
+                    .flat_map(|sample| match &sample[&orig_property_name] {
+                        Value::Array(values) => values.iter().collect::<Vec<&Value>>(),
+                        Value::Null => vec![],
+                        value => vec![value],
+                    })
+                    .collect::<Vec<&Value>>();
+                let is_midnight = |value: &&Value| match value.as_i64() {
+                    Some(epoch) => epoch.rem_euclid(86_400_000) == 0,
+                    None => false,
+                };
+                if !values.is_empty() && values.iter().all(is_midnight) {
+                    inferences.extend(self.set_date_property(
+                        orig_class_name,
+                        &property_id,
+                        values.len(),
+                    ));
+                }
+            }
+            inferences
+        }
+
+        // the (v2 predicate name, property id) of the class's properties that hold v2 instants
+        fn instant_properties(&self, orig_class_name: &str) -> Vec<(String, String)> {
+            let class_name = match self.classes.get(orig_class_name) {
+                Some(class) => &class.id,
+                None => return vec![],
+            };
+            let shacl_shape = match self.shacl_shapes.get(class_name) {
+                Some(shacl_shape) => shacl_shape,
+                None => return vec![],
+            };
+            let mut instant_properties = self
+                .properties
+                .iter()
+                .filter(|(_, property)| property.data_types.contains("xsd:dateTime"))
+                .filter(|(_, property)| {
+                    shacl_shape
+                        .property
+                        .iter()
+                        .any(|x| x.path.get("@id") == Some(&property.id))
+                })
+                .map(|(property_key, property)| {
+                    // renamed properties are keyed by "collection/predicate"
+                    let orig_property_name =
+                        property_key.rsplit('/').next().unwrap_or(property_key);
+                    (orig_property_name.to_owned(), property.id.to_owned())
+                })
+                .collect::<Vec<(String, String)>>();
+            instant_properties.sort();
+            instant_properties
+        }
+
+        fn set_date_property(
+            &mut self,
+            orig_class_name: &str,
+            property_id: &str,
+            sampled_values: usize,
+        ) -> Option<DatatypeInference> {
+            let class_name = self.classes.get(orig_class_name)?.id.to_owned();
+            let shacl_shape = self.shacl_shapes.get_mut(&class_name)?;
+            let shacl_property = shacl_shape
+                .property
+                .iter_mut()
+                .find(|x| x.path.get("@id").map(String::as_str) == Some(property_id))?;
+            let datatype = shacl_property.datatype.as_mut()?;
+            if datatype.get("@id").map(String::as_str) != Some("xsd:dateTime") {
+                return None;
+            }
+            datatype.insert("@id".to_string(), "xsd:date".to_string());
+            Some(DatatypeInference {
+                class: class_name,
+                property: property_id.to_string(),
+                v2_types: vec!["instant".to_string()],
+                datatype: "xsd:date".to_string(),
+                sampled_values,
+            })
+        }
+
+        /// Types the values of floating point v2 properties as xsd:decimal, in the shapes and the migrated data.
+        /// Returns the ids of the affected properties
+        pub fn preserve_decimals(&mut self) -> Vec<String> {
+            let decimal_properties = self
+                .properties
+                .values()
+                .filter(|property| {
+                    property.data_types.contains("xsd:float")
+                        || property.untyped_v2_types.contains("double")
+                        || property.untyped_v2_types.contains("bigdec")
+                })
+                .map(|property| property.id.to_owned())
+                .collect::<HashSet<String>>();
+            for shacl_shape in self.shacl_shapes.values_mut() {
+                let class_name = shacl_shape.target_class["@id"].to_owned();
+                for shacl_property in shacl_shape.property.iter_mut() {
+                    let path = shacl_property.path["@id"].to_owned();
+                    if !decimal_properties.contains(&path) {
+                        continue;
+                    }
+                    shacl_property.datatype = Some(HashMap::from([(
+                        "@id".to_string(),
+                        "xsd:decimal".to_string(),
+                    )]));
+                    self.inferred_datatypes
+                        .entry(class_name.to_owned())
+                        .or_default()
+                        .insert(path, "xsd:decimal".to_string());
+                }
+            }
+            if !decimal_properties.is_empty() {
+                self.data_context.insert(
+                    "xsd".to_string(),
+                    "http://www.w3.org/2001/XMLSchema#".to_string(),
+                );
+            }
+            let mut decimal_properties = decimal_properties.into_iter().collect::<Vec<String>>();
+            decimal_properties.sort();
+            decimal_properties
+        }
+
+        /// Describes the parsed schema as a migration plan, with the entity count of each collection
+        /// Derives a plan from a previously generated (and possibly hand-edited) vocab, for `--vocab-file`.
+        /// Each v2 collection and predicate is matched with the vocab class or property whose @id or rdfs:label
+        /// is its default term (or its v2 name), and the vocab's SHACL shapes supply the datatypes and max counts.
+        /// Collections and predicates without a match aren't migrated, and are listed in the plan's warnings
+        pub fn vocab_plan(
+            vocab: &Value,
+            predicates: &[Value],
+            source: &str,
+            ledger: &str,
+        ) -> MigrationPlan {
+            let nodes = match vocab {
+                Value::Array(nodes) => nodes.as_slice(),
+                vocab => vocab
+                    .get("insert")
+                    .or(vocab.get("@graph"))
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+            };
+            let ids = |value: &Value| -> Vec<String> {
+                match value {
+                    Value::Array(values) => values
+                        .iter()
+                        .filter_map(|value| value["@id"].as_str().or(value.as_str()))
+                        .map(str::to_string)
+                        .collect(),
+                    value => value["@id"]
+                        .as_str()
+                        .or(value.as_str())
+                        .map(|id| vec![id.to_string()])
+                        .unwrap_or_default(),
+                }
+            };
+            let of_type = |type_: &str| {
+                nodes
+                    .iter()
+                    .filter(|node| ids(&node["@type"]).iter().any(|id| id == type_))
+                    .collect::<Vec<&Value>>()
+            };
+            let (class_nodes, property_nodes, shape_nodes) = (
+                of_type("rdfs:Class"),
+                of_type("rdf:Property"),
+                of_type("sh:NodeShape"),
+            );
+            let is_named = |node: &Value, term: &str, orig_name: &str| {
+                // a label may be a language-tagged string
+                let label = node["rdfs:label"]
+                    .as_str()
+                    .or(node["rdfs:label"]["@value"].as_str());
+                node["@id"].as_str() == Some(term)
+                    || label == Some(term)
+                    || label == Some(orig_name)
+            };
+
+            let mut collections: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+            for item in predicates {
+                let (orig_class_name, orig_property_name) = parse_for_class_and_property_name(item);
+                let type_value = item["type"].as_str().unwrap_or_default().to_string();
+                collections
+                    .entry(orig_class_name)
+                    .or_default()
+                    .push((orig_property_name, type_value));
+            }
+
+            let mut warnings = vec![];
+            let mut classes = vec![];
+            for (orig_class_name, orig_properties) in collections {
+                let default_class = standardize_class_name(&orig_class_name);
+                let class_node = match class_nodes
+                    .iter()
+                    .find(|node| is_named(node, &default_class, &orig_class_name))
+                {
+                    Some(class_node) => class_node,
+                    None => {
+                        warnings.push(format!(
+                            "Collection, \"{}\", is not in the vocab, and is not migrated",
+                            orig_class_name
+                        ));
+                        continue;
+                    }
+                };
+                let class_name = class_node["@id"].as_str().unwrap_or(&default_class);
+                let class_range = ids(&class_node["rdfs:range"]);
+                let shacl_properties = shape_nodes
+                    .iter()
+                    .find(|node| {
+                        ids(&node["sh:targetClass"])
+                            .iter()
+                            .any(|id| id == class_name)
+                    })
+                    .and_then(|node| node["sh:property"].as_array())
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let mut properties = vec![];
+                for (orig_property_name, type_value) in orig_properties {
+                    let default_term = standardize_property_name(&orig_property_name);
+                    let candidates = property_nodes
+                        .iter()
+                        .filter(|node| is_named(node, &default_term, &orig_property_name))
+                        .collect::<Vec<&&Value>>();
+                    // a term shared by several classes is told apart by the class's range or the property's domain
+                    let property_node = candidates
+                        .iter()
+                        .find(|node| {
+                            let id = node["@id"].as_str().unwrap_or_default();
+                            class_range.iter().any(|range| range == id)
+                                || ids(&node["rdfs:domain"])
+                                    .iter()
+                                    .any(|domain| domain == class_name)
+                        })
+                        .or(candidates.first());
+                    let term = match property_node.and_then(|node| node["@id"].as_str()) {
+                        Some(term) => term.to_string(),
+                        None => {
+                            warnings.push(format!(
+                                "Predicate, \"{}/{}\", is not in the vocab, and is not migrated",
+                                orig_class_name, orig_property_name
+                            ));
+                            continue;
+                        }
+                    };
+                    let shacl_property = shacl_properties
+                        .iter()
+                        .find(|x| ids(&x["sh:path"]).first() == Some(&term));
+                    let source_types = match Property::normalize_type_value(&type_value) {
+                        Some(data_type) => vec![data_type],
+                        None if type_value != "ref" => vec![type_value],
+                        None => vec![],
+                    };
+                    properties.push(PlannedProperty {
+                        predicate: orig_property_name,
+                        term,
+                        source_types,
+                        datatype: shacl_property
+                            .and_then(|x| ids(&x["sh:datatype"]).first().cloned()),
+                        ref_class: shacl_property
+                            .and_then(|x| ids(&x["sh:class"]).first().cloned()),
+                        max_count: shacl_property
+                            .and_then(|x| x["sh:maxCount"].as_u64())
+                            .map(|max_count| max_count as u32),
+                    });
+                }
+                classes.push(PlannedClass {
+                    collection: orig_class_name,
+                    class: class_name.to_string(),
+                    entity_count: None,
+                    properties,
+                });
+            }
+            MigrationPlan {
+                source: source.to_string(),
+                ledger: ledger.to_string(),
+                chunk_size: DATA_CHUNK_SIZE,
+                classes,
+                warnings,
+            }
+        }
+
+        /// Adds the prefixes of a vocab's @context (e.g. ones used in hand-edited class names) to the data @context
+        pub fn add_vocab_prefixes(&mut self, vocab: &Value) {
+            let Some(context) = vocab["@context"].as_object() else {
+                return;
+            };
+            for (prefix, iri) in context {
+                if let (false, Some(iri)) = (prefix.starts_with('@'), iri.as_str()) {
+                    self.data_context
+                        .entry(prefix.to_owned())
+                        .or_insert(iri.to_string());
+                }
+            }
+        }
+
+        pub fn plan(
+            &self,
+            source: &str,
+            ledger: &str,
+            chunk_size: u64,
+            entity_counts: &HashMap<String, usize>,
+            warnings: Vec<String>,
+        ) -> MigrationPlan {
+            let mut orig_class_names = self.classes.keys().cloned().collect::<Vec<String>>();
+            orig_class_names.sort();
+            let classes = orig_class_names
+                .iter()
+                .map(|orig_class_name| {
+                    let class_name = self.class_name(orig_class_name);
+                    let shacl_properties = self
+                        .shacl_shapes
+                        .get(&class_name)
+                        .map(|shacl_shape| shacl_shape.property.as_slice())
+                        .unwrap_or_default();
+                    let mut properties = self
+                        .properties
+                        .iter()
+                        .filter(|(_, property)| {
+                            property
+                                .domain
+                                .iter()
+                                .any(|domain| domain["@id"] == class_name)
+                        })
+                        .map(|(property_key, property)| {
+                            let shacl_property = shacl_properties
+                                .iter()
+                                .find(|x| x.path.get("@id") == Some(&property.id));
+                            let mut source_types = property
+                                .data_types
+                                .iter()
+                                .chain(property.untyped_v2_types.iter())
+                                .cloned()
+                                .collect::<Vec<String>>();
+                            source_types.sort();
+                            PlannedProperty {
+                                predicate: property_key
+                                    .rsplit('/')
+                                    .next()
+                                    .unwrap_or(property_key)
+                                    .to_string(),
+                                term: property.id.to_owned(),
+                                source_types,
+                                datatype: shacl_property
+                                    .and_then(|x| x.datatype.as_ref())
+                                    .and_then(|datatype| datatype.get("@id").cloned()),
+                                ref_class: shacl_property
+                                    .and_then(|x| x.class.as_ref())
+                                    .and_then(|class| class.get("@id").cloned()),
+                                max_count: shacl_property.and_then(|x| x.max_count),
+                            }
+                        })
+                        .collect::<Vec<PlannedProperty>>();
+                    properties.sort_by(|a, b| a.predicate.cmp(&b.predicate));
+                    PlannedClass {
+                        collection: orig_class_name.to_owned(),
+                        class: class_name,
+                        entity_count: entity_counts.get(orig_class_name).copied(),
+                        properties,
+                    }
+                })
+                .collect();
+            MigrationPlan {
+                source: source.to_string(),
+                ledger: ledger.to_string(),
+                chunk_size,
+                classes,
+                warnings,
+            }
+        }
+
+        /// Counts, for each migrated collection, how its v2 predicates (the schema query's results) map to the
+        /// properties of its class: how many are migrated, refs, of conflicting datatypes, dropped, or renamed
+        pub fn schema_statistics(&self, json_results: &[Value]) -> Vec<ClassStatistics> {
+            let mut statistics = BTreeMap::<String, ClassStatistics>::new();
+            for item in json_results {
+                let (orig_class_name, orig_property_name) = parse_for_class_and_property_name(item);
+                if !self.classes.contains_key(&orig_class_name) {
+                    continue;
+                }
+                let class_name = self.class_name(&orig_class_name);
+                let class_statistics =
+                    statistics
+                        .entry(orig_class_name.to_owned())
+                        .or_insert_with(|| ClassStatistics {
+                            collection: orig_class_name.to_owned(),
+                            class: class_name.to_owned(),
+                            renamed: class_name
+                                != class_term(
+                                    &orig_class_name,
+                                    &HashMap::new(),
+                                    self.preserve_names,
+                                ),
+                            predicates: 0,
+                            properties: 0,
+                            refs: 0,
+                            datatype_conflicts: 0,
+                            dropped: 0,
+                            renamed_properties: 0,
+                        });
+                class_statistics.predicates += 1;
+                let property = self
+                    .properties
+                    .get(&self.property_key(&orig_class_name, &orig_property_name))
+                    .filter(|property| {
+                        !self.is_dropped_property(&orig_class_name, &orig_property_name)
+                            && property
+                                .domain
+                                .iter()
+                                .any(|domain| domain["@id"] == class_name)
+                    });
+                let Some(property) = property else {
+                    class_statistics.dropped += 1;
+                    continue;
+                };
+                class_statistics.properties += 1;
+                if item["type"] == "ref" {
+                    class_statistics.refs += 1;
+                }
+                if property.data_types.len() > 1 {
+                    class_statistics.datatype_conflicts += 1;
+                }
+                if property.id != default_property_term(&orig_property_name, self.preserve_names) {
+                    class_statistics.renamed_properties += 1;
+                }
+            }
+            statistics.into_values().collect()
+        }
 
-    use super::opt::Opt;
+        /// Applies a `migrate --plan` plan to the parsed schema: collections left out of the plan aren't migrated,
+        /// and the plan's datatypes and max counts replace the ones derived from the v2 schema.
+        /// Returns warnings for planned collections that aren't in the source
+        pub fn apply_plan(&mut self) -> Vec<String> {
+            let plan = match self.plan.clone() {
+                Some(plan) => plan,
+                None => return vec![],
+            };
+            let mut warnings = vec![];
+            for planned_class in &plan.classes {
+                if !self.classes.contains_key(&planned_class.collection) {
+                    warnings.push(format!(
+                        "Planned collection, \"{}\", is not in the source schema",
+                        planned_class.collection
+                    ));
+                }
+            }
 
-    pub struct Parser {
-        pub classes: HashMap<String, Class>,
-        pub properties: HashMap<String, Property>,
-        pub shacl_shapes: HashMap<String, ShaclShape>,
-        pub vocab_context: HashMap<String, String>,
-        pub data_context: HashMap<String, String>,
-        pub network_name: String,
-        pub db_name: String,
-    }
+            let excluded = self
+                .classes
+                .iter()
+                .filter(|(orig_class_name, _)| plan.class(orig_class_name).is_none())
+                .map(|(orig_class_name, class)| (orig_class_name.to_owned(), class.id.to_owned()))
+                .collect::<Vec<(String, String)>>();
+            for (orig_class_name, class_name) in &excluded {
+                self.classes.remove(orig_class_name);
+                self.shacl_shapes.remove(class_name);
+                for property in self.properties.values_mut() {
+                    property
+                        .domain
+                        .retain(|domain| &domain["@id"] != class_name);
+                }
+            }
+            self.properties
+                .retain(|_, property| !property.domain.is_empty());
 
-    impl Parser {
-        pub fn new(opt: &Opt, source_instance: &FlureeInstance) -> Self {
-            Parser {
-                classes: HashMap::new(),
-                properties: HashMap::new(),
-                shacl_shapes: HashMap::new(),
-                vocab_context: create_vocab_context(opt, source_instance),
-                data_context: create_data_context(opt, source_instance),
-                network_name: source_instance.network_name.to_owned(),
-                db_name: source_instance.db_name.to_owned(),
+            for planned_class in &plan.classes {
+                let shacl_shape = match self.shacl_shapes.get_mut(&planned_class.class) {
+                    Some(shacl_shape) => shacl_shape,
+                    None => continue,
+                };
+                for planned_property in &planned_class.properties {
+                    let shacl_property = match shacl_shape
+                        .property
+                        .iter_mut()
+                        .find(|x| x.path.get("@id") == Some(&planned_property.term))
+                    {
+                        Some(shacl_property) => shacl_property,
+                        None => continue,
+                    };
+                    shacl_property.max_count = planned_property.max_count;
+                    let datatype = match &planned_property.datatype {
+                        Some(datatype) => datatype,
+                        None => {
+                            shacl_property.datatype = None;
+                            continue;
+                        }
+                    };
+                    shacl_property.datatype =
+                        Some(HashMap::from([("@id".to_string(), datatype.to_owned())]));
+                    // v2 instants are converted by their shape's datatype, other values are typed explicitly
+                    if !planned_property
+                        .source_types
+                        .contains(&"xsd:dateTime".to_string())
+                    {
+                        self.inferred_datatypes
+                            .entry(planned_class.class.to_owned())
+                            .or_default()
+                            .insert(planned_property.term.to_owned(), datatype.to_owned());
+                    }
+                }
+            }
+            if !self.inferred_datatypes.is_empty() {
+                self.data_context.insert(
+                    "xsd".to_string(),
+                    "http://www.w3.org/2001/XMLSchema#".to_string(),
+                );
             }
+            warnings
         }
 
-        pub fn get_vocab_json(&self, opt: &Opt) -> Map<String, Value> {
-            let classes = self
-                .classes
-                .values()
-                .map(|class| serde_json::to_value(class).unwrap());
+        /// Builds a skos:ConceptScheme from the v2 `_tag` entries, with a skos:Concept per tag,
+        /// and points the property shapes of tag properties at skos:Concept.
+        /// Returns the scheme and its concepts, to be inserted with the data
+        pub fn set_tag_concepts(&mut self, tags: &[Value]) -> Vec<Value> {
+            let scheme_id = "_tag".to_string();
+            let mut concepts = vec![json!({
+                "@id": scheme_id,
+                "@type": "skos:ConceptScheme",
+                "skos:prefLabel": format!("{}/{} tags", self.network_name, self.db_name),
+            })];
+            for tag in tags {
+                let tag_name = match tag["id"].as_str() {
+                    Some(tag_name) => tag_name,
+                    None => continue,
+                };
+                let concept_id = tag["_id"].to_string();
+                let mut concept = json!({
+                    "@id": concept_id,
+                    "@type": "skos:Concept",
+                    "skos:prefLabel": tag_name,
+                    "skos:inScheme": { "@id": scheme_id },
+                });
+                if let Some(doc) = tag["doc"].as_str() {
+                    concept["skos:definition"] = json!(doc);
+                }
+                self.tag_concepts.insert(tag_name.to_string(), concept_id);
+                concepts.push(concept);
+            }
 
-            let properties: Vec<Value> = self
+            let tag_properties = self
                 .properties
                 .values()
-                .map(|property| serde_json::to_value(property).unwrap())
-                .collect();
+                .filter(|property| property.untyped_v2_types.contains("tag"))
+                .map(|property| property.id.to_owned())
+                .collect::<HashSet<String>>();
+            for shacl_shape in self.shacl_shapes.values_mut() {
+                for shacl_property in shacl_shape.property.iter_mut() {
+                    if tag_properties.contains(&shacl_property.path["@id"]) {
+                        shacl_property.class = Some(HashMap::from([(
+                            "@id".to_string(),
+                            "skos:Concept".to_string(),
+                        )]));
+                    }
+                }
+            }
 
-            let class_shacl_shapes: Vec<Value> = self
-                .shacl_shapes
-                .values()
-                .map(|shape| serde_json::to_value(shape).unwrap())
-                .collect();
+            let skos = "http://www.w3.org/2004/02/skos/core#".to_string();
+            self.vocab_context
+                .insert("skos".to_string(), skos.to_owned());
+            self.data_context.insert("skos".to_string(), skos);
+            concepts
+        }
 
-            let results = match opt.shacl {
-                true => classes
-                    .chain(properties)
-                    .chain(class_shacl_shapes)
-                    .map(|value| value.to_owned())
-                    .collect(),
-                false => classes
-                    .chain(properties)
-                    .map(|value| value.to_owned())
-                    .collect(),
+        /// Builds an identity for each v2 `_auth` record, named by its DID and labeled with the username of its `_user`,
+        /// and points the property shapes of refs to `_user` or `_auth` at them. A `_user` is migrated as the identity
+        /// of its first `_auth`, or as an identity of its own if it has none.
+        /// Returns the identities, to be inserted with the data
+        pub fn set_identities(&mut self, users: &[Value], auths: &[Value]) -> Vec<Value> {
+            // a user's `auth` is a single ref or a list of them
+            let auth_ids = |user: &Value| match &user["auth"] {
+                Value::Array(auths) => auths.iter().map(|auth| auth["_id"].to_string()).collect(),
+                Value::Null => vec![],
+                auth => vec![auth["_id"].to_string()],
             };
+            let mut usernames: HashMap<String, String> = HashMap::new();
+            for user in users {
+                if let Some(username) = user["username"].as_str() {
+                    for auth_id in auth_ids(user) {
+                        usernames.insert(auth_id, username.to_string());
+                    }
+                }
+            }
+            let mut identities = vec![];
+            for auth in auths {
+                let Some(auth_id) = auth["id"].as_str() else {
+                    continue;
+                };
+                let did = format!("did:fluree:{}", auth_id);
+                let mut identity = json!({
+                    "@id": did,
+                    "@type": "foaf:OnlineAccount",
+                });
+                if let Some(username) = usernames.get(&auth["_id"].to_string()) {
+                    identity["foaf:accountName"] = json!(username);
+                }
+                self.identities.insert(auth["_id"].to_string(), did);
+                identities.push(identity);
+            }
+            for user in users {
+                let user_id = user["_id"].to_string();
+                let did = auth_ids(user)
+                    .first()
+                    .and_then(|auth_id| self.identities.get(auth_id));
+                let identity_id = match did {
+                    Some(did) => did.to_owned(),
+                    None => {
+                        let mut identity = json!({
+                            "@id": user_id,
+                            "@type": "foaf:OnlineAccount",
+                        });
+                        if let Some(username) = user["username"].as_str() {
+                            identity["foaf:accountName"] = json!(username);
+                        }
+                        identities.push(identity);
+                        user_id.to_owned()
+                    }
+                };
+                self.identities.insert(user_id, identity_id);
+            }
 
-            let mut vocab_results_map = serde_json::Map::new();
+            let identity_properties = self
+                .ref_collections
+                .iter()
+                .filter(|(_, collection)| ["_user", "_auth"].contains(&collection.as_str()))
+                .filter_map(|(property_key, _)| self.properties.get(property_key))
+                .map(|property| property.id.to_owned())
+                .collect::<HashSet<String>>();
+            for shacl_shape in self.shacl_shapes.values_mut() {
+                for shacl_property in shacl_shape.property.iter_mut() {
+                    if identity_properties.contains(&shacl_property.path["@id"]) {
+                        shacl_property.class = Some(HashMap::from([(
+                            "@id".to_string(),
+                            "foaf:OnlineAccount".to_string(),
+                        )]));
+                    }
+                }
+            }
 
-            let ledger_name = match &opt.ledger_name {
-                Some(ledger_name) => ledger_name.to_string(),
-                None => format!("{}/{}", self.network_name, self.db_name),
-            };
+            let foaf = "http://xmlns.com/foaf/0.1/".to_string();
+            self.vocab_context
+                .insert("foaf".to_string(), foaf.to_owned());
+            self.data_context.insert("foaf".to_string(), foaf);
+            identities
+        }
 
-            vocab_results_map.insert("ledger".to_string(), serde_json::json!(ledger_name));
+        // refs to v2 `_user` and `_auth` entities become refs to the identities they're migrated as
+        fn identity_refs(&self, value: &Value) -> Value {
+            match value {
+                Value::Array(values) => Value::Array(
+                    values
+                        .iter()
+                        .map(|value| self.identity_refs(value))
+                        .collect(),
+                ),
+                Value::Object(object) => {
+                    let mut object = object.to_owned();
+                    if let Some(identity) = object
+                        .get("@id")
+                        .and_then(Value::as_str)
+                        .and_then(|id| self.identities.get(id))
+                    {
+                        object.insert("@id".to_string(), json!(identity));
+                    }
+                    Value::Object(object)
+                }
+                value => value.to_owned(),
+            }
+        }
 
-            vocab_results_map.insert(
-                "@context".to_string(),
-                Value::Object(
-                    self.vocab_context
+        // v2 returns tag values by name, either in full (`collection/predicate:name`) or without the predicate
+        fn tag_concept_refs(&self, orig_predicate_name: &str, value: &Value) -> Value {
+            match value {
+                Value::Array(values) => Value::Array(
+                    values
                         .iter()
-                        .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                        .map(|value| self.tag_concept_refs(orig_predicate_name, value))
                         .collect(),
                 ),
-            );
+                Value::String(tag_name) => {
+                    let concept_id = self.tag_concepts.get(tag_name).or_else(|| {
+                        self.tag_concepts
+                            .get(&format!("{}:{}", orig_predicate_name, tag_name))
+                    });
+                    match concept_id {
+                        Some(concept_id) => json!({ "@id": concept_id }),
+                        None => value.to_owned(),
+                    }
+                }
+                _ => value.to_owned(),
+            }
+        }
 
-            vocab_results_map.insert("insert".to_string(), Value::Array(results));
+        /// Carries a v2 `_collection` record's `doc` onto its class as `rdfs:comment`,
+        /// its `specDoc` onto the class's NodeShape as `sh:message`, and its recognizable specs onto the
+        /// NodeShape as constraints, and records the collection's index (and that of a collection with a `base` of
+        /// its own). Returns the specs that aren't recognizable
+        pub fn set_collection_metadata(&mut self, collection: &Value) -> Vec<UnsupportedFeature> {
+            let orig_class_name = match collection["name"].as_str() {
+                Some(orig_class_name) => orig_class_name,
+                None => return vec![],
+            };
+            if let Some(id) = collection["_id"].as_i64() {
+                let index = id & ((1 << SUBJECT_ID_BITS) - 1);
+                self.collection_indexes
+                    .insert(index, orig_class_name.to_string());
+                if let Some(prefix) = self.mapping.base_prefix(orig_class_name) {
+                    self.id_prefixes.insert(index, prefix);
+                }
+            }
+            let class_object = match self.classes.get_mut(orig_class_name) {
+                Some(class_object) => class_object,
+                None => return vec![],
+            };
+            if let Some(doc) = collection["doc"].as_str() {
+                class_object.comment = Some(doc.to_string());
+            }
+            let class_name = class_object.id.to_owned();
+            if let (Some(spec_doc), Some(shacl_shape)) = (
+                collection["specDoc"].as_str(),
+                self.shacl_shapes.get_mut(&class_name),
+            ) {
+                shacl_shape.message = spec_doc.to_string();
+            }
 
-            vocab_results_map
+            let mut unsupported = vec![];
+            for spec in spec_functions(&collection["spec"]) {
+                let code = spec["code"].as_str();
+                let shape_constraint = code
+                    .and_then(collection_requirement)
+                    .filter(|requirement| self.requirement_shape(requirement).is_some());
+                match (
+                    shape_constraint,
+                    self.shacl_shapes.contains_key(&class_name),
+                ) {
+                    (Some(requirement), true) => self.set_requirement(&class_name, &requirement),
+                    _ => unsupported.push(UnsupportedFeature {
+                        feature: "collectionSpec".to_string(),
+                        class: orig_class_name.to_string(),
+                        property: None,
+                        name: spec["name"].as_str().unwrap_or_default().to_string(),
+                        code: code.map(str::to_string),
+                    }),
+                }
+            }
+            unsupported
         }
 
-        pub fn get_or_create_class(&self, orig_class_name: &str) -> Class {
-            let class_name = &standardize_class_name(orig_class_name);
-            let class_object = self.classes.get(orig_class_name);
-            let class_object = match class_object {
-                Some(class_object) => class_object.to_owned(),
-                None => Class::new(class_name),
-            };
-            class_object
+        // the v3 property a collection spec reads, unless it isn't migrated
+        fn requirement_property(&self, predicate: &str) -> Option<String> {
+            let (orig_class_name, orig_property_name) = predicate.split_once('/')?;
+            if self.is_dropped_property(orig_class_name, orig_property_name) {
+                return None;
+            }
+            self.properties
+                .get(&self.property_key(orig_class_name, orig_property_name))
+                .map(|property| property.id.to_owned())
         }
 
-        pub fn get_or_create_property(&self, property_name: &str, type_value: &str) -> Property {
-            let property_object = self.properties.get(property_name);
-            let property_object = match property_object {
-                Some(property_object) => property_object.update_types_and_own(type_value),
-                None => Property::new(property_name, type_value),
+        // a collection spec's requirement as a SHACL shape, e.g. {"sh:or": {"@list": [...]}}
+        fn requirement_shape(&self, requirement: &Requirement) -> Option<Value> {
+            let shapes = |requirements: &[Requirement]| {
+                requirements
+                    .iter()
+                    .map(|requirement| self.requirement_shape(requirement))
+                    .collect::<Option<Vec<Value>>>()
             };
-            property_object
+            match requirement {
+                Requirement::Has(predicate) => Some(json!({
+                    "sh:path": { "@id": self.requirement_property(predicate)? },
+                    "sh:minCount": 1
+                })),
+                Requirement::All(requirements) => {
+                    Some(json!({ "sh:and": { "@list": shapes(requirements)? } }))
+                }
+                Requirement::Any(requirements) => {
+                    Some(json!({ "sh:or": { "@list": shapes(requirements)? } }))
+                }
+            }
         }
 
-        pub fn get_or_create_shacl_shape(
-            &self,
-            class_name: &str,
-            closed_shapes: bool,
-        ) -> ShaclShape {
-            let shacl_shape = self.shacl_shapes.get(class_name);
-            let shacl_shape = match shacl_shape {
-                Some(shacl_shape) => shacl_shape.to_owned(),
-                None => ShaclShape::new(class_name, closed_shapes),
-            };
-            shacl_shape
+        // applies a collection spec's requirement to the class's NodeShape: each required property gets
+        // `sh:minCount 1`, and each alternative of properties becomes an `sh:or` of the shape
+        fn set_requirement(&mut self, class_name: &str, requirement: &Requirement) {
+            match requirement {
+                Requirement::All(requirements) => {
+                    for requirement in requirements {
+                        self.set_requirement(class_name, requirement);
+                    }
+                }
+                Requirement::Has(predicate) => {
+                    let property = self.requirement_property(predicate);
+                    let shacl_shape = self.shacl_shapes.get_mut(class_name).unwrap();
+                    match shacl_shape
+                        .property
+                        .iter_mut()
+                        .find(|shacl_property| shacl_property.path.get("@id") == property.as_ref())
+                    {
+                        Some(shacl_property) => shacl_property.min_count = Some(1),
+                        None => {
+                            let mut shacl_property = ShaclProperty::new(&property.unwrap());
+                            shacl_property.min_count = Some(1);
+                            shacl_shape.property.push(shacl_property);
+                        }
+                    }
+                }
+                Requirement::Any(_) => {
+                    let Some(mut shape) = self.requirement_shape(requirement) else {
+                        return;
+                    };
+                    let alternatives = shape["sh:or"].take();
+                    self.shacl_shapes
+                        .get_mut(class_name)
+                        .unwrap()
+                        .or
+                        .push(alternatives);
+                }
+            }
         }
 
         // TODO: if another shacl_shape in parser.shacl_shapes has the same property name, and if it has a different datatype, then I need to log a warning and I need to update the property name to be the Class/Property (e.g. Person/age and Animal/age)
@@ -462,11 +3963,10 @@ pub mod parser {
         use std::collections::{HashMap, HashSet};
 
         use serde::{Deserialize, Serialize};
-        use serde_json::Value;
+        use serde_json::{json, Value};
 
-        use crate::functions::{
-            remove_namespace, standardize_class_name, standardize_property_name,
-        };
+        use crate::functions::{remove_namespace, restrict_collections, standardize_property_name};
+        use crate::spec::{spec_constraints, spec_functions};
 
         #[derive(Debug, Clone, Deserialize, Serialize)]
         pub struct Class {
@@ -516,8 +4016,13 @@ pub mod parser {
             pub comment: String,
             #[serde(rename = "rdfs:domain")]
             pub domain: Vec<HashMap<String, String>>,
+            /// Whether the v2 predicate was marked `index`, so the target can be indexed the same way
+            #[serde(rename = "fm:indexed", skip_serializing_if = "Option::is_none")]
+            pub indexed: Option<bool>,
             #[serde(skip_serializing)]
             pub data_types: HashSet<String>,
+            #[serde(skip)]
+            pub untyped_v2_types: HashSet<String>,
         }
 
         impl Property {
@@ -528,13 +4033,24 @@ pub mod parser {
                     Some(data_type) => vec![data_type].into_iter().collect(),
                     None => HashSet::new(),
                 };
-                Property {
+                let mut property = Property {
                     id: standard_property_name.clone(),
                     type_: "rdf:Property".to_string(),
                     label: remove_namespace(&standard_property_name),
                     comment: String::new(),
                     domain: Vec::new(),
+                    indexed: None,
                     data_types,
+                    untyped_v2_types: HashSet::new(),
+                };
+                property.track_untyped_v2_type(type_value);
+                property
+            }
+
+            // v2 types without an xsd equivalent (other than refs) can have a datatype inferred from the data
+            fn track_untyped_v2_type(&mut self, type_value: &str) {
+                if type_value != "ref" && Self::normalize_type_value(type_value).is_none() {
+                    self.untyped_v2_types.insert(type_value.to_string());
                 }
             }
 
@@ -560,12 +4076,10 @@ pub mod parser {
             pub fn update_types_and_own(&self, type_value: &str) -> Self {
                 let mut property = self.to_owned();
                 let data_type = Self::normalize_type_value(type_value);
-                match data_type {
-                    Some(data_type) => {
-                        property.data_types.insert(data_type);
-                    }
-                    None => {}
+                if let Some(data_type) = data_type {
+                    property.data_types.insert(data_type);
                 }
+                property.track_untyped_v2_type(type_value);
                 property
             }
 
@@ -595,6 +4109,11 @@ pub mod parser {
             pub closed: Option<bool>,
             #[serde(rename = "sh:ignoredProperties", skip_serializing_if = "Vec::is_empty")]
             pub ignored_properties: Vec<HashMap<String, String>>,
+            #[serde(rename = "sh:message", skip_serializing_if = "String::is_empty")]
+            pub message: String,
+            /// Lists of alternative shapes, one of which each instance must conform to
+            #[serde(rename = "sh:or", skip_serializing_if = "Vec::is_empty")]
+            pub or: Vec<Value>,
         }
 
         impl ShaclShape {
@@ -625,6 +4144,8 @@ pub mod parser {
                     node_kind: String::new(),
                     closed,
                     ignored_properties,
+                    message: String::new(),
+                    or: vec![],
                 }
             }
 
@@ -632,6 +4153,7 @@ pub mod parser {
                 &mut self,
                 property_object: &mut Property,
                 item: &Value,
+                class_name: &dyn Fn(&str) -> String,
             ) -> Result<(), Vec<String>> {
                 let mut result = Ok(());
                 let mut shacl_property = ShaclProperty::new(&property_object.id);
@@ -671,24 +4193,50 @@ pub mod parser {
                                 ];
                                 result = Err(error_vec);
                             } else {
-                                match property_types.iter().next() {
-                                    Some(data_type) => {
-                                        shacl_property.datatype = Some(HashMap::from([(
-                                            "@id".to_string(),
-                                            data_type.to_string(),
-                                        )]));
-                                    }
-                                    None => {}
+                                if let Some(data_type) = property_types.iter().next() {
+                                    shacl_property.datatype = Some(HashMap::from([(
+                                        "@id".to_string(),
+                                        data_type.to_string(),
+                                    )]));
                                 }
                             }
                         }
                         "restrictCollection" => {
-                            shacl_property.class = Some(HashMap::from([(
-                                "@id".to_string(),
-                                standardize_class_name(
-                                    item["restrictCollection"].as_str().unwrap(),
-                                ),
-                            )]));
+                            let classes = restrict_collections(item)
+                                .iter()
+                                .map(|collection| class_name(collection))
+                                .collect::<Vec<String>>();
+                            match classes.as_slice() {
+                                [] => {}
+                                [class] => {
+                                    shacl_property.class = Some(HashMap::from([(
+                                        "@id".to_string(),
+                                        class.to_string(),
+                                    )]));
+                                }
+                                // a ref to several collections can be an instance of any of their classes
+                                classes => {
+                                    shacl_property.or = Some(json!({
+                                        "@list": classes
+                                            .iter()
+                                            .map(|class| json!({ "sh:class": { "@id": class } }))
+                                            .collect::<Vec<Value>>()
+                                    }));
+                                }
+                            }
+                        }
+                        "specDoc" => {
+                            shacl_property.message =
+                                item["specDoc"].as_str().unwrap_or_default().to_string();
+                        }
+                        "spec" => {
+                            // specs that aren't recognizable are reported as unsupported features
+                            for spec in spec_functions(&item["spec"]) {
+                                let constraints = spec["code"].as_str().and_then(spec_constraints);
+                                for (constraint, value) in constraints.into_iter().flatten() {
+                                    shacl_property.set_constraint(&constraint, value);
+                                }
+                            }
                         }
                         "restrictTag" => {
                             // this is a boolean
@@ -729,6 +4277,23 @@ pub mod parser {
             pub node_kind: String,
             #[serde(rename = "sh:pattern", skip_serializing_if = "String::is_empty")]
             pub pattern: String,
+            #[serde(rename = "sh:minInclusive", skip_serializing_if = "Option::is_none")]
+            pub min_inclusive: Option<Value>,
+            #[serde(rename = "sh:maxInclusive", skip_serializing_if = "Option::is_none")]
+            pub max_inclusive: Option<Value>,
+            #[serde(rename = "sh:minExclusive", skip_serializing_if = "Option::is_none")]
+            pub min_exclusive: Option<Value>,
+            #[serde(rename = "sh:maxExclusive", skip_serializing_if = "Option::is_none")]
+            pub max_exclusive: Option<Value>,
+            #[serde(rename = "sh:minLength", skip_serializing_if = "Option::is_none")]
+            pub min_length: Option<u64>,
+            #[serde(rename = "sh:maxLength", skip_serializing_if = "Option::is_none")]
+            pub max_length: Option<u64>,
+            #[serde(rename = "sh:message", skip_serializing_if = "String::is_empty")]
+            pub message: String,
+            /// The alternatives a value can satisfy, e.g. the `sh:class` of each collection a ref is restricted to
+            #[serde(rename = "sh:or", skip_serializing_if = "Option::is_none")]
+            pub or: Option<Value>,
         }
 
         impl ShaclProperty {
@@ -745,8 +4310,72 @@ pub mod parser {
                     datatype: None,
                     node_kind: String::new(),
                     pattern: String::new(),
+                    min_inclusive: None,
+                    max_inclusive: None,
+                    min_exclusive: None,
+                    max_exclusive: None,
+                    min_length: None,
+                    max_length: None,
+                    message: String::new(),
+                    or: None,
                 }
             }
+
+            // sets a constraint recognized from a v2 spec, e.g. ("sh:minInclusive", 0)
+            fn set_constraint(&mut self, constraint: &str, value: Value) {
+                match constraint {
+                    "sh:minInclusive" => self.min_inclusive = Some(value),
+                    "sh:maxInclusive" => self.max_inclusive = Some(value),
+                    "sh:minExclusive" => self.min_exclusive = Some(value),
+                    "sh:maxExclusive" => self.max_exclusive = Some(value),
+                    "sh:minLength" => self.min_length = value.as_u64(),
+                    "sh:maxLength" => self.max_length = value.as_u64(),
+                    "sh:pattern" => self.pattern = value.as_str().unwrap_or_default().to_string(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn group(ids: &[&str]) -> Vec<String> {
+            ids.iter().map(|id| id.to_string()).collect()
+        }
+
+        #[test]
+        fn aliases_a_group_to_its_first_entity() {
+            let mut aliases = HashMap::new();
+            alias_group(&mut aliases, &group(&["1", "2", "3"]));
+            assert_eq!(resolve_alias(&aliases, "2"), "1");
+            assert_eq!(resolve_alias(&aliases, "3"), "1");
+            assert_eq!(resolve_alias(&aliases, "1"), "1");
+        }
+
+        #[test]
+        fn joins_groups_with_overlapping_keys() {
+            let mut aliases = HashMap::new();
+            // 2 shares an email with 1 and a phone with 3, and 4 shares a phone with 3
+            alias_group(&mut aliases, &group(&["1", "2"]));
+            alias_group(&mut aliases, &group(&["3", "4"]));
+            alias_group(&mut aliases, &group(&["2", "3"]));
+            for id in ["1", "2", "3", "4"] {
+                assert_eq!(resolve_alias(&aliases, id), "1");
+            }
+        }
+
+        #[test]
+        fn keeps_an_alias_when_a_later_group_starts_with_an_aliased_entity() {
+            let mut aliases = HashMap::new();
+            alias_group(&mut aliases, &group(&["1", "5"]));
+            alias_group(&mut aliases, &group(&["2", "5"]));
+            alias_group(&mut aliases, &group(&["5", "1"]));
+            let canonical_id = resolve_alias(&aliases, "5");
+            for id in ["1", "2"] {
+                assert_eq!(resolve_alias(&aliases, id), canonical_id);
+            }
         }
     }
 }
@@ -755,7 +4384,6 @@ pub mod local_directory {
     use std::{
         fs,
         path::{Path, PathBuf},
-        thread,
         time::{Duration, Instant},
     };
 
@@ -766,12 +4394,34 @@ pub mod local_directory {
     use serde_json::Value;
 
     use crate::{
-        console::pretty_print,
+        changes::ENTITY_HASHES_FILE,
+        console::{pretty_print, ERROR_COLOR},
         fluree::FlureeInstance,
-        functions::{format_bytes, pretty_log, truncate_tail},
+        functions::{
+            content_hash, format_bytes, pretty_log, truncate_tail, with_default_context,
+            with_target_ledger,
+        },
+        hints::INDEX_HINTS_FILE,
+        report::{MigrationReport, SkippedFile},
+        state as run_state, trace,
+        uploads::{self, UPLOADS_FILE},
+        verify::MANIFEST_FILE,
+    };
+
+    use super::{
+        opt::{Opt, UploadFailure},
+        source::Migrate,
     };
 
-    use super::{opt::Opt, source::Migrate};
+    const RUN_FILES: [&str; 7] = [
+        MANIFEST_FILE,
+        INDEX_HINTS_FILE,
+        run_state::STATE_FILE,
+        run_state::LOCK_FILE,
+        run_state::PAUSE_FILE,
+        UPLOADS_FILE,
+        ENTITY_HASHES_FILE,
+    ];
 
     pub struct LocalDirectory {
         pub path: PathBuf,
@@ -802,14 +4452,26 @@ pub mod local_directory {
 
     #[async_trait::async_trait]
     impl Migrate for LocalDirectory {
-        async fn migrate(&mut self) {
+        async fn migrate(&mut self) -> MigrationReport {
+            if self.opt.command.is_some() {
+                pretty_print(
+                    "Commands (e.g. plan) need a v2 --source, and can't be used with --input",
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
             let path = Path::new(&self.path);
             let files: Vec<PathBuf> = fs::read_dir(path)
                 .unwrap()
                 .filter_map(|entry| {
                     if let Ok(entry) = entry {
                         let path = entry.path();
-                        if path.is_file() {
+                        // the files a run writes about itself next to its output aren't transactions
+                        let is_run_file = path
+                            .file_name()
+                            .is_some_and(|name| RUN_FILES.iter().any(|run_file| name == *run_file));
+                        if path.is_file() && !is_run_file {
                             Some(path)
                         } else {
                             None
@@ -835,7 +4497,7 @@ pub mod local_directory {
 
             // read the file, parse it to serde_json
             let file_parsed_json =
-                serde_json::from_slice::<Value>(&fs::read(&smallest_file).unwrap())
+                serde_json::from_slice::<Value>(&fs::read(smallest_file).unwrap())
                     .expect("Could not parse JSON");
 
             // file_parsed_json must be an object (otherwise panic). It must have a "ledger" key. We need the string value of the ledger key:
@@ -845,7 +4507,7 @@ pub mod local_directory {
                 self.opt.ledger_name.clone().unwrap()
             } else {
                 match ledger_name_from_file {
-                    Some(ledger_name) => ledger_name.to_string(),
+                    Some(ledger_name) => self.opt.target_ledger_name(ledger_name),
                     None => {
                         pretty_print(
                             "Could not find ledger name in source files. Please provide a ledger name with \"--ledger-name\"",
@@ -914,6 +4576,18 @@ pub mod local_directory {
                 None => std::collections::HashSet::new(),
             };
 
+            // when creating the ledger, the data @context becomes the ledger's default context
+            let default_context = match self.opt.is_create_ledger {
+                true => files
+                    .iter()
+                    .filter(|file| file.to_str().unwrap().contains("data"))
+                    .find_map(|file| {
+                        let json = serde_json::from_slice::<Value>(&fs::read(file).ok()?).ok()?;
+                        json.get("@context").cloned()
+                    }),
+                false => None,
+            };
+
             let mut pb = self.opt.pb.clone();
             pb.reset();
             pb.set_length(files.len() as u64);
@@ -936,16 +4610,23 @@ pub mod local_directory {
             pb = pb.with_finish(indicatif::ProgressFinish::AndLeave);
             pb.set_prefix("Writing v3 Data");
 
+            self.opt.confirm_create(&ledger_name);
+            if self.opt.content_addressed && self.opt.is_create_ledger {
+                uploads::forget(&self.opt, &ledger_name);
+            }
+
+            if let Err(e) = target_instance.create_dataset(&ledger_name, &pb).await {
+                run_state::abort(&self.opt, &e);
+            }
+
             pretty_log(Level::Info, &mut pb, "Starting v3 Data Txns");
             let start_time = Instant::now();
             let mut last_txn_time = Instant::now();
             let mut cumulative_file_size = 0;
-            let mut retry_count = 0;
+            let mut report = MigrationReport::default();
 
             for (index, file) in files.iter().enumerate() {
-                if txn_id_hash_set
-                    .contains(&file.file_name().unwrap().to_str().unwrap().to_string())
-                {
+                if txn_id_hash_set.contains(file.file_name().unwrap().to_str().unwrap()) {
                     pretty_log(
                         Level::Info,
                         &mut pb,
@@ -963,9 +4644,33 @@ pub mod local_directory {
                     continue;
                 }
 
-                let file_bytes = std::fs::read(&file).expect("Could not read file");
+                let file_bytes = std::fs::read(file).expect("Could not read file");
                 let file_size = file_bytes.len();
 
+                // with --content-addressed, files whose contents were already transacted are skipped too,
+                // whatever they're named
+                let hash = self
+                    .opt
+                    .content_addressed
+                    .then(|| content_hash(&file_bytes));
+                if let Some(hash) = &hash {
+                    if uploads::is_uploaded(&self.opt, &ledger_name, hash) {
+                        pretty_log(
+                            Level::Info,
+                            &mut pb,
+                            &format!(
+                                "Skipping: {:40} | {}/{} | Already transacted",
+                                truncate_tail(&format!("{}", file.display()), 40),
+                                index + 1,
+                                files.len(),
+                            ),
+                        );
+                        pb.inc(1);
+                        pb.set_message(format!("{:3}%", 100 * (index + 1) / files.len()));
+                        continue;
+                    }
+                }
+
                 if file_size < 1000 {
                     let json_parsed_value =
                         serde_json::from_slice::<Value>(&file_bytes).expect("Could not parse JSON");
@@ -1010,31 +4715,77 @@ pub mod local_directory {
 
                 let file_string =
                     String::from_utf8(file_bytes).expect("Could not parse JSON bytes");
+                let file_string = with_target_ledger(&file_string, &self.opt);
+                let file_string = match (&default_context, target_instance.is_created) {
+                    (Some(default_context), false) => {
+                        with_default_context(&file_string, default_context)
+                    }
+                    _ => file_string,
+                };
                 let response_string: Option<Value> = None;
+                let is_creating_ledger = !target_instance.is_created;
                 let red_bold = Style::new().red().bold();
+                let mut timeouts = 0;
+                let mut is_skipped = false;
 
                 while !target_instance.is_available
                     || !target_instance.is_authorized
                     || response_string.is_none()
                 {
                     if !target_instance.is_available {
-                        if retry_count < 5 {
-                            pretty_log(
-                                Level::Warn,
-                                &mut pb,
-                                &format!(
-                                    "Timeout: {:40} | Moving on to next file in 15 seconds...",
-                                    truncate_tail(&format!("{}", file.display()), 40),
-                                ),
-                            );
-                            target_instance.is_available = true;
-                            target_instance.is_authorized = true;
-                            thread::sleep(Duration::from_secs(15));
-                            retry_count += 1;
-                            break;
-                        } else {
-                            target_instance.prompt_fix_url();
+                        timeouts += 1;
+                        if timeouts > self.opt.upload_retries {
+                            match self.opt.on_upload_failure {
+                                UploadFailure::Skip => {
+                                    pretty_log(
+                                        Level::Warn,
+                                        &mut pb,
+                                        &format!(
+                                            "Skipping: {:40} | No answer after {} attempts",
+                                            truncate_tail(&format!("{}", file.display()), 40),
+                                            timeouts,
+                                        ),
+                                    );
+                                    report.skipped_files.push(SkippedFile {
+                                        file: file.display().to_string(),
+                                        attempts: timeouts,
+                                    });
+                                    target_instance.is_available = true;
+                                    target_instance.is_authorized = true;
+                                    is_skipped = true;
+                                    break;
+                                }
+                                UploadFailure::Abort => {
+                                    pb.finish_and_clear();
+                                    report.save(&self.opt);
+                                    pretty_print(
+                                        &format!(
+                                            "The target didn't answer {} after {} attempts. Rerun to resume; the \
+                                             files already transacted are skipped",
+                                            file.display(),
+                                            timeouts
+                                        ),
+                                        ERROR_COLOR,
+                                        true,
+                                    );
+                                    std::process::exit(1);
+                                }
+                            }
                         }
+                        pretty_log(
+                            Level::Warn,
+                            &mut pb,
+                            &format!(
+                                "Timeout: {:40} | Retrying in {} seconds ({}/{})",
+                                truncate_tail(&format!("{}", file.display()), 40),
+                                self.opt.upload_retry_delay,
+                                timeouts,
+                                self.opt.upload_retries,
+                            ),
+                        );
+                        target_instance.is_available = true;
+                        target_instance.is_authorized = true;
+                        tokio::time::sleep(Duration::from_secs(self.opt.upload_retry_delay)).await;
                     }
 
                     if !target_instance.is_authorized {
@@ -1043,49 +4794,80 @@ pub mod local_directory {
                     if pb.is_finished() {
                         pb.reset();
                     }
+                    run_state::wait_while_paused(&pb).await;
+                    run_state::begin(&file.display().to_string());
                     let response_result = target_instance.v3_transact(file_string.clone()).await;
-                    let validate_attempt = target_instance.validate_result(&response_result);
-
-                    if let Err(e) = validate_attempt {
-                        pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
-                    }
-
-                    // let awaited_response = response_result.unwrap().text().await.unwrap();
-                    let awaited_response = match response_result {
-                        Ok(response) => response.text().await.unwrap(),
-                        Err(_) => {
-                            pb.finish_and_clear();
-                            continue;
+                    match target_instance.validate_result(response_result).await {
+                        Ok(_) => {
+                            run_state::complete(&file.display().to_string());
+                            if let Some(hash) = &hash {
+                                uploads::record(&self.opt, &ledger_name, hash);
+                            }
+                            break;
                         }
-                    };
-
-                    if target_instance.is_available && target_instance.is_authorized {
-                        // let awaited_response = response_result.unwrap().text().await.unwrap();
-                        // response_string = serde_json::from_str(&awaited_response).unwrap();
-                        // println!("Response: {:?}", response_string);
-                        retry_count = 0;
-                        break;
-                    } else {
-                        let error = serde_json::from_str::<Value>(&awaited_response);
-                        if let Ok(error) = error {
-                            if let Some(error) = error["error"].as_str() {
-                                pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), error));
+                        Err(e) => {
+                            pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                            if target_instance.is_available && target_instance.is_authorized {
+                                if let Err(e) = target_instance.wait_to_retry(&pb).await {
+                                    report.save(&self.opt);
+                                    run_state::abort(&self.opt, &e);
+                                }
                             }
+                            pb.finish_and_clear();
+                            continue;
                         }
-                        pb.finish_and_clear();
-                        continue;
+                    }
+                }
+                if is_creating_ledger && !is_skipped {
+                    if let Err(e) = target_instance
+                        .transact_initial_policy(&ledger_name, &pb)
+                        .await
+                    {
+                        report.save(&self.opt);
+                        run_state::abort(&self.opt, &e);
                     }
                 }
                 pb.inc(1);
                 pb.set_message(format!("{:3}%", 100 * (index + 1) / files.len()));
             }
+            pb.finish_and_clear();
+            println!(
+                "{:>12} {} files to Target Ledger [{}] in {} (run {})",
+                Style::new().green().bold().apply_to("Finished"),
+                files.len(),
+                ledger_name,
+                HumanDuration(start_time.elapsed()),
+                trace::correlation_id(),
+            );
+            if !report.skipped_files.is_empty() {
+                pretty_print(
+                    &format!(
+                        "{} files were skipped because the target didn't answer them: {}. Rerun to transact \
+                         them; the files already transacted are skipped",
+                        report.skipped_files.len(),
+                        report
+                            .skipped_files
+                            .iter()
+                            .map(|skipped| skipped.file.as_str())
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    ),
+                    ERROR_COLOR,
+                    true,
+                );
+            }
+            report.save(&self.opt);
+            report
         }
     }
 }
 
 pub mod source {
+    use crate::report::MigrationReport;
+
     #[async_trait::async_trait]
     pub trait Migrate {
-        async fn migrate(&mut self);
+        /// Runs the migration, returning its findings
+        async fn migrate(&mut self) -> MigrationReport;
     }
 }
@@ -2,6 +2,8 @@ use crossterm::style::{Print, ResetColor, SetForegroundColor};
 use crossterm::{execute, style::Color};
 use std::io::{self};
 
+use crate::redact::redact;
+
 pub const ERROR_COLOR: Color = Color::Yellow;
 pub fn pretty_print(string: &str, color: Color, newline: bool) {
     let newline = match newline {
@@ -11,7 +13,7 @@ pub fn pretty_print(string: &str, color: Color, newline: bool) {
     execute!(
         io::stdout(),
         SetForegroundColor(color),
-        Print(string),
+        Print(redact(string)),
         Print(newline),
         ResetColor
     )
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::plan::{MigrationPlan, PlannedProperty};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramFormat {
+    /// A Mermaid class diagram, which GitHub, GitLab, and most Markdown viewers render
+    Mermaid,
+    /// A Graphviz digraph, e.g. for `dot -Tsvg`
+    Dot,
+}
+
+// the type a property's values are shown with: its datatype (or its v2 type, e.g. for a ref to any collection),
+// and `[]` when it holds several values
+fn value_type(property: &PlannedProperty) -> String {
+    let value_type = property
+        .datatype
+        .to_owned()
+        .or_else(|| property.source_types.first().cloned())
+        .unwrap_or("any".to_string());
+    match property.max_count {
+        Some(1) => value_type,
+        _ => format!("{}[]", value_type),
+    }
+}
+
+// the multiplicity a ref's target end is labeled with
+fn multiplicity(property: &PlannedProperty) -> String {
+    match property.max_count {
+        Some(1) => "0..1".to_string(),
+        Some(max_count) => format!("0..{}", max_count),
+        None => "*".to_string(),
+    }
+}
+
+/// The classes of a migration plan as a diagram: each class with its datatype properties, and an edge labeled with
+/// the term for each ref property restricted to a class
+pub fn render(plan: &MigrationPlan, format: DiagramFormat) -> String {
+    match format {
+        DiagramFormat::Mermaid => mermaid(plan),
+        DiagramFormat::Dot => dot(plan),
+    }
+}
+
+// Mermaid class ids can't hold e.g. the `:` of a prefixed class name, so the names are given as labels
+fn mermaid_id(class_name: &str) -> String {
+    class_name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+fn mermaid(plan: &MigrationPlan) -> String {
+    let mut lines = vec!["classDiagram".to_string()];
+    let mut edges = vec![];
+    for class in &plan.classes {
+        let id = mermaid_id(&class.class);
+        match id == class.class {
+            true => lines.push(format!("    class {} {{", id)),
+            false => lines.push(format!("    class {}[\"{}\"] {{", id, class.class)),
+        }
+        for property in &class.properties {
+            match &property.ref_class {
+                Some(ref_class) => edges.push(format!(
+                    "    {} --> \"{}\" {} : {}",
+                    id,
+                    multiplicity(property),
+                    mermaid_id(ref_class),
+                    property.term
+                )),
+                None => lines.push(format!(
+                    "        {} {}",
+                    value_type(property),
+                    property.term
+                )),
+            }
+        }
+        lines.push("    }".to_string());
+    }
+    lines.extend(edges);
+    lines.join("\n") + "\n"
+}
+
+// escapes the characters that structure a Graphviz record label
+fn dot_escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+fn dot(plan: &MigrationPlan) -> String {
+    let mut lines = vec![
+        "digraph schema {".to_string(),
+        "    rankdir=LR;".to_string(),
+        "    node [shape=record, fontname=\"Helvetica\"];".to_string(),
+        "    edge [fontname=\"Helvetica\", fontsize=10];".to_string(),
+    ];
+    let mut edges = vec![];
+    for class in &plan.classes {
+        let members = class
+            .properties
+            .iter()
+            .filter(|property| property.ref_class.is_none())
+            .map(|property| {
+                format!(
+                    "{}: {}\\l",
+                    dot_escape(&property.term),
+                    dot_escape(&value_type(property))
+                )
+            })
+            .collect::<String>();
+        lines.push(format!(
+            "    \"{}\" [label=\"{{{}|{}}}\"];",
+            class.class.replace('"', "\\\""),
+            dot_escape(&class.class),
+            members
+        ));
+        for property in &class.properties {
+            if let Some(ref_class) = &property.ref_class {
+                edges.push(format!(
+                    "    \"{}\" -> \"{}\" [label=\"{}\", headlabel=\"{}\"];",
+                    class.class.replace('"', "\\\""),
+                    ref_class.replace('"', "\\\""),
+                    property.term.replace('"', "\\\""),
+                    multiplicity(property)
+                ));
+            }
+        }
+    }
+    lines.extend(edges);
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+}
+
+pub fn write(plan: &MigrationPlan, format: DiagramFormat, path: &Path) {
+    if let Err(e) = std::fs::write(path, render(plan, format)) {
+        pretty_print(
+            &format!("Could not write diagram to {}: {}", path.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use dialoguer::console::Style;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::canonical::{document_quads, Quad, Term, RDF_TYPE};
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+use crate::redact::redact;
+use crate::state;
+use crate::verify::row_node;
+
+const DIFF_PAGE_SIZE: usize = 10_000;
+
+// the entities and property values of each kind of difference that are printed; the report lists all of them
+const MAX_PRINTED_EXAMPLES: usize = 5;
+
+/// Types in these namespaces describe the vocab and shapes rather than data
+pub const META_NAMESPACES: [&str; 6] = [
+    "http://www.w3.org/1999/02/22-rdf-syntax-ns#",
+    "http://www.w3.org/2000/01/rdf-schema#",
+    "http://www.w3.org/ns/shacl#",
+    "http://www.w3.org/2002/07/owl#",
+    "http://www.w3.org/2001/XMLSchema#",
+    "https://ns.flur.ee/",
+];
+
+/// The statements of each entity of a ledger or output directory, as (predicate, object) pairs by subject IRI
+#[derive(Debug, Default)]
+struct Entities {
+    statements: BTreeMap<String, HashSet<(String, Term)>>,
+    skipped_statements: u64,
+}
+
+impl Entities {
+    fn add(&mut self, quad: Quad) {
+        match quad.subject {
+            Term::Iri(subject) if !quad.has_blank_nodes() => {
+                self.statements
+                    .entry(subject)
+                    .or_default()
+                    .insert((quad.predicate, quad.object));
+            }
+            _ => self.skipped_statements += 1,
+        }
+    }
+
+    // drops the subjects that only describe the vocab (e.g. classes, properties, and shapes)
+    fn retain_data(&mut self) {
+        self.statements.retain(|_, statements| {
+            statements.iter().any(|(predicate, object)| match object {
+                Term::Iri(class) if predicate == RDF_TYPE => !META_NAMESPACES
+                    .iter()
+                    .any(|namespace| class.starts_with(namespace)),
+                _ => false,
+            })
+        });
+    }
+}
+
+/// A property value that one side holds and the other doesn't
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueChange {
+    pub property: String,
+    pub value: String,
+}
+
+/// An entity both sides hold with different property values
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedEntity {
+    pub id: String,
+    /// Values only --target-b holds
+    pub added: Vec<ValueChange>,
+    /// Values only --target-a holds
+    pub removed: Vec<ValueChange>,
+}
+
+/// The differences `diff` found, written as JSON when `--report` is given
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffReport {
+    pub a: String,
+    pub b: String,
+    /// Entities only --target-b holds
+    pub added: Vec<String>,
+    /// Entities only --target-a holds
+    pub deleted: Vec<String>,
+    pub changed: Vec<ChangedEntity>,
+    pub unchanged: u64,
+    pub skipped_statements: u64,
+}
+
+fn is_url(target: &str) -> bool {
+    target.starts_with("http://") || target.starts_with("https://")
+}
+
+fn term_string(term: &Term) -> String {
+    match term {
+        Term::Iri(iri) => format!("<{}>", iri),
+        Term::BlankNode(label) => format!("_:{}", label),
+        Term::Literal {
+            value,
+            language: Some(language),
+            ..
+        } => format!("{:?}@{}", value, language),
+        Term::Literal {
+            value, datatype, ..
+        } if datatype.ends_with("#string") => format!("{:?}", value),
+        Term::Literal {
+            value, datatype, ..
+        } => format!("{:?}^^<{}>", value, datatype),
+    }
+}
+
+// the documents of every JSON-LD file in an output directory
+fn directory_documents(directory: &Path) -> Vec<Map<String, Value>> {
+    let mut files = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|extension| extension == "jsonld" || extension == "json")
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            pretty_print(
+                &format!("Could not read directory {}: {}", directory.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    };
+    files.sort();
+    files
+        .iter()
+        .filter_map(|file| fs::read(file).ok())
+        .filter_map(|contents| serde_json::from_slice::<Map<String, Value>>(&contents).ok())
+        .collect()
+}
+
+fn directory_entities(documents: &[Map<String, Value>]) -> Entities {
+    let mut entities = Entities::default();
+    for document in documents {
+        for quad in document_quads(document) {
+            entities.add(quad);
+        }
+    }
+    entities.retain_data();
+    entities
+}
+
+// every statement about the instances of the ledger's data classes, queried with full IRIs
+async fn ledger_entities(opt: &Opt, url: &str, ledger: &str) -> Entities {
+    let mut target_opt = opt.clone();
+    target_opt.target = Some(url.to_string());
+    let mut target_instance = FlureeInstance::new_target(&target_opt);
+    let class_query = json!({
+        "@context": {},
+        "from": ledger,
+        "selectDistinct": ["?class"],
+        "where": [{ "@id": "?s", "@type": "?class" }]
+    });
+    let classes = target_instance
+        .v3_query_array(&class_query)
+        .await
+        .unwrap_or_else(|e| state::abort(opt, &e))
+        .iter()
+        .filter_map(|row| row.as_str().or(row.get(0).and_then(Value::as_str)))
+        .filter(|class| {
+            !META_NAMESPACES
+                .iter()
+                .any(|namespace| class.starts_with(namespace))
+        })
+        .map(str::to_string)
+        .collect::<BTreeSet<String>>();
+
+    let mut entities = Entities::default();
+    opt.pb.set_length(classes.len() as u64);
+    opt.pb.set_position(0);
+    opt.pb.set_prefix("Querying");
+    for class in &classes {
+        opt.pb.inc(1);
+        opt.pb.set_message(format!("{} {}", ledger, class));
+        let mut offset = 0;
+        loop {
+            let query = json!({
+                "@context": {},
+                "from": ledger,
+                "select": ["?s", "?p", "?o", "?dt"],
+                "where": [
+                    { "@id": "?s", "@type": class },
+                    { "@id": "?s", "?p": "?o" },
+                    ["bind", "?dt", "(datatype ?o)"]
+                ],
+                "orderBy": ["?s", "?p"],
+                "limit": DIFF_PAGE_SIZE,
+                "offset": offset
+            });
+            let rows = target_instance
+                .v3_query_array(&query)
+                .await
+                .unwrap_or_else(|e| state::abort(opt, &e));
+            let mut document = Map::new();
+            document.insert("@context".to_string(), json!({}));
+            document.insert(
+                "insert".to_string(),
+                Value::Array(rows.iter().filter_map(row_node).collect()),
+            );
+            for quad in document_quads(&document) {
+                entities.add(quad);
+            }
+            if rows.len() < DIFF_PAGE_SIZE {
+                break;
+            }
+            offset += DIFF_PAGE_SIZE;
+        }
+    }
+    entities.retain_data();
+    entities
+}
+
+// the entities of one side of the diff: a v3 server's ledger, or an output directory
+async fn entities(
+    opt: &Opt,
+    target: &str,
+    ledger: &Option<String>,
+    other_target: &str,
+) -> (String, Entities) {
+    if !is_url(target) {
+        let documents = directory_documents(Path::new(target));
+        return (target.to_string(), directory_entities(&documents));
+    }
+    // a ledger compared with an output directory defaults to the ledger the directory was written for
+    let ledger = ledger.clone().or(opt.ledger_name.clone()).or_else(|| {
+        (!is_url(other_target))
+            .then(|| directory_documents(Path::new(other_target)))
+            .into_iter()
+            .flatten()
+            .find_map(|document| document.get("ledger")?.as_str().map(str::to_string))
+    });
+    let Some(ledger) = ledger else {
+        pretty_print(
+            &format!(
+                "Diffing {} needs the name of its ledger, given with --ledger-a, --ledger-b, or --ledger-name",
+                target
+            ),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    };
+    let entities = ledger_entities(opt, target, &ledger).await;
+    (format!("{} ({})", ledger, target), entities)
+}
+
+/// Compares the entities of two migrated ledgers, or of a ledger and an output directory, and reports the
+/// entities only one of them holds and the property values that differ, e.g. between a re-run and a previous migration
+pub async fn diff(
+    opt: &Opt,
+    target_a: &str,
+    target_b: &str,
+    ledger_a: &Option<String>,
+    ledger_b: &Option<String>,
+) {
+    let green_bold = Style::new().green().bold();
+    let red_bold = Style::new().red().bold();
+    let yellow_bold = Style::new().yellow().bold();
+    let (name_a, a) = entities(opt, target_a, ledger_a, target_b).await;
+    let (name_b, b) = entities(opt, target_b, ledger_b, target_a).await;
+    opt.pb.finish_and_clear();
+
+    let mut report = DiffReport {
+        a: name_a.to_owned(),
+        b: name_b.to_owned(),
+        skipped_statements: a.skipped_statements + b.skipped_statements,
+        ..Default::default()
+    };
+    for (id, statements_a) in &a.statements {
+        let Some(statements_b) = b.statements.get(id) else {
+            report.deleted.push(id.to_owned());
+            continue;
+        };
+        let value_changes = |from: &HashSet<(String, Term)>, to: &HashSet<(String, Term)>| {
+            let mut changes = from
+                .difference(to)
+                .map(|(property, value)| ValueChange {
+                    property: property.to_owned(),
+                    value: term_string(value),
+                })
+                .collect::<Vec<ValueChange>>();
+            changes.sort_by(|x, y| (&x.property, &x.value).cmp(&(&y.property, &y.value)));
+            changes
+        };
+        let added = value_changes(statements_b, statements_a);
+        let removed = value_changes(statements_a, statements_b);
+        if added.is_empty() && removed.is_empty() {
+            report.unchanged += 1;
+        } else {
+            report.changed.push(ChangedEntity {
+                id: id.to_owned(),
+                added,
+                removed,
+            });
+        }
+    }
+    report.added = b
+        .statements
+        .keys()
+        .filter(|id| !a.statements.contains_key(*id))
+        .cloned()
+        .collect();
+
+    if let Some(report_path) = &opt.report {
+        if let Err(e) = fs::write(
+            report_path,
+            redact(&serde_json::to_string_pretty(&report).unwrap()),
+        ) {
+            pretty_print(
+                &format!("Could not write report to {}: {}", report_path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+        }
+    }
+    if report.skipped_statements > 0 {
+        println!(
+            "{:>12} {} statements about blank nodes can't be matched between the two, and weren't compared",
+            yellow_bold.apply_to("WARNING"),
+            report.skipped_statements
+        );
+    }
+    if report.added.is_empty() && report.deleted.is_empty() && report.changed.is_empty() {
+        println!(
+            "{:>12} {} entities of {} and {} are identical",
+            green_bold.apply_to("Compared"),
+            report.unchanged,
+            name_a,
+            name_b
+        );
+        return;
+    }
+    for (verb, ids) in [("Added", &report.added), ("Deleted", &report.deleted)] {
+        if ids.is_empty() {
+            continue;
+        }
+        println!("{:>12} {} entities", yellow_bold.apply_to(verb), ids.len());
+        for id in ids.iter().take(MAX_PRINTED_EXAMPLES) {
+            println!("{:>12} {}", "", id);
+        }
+    }
+    if !report.changed.is_empty() {
+        println!(
+            "{:>12} {} entities",
+            yellow_bold.apply_to("Changed"),
+            report.changed.len()
+        );
+        for changed in report.changed.iter().take(MAX_PRINTED_EXAMPLES) {
+            println!("{:>12} {}", "", changed.id);
+            for (sign, changes) in [("-", &changed.removed), ("+", &changed.added)] {
+                for change in changes.iter().take(MAX_PRINTED_EXAMPLES) {
+                    println!("{:>14} <{}> {}", sign, change.property, change.value);
+                }
+            }
+        }
+    }
+    println!(
+        "{:>12} {} differs from {}: {} added, {} deleted, {} changed, and {} unchanged entities",
+        red_bold.apply_to("ERROR"),
+        name_b,
+        name_a,
+        report.added.len(),
+        report.deleted.len(),
+        report.changed.len(),
+        report.unchanged
+    );
+    std::process::exit(1);
+}
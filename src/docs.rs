@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::cli::file_template::file_name_safe;
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:72rem;margin:2rem auto;padding:0 1rem;color:#222}\
+table{border-collapse:collapse;width:100%;margin:1rem 0}\
+th,td{border:1px solid #ddd;padding:.4rem .6rem;text-align:left;vertical-align:top}\
+th{background:#f4f4f4}code{background:#f4f4f4;padding:0 .2rem}a{color:#0b5cad}";
+
+// the SHACL keys shown in their own columns of a class's property table; the others are listed as constraints
+const PROPERTY_SHAPE_COLUMNS: [&str; 5] = [
+    "sh:path",
+    "sh:datatype",
+    "sh:class",
+    "sh:minCount",
+    "sh:maxCount",
+];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+fn class_page(class: &str) -> String {
+    format!("class-{}.html", file_name_safe(class))
+}
+
+// a string value of a node, which may be a language-tagged string
+fn str_of<'a>(node: &'a Value, key: &str) -> &'a str {
+    node[key]
+        .as_str()
+        .or(node[key]["@value"].as_str())
+        .unwrap_or_default()
+}
+
+fn ids(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(ids).collect(),
+        Value::Object(node) => node
+            .get("@id")
+            .and_then(Value::as_str)
+            .map(|id| vec![id.to_string()])
+            .unwrap_or_default(),
+        Value::String(id) => vec![id.to_string()],
+        _ => vec![],
+    }
+}
+
+// a SHACL value as text: the @id of a node, the items of a list, or the value itself
+fn constraint_value(value: &Value) -> String {
+    match value {
+        Value::Object(node) if node.contains_key("@id") => str_of(value, "@id").to_string(),
+        Value::Object(node) if node.contains_key("@list") => constraint_value(&node["@list"]),
+        Value::Array(values) => values
+            .iter()
+            .map(constraint_value)
+            .collect::<Vec<String>>()
+            .join(", "),
+        Value::String(text) => text.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Renders a vocab (as written to the vocab file) into static HTML reference documentation in `directory`: an
+/// `index.html` listing the classes and properties, and a page per class with its shape's constraints, when the
+/// vocab has SHACL shapes. Returns the number of class pages written
+pub fn write_docs(vocab: &Map<String, Value>, directory: &Path) -> std::io::Result<usize> {
+    let nodes = vocab
+        .get("insert")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let ledger = vocab
+        .get("ledger")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let of_type = |type_: &str| {
+        nodes
+            .iter()
+            .filter(|node| node["@type"] == type_)
+            .map(|node| (str_of(node, "@id").to_string(), node))
+            .collect::<BTreeMap<String, &Value>>()
+    };
+    let classes = of_type("rdfs:Class");
+    let properties = of_type("rdf:Property");
+    let shapes = nodes
+        .iter()
+        .filter(|node| node["@type"] == "sh:NodeShape")
+        .flat_map(|shape| {
+            ids(&shape["sh:targetClass"])
+                .into_iter()
+                .map(move |class| (class, shape))
+        })
+        .collect::<BTreeMap<String, &Value>>();
+
+    std::fs::create_dir_all(directory)?;
+
+    let class_link = |class: &str| match classes.contains_key(class) {
+        true => format!(
+            "<a href=\"{}\">{}</a>",
+            escape(&class_page(class)),
+            escape(class)
+        ),
+        false => format!("<code>{}</code>", escape(class)),
+    };
+
+    let mut index = format!("<h1>{} vocabulary</h1>\n", escape(ledger));
+    index.push_str(&format!(
+        "<h2>Classes ({})</h2>\n<table>\n<tr><th>Class</th><th>Label</th><th>Description</th><th>Properties</th></tr>\n",
+        classes.len()
+    ));
+    for (id, class) in &classes {
+        index.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            class_link(id),
+            escape(str_of(class, "rdfs:label")),
+            escape(str_of(class, "rdfs:comment")),
+            ids(&class["rdfs:range"]).len()
+        ));
+    }
+    index.push_str("</table>\n");
+    index.push_str(&format!(
+        "<h2>Properties ({})</h2>\n<table>\n<tr><th>Property</th><th>Label</th><th>Description</th><th>Domain</th><th>Indexed</th></tr>\n",
+        properties.len()
+    ));
+    for (id, property) in &properties {
+        index.push_str(&format!(
+            "<tr><td id=\"{}\"><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(id),
+            escape(id),
+            escape(str_of(property, "rdfs:label")),
+            escape(str_of(property, "rdfs:comment")),
+            ids(&property["rdfs:domain"])
+                .iter()
+                .map(|class| class_link(class))
+                .collect::<Vec<String>>()
+                .join(", "),
+            match property["fm:indexed"].as_bool() {
+                Some(true) => "yes",
+                _ => "",
+            }
+        ));
+    }
+    index.push_str("</table>\n");
+    std::fs::write(
+        directory.join("index.html"),
+        page(&format!("{} vocabulary", ledger), &index),
+    )?;
+
+    for (id, class) in &classes {
+        let shape = shapes.get(id);
+        let property_shapes = shape
+            .and_then(|shape| shape["sh:property"].as_array())
+            .map(|property_shapes| {
+                property_shapes
+                    .iter()
+                    .filter_map(|property_shape| {
+                        ids(&property_shape["sh:path"])
+                            .pop()
+                            .map(|path| (path, property_shape))
+                    })
+                    .collect::<BTreeMap<String, &Value>>()
+            })
+            .unwrap_or_default();
+
+        let mut body = format!(
+            "<p><a href=\"index.html\">{} vocabulary</a></p>\n<h1>{}</h1>\n",
+            escape(ledger),
+            escape(id)
+        );
+        for (key, label) in [("rdfs:label", "Label"), ("rdfs:comment", "Description")] {
+            if !str_of(class, key).is_empty() {
+                body.push_str(&format!(
+                    "<p><strong>{}:</strong> {}</p>\n",
+                    label,
+                    escape(str_of(class, key))
+                ));
+            }
+        }
+        if let Some(message) = shape.and_then(|shape| shape["sh:message"].as_str()) {
+            body.push_str(&format!(
+                "<p><strong>Shape message:</strong> {}</p>\n",
+                escape(message)
+            ));
+        }
+        body.push_str("<table>\n<tr><th>Property</th><th>Description</th><th>Type</th><th>Min</th><th>Max</th><th>Constraints</th></tr>\n");
+        let mut range = ids(&class["rdfs:range"]);
+        range.sort();
+        for property in range {
+            let property_shape = property_shapes.get(&property).copied();
+            let shape_value = |key: &str| {
+                property_shape
+                    .and_then(|property_shape| property_shape.get(key))
+                    .map(constraint_value)
+                    .unwrap_or_default()
+            };
+            let type_ =
+                match property_shape.and_then(|property_shape| property_shape.get("sh:class")) {
+                    Some(ref_class) => class_link(&constraint_value(ref_class)),
+                    None => escape(&shape_value("sh:datatype")),
+                };
+            let constraints = property_shape
+                .and_then(Value::as_object)
+                .map(|property_shape: &Map<String, Value>| {
+                    property_shape
+                        .iter()
+                        .filter(|(key, _)| !PROPERTY_SHAPE_COLUMNS.contains(&key.as_str()))
+                        .map(|(key, value)| {
+                            format!(
+                                "<code>{}</code> {}",
+                                escape(key),
+                                escape(&constraint_value(value))
+                            )
+                        })
+                        .collect::<Vec<String>>()
+                        .join("<br>")
+                })
+                .unwrap_or_default();
+            body.push_str(&format!(
+                "<tr><td><a href=\"index.html#{}\"><code>{}</code></a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape(&property),
+                escape(&property),
+                escape(
+                    properties
+                        .get(&property)
+                        .map(|node| str_of(node, "rdfs:comment"))
+                        .unwrap_or_default()
+                ),
+                type_,
+                escape(&shape_value("sh:minCount")),
+                escape(&shape_value("sh:maxCount")),
+                constraints
+            ));
+        }
+        body.push_str("</table>\n");
+        std::fs::write(directory.join(class_page(id)), page(id, &body))?;
+    }
+    Ok(classes.len())
+}
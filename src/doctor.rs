@@ -0,0 +1,308 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use dialoguer::console::Style;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::cli::opt::Opt;
+use crate::fluree::FlureeInstance;
+use crate::functions::format_bytes;
+use crate::probe;
+
+// the number of requests each server's round-trip latency is measured over
+const LATENCY_ROUNDS: usize = 3;
+
+// below this much free space where `.tmp` is written, a run that spools its fetched pages may run out of disk
+const MIN_TEMP_SPACE: u64 = 1 << 30;
+
+// a ledger name for checking the target when no --ledger-name is given; a v3 server answers a query of a missing
+// ledger with an error, which is enough to tell that it's there and speaks the v3 API
+const PROBE_LEDGER: &str = "fluree-migrate/doctor";
+
+#[derive(Default)]
+struct Checkup {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Checkup {
+    fn ok(&self, message: &str) {
+        println!(
+            "{:>12} {}",
+            Style::new().green().bold().apply_to("OK"),
+            message
+        );
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.warnings += 1;
+        println!(
+            "{:>12} {}",
+            Style::new().yellow().bold().apply_to("WARNING"),
+            message
+        );
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors += 1;
+        println!(
+            "{:>12} {}",
+            Style::new().red().bold().apply_to("ERROR"),
+            message
+        );
+    }
+}
+
+// the fastest and slowest of a server's round trips
+fn latency(round_trips: &[Duration]) -> String {
+    let fastest = round_trips.iter().min().copied().unwrap_or_default();
+    let slowest = round_trips.iter().max().copied().unwrap_or_default();
+    format!(
+        "{}-{} ms over {} requests",
+        fastest.as_millis(),
+        slowest.as_millis(),
+        round_trips.len()
+    )
+}
+
+/// Checks, before a long run, that the --source and --target can be reached and accept the run's credentials, that
+/// they speak the v2 and v3 APIs, how long a request to each takes, and how much disk space there is for `.tmp`.
+/// Exits with an error if any check fails
+pub async fn doctor(opt: &Opt) {
+    let mut checkup = Checkup::default();
+    check_source(opt, &mut checkup).await;
+    check_target(opt, &mut checkup).await;
+    check_temp_space(&mut checkup);
+
+    match checkup.errors {
+        0 => println!(
+            "{:>12} Ready to migrate ({} warnings)",
+            Style::new().green().bold().apply_to("Finished"),
+            checkup.warnings
+        ),
+        errors => {
+            println!(
+                "{:>12} {} checks failed ({} warnings)",
+                Style::new().red().bold().apply_to("Finished"),
+                errors,
+                checkup.warnings
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn check_source(opt: &Opt, checkup: &mut Checkup) {
+    if opt.mock || opt.extract_directory().is_some() || opt.from_cache.is_some() {
+        checkup.ok("Source isn't queried (--mock, transform, or --from-cache)");
+        return;
+    }
+    let Some(url) = opt.source.clone() else {
+        checkup.warning("No --source is given, so it wasn't checked");
+        return;
+    };
+    let mut source_instance = FlureeInstance::new_source(opt);
+
+    // the smallest query there is: one collection's _id
+    let ping = json!({
+        "select": ["_id"],
+        "from": "_collection",
+        "opts": { "limit": 1 }
+    })
+    .to_string();
+    let mut round_trips = vec![];
+    for _ in 0..LATENCY_ROUNDS {
+        let sent = Instant::now();
+        let response_result = source_instance.issue_data_query(ping.to_owned()).await;
+        let round_trip = sent.elapsed();
+        if let Err(e) = source_instance.validate_result(response_result).await {
+            match (source_instance.is_available, source_instance.is_authorized) {
+                (false, _) => match probe::explain(&probe::detect(&source_instance).await, &url) {
+                    Some(message) => checkup.error(&message),
+                    None => checkup.error(&format!("Source {} can't be reached: {}", url, e)),
+                },
+                (_, false) => checkup.error(&format!(
+                    "Source {} refused the request{}: {}",
+                    url,
+                    match opt.source_auth {
+                        Some(_) => "; check the --source-auth API key",
+                        None => "; it needs an API key, given with --source-auth",
+                    },
+                    e
+                )),
+                _ => checkup.error(&format!("Source {} answered with an error: {}", url, e)),
+            }
+            return;
+        }
+        round_trips.push(round_trip);
+    }
+    checkup.ok(&format!(
+        "Source {} answered in {}",
+        url,
+        latency(&round_trips)
+    ));
+    checkup.ok(&format!(
+        "Source accepted {}",
+        match opt.source_auth {
+            Some(_) => "the --source-auth API key",
+            None => "requests without an API key",
+        }
+    ));
+
+    let response_result = source_instance.issue_initial_query().await;
+    let schema = source_instance
+        .validate_result(response_result)
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_str::<Value>(&body).ok())
+        .unwrap_or_default();
+    match (
+        schema["current_predicates"].as_array(),
+        schema["collections"].as_array(),
+    ) {
+        (Some(predicates), Some(collections)) => checkup.ok(&format!(
+            "Source answers the v2 schema query ({} predicates, {} collections)",
+            predicates.len(),
+            collections.len()
+        )),
+        _ => match probe::explain(&probe::detect(&source_instance).await, &url) {
+            Some(message) => checkup.error(&message),
+            None => checkup.error(&format!(
+                "Source {} didn't answer the v2 schema query; is it the URL of a v2 ledger, e.g. \
+                 http://localhost:8090/fdb/network/ledger?",
+                url
+            )),
+        },
+    }
+}
+
+async fn check_target(opt: &Opt, checkup: &mut Checkup) {
+    let Some(url) = opt.target.clone() else {
+        checkup.ok("No --target is given, so the output is written locally");
+        return;
+    };
+    let mut target_instance = FlureeInstance::new_target(opt);
+    let ledger = opt.ledger_name.clone().unwrap_or(PROBE_LEDGER.to_string());
+    let query = json!({
+        "from": ledger,
+        "select": ["?s"],
+        "where": { "@id": "?s" },
+        "limit": 1
+    })
+    .to_string();
+
+    let mut round_trips = vec![];
+    let mut answer = None;
+    for _ in 0..LATENCY_ROUNDS {
+        let sent = Instant::now();
+        let response_result = target_instance.v3_query(query.to_owned()).await;
+        round_trips.push(sent.elapsed());
+        match response_result {
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                answer = Some((status, body));
+            }
+            Err(e) => {
+                checkup.error(&format!("Target {} can't be reached: {}", url, e));
+                return;
+            }
+        }
+    }
+    let Some((status, body)) = answer else {
+        return;
+    };
+    checkup.ok(&format!(
+        "Target {} answered in {}",
+        url,
+        latency(&round_trips)
+    ));
+
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        checkup.error(&format!(
+            "Target {} refused the request ({}){}",
+            url,
+            status,
+            match opt.target_auth {
+                Some(_) => "; check the --target-auth API key",
+                None => "; it needs an API key, given with --target-auth or --auth-file",
+            }
+        ));
+        return;
+    }
+    checkup.ok(&format!(
+        "Target accepted {}",
+        match opt.target_auth {
+            Some(_) => "the --target-auth API key",
+            None => "requests without an API key",
+        }
+    ));
+
+    if serde_json::from_str::<Value>(&body).is_err() {
+        checkup.error(&format!(
+            "Target {} didn't answer a v3 query with JSON ({}); is it the URL of a v3 server, e.g. \
+             http://localhost:58090?",
+            url, status
+        ));
+        return;
+    }
+    match (
+        status.is_success(),
+        opt.ledger_name.is_some(),
+        opt.is_create_ledger || opt.create_dataset,
+    ) {
+        (true, true, true) => checkup.warning(&format!(
+            "Target ledger {} already exists, but --create-ledger (or --create-dataset) would create it",
+            ledger
+        )),
+        (true, true, false) => checkup.ok(&format!("Target ledger {} exists", ledger)),
+        (false, true, true) => checkup.ok(&format!(
+            "Target ledger {} doesn't exist yet, and will be created",
+            ledger
+        )),
+        (false, true, false) => checkup.warning(&format!(
+            "Target ledger {} couldn't be queried ({}); unless it exists, add --create-ledger",
+            ledger, status
+        )),
+        (_, false, _) => checkup.ok("Target answers v3 queries"),
+    }
+}
+
+// the free space, in bytes, of the filesystem holding `path`, as `df` reports it
+fn available_space(path: &Path) -> Option<u64> {
+    let output = std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let available_kilobytes = output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse::<u64>()
+        .ok()?;
+    Some(available_kilobytes * 1024)
+}
+
+fn check_temp_space(checkup: &mut Checkup) {
+    let working_directory = std::env::current_dir().unwrap_or_default();
+    match available_space(&working_directory) {
+        Some(space) if space < MIN_TEMP_SPACE => checkup.warning(&format!(
+            "Only {} is free for .tmp in {}; runs that spool fetched pages need about the size of the v2 data",
+            format_bytes(space as usize),
+            working_directory.display()
+        )),
+        Some(space) => checkup.ok(&format!(
+            "{} is free for .tmp in {}",
+            format_bytes(space as usize),
+            working_directory.display()
+        )),
+        None => checkup.warning(&format!(
+            "Couldn't measure the free space for .tmp in {}",
+            working_directory.display()
+        )),
+    }
+}
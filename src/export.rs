@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Instant;
+
+use dialoguer::console::Style;
+use indicatif::HumanDuration;
+use serde_json::{json, Map, Value};
+
+use crate::cli::opt::{Opt, OutputFormat};
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::diff::META_NAMESPACES;
+use crate::fluree::{FlureeInstance, DATA_CHUNK_SIZE};
+use crate::parquet;
+use crate::state;
+use crate::verify::row_node;
+
+const EXPORT_PAGE_SIZE: usize = 10_000;
+
+/// The JSON-LD files of an export, cut into data files as a migration's are
+struct ExportFiles {
+    opt: Opt,
+    ledger: String,
+    vocab: Vec<Value>,
+    data: Vec<Value>,
+    data_size: usize,
+    data_classes: BTreeSet<String>,
+    file_num: u64,
+    entities: u64,
+}
+
+// adds the property (or type) of a row's node to the node of its subject; a property with several values becomes
+// an array of them
+fn merge_row_node(node: &mut Map<String, Value>, row_node: Value) {
+    let Value::Object(row_node) = row_node else {
+        return;
+    };
+    for (key, value) in row_node {
+        match node.get_mut(&key) {
+            None => {
+                node.insert(key, value);
+            }
+            Some(_) if key == "@id" => {}
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+        }
+    }
+}
+
+fn node_types(node: &Map<String, Value>) -> Vec<&str> {
+    match node.get("@type") {
+        Some(Value::String(class)) => vec![class.as_str()],
+        Some(Value::Array(classes)) => classes.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    }
+}
+
+// a node only typed with classes that describe the vocab and shapes (e.g. rdfs:Class, rdf:Property, sh:NodeShape)
+fn is_vocab_node(node: &Map<String, Value>) -> bool {
+    let types = node_types(node);
+    !types.is_empty()
+        && types.iter().all(|class| {
+            META_NAMESPACES
+                .iter()
+                .any(|namespace| class.starts_with(namespace))
+        })
+}
+
+// the last segment of a class IRI, as the {class} of a --file-template
+fn class_name(class: &str) -> String {
+    class
+        .rsplit(['/', '#', ':'])
+        .next()
+        .unwrap_or(class)
+        .to_string()
+}
+
+// a transaction of nodes with full IRIs, as the ledger answered them
+fn document(ledger: &str, nodes: Vec<Value>) -> String {
+    let document = json!({
+        "ledger": ledger,
+        "@context": {},
+        "insert": nodes,
+    });
+    serde_json::to_string_pretty(&document).unwrap()
+}
+
+impl ExportFiles {
+    fn new(opt: &Opt, ledger: &str) -> Self {
+        ExportFiles {
+            opt: opt.to_owned(),
+            ledger: ledger.to_string(),
+            vocab: vec![],
+            data: vec![],
+            data_size: 0,
+            data_classes: BTreeSet::new(),
+            file_num: opt.first_data_file_number(ledger),
+            entities: 0,
+        }
+    }
+
+    async fn add(&mut self, node: Map<String, Value>) {
+        self.entities += 1;
+        if is_vocab_node(&node) {
+            self.vocab.push(Value::Object(node));
+            return;
+        }
+        self.data_size += serde_json::to_string(&node).unwrap_or_default().len();
+        self.data_classes
+            .extend(node_types(&node).into_iter().map(class_name));
+        self.data.push(Value::Object(node));
+        if self.data_size as u64 >= DATA_CHUNK_SIZE {
+            self.write_data().await;
+        }
+    }
+
+    async fn write_data(&mut self) {
+        if self.data.is_empty() {
+            return;
+        }
+        let classes = std::mem::take(&mut self.data_classes)
+            .into_iter()
+            .collect::<Vec<String>>();
+        let file_name = self
+            .opt
+            .data_file_name(self.file_num, &classes, &self.ledger);
+        let data = document(&self.ledger, std::mem::take(&mut self.data));
+        if let Err(e) = self.opt.write_or_print(file_name, data, None).await {
+            state::abort(&self.opt, &e);
+        }
+        self.data_size = 0;
+        self.file_num += 1;
+    }
+
+    async fn finish(&mut self) {
+        self.write_data().await;
+        if !self.opt.data_only {
+            let vocab = document(&self.ledger, std::mem::take(&mut self.vocab));
+            if let Err(e) = self
+                .opt
+                .write_or_print(self.opt.vocab_file_name(&self.ledger), vocab, None)
+                .await
+            {
+                state::abort(&self.opt, &e);
+            }
+        }
+        if self.opt.output_format == OutputFormat::Parquet {
+            parquet::write_class_files(&self.opt);
+        }
+    }
+}
+
+/// Reads every statement of a v3 ledger (the --target with --ledger-name) and writes them to a directory as a
+/// migration would have: its classes, properties, and shapes to the vocab file, and the rest of its entities to
+/// data files of about the same size, so `load` (or --input) can transact them to a ledger again
+pub async fn export(opt: &Opt, directory: &Path) {
+    let start = Instant::now();
+    let green_bold = Style::new().green().bold();
+    let Some(ledger) = opt.ledger_name.clone() else {
+        pretty_print(
+            "Exporting needs the --ledger-name of the v3 ledger to read",
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    };
+    let mut target_instance = FlureeInstance::new_target(opt);
+
+    // the files are written locally, never transacted back to the --target they're read from
+    let mut write_opt = opt.clone();
+    write_opt.target = None;
+    write_opt.txn_opts = None;
+    write_opt.output = Some(directory.to_path_buf());
+    write_opt.print = false;
+    write_opt.clear_output_directory();
+    let mut files = ExportFiles::new(&write_opt, &ledger);
+
+    opt.pb.set_prefix("Exporting");
+    let mut node = Map::new();
+    let mut statements = 0;
+    let mut offset = 0;
+    loop {
+        let query = json!({
+            "@context": {},
+            "from": ledger,
+            "select": ["?s", "?p", "?o", "?dt"],
+            "where": [
+                { "@id": "?s", "?p": "?o" },
+                ["bind", "?dt", "(datatype ?o)"]
+            ],
+            "orderBy": ["?s", "?p"],
+            "limit": EXPORT_PAGE_SIZE,
+            "offset": offset
+        });
+        let rows = target_instance
+            .v3_query_array(&query)
+            .await
+            .unwrap_or_else(|e| state::abort(opt, &e));
+        // the rows are ordered by subject, so a node is complete once the next row is of another subject, which may
+        // be on the next page
+        for row in &rows {
+            let Some(row_node) = row_node(row) else {
+                continue;
+            };
+            statements += 1;
+            if node.contains_key("@id") && node.get("@id") != row_node.get("@id") {
+                files.add(std::mem::take(&mut node)).await;
+            }
+            merge_row_node(&mut node, row_node);
+        }
+        opt.pb
+            .set_message(format!("{} statements of {}", statements, ledger));
+        if rows.len() < EXPORT_PAGE_SIZE {
+            break;
+        }
+        offset += EXPORT_PAGE_SIZE;
+    }
+    if !node.is_empty() {
+        files.add(node).await;
+    }
+    files.finish().await;
+    opt.pb.finish_and_clear();
+
+    println!(
+        "{:>12} {} ({} entities, {} statements) to {} in {}",
+        green_bold.apply_to("Exported"),
+        ledger,
+        files.entities,
+        statements,
+        directory.display(),
+        HumanDuration(start.elapsed())
+    );
+}
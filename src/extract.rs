@@ -0,0 +1,525 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use dialoguer::console::Style;
+use indicatif::HumanDuration;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::cassette::response;
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+use crate::functions::read_json_file;
+use crate::id_ranges::IdRanges;
+use crate::mock::page_response;
+use crate::probe;
+use crate::state;
+use crate::store;
+
+/// The file, in an extract directory, that describes the snapshot: its source, block, and collections
+pub const EXTRACT_MANIFEST_FILE: &str = "manifest.json";
+
+/// The file, in an extract directory, holding the source's answer to the v2 schema multi-query, as is
+pub const SCHEMA_FILE: &str = "schema.json";
+
+/// The file, in an extract directory, recording how far each collection was fetched, so a rerun of an interrupted
+/// extract resumes where it stopped. It's removed once the extract completes
+pub const EXTRACT_CHECKPOINT_FILE: &str = "checkpoint.json";
+
+// the directory `extract` writes to when no --output is given
+const DEFAULT_EXTRACT_DIRECTORY: &str = "raw";
+
+const EXTRACT_PAGE_SIZE: u64 = 5000;
+
+// the system collections whose entities a migration reads: tags, users, and auth records
+const SYSTEM_COLLECTIONS: [&str; 3] = ["_tag", "_user", "_auth"];
+
+/// What `extract` wrote: a snapshot of a v2 ledger's schema and entities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractManifest {
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<String>,
+    pub extracted_at: String,
+    pub schema: String,
+    pub collections: Vec<ExtractedCollection>,
+}
+
+/// The entities of a v2 collection, one compact JSON object per line of `file`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractedCollection {
+    pub name: String,
+    pub file: String,
+    pub entities: u64,
+}
+
+/// How far an extract got, saved after every page it fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExtractCheckpoint {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_of: Option<String>,
+    #[serde(rename = "where", skip_serializing_if = "Option::is_none")]
+    where_filters: Option<Map<String, Value>>,
+    collections: BTreeMap<String, CollectionCheckpoint>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionCheckpoint {
+    /// The offset of the next page to fetch
+    pub offset: u64,
+    pub entities: u64,
+    /// The length of the collection's file after the last page fetched; anything past it is from a page that was
+    /// being written when the extract stopped
+    pub bytes: u64,
+    pub complete: bool,
+}
+
+impl ExtractCheckpoint {
+    fn new(opt: &Opt, source: &str) -> Self {
+        ExtractCheckpoint {
+            source: source.to_string(),
+            block: opt.block,
+            as_of: opt.as_of.to_owned(),
+            where_filters: opt.where_filters.to_owned(),
+            collections: BTreeMap::new(),
+        }
+    }
+
+    // the checkpoint an interrupted extract of the same snapshot (source, --block or --as-of, and --where) left in
+    // the directory (or in the --state-db), if any
+    fn read(directory: &Path, opt: &Opt, source: &str) -> Option<Self> {
+        let checkpoint = match store::is_started() {
+            true => {
+                let mut checkpoint =
+                    serde_json::from_value::<Self>(store::extract_snapshot(directory)?).ok()?;
+                checkpoint.collections = store::extract_progress(directory).into_iter().collect();
+                checkpoint
+            }
+            false => {
+                let contents = std::fs::read(directory.join(EXTRACT_CHECKPOINT_FILE)).ok()?;
+                serde_json::from_slice::<Self>(&contents).ok()?
+            }
+        };
+        let snapshot = Self::new(opt, source);
+        (checkpoint.source == snapshot.source
+            && checkpoint.block == snapshot.block
+            && checkpoint.as_of == snapshot.as_of
+            && checkpoint.where_filters == snapshot.where_filters)
+            .then_some(checkpoint)
+    }
+
+    // a new extract forgets the progress any other one recorded in the --state-db for the directory
+    fn begin(&self, directory: &Path) {
+        if store::is_started() {
+            store::start_extract(directory, &serde_json::to_value(self).unwrap());
+        }
+    }
+
+    // records the progress of a collection after a page, along with the ids of the page's entities in the
+    // --state-db, or else in the checkpoint file
+    fn save(
+        &mut self,
+        directory: &Path,
+        collection: &str,
+        progress: CollectionCheckpoint,
+        page_ids: &[i64],
+    ) {
+        self.collections
+            .insert(collection.to_string(), progress.clone());
+        match store::is_started() {
+            true => store::save_extract_page(directory, collection, page_ids, &progress),
+            false => self.write(directory),
+        }
+    }
+
+    fn remove(directory: &Path) {
+        match store::is_started() {
+            true => store::finish_extract(directory),
+            false => {
+                let _ = std::fs::remove_file(directory.join(EXTRACT_CHECKPOINT_FILE));
+            }
+        }
+    }
+
+    // written to a temp file and renamed, so a run killed while writing it leaves the previous checkpoint whole
+    fn write(&self, directory: &Path) {
+        let path = directory.join(EXTRACT_CHECKPOINT_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        write_file(
+            &temp_path,
+            serde_json::to_string_pretty(self).unwrap().as_bytes(),
+        );
+        if let Err(e) = std::fs::rename(&temp_path, &path) {
+            pretty_print(
+                &format!("Could not write {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+impl ExtractManifest {
+    pub fn read(directory: &Path) -> Self {
+        serde_json::from_value(read_json_file(
+            &directory.join(EXTRACT_MANIFEST_FILE),
+            "extract manifest",
+        ))
+        .unwrap_or_else(|e| {
+            pretty_print(
+                &format!("Invalid extract manifest in {}: {}", directory.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        })
+    }
+}
+
+/// The answer to the v2 schema multi-query from an extract directory, for `transform`
+pub fn schema_response(directory: &Path) -> Response {
+    let manifest = ExtractManifest::read(directory);
+    let schema = read_json_file(&directory.join(manifest.schema), "extracted schema");
+    response(200, schema.to_string())
+}
+
+/// The answer to a v2 data query from an extract directory, for `transform`: the entities of its `from` collection
+/// (or with its `from` ids), paged by its `offset` and `limit`. Its `where` and `block` were applied by `extract`
+pub fn query_response(directory: &Path, query: &str) -> Response {
+    let query = serde_json::from_str::<Value>(query).unwrap_or_default();
+    let manifest = ExtractManifest::read(directory);
+    let entities = |collection: &ExtractedCollection| {
+        let path = directory.join(&collection.file);
+        let file = File::open(&path).unwrap_or_else(|e| {
+            pretty_print(
+                &format!("Could not read {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        });
+        BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<Value>(&line).ok())
+    };
+    match &query["from"] {
+        Value::String(name) => match manifest
+            .collections
+            .iter()
+            .find(|collection| &collection.name == name)
+        {
+            Some(collection) => page_response(&query, entities(collection)),
+            None => page_response(&query, std::iter::empty()),
+        },
+        Value::Array(ids) => page_response(
+            &query,
+            manifest
+                .collections
+                .iter()
+                .flat_map(entities)
+                .filter(|entity| ids.contains(&entity["_id"])),
+        ),
+        _ => page_response(&query, std::iter::empty()),
+    }
+}
+
+/// Fetches the schema and the entities of every migrated collection of the v2 --source, and writes them to the
+/// --output directory as is, without transforming them: `schema.json`, one `<collection>.jsonl` per collection,
+/// and a `manifest.json` describing them
+pub async fn extract(opt: &Opt) {
+    let start = Instant::now();
+    let green_bold = Style::new().green().bold();
+    let red_bold = Style::new().red().bold();
+    let yellow_bold = Style::new().yellow().bold();
+    let mut opt = opt.clone();
+    let directory = opt
+        .output
+        .get_or_insert_with(|| PathBuf::from(DEFAULT_EXTRACT_DIRECTORY))
+        .to_owned();
+    let mut source_instance = FlureeInstance::new_source(&opt);
+
+    // an interrupted extract of the same snapshot is resumed (unless --force starts it over), rather than the
+    // directory it was writing cleared
+    let resumed = match opt.force {
+        true => None,
+        false => ExtractCheckpoint::read(&directory, &opt, &source_instance.url),
+    };
+    if resumed.is_none() {
+        opt.clear_output_directory();
+    }
+    if let Err(e) = std::fs::create_dir_all(&directory) {
+        pretty_print(
+            &format!("Could not create directory {}: {}", directory.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    }
+    let mut checkpoint = match resumed {
+        Some(checkpoint) => checkpoint,
+        None => {
+            let checkpoint = ExtractCheckpoint::new(&opt, &source_instance.url);
+            checkpoint.begin(&directory);
+            checkpoint
+        }
+    };
+    opt.pb.println(format!(
+        "{:>12} v2 Schema",
+        green_bold.apply_to("Extracting")
+    ));
+    let schema = loop {
+        let response_result = source_instance.issue_initial_query().await;
+        match source_instance.validate_result(response_result).await {
+            Ok(schema) => {
+                if !probe::is_v2_schema(&schema) {
+                    probe::exit_unless_v2(&source_instance).await;
+                }
+                break schema;
+            }
+            Err(e) => {
+                opt.pb
+                    .println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                if !source_instance.is_available {
+                    probe::exit_unless_v2(&source_instance).await;
+                    source_instance.prompt_fix_url();
+                }
+                if !source_instance.is_authorized {
+                    source_instance.prompt_api_key();
+                }
+                if source_instance.is_available && source_instance.is_authorized {
+                    if let Err(e) = source_instance.wait_to_retry(&opt.pb).await {
+                        state::abort(&opt, &e);
+                    }
+                }
+            }
+        }
+    };
+    write_file(&directory.join(SCHEMA_FILE), schema.as_bytes());
+
+    let collection_names = serde_json::from_str::<Value>(&schema)
+        .ok()
+        .and_then(|schema| schema["collections"].as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|collection| collection["name"].as_str().map(str::to_string))
+        .filter(|name| !name.starts_with('_') || SYSTEM_COLLECTIONS.contains(&name.as_str()))
+        .filter(|name| name.starts_with('_') || opt.is_migrated_class(name))
+        .collect::<Vec<String>>();
+
+    opt.pb.set_length(collection_names.len() as u64);
+    opt.pb.set_prefix("Extracting");
+    let mut collections = vec![];
+    for name in collection_names {
+        opt.pb.set_message(name.to_owned());
+        let file_name = format!("{}.jsonl", name);
+        let progress = checkpoint
+            .collections
+            .get(&name)
+            .cloned()
+            .unwrap_or_default();
+        let entities = match progress.complete {
+            true => {
+                opt.pb.println(format!(
+                    "{:>12} {} ({} entities already extracted)",
+                    yellow_bold.apply_to("Skipping"),
+                    name,
+                    progress.entities
+                ));
+                progress.entities
+            }
+            false => {
+                if progress.offset > 0 {
+                    opt.pb.println(format!(
+                        "{:>12} {} at offset {} ({} entities already extracted)",
+                        yellow_bold.apply_to("Resuming"),
+                        name,
+                        progress.offset,
+                        progress.entities
+                    ));
+                }
+                let entities = extract_collection(
+                    &mut source_instance,
+                    &name,
+                    &directory,
+                    &file_name,
+                    &mut checkpoint,
+                )
+                .await;
+                opt.pb.println(format!(
+                    "{:>12} {} entities of {}",
+                    green_bold.apply_to("Extracted"),
+                    entities,
+                    name
+                ));
+                entities
+            }
+        };
+        opt.pb.inc(1);
+        collections.push(ExtractedCollection {
+            name,
+            file: file_name,
+            entities,
+        });
+    }
+    opt.pb.finish_and_clear();
+
+    let manifest = ExtractManifest {
+        source: source_instance.url.to_owned(),
+        block: opt.block,
+        as_of: opt.as_of.to_owned(),
+        extracted_at: chrono::Utc::now().to_rfc3339(),
+        schema: SCHEMA_FILE.to_string(),
+        collections,
+    };
+    write_file(
+        &directory.join(EXTRACT_MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest).unwrap().as_bytes(),
+    );
+    ExtractCheckpoint::remove(&directory);
+    println!(
+        "{:>12} {} collections ({} entities) to {}/ in {}",
+        green_bold.apply_to("Extracted"),
+        manifest.collections.len(),
+        manifest
+            .collections
+            .iter()
+            .map(|collection| collection.entities)
+            .sum::<u64>(),
+        directory.display(),
+        HumanDuration(start.elapsed())
+    );
+}
+
+// opens a collection's file to resume writing it, cut back to the length it had at its last checkpoint; the
+// entities it holds count as seen, and are read back from it unless the --state-db kept their ids
+fn open_resumed(path: &Path, bytes: u64, seen_ids: &mut IdRanges) -> std::io::Result<File> {
+    let file = OpenOptions::new().read(true).append(true).open(path)?;
+    file.set_len(bytes)?;
+    if store::is_started() {
+        return Ok(file);
+    }
+    for line in BufReader::new(&file).lines() {
+        if let Some(id) = serde_json::from_str::<Value>(&line?)
+            .ok()
+            .and_then(|entity| entity["_id"].as_i64())
+        {
+            seen_ids.insert(id);
+        }
+    }
+    Ok(file)
+}
+
+// pages through the entities of a collection (at the --block, filtered by --where) into a JSON-lines file,
+// returning how many were written. Paging stops at an empty page, or one holding only entities already seen.
+// The checkpoint is saved after every page, and a collection it has an offset for is resumed from there
+async fn extract_collection(
+    source_instance: &mut FlureeInstance,
+    name: &str,
+    directory: &Path,
+    file_name: &str,
+    checkpoint: &mut ExtractCheckpoint,
+) -> u64 {
+    let path = directory.join(file_name);
+    let progress = checkpoint
+        .collections
+        .get(name)
+        .cloned()
+        .unwrap_or_default();
+    let mut seen_ids = match store::is_started() {
+        true => store::extracted_ids(directory, name),
+        false => IdRanges::default(),
+    };
+    let file = match progress.bytes {
+        0 => File::create(&path),
+        bytes => open_resumed(&path, bytes, &mut seen_ids),
+    }
+    .unwrap_or_else(|e| {
+        pretty_print(
+            &format!("Could not create {}: {}", path.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    });
+    let mut writer = BufWriter::new(file);
+    let mut offset = progress.offset;
+    let mut bytes = progress.bytes;
+    loop {
+        let query = source_instance.filtered(
+            json!({
+                "select": ["*"],
+                "from": name,
+                "opts": {
+                    "compact": true,
+                    "limit": EXTRACT_PAGE_SIZE,
+                    "fuel": 9999999999u64,
+                    "offset": offset
+                }
+            }),
+            name,
+        );
+        let page = source_instance
+            .query_array(&query)
+            .await
+            .unwrap_or_else(|e| state::abort(&source_instance.opt, &e));
+        let mut page_ids = vec![];
+        for entity in page {
+            if let Some(id) = entity["_id"].as_i64().filter(|&id| seen_ids.insert(id)) {
+                page_ids.push(id);
+                let line = format!("{}\n", entity);
+                writer
+                    .write_all(line.as_bytes())
+                    .expect("Unable to write data");
+                bytes += line.len() as u64;
+            }
+        }
+        if page_ids.is_empty() {
+            break;
+        }
+        offset += EXTRACT_PAGE_SIZE;
+        writer.flush().expect("Unable to write data");
+        let progress = CollectionCheckpoint {
+            offset,
+            entities: seen_ids.len(),
+            bytes,
+            complete: false,
+        };
+        checkpoint.save(directory, name, progress, &page_ids);
+    }
+    writer.flush().expect("Unable to write data");
+    let progress = CollectionCheckpoint {
+        offset,
+        entities: seen_ids.len(),
+        bytes,
+        complete: true,
+    };
+    checkpoint.save(directory, name, progress, &[]);
+    seen_ids.len()
+}
+
+fn write_file(path: &Path, contents: &[u8]) {
+    if let Err(e) = std::fs::write(path, contents) {
+        pretty_print(
+            &format!("Could not write {}: {}", path.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    }
+}
@@ -1,28 +1,58 @@
-use std::collections::{HashMap, HashSet};
-use std::io::stdout;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crossterm::execute;
-use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::style::Color;
 use dialoguer::console::{Style, Term};
-use dialoguer::{theme::ColorfulTheme, Input};
-use indicatif::{HumanDuration, ProgressStyle};
+use dialoguer::{theme::ColorfulTheme, Input, Password};
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use log::Level;
 use reqwest::{header::HeaderMap, Client, Error, Response};
 use serde_json::{json, Value};
 use tokio::sync::Mutex;
 
-use crate::cli::opt::Opt;
+use crate::align;
+use crate::batching::is_batch_too_large;
+use crate::cache::QueryCache;
+use crate::cassette;
+use crate::changes::{EntityHashes, WrittenHashes};
+use crate::cli::opt::{
+    Command, DanglingRefs, DatasetVisibility, Opt, OutputFormat, ShardBy, TxnMode,
+};
 use crate::cli::parser::Parser;
 use crate::cli::source::Migrate;
-use crate::cli::temp_files::TempFile;
+use crate::cli::temp_files::{TempFile, TEMP_DIRECTORY};
 use crate::console::{pretty_print, ERROR_COLOR};
+use crate::diagram;
+use crate::docs;
+use crate::extract;
+use crate::frame::Frame;
 use crate::functions::{
-    capitalize, case_normalize, instant_to_iso_string, parse_current_predicates,
-    parse_for_class_and_property_name, represent_fluree_value, standardize_class_name,
-    standardize_property_name,
+    capitalize, case_normalize, format_bytes, parse_current_predicates,
+    parse_for_class_and_property_name, policy_txn, pretty_log, read_json_file,
+    response_error_message, seeded_hash,
+};
+use crate::graphql;
+use crate::hints::{IndexHints, INDEX_HINTS_FILE};
+use crate::id_ranges::IdRanges;
+use crate::keys::basic_auth_header;
+use crate::mock;
+use crate::parquet;
+use crate::probe;
+use crate::profile::DataProfiler;
+use crate::redact::{redact, register_secret};
+use crate::report::{
+    ClassStatistics, DanglingReference, MigrationReport, PiiScanner, ReferenceTracker,
 };
+use crate::state as run_state;
+use crate::store;
+use crate::trace;
+use crate::transaction::{
+    merge_txn_opts, merge_typed_nodes, merge_upserted_nodes, replace_existing_values, upsert_nodes,
+};
+use crate::verify::{DatasetHash, DatasetManifest, MANIFEST_FILE};
 
 const SCHEMA_QUERY: &str = r#"{
     "initial_predicates": {
@@ -36,7 +66,7 @@ const SCHEMA_QUERY: &str = r#"{
         }
     },
     "current_predicates": {
-        "select": {"?pred": ["*"]},
+        "select": {"?pred": ["*", {"_predicate/spec": ["*"]}]},
         "where": [
             ["?pred", "_predicate/name", "?pN"]
         ],
@@ -44,9 +74,42 @@ const SCHEMA_QUERY: &str = r#"{
             "compact": true,
             "limit": 9999999
         }
+    },
+    "collections": {
+        "select": {"?coll": ["*", {"_collection/spec": ["*"]}]},
+        "where": [
+            ["?coll", "_collection/name", "?cN"]
+        ],
+        "opts": {
+            "compact": true,
+            "limit": 9999999
+        }
     }
 }"#;
 
+pub const MAX_ERROR_RETRIES: u32 = 5;
+pub const ERROR_RETRY_DELAY: Duration = Duration::from_secs(5);
+// data files are cut whenever the v2 data behind them passes this many bytes
+pub const DATA_CHUNK_SIZE: u64 = 2_500_000;
+// the number of entities per page of a class's data query, before --adaptive-batching adjusts it
+const SOURCE_PAGE_SIZE: u64 = 5000;
+// the number of transformed data chunks that can wait for the one being written
+const WRITE_QUEUE_CHUNKS: usize = 2;
+// a data file's name, its document, and the --changed-only content hashes of its entities
+type Chunk = (
+    String,
+    serde_json::Map<String, Value>,
+    HashMap<String, String>,
+);
+// the chunk writer's task, which answers with the hashes of the entities it wrote or the error it gave up on
+type ChunkWriter = tokio::task::JoinHandle<Result<WrittenHashes, String>>;
+// the number of fetched pages that can wait to be transformed when they're streamed rather than spooled
+const FETCH_QUEUE_PAGES: usize = 4;
+// the v2 class of a fetched page and its entities
+type Page = (String, Vec<Value>);
+// the number of entities per class that --estimate measures
+const ESTIMATE_SAMPLE: usize = 100;
+
 #[derive(Debug, Clone)]
 pub struct FlureeInstance {
     pub url: String,
@@ -55,8 +118,15 @@ pub struct FlureeInstance {
     pub is_available: bool,
     pub is_authorized: bool,
     pub api_key: Option<String>,
+    /// The `Authorization` header value for a basic-auth proxy in front of the instance
+    pub basic_auth: Option<String>,
     pub client: Client,
     pub is_created: bool,
+    /// Whether the target's Nexus dataset exists, or needn't be created with --create-dataset
+    pub is_dataset_created: bool,
+    pub error_retries: u32,
+    pub initial_policy: Option<Value>,
+    pub cache: Option<QueryCache>,
     pub opt: Opt,
 }
 
@@ -71,8 +141,13 @@ impl FlureeInstance {
             is_available: true,
             is_authorized: true,
             api_key: opt.source_auth.clone(),
+            basic_auth: opt.source_basic_auth.as_deref().map(basic_auth_header),
             client: reqwest::Client::new(),
             is_created: true,
+            is_dataset_created: true,
+            error_retries: 0,
+            initial_policy: None,
+            cache: QueryCache::new(opt, &url),
             opt: opt.clone(),
         }
     }
@@ -81,6 +156,10 @@ impl FlureeInstance {
         let url = opt.check_url(false);
         let (network_name, db_name) = Self::get_db_name(&url);
         let is_created = !opt.is_create_ledger;
+        let initial_policy = opt
+            .initial_policy
+            .as_ref()
+            .map(|path| read_json_file(path, "initial policy"));
         FlureeInstance {
             url: url.to_string(),
             network_name,
@@ -88,13 +167,18 @@ impl FlureeInstance {
             is_available: true,
             is_authorized: true,
             api_key: opt.target_auth.clone(),
+            basic_auth: opt.target_basic_auth.as_deref().map(basic_auth_header),
             client: reqwest::Client::new(),
             is_created,
+            is_dataset_created: !opt.create_dataset,
+            error_retries: 0,
+            initial_policy,
+            cache: None,
             opt: opt.clone(),
         }
     }
 
-    fn get_db_name(url: &str) -> (String, String) {
+    pub fn get_db_name(url: &str) -> (String, String) {
         let mut url_parts = url
             .split("/")
             .collect::<Vec<&str>>()
@@ -113,7 +197,7 @@ impl FlureeInstance {
             .show_default(true)
             .validate_with({
                 move |input: &String| -> Result<(), &str> {
-                    if let Ok(_) = reqwest::Url::parse(input) {
+                    if reqwest::Url::parse(input).is_ok() {
                         Ok(())
                     } else {
                         Err("Please provide a valid URL")
@@ -125,38 +209,70 @@ impl FlureeInstance {
     }
 
     pub fn prompt_api_key(&mut self) {
-        self.api_key = Some(
-            Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Nexus API Key:")
-                .interact_text()
-                .unwrap(),
-        );
+        let api_key = Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("Nexus API Key:")
+            .interact()
+            .unwrap();
+        register_secret(&api_key);
+        self.api_key = Some(api_key);
     }
 
     pub async fn v3_transact(&mut self, body: String) -> Result<Response, Error> {
-        let mut request_headers = HeaderMap::new();
-        request_headers.insert("Content-Type", "application/json".parse().unwrap());
-        if let Some(auth) = self.api_key.clone() {
-            request_headers.insert(
-                reqwest::header::AUTHORIZATION,
-                reqwest::header::HeaderValue::from_str(&format!("{}", &auth)).unwrap(),
-            );
-        }
-
         let path = if self.is_created {
             "transact"
         } else {
             "create"
         };
 
+        // the create-ledger transaction can be issued and signed with keys of its own
+        let (auth, signing_key) = match self.is_created {
+            true => (self.api_key.clone(), self.opt.signing_key.clone()),
+            false => (
+                self.opt.create_auth.clone().or(self.api_key.clone()),
+                self.opt
+                    .create_signing_key
+                    .clone()
+                    .or(self.opt.signing_key.clone()),
+            ),
+        };
+
+        let mut request_headers = HeaderMap::new();
+        let content_type = match signing_key {
+            Some(_) => "application/jwt",
+            None => "application/json",
+        };
+        request_headers.insert("Content-Type", content_type.parse().unwrap());
+        if let Some(auth) = auth {
+            request_headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(&auth).unwrap(),
+            );
+        }
+
         self.is_created = true;
 
-        self.client
-            .post(&format!("{}/fluree/{}", self.url, path))
-            .headers(request_headers)
-            .body(body)
-            .send()
-            .await
+        let body = match &self.opt.txn_opts {
+            Some(txn_opts) => match serde_json::from_str::<Value>(&body) {
+                Ok(Value::Object(mut transaction)) => {
+                    merge_txn_opts(&mut transaction, txn_opts);
+                    Value::Object(transaction).to_string()
+                }
+                _ => body,
+            },
+            None => body,
+        };
+        // a signed transaction is sent as the compact JWS of its JSON
+        let body = match &signing_key {
+            Some(signing_key) => signing_key.sign(&body),
+            None => body,
+        };
+
+        self.post(
+            format!("{}/fluree/{}", self.url, path),
+            request_headers,
+            body,
+        )
+        .await
     }
 
     pub async fn v3_query(&mut self, body: String) -> Result<Response, Error> {
@@ -165,19 +281,24 @@ impl FlureeInstance {
         if let Some(auth) = self.api_key.clone() {
             request_headers.insert(
                 reqwest::header::AUTHORIZATION,
-                reqwest::header::HeaderValue::from_str(&format!("{}", &auth)).unwrap(),
+                reqwest::header::HeaderValue::from_str(&auth).unwrap(),
             );
         }
 
-        self.client
-            .post(&format!("{}/fluree/query", self.url))
-            .headers(request_headers)
-            .body(body)
-            .send()
+        self.post(format!("{}/fluree/query", self.url), request_headers, body)
             .await
     }
 
     pub async fn issue_initial_query(&self) -> Result<Response, Error> {
+        if self.opt.mock {
+            return Ok(mock::schema_response());
+        }
+        if self.opt.graphql {
+            return graphql::schema_response(self).await;
+        }
+        if let Some(directory) = self.opt.extract_directory() {
+            return Ok(extract::schema_response(directory));
+        }
         let mut request_headers = HeaderMap::new();
         request_headers.insert("Content-Type", "application/json".parse().unwrap());
         if let Some(auth) = self.api_key.clone() {
@@ -186,15 +307,31 @@ impl FlureeInstance {
                 reqwest::header::HeaderValue::from_str(&format!("Bearer {}", &auth)).unwrap(),
             );
         }
-        self.client
-            .post(&format!("{}/multi-query", self.url))
-            .headers(request_headers)
-            .body(SCHEMA_QUERY)
-            .send()
-            .await
+        let mut schema_query = serde_json::from_str::<Value>(SCHEMA_QUERY).unwrap();
+        if let Some(block) = self.opt.source_block() {
+            // the initial predicates are those of the first block, whatever the snapshot
+            for query in ["current_predicates", "collections"] {
+                schema_query[query]["block"] = block.to_owned();
+            }
+        }
+        self.query_source(
+            format!("{}/multi-query", self.url),
+            request_headers,
+            schema_query.to_string(),
+        )
+        .await
     }
 
     pub async fn issue_data_query(&self, query: String) -> Result<Response, Error> {
+        if self.opt.mock {
+            return Ok(mock::query_response(&query));
+        }
+        if self.opt.graphql {
+            return graphql::query_response(self, &query).await;
+        }
+        if let Some(directory) = self.opt.extract_directory() {
+            return Ok(extract::query_response(directory, &query));
+        }
         let mut request_headers = HeaderMap::new();
         request_headers.insert("Content-Type", "application/json".parse().unwrap());
         if let Some(auth) = self.api_key.clone() {
@@ -203,66 +340,536 @@ impl FlureeInstance {
                 reqwest::header::HeaderValue::from_str(&format!("Bearer {}", &auth)).unwrap(),
             );
         }
-        self.client
-            .post(&format!("{}/query", self.url))
-            .headers(request_headers.clone())
-            .body(query)
-            .send()
-            .await
+        self.query_source(
+            format!("{}/query", self.url),
+            request_headers,
+            self.at_source_block(query),
+        )
+        .await
     }
 
-    pub fn validate_result(&mut self, result: &Result<Response, Error>) -> Result<(), String> {
-        let mut final_result = Ok(());
-        (self.is_available, self.is_authorized) = match result {
-            Ok(response) => match response.status() {
-                reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => (true, true),
-                reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
-                    println!("Response: {:?}", response.error_for_status_ref());
-                    match self.api_key {
-                        Some(_) => {
-                            final_result = Err(format!("The API Key you provided is not authorized to access this database. Please try again."));
-                        }
-                        None => {
-                            final_result = Err(format!("It appears you need to provide an API Key to access this database. Please try again."));
-                        }
-                    };
-                    (true, false)
+    /// Issues a GraphQL query to the source's `/graphql` endpoint, for a `--graphql` source
+    pub async fn issue_graphql_query(&self, query: &str) -> Result<Response, Error> {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert("Content-Type", "application/json".parse().unwrap());
+        if let Some(auth) = self.api_key.clone() {
+            request_headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(&format!("Bearer {}", &auth)).unwrap(),
+            );
+        }
+        self.query_source(
+            format!("{}/graphql", self.url),
+            request_headers,
+            json!({ "query": query }).to_string(),
+        )
+        .await
+    }
+
+    /// Posts a JSON body to any URL of the source's server, with its API key, to probe what kind of server it is.
+    /// A probe is never cached
+    pub async fn issue_probe(&self, url: String, body: String) -> Result<Response, Error> {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert("Content-Type", "application/json".parse().unwrap());
+        if let Some(auth) = self.api_key.clone() {
+            request_headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(&format!("Bearer {}", &auth)).unwrap(),
+            );
+        }
+        self.post(url, request_headers, body).await
+    }
+
+    // a query to the source is answered from the --cache directory when an earlier run cached its response
+    async fn query_source(
+        &self,
+        url: String,
+        headers: HeaderMap,
+        body: String,
+    ) -> Result<Response, Error> {
+        let Some(cache) = &self.cache else {
+            return self.post(url, headers, body).await;
+        };
+        if let Some(response) = cache.get(&url, &body) {
+            pretty_log(
+                Level::Debug,
+                &mut self.opt.pb.clone(),
+                &format!("Cached POST {}\n{}", url, body),
+            );
+            return Ok(response);
+        }
+        if cache.is_offline {
+            self.opt.pb.finish_and_clear();
+            pretty_print(
+                &format!(
+                    "The cache has no response to a query to {}, which a run with --cache and the same options \
+                     must fetch first:\n{}",
+                    url, body
+                ),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+        let result = self.post(url.clone(), headers, body.clone()).await;
+        cache.put(&url, &body, result).await
+    }
+
+    // every request to the source and target is a POST, answered from the --replay cassette or recorded to the
+    // --record one when they're given, and logged with --trace-http
+    async fn post(
+        &self,
+        url: String,
+        mut headers: HeaderMap,
+        body: String,
+    ) -> Result<Response, Error> {
+        headers.insert(
+            reqwest::header::USER_AGENT,
+            reqwest::header::HeaderValue::from_static(trace::USER_AGENT),
+        );
+        headers.insert(
+            trace::CORRELATION_ID_HEADER,
+            reqwest::header::HeaderValue::from_static(trace::correlation_id()),
+        );
+        // a basic-auth proxy in front of the instance gets its credentials with every request to the instance (but
+        // not e.g. to the Nexus API), in Proxy-Authorization when the API key already holds Authorization
+        if let Some(basic_auth) = self
+            .basic_auth
+            .as_ref()
+            .filter(|_| is_same_origin(&url, &self.url))
+        {
+            let header = match headers.contains_key(reqwest::header::AUTHORIZATION) {
+                true => reqwest::header::PROXY_AUTHORIZATION,
+                false => reqwest::header::AUTHORIZATION,
+            };
+            headers.insert(
+                header,
+                reqwest::header::HeaderValue::from_str(basic_auth).unwrap(),
+            );
+        }
+        trace::request("POST", &url, &headers, &body);
+        pretty_log(
+            Level::Debug,
+            &mut self.opt.pb.clone(),
+            &format!("POST {}\n{}", url, body),
+        );
+        let result = match cassette::replayed(&url, &body) {
+            Some(response) => Ok(response),
+            None => {
+                let result = self
+                    .client
+                    .post(&url)
+                    .headers(headers)
+                    .body(body.to_owned())
+                    .send()
+                    .await;
+                cassette::record(&url, &body, result).await
+            }
+        };
+        trace::response(&url, result).await
+    }
+
+    /// Transacts the `--initial-policy` document right after the ledger is created,
+    /// so the ledger never holds data without access control
+    pub async fn transact_initial_policy(
+        &mut self,
+        ledger_name: &str,
+        pb: &ProgressBar,
+    ) -> Result<(), String> {
+        let policy = match &self.initial_policy {
+            Some(policy) => policy_txn(policy, ledger_name),
+            None => return Ok(()),
+        };
+        let green_bold = Style::new().green().bold();
+        let red_bold = Style::new().red().bold();
+        let body = serde_json::to_string(&policy).unwrap();
+
+        pb.println(format!(
+            "{:>12} Initial Policy to v3 Ledger",
+            green_bold.apply_to("Transacting")
+        ));
+
+        loop {
+            if !self.is_available {
+                self.prompt_fix_url();
+            }
+            if !self.is_authorized {
+                self.prompt_api_key();
+            }
+            let response_result = self.v3_transact(body.clone()).await;
+            match self.validate_result(response_result).await {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                    if self.is_available && self.is_authorized {
+                        self.wait_to_retry(pb).await?;
+                    }
                 }
-                _ => {
-                    let url = response.url();
-                    final_result = Err(format!(
-                        "The request to [{}] returned a status code of {}. Please try again.",
-                        url,
-                        response.status()
+            }
+        }
+    }
+
+    /// Creates the target's dataset through the Nexus management API with --create-dataset, before the first
+    /// transaction to the ledger. A dataset that already exists is left as is
+    pub async fn create_dataset(
+        &mut self,
+        ledger_name: &str,
+        pb: &ProgressBar,
+    ) -> Result<(), String> {
+        if self.is_dataset_created {
+            return Ok(());
+        }
+        let green_bold = Style::new().green().bold();
+        let yellow_bold = Style::new().yellow().bold();
+        let red_bold = Style::new().red().bold();
+        let name = match &self.opt.dataset_name {
+            Some(name) => name.to_owned(),
+            None => ledger_name
+                .rsplit('/')
+                .next()
+                .unwrap_or(ledger_name)
+                .to_string(),
+        };
+        let mut body = json!({
+            "name": name,
+            "visibility": match self.opt.dataset_visibility {
+                DatasetVisibility::Private => "private",
+                DatasetVisibility::Public => "public",
+            },
+        });
+        if let Some(description) = &self.opt.dataset_description {
+            body["description"] = json!(description);
+        }
+        let url = format!("{}/datasets", self.opt.nexus_api.trim_end_matches('/'));
+
+        pb.println(format!(
+            "{:>12} Nexus Dataset {}",
+            green_bold.apply_to("Creating"),
+            name
+        ));
+
+        loop {
+            if !self.is_authorized {
+                self.prompt_api_key();
+            }
+            let mut request_headers = HeaderMap::new();
+            request_headers.insert("Content-Type", "application/json".parse().unwrap());
+            if let Some(auth) = self.api_key.clone() {
+                request_headers.insert(
+                    reqwest::header::AUTHORIZATION,
+                    reqwest::header::HeaderValue::from_str(&auth).unwrap(),
+                );
+            }
+            let response_result = self
+                .post(url.to_owned(), request_headers, body.to_string())
+                .await;
+            if let Ok(response) = &response_result {
+                if response.status() == reqwest::StatusCode::CONFLICT {
+                    pb.println(format!(
+                        "{:>12} Nexus Dataset {} (already exists)",
+                        yellow_bold.apply_to("Skipping"),
+                        name
                     ));
-                    (false, !self.api_key.is_some())
+                    break;
                 }
-            },
+            }
+            match self.validate_result(response_result).await {
+                Ok(_) => break,
+                Err(e) => {
+                    pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                    // the management API isn't the --target, so there's no URL to fix when it can't be reached
+                    self.is_available = true;
+                    if self.is_authorized {
+                        self.wait_to_retry(pb).await?;
+                    }
+                }
+            }
+        }
+        self.is_dataset_created = true;
+        Ok(())
+    }
+
+    /// Fetches up to `limit` entities of a v2 collection, for inferring schema details from real data
+    pub async fn sample_class(
+        &mut self,
+        class_name: &str,
+        limit: usize,
+    ) -> Result<Vec<Value>, String> {
+        let query = self.filtered(
+            json!({
+                "select": ["*"],
+                "from": class_name,
+                "opts": {
+                    "compact": true,
+                    "limit": limit
+                }
+            }),
+            class_name,
+        );
+        self.query_array(&query).await
+    }
+
+    /// Fetches a reproducible pseudorandom subset of `sample` entities of a v2 collection for `--sample-seed`.
+    /// Only the `_id`s of the whole collection are paged through, and the subset is the ids with the lowest seeded hashes,
+    /// so the same seed selects the same entities no matter what order the source returns them in
+    pub async fn seeded_sample(
+        &mut self,
+        class_name: &str,
+        sample: u64,
+        seed: u64,
+    ) -> Result<Vec<Value>, String> {
+        let mut ids = self.collection_ids(class_name).await?;
+        ids.sort_by_key(|id| (seeded_hash(seed, *id as u64), *id));
+        ids.truncate(sample as usize);
+        ids.sort();
+
+        let mut results = vec![];
+        for ids in ids.chunks(5000) {
+            let query = json!({
+                "select": ["*"],
+                "from": ids,
+                "opts": {
+                    "compact": true,
+                    "limit": 5000,
+                    "fuel": 9999999999u64
+                }
+            });
+            results.extend(self.query_array(&query).await?);
+        }
+        Ok(results)
+    }
+
+    /// Pages through the `_id`s of every entity in a v2 collection, without fetching their data
+    pub async fn collection_ids(&mut self, class_name: &str) -> Result<Vec<i64>, String> {
+        let mut ids: Vec<i64> = vec![];
+        let mut offset = 0;
+        loop {
+            let query = self.filtered(
+                json!({
+                    "select": ["_id"],
+                    "from": class_name,
+                    "opts": {
+                        "compact": true,
+                        "limit": 5000,
+                        "fuel": 9999999999u64,
+                        "offset": offset
+                    }
+                }),
+                class_name,
+            );
+            let response = self.query_array(&query).await?;
+            if response.is_empty() {
+                break;
+            }
+            ids.extend(response.iter().filter_map(|value| value["_id"].as_i64()));
+            offset += 5000;
+        }
+        Ok(ids)
+    }
+
+    /// Counts the entities of a v2 collection with an analytical `count` query, rather than paging through their
+    /// `_id`s. A collection with a --where filter (which only a basic query takes), or of a source that only answers
+    /// basic queries (a mock, GraphQL, or extracted one), is paged through instead
+    pub async fn collection_count(&mut self, class_name: &str) -> Result<usize, String> {
+        if self.opt.mock
+            || self.opt.graphql
+            || self.opt.extract_directory().is_some()
+            || self.opt.where_clause(class_name).is_some()
+        {
+            return Ok(self.collection_ids(class_name).await?.len());
+        }
+        let red_bold = Style::new().red().bold();
+        let query = json!({
+            "select": "(count ?e)",
+            "where": [["?e", "rdf:type", class_name]],
+            "opts": {
+                "fuel": 9999999999u64
+            }
+        });
+        loop {
+            let response_result = self.issue_data_query(query.to_string()).await;
+            match self.validate_result(response_result).await {
+                Ok(response) => {
+                    // the count is answered bare, or as the single row of the result
+                    let count = match serde_json::from_str::<Value>(&response) {
+                        Ok(Value::Array(rows)) => rows.first().and_then(Value::as_u64),
+                        Ok(count) => count.as_u64(),
+                        Err(_) => None,
+                    };
+                    return match count {
+                        Some(count) => Ok(count as usize),
+                        None => Ok(self.collection_ids(class_name).await?.len()),
+                    };
+                }
+                Err(e) => {
+                    let pb = self.opt.pb.clone();
+                    pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                    self.wait_to_retry(&pb).await?;
+                }
+            }
+        }
+    }
+
+    /// Issues a source query at the --block (or --as-of) snapshot, if one is given
+    fn at_source_block(&self, query: String) -> String {
+        let Some(block) = self.opt.source_block() else {
+            return query;
+        };
+        match serde_json::from_str::<Value>(&query) {
+            Ok(Value::Object(mut query)) => {
+                query.insert("block".to_string(), block);
+                Value::Object(query).to_string()
+            }
+            _ => query,
+        }
+    }
+
+    /// Adds the `where` clause of the --where filters on a collection's predicates to a query of its entities
+    pub fn filtered(&self, mut query: Value, class_name: &str) -> Value {
+        if let Some(where_clause) = self.opt.where_clause(class_name) {
+            query["where"] = Value::String(where_clause);
+        }
+        query
+    }
+
+    /// Issues a source query whose response is an array, retrying on errors
+    pub async fn query_array(&mut self, query: &Value) -> Result<Vec<Value>, String> {
+        let red_bold = Style::new().red().bold();
+        loop {
+            let response_result = self.issue_data_query(query.to_string()).await;
+            match self.validate_result(response_result).await {
+                Ok(response) => {
+                    return Ok(serde_json::from_str::<Value>(&response)
+                        .ok()
+                        .and_then(|response| response.as_array().cloned())
+                        .unwrap_or_default());
+                }
+                Err(e) => {
+                    let pb = self.opt.pb.clone();
+                    pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                    self.wait_to_retry(&pb).await?;
+                }
+            }
+        }
+    }
+
+    /// Issues a v3 query against the target, retrying failed requests, and returns the rows of its result
+    pub async fn v3_query_array(&mut self, query: &Value) -> Result<Vec<Value>, String> {
+        let red_bold = Style::new().red().bold();
+        loop {
+            let response_result = self.v3_query(query.to_string()).await;
+            match self.validate_result(response_result).await {
+                Ok(response) => {
+                    return Ok(serde_json::from_str::<Value>(&response)
+                        .ok()
+                        .and_then(|response| response.as_array().cloned())
+                        .unwrap_or_default());
+                }
+                Err(e) => {
+                    let pb = self.opt.pb.clone();
+                    pb.println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                    self.wait_to_retry(&pb).await?;
+                }
+            }
+        }
+    }
+
+    /// Waits before re-issuing a request whose response carried an error payload. Once `MAX_ERROR_RETRIES`
+    /// consecutive attempts have failed, it returns an error for the run to give up on
+    pub async fn wait_to_retry(&mut self, pb: &ProgressBar) -> Result<(), String> {
+        let yellow_bold = Style::new().yellow().bold();
+        self.error_retries += 1;
+        if self.error_retries > MAX_ERROR_RETRIES {
+            return Err(format!(
+                "Giving up after {} failed attempts",
+                MAX_ERROR_RETRIES
+            ));
+        }
+        pb.println(format!(
+            "{:>12} Retrying in {} seconds ({}/{})",
+            yellow_bold.apply_to("WARNING"),
+            ERROR_RETRY_DELAY.as_secs(),
+            self.error_retries,
+            MAX_ERROR_RETRIES
+        ));
+        tokio::time::sleep(ERROR_RETRY_DELAY).await;
+        Ok(())
+    }
+
+    pub async fn validate_result(
+        &mut self,
+        result: Result<Response, Error>,
+    ) -> Result<String, String> {
+        let final_result;
+        (self.is_available, self.is_authorized) = match result {
+            Ok(response) => {
+                let url = response.url().to_string();
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                match status {
+                    reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => {
+                        // some deployments report failures with a 200 and an error payload
+                        match response_error_message(&body) {
+                            Some(error) => {
+                                final_result = Err(format!(
+                                    "The request to [{}] returned an error: {}",
+                                    url, error
+                                ));
+                            }
+                            None => {
+                                self.error_retries = 0;
+                                final_result = Ok(body);
+                            }
+                        }
+                        (true, true)
+                    }
+                    reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                        match self.api_key {
+                            Some(_) => {
+                                final_result = Err("The API Key you provided is not authorized to access this database. Please try again.".to_string());
+                            }
+                            None => {
+                                final_result = Err("It appears you need to provide an API Key to access this database. Please try again.".to_string());
+                            }
+                        };
+                        (true, false)
+                    }
+                    _ => {
+                        final_result = Err(match response_error_message(&body) {
+                            Some(error) => format!(
+                                "The request to [{}] returned a status code of {}: {}",
+                                url, status, error
+                            ),
+                            None => format!(
+                                "The request to [{}] returned a status code of {}. Please try again.",
+                                url, status
+                            ),
+                        });
+                        (false, self.api_key.is_none())
+                    }
+                }
+            }
             Err(_) => {
-                execute!(
-                    stdout(),
-                    SetForegroundColor(ERROR_COLOR),
-                    Print("The request to the database failed. Please try again."),
-                    Print("\n"),
-                    ResetColor
-                )
-                .unwrap();
+                final_result =
+                    Err("The request to the database failed. Please try again.".to_string());
                 (false, true)
             }
         };
-        final_result
+        // error payloads can echo the request, key included
+        final_result.map_err(|error| redact(&error))
     }
 }
 
 #[async_trait::async_trait]
 impl Migrate for FlureeInstance {
-    async fn migrate(&mut self) {
+    async fn migrate(&mut self) -> MigrationReport {
         let start = Instant::now();
         let green_bold = Style::new().green().bold();
         let yellow_bold = Style::new().yellow().bold();
         let red_bold = Style::new().red().bold();
 
         let mut response_string: Option<Value> = None;
+        let mut report = MigrationReport::default();
 
         let mut source_instance = self.clone();
         let opt = self.opt.clone();
@@ -306,20 +913,29 @@ impl Migrate for FlureeInstance {
 
             let response_result = source_instance.issue_initial_query().await;
 
-            let validate_attempt = source_instance.validate_result(&response_result);
-
-            if let Err(e) = validate_attempt {
-                opt.pb
-                    .println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
-            }
-
-            if source_instance.is_available && source_instance.is_authorized {
-                let awaited_response = response_result.unwrap().text().await.unwrap();
-                response_string = serde_json::from_str(&awaited_response).unwrap();
-                break;
-            } else {
-                opt.pb.finish_and_clear();
-                continue;
+            match source_instance.validate_result(response_result).await {
+                Ok(awaited_response) => {
+                    if !probe::is_v2_schema(&awaited_response) {
+                        probe::exit_unless_v2(&source_instance).await;
+                    }
+                    response_string =
+                        Some(serde_json::from_str(&awaited_response).unwrap_or_default());
+                    break;
+                }
+                Err(e) => {
+                    opt.pb
+                        .println(format!("{:>12} {}", red_bold.apply_to("ERROR"), e));
+                    if !source_instance.is_available {
+                        probe::exit_unless_v2(&source_instance).await;
+                    }
+                    if source_instance.is_available && source_instance.is_authorized {
+                        if let Err(e) = source_instance.wait_to_retry(&opt.pb).await {
+                            abort_migration(&opt, &report, &e);
+                        }
+                    }
+                    opt.pb.finish_and_clear();
+                    continue;
+                }
             }
         }
 
@@ -329,31 +945,82 @@ impl Migrate for FlureeInstance {
         ));
         opt.pb.inc(1);
 
-        let json = parse_current_predicates(response_string.unwrap());
-
-        let mut parser = Parser::new(&opt, &source_instance);
+        let response_json = response_string.unwrap();
+        let collections = response_json["collections"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let json = parse_current_predicates(response_json);
+
+        let vocab = opt
+            .vocab_file
+            .as_ref()
+            .map(|path| read_json_file(path, "vocab"));
+        let vocab_plan = vocab.as_ref().map(|vocab| {
+            Parser::vocab_plan(
+                vocab,
+                json.as_array().unwrap(),
+                &self.url,
+                &opt.target_ledger_name(&format!("{}/{}", self.network_name, self.db_name)),
+            )
+        });
+        for warning in vocab_plan.iter().flat_map(|plan| plan.warnings.iter()) {
+            opt.pb.println(format!(
+                "{:>12} {}",
+                yellow_bold.apply_to("WARNING"),
+                warning
+            ));
+        }
+        let mut parser = Parser::new(&opt, &source_instance, vocab_plan);
+        if let Some(vocab) = &vocab {
+            parser.add_vocab_prefixes(vocab);
+        }
 
         let json_results = json.as_array().unwrap();
 
         for item in json_results {
             let (orig_class_name, orig_property_name) = parse_for_class_and_property_name(item);
+            if !opt.is_migrated_class(&orig_class_name) {
+                continue;
+            }
 
             let class_object = parser.get_or_create_class(&orig_class_name);
 
+            parser
+                .classes
+                .insert(orig_class_name.to_string(), class_object);
+
+            if parser.is_dropped_property(&orig_class_name, &orig_property_name) {
+                continue;
+            }
+            let orig_property_name = parser.property_key(&orig_class_name, &orig_property_name);
+
             let type_value = item["type"].as_str().unwrap();
 
             let property_obj = parser.get_or_create_property(&orig_property_name, type_value);
 
-            parser
-                .classes
-                .insert(orig_class_name.to_string(), class_object);
             parser
                 .properties
                 .insert(orig_property_name.to_string(), property_obj);
         }
 
+        let mut schema_warnings: Vec<String> = vec![];
+
         for item in json_results {
             let (orig_class_name, orig_property_name) = parse_for_class_and_property_name(item);
+            if !opt.is_migrated_class(&orig_class_name)
+                || parser.is_dropped_property(&orig_class_name, &orig_property_name)
+            {
+                continue;
+            }
+            parser.set_upsert_key(&orig_class_name, &orig_property_name, item);
+            let orig_property_name = parser.property_key(&orig_class_name, &orig_property_name);
+            report.unsupported_features.extend(parser.unsupported_specs(
+                &orig_class_name,
+                &orig_property_name,
+                item,
+            ));
+            parser.set_ref_collection(&orig_property_name, item);
 
             let mut class_object = parser.get_or_create_class(&orig_class_name);
 
@@ -362,8 +1029,8 @@ impl Migrate for FlureeInstance {
             let mut property_object =
                 parser.get_or_create_property(&orig_property_name, type_value);
 
-            let class_name = standardize_class_name(&orig_class_name);
-            let property_name = standardize_property_name(&orig_property_name);
+            let class_name = parser.class_name(&orig_class_name);
+            let property_name = property_object.id.to_owned();
 
             let mut class_shacl_shape =
                 parser.get_or_create_shacl_shape(&class_name, opt.closed_shapes);
@@ -373,12 +1040,16 @@ impl Migrate for FlureeInstance {
 
             // TODO: if another shacl_shape in parser.shacl_shapes has the same property name, and if it has a different datatype, then I need to log a warning and I need to update the property name to be the Class/Property (e.g. Person/age and Animal/age)
 
-            let attempt_set_property = class_shacl_shape.set_property(&mut property_object, item);
+            let attempt_set_property =
+                class_shacl_shape.set_property(&mut property_object, item, &|collection| {
+                    parser.class_name(collection)
+                });
 
             if let Err(e) = attempt_set_property {
                 for error in e {
                     opt.pb
                         .println(format!("{:>12} {}", yellow_bold.apply_to("WARNING"), error));
+                    schema_warnings.push(error);
                 }
             }
 
@@ -393,46 +1064,416 @@ impl Migrate for FlureeInstance {
                 .insert(orig_property_name.to_string(), property_object);
         }
 
-        let vocab_results_map = parser.get_vocab_json(&opt);
-        if !opt.print && opt.output.is_some() {
-            std::fs::remove_dir_all(opt.output.clone().unwrap()).unwrap_or_else(|why| {
-                if why.kind() != std::io::ErrorKind::NotFound {
-                    panic!("Unable to remove existing output directory: {}", why);
-                }
-            });
+        for collection in &collections {
+            report
+                .unsupported_features
+                .extend(parser.set_collection_metadata(collection));
         }
-
-        let mut target_instance = opt
-            .write_or_print(
-                "0_vocab.jsonld",
-                serde_json::to_string_pretty(&vocab_results_map).unwrap(),
-                None,
-            )
-            .await;
-
-        let query_classes: Vec<String> = parser.classes.keys().map(|key| key.to_owned()).collect();
-
-        let mut data_results_map = serde_json::Map::new();
-
-        let ledger_name = match &opt.ledger_name {
-            Some(ledger_name) => ledger_name.to_string(),
-            None => format!("{}/{}", self.network_name, self.db_name),
+        for feature in &report.unsupported_features {
+            opt.pb.println(format!(
+                "{:>12} The {} of {}{}, \"{}\", has no SHACL equivalent and isn't migrated",
+                yellow_bold.apply_to("WARNING"),
+                match feature.feature.as_str() {
+                    "collectionSpec" => "spec",
+                    _ => "predicate spec",
+                },
+                feature.class,
+                feature
+                    .property
+                    .as_ref()
+                    .map(|property| format!("/{}", property))
+                    .unwrap_or_default(),
+                feature.name
+            ));
+        }
+        parser.apply_external_refs();
+
+        let tag_concepts = match opt.tags_as_concepts {
+            true => {
+                let tags = source_instance
+                    .sample_class("_tag", 9_999_999)
+                    .await
+                    .unwrap_or_else(|e| abort_migration(&opt, &report, &e));
+                let tag_concepts = parser.set_tag_concepts(&tags);
+                opt.pb.println(format!(
+                    "{:>12} {} v2 tags as skos:Concept individuals",
+                    green_bold.apply_to("Mapping"),
+                    tag_concepts.len() - 1
+                ));
+                tag_concepts
+            }
+            false => vec![],
+        };
+        let identities = match opt.identities {
+            true => {
+                let users = source_instance
+                    .sample_class("_user", 9_999_999)
+                    .await
+                    .unwrap_or_else(|e| abort_migration(&opt, &report, &e));
+                let auths = source_instance
+                    .sample_class("_auth", 9_999_999)
+                    .await
+                    .unwrap_or_else(|e| abort_migration(&opt, &report, &e));
+                let identities = parser.set_identities(&users, &auths);
+                opt.pb.println(format!(
+                    "{:>12} {} v2 users and {} auth records as {} identities",
+                    green_bold.apply_to("Mapping"),
+                    users.len(),
+                    auths.len(),
+                    identities.len()
+                ));
+                identities
+            }
+            false => vec![],
         };
 
-        data_results_map.insert("ledger".to_string(), json!(ledger_name));
+        if opt.preserve_decimals {
+            let decimal_properties = parser.preserve_decimals();
+            if !decimal_properties.is_empty() {
+                opt.pb.println(format!(
+                    "{:>12} [{}] as xsd:decimal",
+                    green_bold.apply_to("Preserving"),
+                    decimal_properties.join(", ")
+                ));
+            }
+        }
 
-        data_results_map.insert(
-            "@context".to_string(),
-            Value::Object(
-                parser
-                    .data_context
-                    .iter()
-                    .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
-                    .collect(),
-            ),
-        );
+        if !opt.date_pattern.is_empty() {
+            let mut pattern_classes = parser.classes.keys().cloned().collect::<Vec<String>>();
+            pattern_classes.sort();
+            for class_name in pattern_classes {
+                for inference in parser.apply_date_patterns(&class_name, &opt.date_pattern) {
+                    opt.pb.println(format!(
+                        "{:>12} {}/{} as {} by --date-pattern",
+                        yellow_bold.apply_to("DATATYPE"),
+                        inference.class,
+                        inference.property,
+                        inference.datatype
+                    ));
+                    report.datatype_inferences.push(inference);
+                }
+            }
+        }
 
-        data_results_map.insert("insert".to_string(), json!([]));
+        // a plan already carries the outcome of schema inference
+        let is_inferring = opt.infer_cardinality || opt.infer_datatypes || opt.detect_dates;
+        if is_inferring && parser.plan.is_none() {
+            opt.pb.println(format!(
+                "{:>12} v2 Data for Schema Inference",
+                green_bold.apply_to("Sampling")
+            ));
+            let mut sample_classes = parser.classes.keys().cloned().collect::<Vec<String>>();
+            sample_classes.sort();
+            for class_name in sample_classes {
+                let samples = source_instance
+                    .sample_class(&class_name, opt.inference_sample)
+                    .await
+                    .unwrap_or_else(|e| abort_migration(&opt, &report, &e));
+                if opt.infer_cardinality {
+                    for change in parser.infer_cardinality(&class_name, &samples) {
+                        opt.pb.println(format!(
+                            "{:>12} {}/{} holds at most {} value(s) across {} sampled entities; sh:maxCount {:?} -> {:?}",
+                            yellow_bold.apply_to("CARDINALITY"),
+                            change.class,
+                            change.property,
+                            change.observed_max_values,
+                            change.sampled_entities,
+                            change.declared_max_count,
+                            change.inferred_max_count
+                        ));
+                        report.cardinality_changes.push(change);
+                    }
+                }
+                if opt.detect_dates {
+                    for inference in parser.detect_dates(&class_name, &samples) {
+                        opt.pb.println(format!(
+                            "{:>12} {}/{} as {} since its {} sampled values are all at midnight UTC",
+                            yellow_bold.apply_to("DATATYPE"),
+                            inference.class,
+                            inference.property,
+                            inference.datatype,
+                            inference.sampled_values
+                        ));
+                        report.datatype_inferences.push(inference);
+                    }
+                }
+                if opt.infer_datatypes {
+                    for inference in parser.infer_datatypes(&class_name, &samples) {
+                        opt.pb.println(format!(
+                            "{:>12} {}/{} (v2 type \"{}\") as {} from {} sampled values",
+                            yellow_bold.apply_to("DATATYPE"),
+                            inference.class,
+                            inference.property,
+                            inference.v2_types.join(", "),
+                            inference.datatype,
+                            inference.sampled_values
+                        ));
+                        report.datatype_inferences.push(inference);
+                    }
+                }
+            }
+        }
+
+        let mut warnings = parser.apply_plan();
+        warnings.extend(parser.apply_multi_class_refs());
+        for warning in warnings {
+            opt.pb.println(format!(
+                "{:>12} {}",
+                yellow_bold.apply_to("WARNING"),
+                warning
+            ));
+        }
+
+        for predicate in opt.where_filters.iter().flat_map(|filters| filters.keys()) {
+            let (collection, _) = predicate.split_once('/').unwrap_or_default();
+            if !parser.classes.contains_key(collection) {
+                opt.pb.println(format!(
+                    "{:>12} The --where filter on \"{}\" matches no migrated collection, so it filters nothing",
+                    yellow_bold.apply_to("WARNING"),
+                    predicate
+                ));
+            }
+        }
+
+        report.schema_statistics = parser.schema_statistics(json_results);
+        let count = |field: fn(&ClassStatistics) -> usize| {
+            report.schema_statistics.iter().map(field).sum::<usize>()
+        };
+        opt.pb.println(format!(
+            "{:>12} {} of {} v2 predicates to {} classes ({} refs, {} with datatype conflicts, {} renamed, {} dropped)",
+            green_bold.apply_to("Mapped"),
+            count(|class| class.properties),
+            count(|class| class.predicates),
+            report.schema_statistics.len(),
+            count(|class| class.refs),
+            count(|class| class.datatype_conflicts),
+            count(|class| class.renamed_properties),
+            count(|class| class.dropped)
+        ));
+
+        if let Some(Command::Plan { path }) = &opt.command {
+            let mut entity_counts = HashMap::new();
+            for orig_class_name in parser.classes.keys() {
+                let entity_count = source_instance
+                    .collection_count(orig_class_name)
+                    .await
+                    .unwrap_or_else(|e| run_state::abort(&opt, &e));
+                entity_counts.insert(orig_class_name.to_owned(), entity_count);
+            }
+            let ledger_name =
+                opt.target_ledger_name(&format!("{}/{}", self.network_name, self.db_name));
+            let plan = parser.plan(
+                &self.url,
+                &ledger_name,
+                DATA_CHUNK_SIZE,
+                &entity_counts,
+                schema_warnings,
+            );
+            plan.write(path);
+            opt.pb.finish_and_clear();
+            println!(
+                "{:>12} Migration plan for {} classes to {}",
+                green_bold.apply_to("Wrote"),
+                plan.classes.len(),
+                path.display()
+            );
+            return report;
+        }
+
+        if let Some(Command::Diagram { path, format }) = &opt.command {
+            let ledger_name =
+                opt.target_ledger_name(&format!("{}/{}", self.network_name, self.db_name));
+            let plan = parser.plan(
+                &self.url,
+                &ledger_name,
+                DATA_CHUNK_SIZE,
+                &HashMap::new(),
+                schema_warnings,
+            );
+            diagram::write(&plan, *format, path);
+            opt.pb.finish_and_clear();
+            println!(
+                "{:>12} Diagram of {} classes to {}",
+                green_bold.apply_to("Wrote"),
+                plan.classes.len(),
+                path.display()
+            );
+            return report;
+        }
+
+        if let Some(Command::SuggestAlignment { path, min_score }) = &opt.command {
+            let ledger_name =
+                opt.target_ledger_name(&format!("{}/{}", self.network_name, self.db_name));
+            let plan = parser.plan(
+                &self.url,
+                &ledger_name,
+                DATA_CHUNK_SIZE,
+                &HashMap::new(),
+                schema_warnings,
+            );
+            let alignment = align::suggest(&plan, *min_score);
+            alignment.write(path);
+            opt.pb.finish_and_clear();
+            println!(
+                "{:>12} schema.org alignment for {} of {} classes and {} of {} properties to {} (review it, then use it with --align)",
+                green_bold.apply_to("Suggested"),
+                alignment.classes.len(),
+                plan.classes.len(),
+                alignment.properties.len(),
+                plan.classes
+                    .iter()
+                    .map(|class| class.properties.len())
+                    .sum::<usize>(),
+                path.display()
+            );
+            return report;
+        }
+
+        if let Some(Command::TransformOne { collection, .. }) = &opt.command {
+            opt.pb.finish_and_clear();
+            transform_one(&parser, collection);
+            return report;
+        }
+
+        if opt.estimate {
+            print_estimate(&mut source_instance, &parser, &opt).await;
+            return report;
+        }
+
+        let full_text = parser.flagged_properties(json_results, "fullText");
+        let indexed = parser.flagged_properties(json_results, "index");
+        parser.set_indexed_properties(&indexed);
+        let vocab_results_map = parser.get_vocab_json(&opt);
+        opt.clear_output_directory();
+        // the run's files (e.g. the index hints) go to the output directory, which the vocab writer may not have
+        // created yet, or at all, with --data-only or as a --shard other than the first
+        if let (Some(output), false, None) = (&opt.output, opt.print, &opt.target) {
+            let _ = std::fs::create_dir_all(output);
+        }
+
+        if let Some(docs_directory) = &opt.docs {
+            match docs::write_docs(&vocab_results_map, docs_directory) {
+                Ok(class_count) => opt.pb.println(format!(
+                    "{:>12} Documentation for {} classes to {}",
+                    green_bold.apply_to("Wrote"),
+                    class_count,
+                    docs_directory.join("index.html").display()
+                )),
+                Err(e) => opt.pb.println(format!(
+                    "{:>12} Could not write the documentation to {}: {}",
+                    yellow_bold.apply_to("WARNING"),
+                    docs_directory.display(),
+                    e
+                )),
+            }
+        }
+
+        let ledger_name =
+            opt.target_ledger_name(&format!("{}/{}", self.network_name, self.db_name));
+
+        opt.confirm_create(&ledger_name);
+
+        // the vocab is written (or transacted) while the classes are fetched and transformed, so a vocab
+        // transaction that's slow to commit doesn't hold them up. The chunk writer waits for it before writing any
+        // data, so the data is still transacted after the vocab. With --shard, only the first shard writes it
+        let vocab_writer = tokio::spawn({
+            let opt = opt.clone();
+            let vocab_file_name = opt.vocab_file_name(&ledger_name);
+            let vocab = serde_json::to_string_pretty(&vocab_results_map).unwrap();
+            async move {
+                match opt.data_only || !opt.is_first_shard() {
+                    true => Ok(None),
+                    false => opt.write_or_print(vocab_file_name, vocab, None).await,
+                }
+            }
+        });
+
+        if (!full_text.is_empty() || !indexed.is_empty()) && !opt.print {
+            let hints = IndexHints::new(
+                &ledger_name,
+                &parser.data_context_json(),
+                &full_text,
+                &indexed,
+            );
+            let hints_path = run_state::run_file_path(&opt, INDEX_HINTS_FILE);
+            match hints.write(&hints_path) {
+                Ok(_) => opt.pb.println(format!(
+                    "{:>12} Index hints for {} full-text and {} indexed classes to {}",
+                    green_bold.apply_to("Wrote"),
+                    hints.full_text.len(),
+                    hints.indexed.len(),
+                    hints_path.display()
+                )),
+                Err(e) => opt.pb.println(format!(
+                    "{:>12} Could not write the index hints to {}: {}",
+                    yellow_bold.apply_to("WARNING"),
+                    hints_path.display(),
+                    e
+                )),
+            }
+        }
+
+        if opt.vocab_only {
+            if let Err(e) = vocab_writer.await.unwrap() {
+                abort_migration(&opt, &report, &e);
+            }
+            opt.pb.finish_and_clear();
+            report.save(&opt);
+            println!(
+                "{:>12} v3 Vocabulary {}in {} (run {})",
+                green_bold.apply_to("Finished"),
+                finish_line(&opt.output, &opt.target),
+                HumanDuration(start.elapsed()),
+                trace::correlation_id(),
+            );
+            return report;
+        }
+
+        parser.deal_shard_classes();
+        let query_classes: Vec<String> = parser
+            .classes
+            .keys()
+            .filter(|key| parser.is_shard_class(key))
+            .map(|key| key.to_owned())
+            .collect();
+        if let Some(shard) = opt.shard {
+            let slice = match opt.shard_by {
+                ShardBy::Classes => format!(
+                    "{} of {} classes",
+                    query_classes.len(),
+                    parser.classes.len()
+                ),
+                ShardBy::Ids => format!("its blocks of _ids of {} classes", query_classes.len()),
+            };
+            opt.pb.println(format!(
+                "{:>12} {} migrates {}",
+                green_bold.apply_to("Shard"),
+                shard,
+                slice
+            ));
+        }
+
+        let mut data_results_map = serde_json::Map::new();
+
+        data_results_map.insert("ledger".to_string(), json!(ledger_name));
+
+        data_results_map.insert("@context".to_string(), parser.data_context_json());
+
+        let mut reference_tracker =
+            ReferenceTracker::with_external_bases(parser.external_refs.values().cloned().collect());
+        let system_nodes = tag_concepts
+            .into_iter()
+            .chain(identities)
+            .collect::<Vec<Value>>();
+        for node in &system_nodes {
+            reference_tracker.track(node);
+        }
+        // the tags and identities are shared by the shards, so only the first writes them
+        let system_nodes = match opt.is_first_shard() {
+            true => system_nodes,
+            false => vec![],
+        };
+        data_results_map.insert("insert".to_string(), Value::Array(system_nodes));
 
         opt.pb.inc_length(query_classes.len() as u64);
         opt.pb.set_style(
@@ -450,12 +1491,37 @@ impl Migrate for FlureeInstance {
         );
         opt.pb.set_prefix("Transforming Fluree v2 Entities");
 
-        let temp_dir = Path::new(".tmp");
-        let temp_file = TempFile::new(temp_dir).expect("Could not create temp file");
-        let temp_file: Arc<_> = Arc::new(Mutex::new(temp_file));
+        // merging entities and handling dangling refs need every entity before any is transformed, so the fetched
+        // pages are spooled to temp files. Otherwise they go straight to the transform through a bounded channel,
+        // so a slow target slows the fetching down instead of the whole ledger piling up in the temp files.
+        // With --content-addressed, the pages are spooled too, so they can be put in an order that doesn't depend
+        // on the fetching, and the same source gives the same files, and with --merge-collections, so the
+        // entities of several collections are known before any of them is transformed
+        let is_streaming = parser.mapping.merge_entities.is_empty()
+            && opt.dangling_refs == DanglingRefs::Keep
+            && !opt.content_addressed
+            && !opt.merge_collections;
+        let temp_dir = Path::new(TEMP_DIRECTORY);
+        let (page_sink, page_receiver) = match is_streaming {
+            true => {
+                let (page_sender, page_receiver) =
+                    tokio::sync::mpsc::channel::<Page>(FETCH_QUEUE_PAGES);
+                (PageSink::Stream(page_sender), Some(page_receiver))
+            }
+            false => {
+                let temp_file = TempFile::new(temp_dir)
+                    .expect("Could not create temp file")
+                    .content_addressed(opt.content_addressed);
+                (PageSink::Spool(Arc::new(Mutex::new(temp_file))), None)
+            }
+        };
 
         let mut handles = vec![];
-        let semaphore = tokio::sync::Semaphore::new(10);
+        store::clear_fetched_ids();
+        // the permits are taken by the fetch tasks themselves, so the tasks of the classes beyond the limit wait
+        // for one to finish rather than holding up the spawning (and with it the transform of streamed pages)
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(opt.fetch_concurrency as usize));
+        let is_fetch_failed = Arc::new(AtomicBool::new(false));
         let shared_fluree_instance = Arc::new(source_instance);
         let (output, target) = (opt.output.clone(), opt.target.clone());
 
@@ -468,7 +1534,7 @@ impl Migrate for FlureeInstance {
         let message = print_classes.join(", ");
         let full_message = if print_classes.len() > 3 {
             format!("[{}...]", message)
-        } else if print_classes.len() > 0 {
+        } else if !print_classes.is_empty() {
             format!("[{}]", message)
         } else {
             "".to_string()
@@ -476,8 +1542,6 @@ impl Migrate for FlureeInstance {
         opt.pb.set_message(full_message);
 
         let shared_opt = Arc::new(opt);
-        let entity_map: HashMap<String, HashSet<i64>> = HashMap::new();
-        let shared_entity_map = Arc::new(Mutex::new(entity_map));
         let processing = Arc::new(Mutex::new(
             query_classes
                 .iter()
@@ -486,102 +1550,158 @@ impl Migrate for FlureeInstance {
         ));
 
         for class_name in query_classes {
-            let permit = semaphore.acquire().await.expect("semaphore error");
-
             let handle = tokio::task::spawn({
-                let source_instance = Arc::clone(&shared_fluree_instance);
-                let temp_file = Arc::clone(&temp_file);
+                let mut source_instance = (*shared_fluree_instance).clone();
+                let page_sink = page_sink.clone();
                 let class_name = class_name.clone();
                 let opt = Arc::clone(&shared_opt);
                 let green_bold = Style::new().green().bold();
-                let entity_map = Arc::clone(&shared_entity_map);
+                let red_bold = Style::new().red().bold();
+                let semaphore = Arc::clone(&semaphore);
                 let processing = Arc::clone(&processing);
+                let is_fetch_failed = Arc::clone(&is_fetch_failed);
                 async move {
+                    let _permit = semaphore.acquire().await.expect("semaphore error");
                     let mut results: Vec<Value> = Vec::new();
-                    let mut offset: u32 = 0;
-
-                    loop {
-                        let query = format!(
-                            r#"{{
-                    "select": ["*"],
-                    "from": "{}",
-                    "opts": {{
-                        "compact": true,
-                        "limit": 5000,
-                        "fuel": 9999999999,
-                        "offset": {}
-                    }}
-                }}"#,
-                            class_name, offset
-                        );
-                        let response_result = source_instance.issue_data_query(query).await;
-                        let response = response_result.unwrap().text().await.unwrap();
-
-                        let response: Value = match serde_json::from_str(&response) {
-                            Ok(response) => response,
-                            Err(e) => {
-                                pretty_print(&format!("[ERROR] {}", e), Color::DarkRed, true);
-                                pretty_print(
-                                    &format!("Fluree Response: {}", response),
-                                    Color::DarkRed,
-                                    true,
-                                );
-                                serde_json::json!([])
-                            }
+                    let mut seen_ids = FetchedIds::new(&class_name);
+                    // once a class can't be fetched, the migration is given up, so the others stop fetching too
+                    let fail = |e: String| {
+                        is_fetch_failed.store(true, Ordering::Relaxed);
+                        Err(e)
+                    };
+                    if is_fetch_failed.load(Ordering::Relaxed) {
+                        return Ok(seen_ids);
+                    }
+                    if let (Some(sample), Some(seed)) = (opt.sample, opt.sample_seed) {
+                        let results = match source_instance
+                            .seeded_sample(&class_name, sample, seed)
+                            .await
+                        {
+                            Ok(results) => results,
+                            Err(e) => return fail(e),
                         };
-                        let response = response.as_array().unwrap();
+                        let ids = results
+                            .iter()
+                            .filter_map(|entity| entity["_id"].as_i64())
+                            .collect::<Vec<i64>>();
+                        seen_ids.insert_page(&ids);
+                        page_sink.put(&class_name, opt.shard_slice(results)).await;
+                    } else {
+                        let mut offset: u32 = 0;
 
-                        let mut entity_map_guard = entity_map.lock().await;
+                        loop {
+                            if is_fetch_failed.load(Ordering::Relaxed) {
+                                return Ok(seen_ids);
+                            }
+                            // the page size is taken anew for each attempt, as --adaptive-batching adjusts it
+                            let (page_size, response) = loop {
+                                let page_size = match opt.sample {
+                                    Some(sample) => {
+                                        opt.source_batch.scale(SOURCE_PAGE_SIZE).min(sample)
+                                    }
+                                    None => opt.source_batch.scale(SOURCE_PAGE_SIZE),
+                                } as u32;
+                                let query = source_instance
+                                    .filtered(
+                                        json!({
+                                            "select": ["*"],
+                                            "from": class_name,
+                                            "opts": {
+                                                "compact": true,
+                                                "limit": page_size,
+                                                "fuel": 9999999999u64,
+                                                "offset": offset
+                                            }
+                                        }),
+                                        &class_name,
+                                    )
+                                    .to_string();
+                                let sent = Instant::now();
+                                let response_result = source_instance.issue_data_query(query).await;
+                                let is_too_large =
+                                    opt.adaptive_batching && is_batch_too_large(&response_result);
+                                match source_instance.validate_result(response_result).await {
+                                    Ok(response) => {
+                                        if opt.adaptive_batching {
+                                            opt.source_batch.observe(sent.elapsed());
+                                        }
+                                        break (page_size, response);
+                                    }
+                                    Err(e) => {
+                                        opt.pb.println(format!(
+                                            "{:>12} {}",
+                                            red_bold.apply_to("ERROR"),
+                                            e
+                                        ));
+                                        if is_too_large {
+                                            opt.source_batch.shrink();
+                                        }
+                                        if let Err(e) = source_instance.wait_to_retry(&opt.pb).await
+                                        {
+                                            return fail(e);
+                                        }
+                                    }
+                                }
+                            };
 
-                        // let class_hash_set = entity_map_guard
-                        //     .entry(class_name.clone())
-                        //     .or_insert_with(HashSet::new);
-                        let response_entity_ids = response
-                            .iter()
-                            .map(|value| value["_id"].as_i64().unwrap())
-                            .collect::<HashSet<i64>>();
-
-                        let all_entities_already_exist =
-                            if let Some(class_hash_set) = entity_map_guard.get_mut(&class_name) {
-                                let result = class_hash_set.is_superset(&response_entity_ids);
-                                class_hash_set.extend(response_entity_ids);
-                                result
-                            } else {
-                                entity_map_guard.insert(class_name.clone(), response_entity_ids);
-                                false
+                            let response: Value = match serde_json::from_str(&response) {
+                                Ok(response) => response,
+                                Err(e) => {
+                                    pretty_print(&format!("[ERROR] {}", e), Color::DarkRed, true);
+                                    pretty_print(
+                                        &format!("Fluree Response: {}", response),
+                                        Color::DarkRed,
+                                        true,
+                                    );
+                                    serde_json::json!([])
+                                }
                             };
+                            let response = response.as_array().unwrap();
+
+                            // a source that ignores the offset answers with the same page again
+                            let ids = response
+                                .iter()
+                                .filter_map(|entity| entity["_id"].as_i64())
+                                .collect::<Vec<i64>>();
+                            let all_entities_already_exist = !seen_ids.insert_page(&ids);
+
+                            if response.is_empty() || all_entities_already_exist {
+                                page_sink
+                                    .put(&class_name, opt.shard_slice(std::mem::take(&mut results)))
+                                    .await;
+                                break;
+                            }
 
-                        drop(entity_map_guard);
+                            results = match offset {
+                                0 => response.to_owned(),
+                                _ => results.into_iter().chain(response.to_owned()).collect(),
+                            };
 
-                        if response.len() == 0 || all_entities_already_exist {
-                            temp_file
-                                .lock()
-                                .await
-                                .write(&class_name, &results)
-                                .expect(format!("Issue writing file for {}", class_name).as_str());
-                            results.clear();
-                            break;
-                        }
+                            // --sample only migrates the first N entities of each class
+                            if let Some(sample) = opt.sample {
+                                let fetched = offset as u64 + response.len() as u64;
+                                if fetched >= sample {
+                                    results.truncate(results.len() - (fetched - sample) as usize);
+                                    page_sink
+                                        .put(
+                                            &class_name,
+                                            opt.shard_slice(std::mem::take(&mut results)),
+                                        )
+                                        .await;
+                                    break;
+                                }
+                            }
 
-                        results = match offset {
-                            0 => response.to_owned(),
-                            _ => results.into_iter().chain(response.to_owned()).collect(),
-                        };
+                            let results_length = results.len();
 
-                        let results_length = results.len();
-
-                        if results_length > 12_500 {
-                            temp_file.lock().await.write(&class_name, &results).expect(
-                                format!(
-                                    "Issue writing file for {} at offset {}",
-                                    class_name, offset
-                                )
-                                .as_str(),
-                            );
-                            results.clear();
-                        }
+                            if results_length > 12_500 {
+                                page_sink
+                                    .put(&class_name, opt.shard_slice(std::mem::take(&mut results)))
+                                    .await;
+                            }
 
-                        offset += 5000;
+                            offset += page_size;
+                        }
                     }
 
                     let mut processing_guard = processing.lock().await;
@@ -600,183 +1720,832 @@ impl Migrate for FlureeInstance {
                     let message = print_classes.join(", ");
                     let full_message = if print_classes.len() > 3 {
                         format!("[{}...]", message)
-                    } else if print_classes.len() > 0 {
+                    } else if !print_classes.is_empty() {
                         format!("[{}]", message)
                     } else {
                         "".to_string()
                     };
                     opt.pb.set_message(full_message);
                     drop(processing_guard);
+                    Ok(seen_ids)
                 }
             });
-            drop(permit);
             handles.push(handle);
         }
 
-        for handle in handles {
-            handle.await.unwrap();
-        }
+        // spooled pages are transformed once every class is fetched, streamed ones as they arrive, so the fetch
+        // tasks are only awaited here when spooling. Each task answers with the _ids it fetched
+        let mut fetched_ids = vec![];
+        let files = match &page_sink {
+            PageSink::Spool(temp_file) => {
+                for handle in handles.drain(..) {
+                    match handle.await.unwrap() {
+                        Ok(ids) => fetched_ids.push(ids),
+                        Err(e) => abort_migration(&shared_opt, &report, &e),
+                    }
+                }
+                let temp_file = temp_file.lock().await;
+                match shared_opt.content_addressed {
+                    true => temp_file.get_files_by_collection(),
+                    false => temp_file.get_files(),
+                }
+                .expect("Could not get files")
+            }
+            PageSink::Stream(_) => vec![],
+        };
+        drop(page_sink);
 
         let mut vec_parsed_results = Vec::new();
-        let files = temp_file
-            .lock()
-            .await
-            .get_files()
-            .expect("Could not get files");
 
-        let mut result_size: u64 = 0;
-        let mut file_num: u64 = 1;
+        let merged_entities = parser.collect_entity_aliases(&files);
+        if merged_entities > 0 {
+            shared_opt.pb.println(format!(
+                "{:>12} {} duplicate entities into canonical subjects",
+                Style::new().green().bold().apply_to("Merging"),
+                merged_entities
+            ));
+        }
 
-        let opt = Arc::clone(&shared_opt);
-        opt.pb.reset();
-        opt.pb.inc_length(files.len() as u64);
-        opt.pb.enable_steady_tick(Duration::from_millis(400));
-        opt.pb.set_message(format!("{:3}%", 100 * 1 / files.len()));
-        opt.pb.set_style(
-            ProgressStyle::with_template(
-                // note that bar size is fixed unlike cargo which is dynamic
-                // and also the truncation in cargo uses trailers (`...`)
-                if Term::stdout().size().1 > 80 {
-                    "{prefix:>12.cyan.bold} [{bar:57}]{msg}  {spinner:.white}"
-                } else {
-                    "{prefix:>12.cyan.bold} [{bar:57}]{msg}"
-                },
-            )
-            .unwrap()
-            .tick_strings(&["🌲🎄🌲", "🎄🌲🎄", "🎄🎄🎄"])
-            .progress_chars("=> "),
+        // with --merge-collections, the entities fetched under several collections are held back from their
+        // pages, and merged into one subject each once every page is transformed
+        let merged_ids = match shared_opt.merge_collections {
+            true => shared_ids(&fetched_ids),
+            false => IdRanges::default(),
+        };
+        if merged_ids.len() > 0 {
+            shared_opt.pb.println(format!(
+                "{:>12} {} entities fetched under several collections into subjects with each class",
+                Style::new().green().bold().apply_to("Merging"),
+                merged_ids.len()
+            ));
+        }
+        let held_back = std::sync::Mutex::new(Vec::<(i64, String, Value)>::new());
+
+        // the dangling refs are found by the tracker that reports them, so every strategy and the report agree on them
+        let dangling_ids = match shared_opt.dangling_refs {
+            DanglingRefs::Keep => HashSet::new(),
+            _ => {
+                track_spooled_references(&parser, &files, &mut reference_tracker);
+                dangling_references(&reference_tracker, &parser)
+                    .into_iter()
+                    .map(|reference| reference.id)
+                    .collect()
+            }
+        };
+        if shared_opt.dangling_refs == DanglingRefs::Fail && !dangling_ids.is_empty() {
+            shared_opt.pb.finish_and_clear();
+            let mut examples = dangling_ids
+                .iter()
+                .take(5)
+                .cloned()
+                .collect::<Vec<String>>();
+            examples.sort();
+            println!(
+                "{:>12} {} referenced entities are never migrated as subjects (e.g. {}). Aborting before any data is written because of --dangling-refs fail",
+                red_bold.apply_to("ERROR"),
+                dangling_ids.len(),
+                examples.join(", ")
+            );
+            std::fs::remove_dir_all(temp_dir).expect("Could not remove temp directory");
+            std::process::exit(1);
+        }
+
+        let mut pii_scanner = PiiScanner::default();
+        let mut data_profiler = DataProfiler::with_properties(
+            parser
+                .classes
+                .values()
+                .map(|class| {
+                    let properties = class
+                        .range
+                        .iter()
+                        .filter_map(|property| property.get("@id").cloned())
+                        .collect();
+                    (class.id.to_owned(), properties)
+                })
+                .collect(),
         );
-        opt.pb.set_prefix("Writing v3 Data");
+        let chunk_size = parser
+            .plan
+            .as_ref()
+            .map_or(DATA_CHUNK_SIZE, |plan| plan.chunk_size);
+        let mut result_size: u64 = 0;
+        let mut file_num: u64 = shared_opt.first_data_file_number(&ledger_name);
+        let mut file_classes: Vec<String> = vec![];
+        let mut dataset_hash = DatasetHash::of_documents();
+        let frame = shared_opt.frame.as_ref().map(|path| Frame::load(path));
+        let mut left_out_of_frame = 0;
+        let upsert_classes = parser.upsert_classes();
+        let mut entity_hashes = shared_opt
+            .changed_only
+            .then(|| EntityHashes::load(&shared_opt, &ledger_name));
+        let written_hashes = match &entity_hashes {
+            Some(entity_hashes) => entity_hashes.written_hashes(),
+            None => WrittenHashes::Kept(HashMap::new()),
+        };
+        let (chunk_sender, chunk_writer) =
+            spawn_chunk_writer(&shared_opt, vocab_writer, written_hashes);
 
-        for (index, file) in files.iter().enumerate() {
-            opt.pb.inc(1);
+        let opt = Arc::clone(&shared_opt);
+        // streamed pages are transformed under the fetch progress bar
+        if !is_streaming {
+            opt.pb.reset();
+            opt.pb.inc_length(files.len() as u64);
+            opt.pb.enable_steady_tick(Duration::from_millis(400));
             opt.pb
-                .set_message(format!("{:3}%", 100 * (index + 1) / files.len()));
-            result_size += file.metadata().expect("Could not get metadata").len();
-
-            let file_bytes = std::fs::read(&file).expect("Could not read file");
-            let file_string = String::from_utf8(file_bytes).expect("Could not convert to string");
-            let results: Vec<Value> =
-                serde_json::from_str(&file_string).expect("Could not parse JSON");
-            let orig_class_name = file
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .split("__")
-                .collect::<Vec<&str>>()
-                .last()
+                .set_message(format!("{:3}%", 100 / files.len().max(1)));
+            opt.pb.set_style(
+                ProgressStyle::with_template(
+                    // note that bar size is fixed unlike cargo which is dynamic
+                    // and also the truncation in cargo uses trailers (`...`)
+                    if Term::stdout().size().1 > 80 {
+                        "{prefix:>12.cyan.bold} [{bar:57}]{msg}  {spinner:.white}"
+                    } else {
+                        "{prefix:>12.cyan.bold} [{bar:57}]{msg}"
+                    },
+                )
                 .unwrap()
-                .to_string();
-
-            for result in results {
-                let mut parsed_result: HashMap<String, Value> = HashMap::new();
-                let string_id: String = result["_id"].to_string();
-                parsed_result.insert("@id".to_string(), json!(string_id));
-
-                let class_name = match parser.classes.get(&orig_class_name) {
-                    Some(class) => class.id.to_owned(),
-                    None => panic!("Could not find class {}", orig_class_name),
-                };
-
-                parsed_result.insert("@type".to_string(), serde_json::json!(&class_name));
-                for (key, value) in result.as_object().unwrap() {
-                    if let Some(canonical_property) = parser.properties.get(key) {
-                        let key = canonical_property.id.to_owned();
-                        let shacl_shape = parser.shacl_shapes.get(&class_name).unwrap();
-                        let shacl_properties = &shacl_shape.property;
-                        let is_datetime = match shacl_properties.iter().find(|&x| {
-                            let shacl_path = x.path.get("@id").unwrap();
-                            let y = "xsd:dateTime";
-                            if x.datatype.is_none() {
-                                return false;
-                            }
-                            shacl_path == &key
-                                && x.datatype.clone().unwrap().get("@id").unwrap() == y
-                        }) {
-                            Some(_) => true,
-                            None => false,
-                        };
-                        let value = match is_datetime {
-                            true => json!(instant_to_iso_string(value.as_i64().unwrap())),
-                            false => value.to_owned(),
-                        };
-                        let ref_type = match shacl_properties.iter().find(|&x| {
-                            let shacl_path = x.path.get("@id").unwrap();
-                            let shacl_class = x.class.is_some();
-                            (shacl_path == &key) && shacl_class
-                        }) {
-                            Some(x) => {
-                                Some(x.class.clone().unwrap().get("@id").unwrap().to_string())
-                            }
-                            None => None,
-                        };
-                        parsed_result.insert(key, represent_fluree_value(&value, ref_type));
+                .tick_strings(&["🌲🎄🌲", "🎄🌲🎄", "🎄🎄🎄"])
+                .progress_chars("=> "),
+            );
+            opt.pb.set_prefix("Writing v3 Data");
+        }
+
+        // the pages are transformed a batch at a time, one thread per page, and then written in their order,
+        // so the output doesn't depend on how many threads there are
+        let transform_threads =
+            std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let is_dropping_dangling =
+            shared_opt.dangling_refs == DanglingRefs::Drop && !dangling_ids.is_empty();
+        let transform = |class_name: &str, results: &[Value]| -> Vec<Value> {
+            results
+                .iter()
+                .filter_map(|result| {
+                    let mut parsed_result = parser.transform_entity(class_name, result);
+                    if is_dropping_dangling {
+                        Parser::drop_references(&mut parsed_result, &dangling_ids);
+                    }
+                    match result["_id"].as_i64().filter(|&id| merged_ids.contains(id)) {
+                        Some(id) => {
+                            held_back.lock().unwrap().push((
+                                id,
+                                class_name.to_string(),
+                                parsed_result,
+                            ));
+                            None
+                        }
+                        None => Some(parsed_result),
                     }
+                })
+                .collect()
+        };
+        let mut index = 0;
+        let mut file_batches = files.chunks(transform_threads);
+        let mut page_receiver = page_receiver;
+        loop {
+            // each transformed page is its v2 class, the size of its v2 data, and its entities
+            let transformed = match page_receiver.as_mut() {
+                Some(page_receiver) => {
+                    let Some(page) = page_receiver.recv().await else {
+                        break;
+                    };
+                    let mut pages = vec![page];
+                    while pages.len() < transform_threads {
+                        match page_receiver.try_recv() {
+                            Ok(page) => pages.push(page),
+                            Err(_) => break,
+                        }
+                    }
+                    tokio::task::block_in_place(|| {
+                        transform_in_parallel(&pages, |(class_name, results)| {
+                            // the size of the page as a temp file, so the chunks are cut where a spooled run cuts them
+                            let size = serde_json::to_string_pretty(results).unwrap().len() as u64;
+                            (class_name.to_owned(), size, transform(class_name, results))
+                        })
+                    })
                 }
-                vec_parsed_results.push(json!(parsed_result));
-            }
-
-            data_results_map
-                .entry("insert".to_string())
-                .and_modify(|e| {
-                    if let Value::Array(array) = e {
-                        array.extend(vec_parsed_results.clone());
+                None => match file_batches.next() {
+                    Some(batch) => {
+                        let transformed = tokio::task::block_in_place(|| {
+                            transform_in_parallel(batch, |file| {
+                                let file_bytes = std::fs::read(file).expect("Could not read file");
+                                let results: Vec<Value> = serde_json::from_slice(&file_bytes)
+                                    .expect("Could not parse JSON");
+                                let orig_class_name = TempFile::collection_name(file);
+                                let parsed_results = transform(&orig_class_name, &results);
+                                (orig_class_name, file_bytes.len() as u64, parsed_results)
+                            })
+                        });
+                        for file in batch {
+                            std::fs::remove_file(file).expect("Could not remove file");
+                            index += 1;
+                            opt.pb.inc(1);
+                            opt.pb
+                                .set_message(format!("{:3}%", 100 * index / files.len()));
+                        }
+                        transformed
                     }
-                });
+                    // the held-back entities of several collections are written last, merged
+                    None => {
+                        let held_back = std::mem::take(&mut *held_back.lock().unwrap());
+                        if held_back.is_empty() {
+                            break;
+                        }
+                        vec![merge_held_back(held_back)]
+                    }
+                },
+            };
+            for (orig_class_name, size, parsed_results) in transformed {
+                result_size += size;
 
-            vec_parsed_results.clear();
+                let class_name = parser.class_name(&orig_class_name);
+                if !file_classes.contains(&class_name) {
+                    file_classes.push(class_name);
+                }
 
-            if result_size > 2_500_000 {
-                target_instance = shared_opt
-                    .write_or_print(
-                        format!("{}_data.jsonld", file_num),
-                        serde_json::to_string_pretty(&data_results_map).unwrap(),
-                        target_instance,
-                    )
-                    .await;
+                for parsed_result in parsed_results {
+                    // the refs were already tracked ahead of the data for --dangling-refs
+                    if shared_opt.dangling_refs == DanglingRefs::Keep {
+                        reference_tracker.track(&parsed_result);
+                    }
+                    if shared_opt.scan_pii {
+                        pii_scanner.scan(&parsed_result);
+                    }
+                    if shared_opt.profile.is_some() {
+                        data_profiler.profile(&parsed_result);
+                    }
+                    // with --changed-only, an entity the previous run already migrated as it is isn't written again
+                    if let Some(entity_hashes) = &mut entity_hashes {
+                        if !entity_hashes.is_changed(&parsed_result) {
+                            continue;
+                        }
+                    }
+                    vec_parsed_results.push(parsed_result);
+                }
+                run_state::add_transformed_entities(vec_parsed_results.len() as u64);
 
-                result_size = 0;
-                file_num += 1;
-                vec_parsed_results.clear();
                 data_results_map
                     .entry("insert".to_string())
                     .and_modify(|e| {
-                        *e = serde_json::json!([]);
+                        if let Value::Array(array) = e {
+                            array.extend(vec_parsed_results.clone());
+                        }
                     });
+
+                vec_parsed_results.clear();
+
+                if result_size > shared_opt.target_batch.scale(chunk_size) {
+                    if let Some(frame) = &frame {
+                        left_out_of_frame += frame.apply_to_document(&mut data_results_map);
+                    }
+                    merge_upserted_nodes(&mut data_results_map, &upsert_classes);
+                    dataset_hash.add_document(&data_results_map);
+                    match shared_opt.txn_mode {
+                        TxnMode::Replace => replace_existing_values(&mut data_results_map),
+                        TxnMode::Insert => upsert_nodes(&mut data_results_map, &upsert_classes),
+                    }
+                    let document = std::mem::take(&mut data_results_map);
+                    data_results_map.insert("ledger".to_string(), document["ledger"].to_owned());
+                    data_results_map
+                        .insert("@context".to_string(), document["@context"].to_owned());
+                    data_results_map.insert("insert".to_string(), json!([]));
+                    let file_name =
+                        shared_opt.data_file_name(file_num, &file_classes, &ledger_name);
+                    let hashes = entity_hashes
+                        .as_mut()
+                        .map(EntityHashes::take_pending)
+                        .unwrap_or_default();
+                    if chunk_sender
+                        .send((file_name, document, hashes))
+                        .await
+                        .is_err()
+                    {
+                        // the writer only hangs up when it gave up on a chunk, so stop transforming and report why
+                        drop(chunk_sender);
+                        let error = match chunk_writer.await.expect("Could not write data") {
+                            Err(e) => e,
+                            Ok(_) => "The chunk writer stopped before the last chunk".to_string(),
+                        };
+                        abort_migration(&shared_opt, &report, &error);
+                    }
+
+                    result_size = 0;
+                    file_num += 1;
+                    file_classes.clear();
+                    vec_parsed_results.clear();
+                }
+            }
+        }
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(ids) => fetched_ids.push(ids),
+                Err(e) => {
+                    // the chunks already sent are written before giving up, so the run state holds them
+                    drop(chunk_sender);
+                    let _ = chunk_writer.await;
+                    abort_migration(&shared_opt, &report, &e);
+                }
             }
+        }
+        let unmerged_ids = match shared_opt.merge_collections {
+            true => IdRanges::default(),
+            false => shared_ids(&fetched_ids),
+        };
+        if unmerged_ids.len() > 0 {
+            println!(
+                "{:>12} {} entities were fetched under several collections, and migrated as a subject per collection. Rerun with --merge-collections to migrate each as one subject with each class",
+                yellow_bold.apply_to("WARNING"),
+                unmerged_ids.len()
+            );
+        }
+        if !is_streaming {
+            std::fs::remove_dir_all(temp_dir).expect("Could not remove temp directory");
+        }
 
-            std::fs::remove_file(file).expect("Could not remove file");
+        report.dangling_references = dangling_references(&reference_tracker, &parser);
+        if shared_opt.dangling_refs == DanglingRefs::Stub {
+            let stubs = report
+                .dangling_references
+                .iter()
+                .map(|reference| reference.stub())
+                .collect::<Vec<Value>>();
+            if let Some(Value::Array(array)) = data_results_map.get_mut("insert") {
+                array.extend(stubs);
+            }
         }
-        std::fs::remove_dir_all(temp_dir).expect("Could not remove temp directory");
 
-        let _ = shared_opt
-            .write_or_print(
-                format!("{}_data.jsonld", file_num),
-                serde_json::to_string_pretty(&data_results_map).unwrap(),
-                target_instance,
-            )
+        if let Some(frame) = &frame {
+            left_out_of_frame += frame.apply_to_document(&mut data_results_map);
+        }
+        merge_upserted_nodes(&mut data_results_map, &upsert_classes);
+        dataset_hash.add_document(&data_results_map);
+        match shared_opt.txn_mode {
+            TxnMode::Replace => replace_existing_values(&mut data_results_map),
+            TxnMode::Insert => upsert_nodes(&mut data_results_map, &upsert_classes),
+        }
+        let file_name = shared_opt.data_file_name(file_num, &file_classes, &ledger_name);
+        let hashes = entity_hashes
+            .as_mut()
+            .map(EntityHashes::take_pending)
+            .unwrap_or_default();
+        let _ = chunk_sender
+            .send((file_name, data_results_map, hashes))
             .await;
+        drop(chunk_sender);
+        let written_hashes = chunk_writer
+            .await
+            .expect("Could not write data")
+            .unwrap_or_else(|e| abort_migration(&shared_opt, &report, &e));
+        if shared_opt.output_format == OutputFormat::Parquet {
+            parquet::write_class_files(&shared_opt);
+        }
+        if let Some(entity_hashes) = &mut entity_hashes {
+            entity_hashes.record_written(written_hashes);
+            entity_hashes.save(&shared_opt);
+        }
 
         shared_opt.pb.finish_and_clear();
 
+        if let Some(entity_hashes) = &entity_hashes {
+            println!(
+                "{:>12} {} entities that hadn't changed since the previous run",
+                green_bold.apply_to("Skipped"),
+                entity_hashes.unchanged()
+            );
+            let missing = entity_hashes.missing();
+            if missing > 0 {
+                println!(
+                    "{:>12} {} entities the previous run migrated weren't migrated by this one (e.g. they were deleted from the source), and weren't retracted",
+                    yellow_bold.apply_to("WARNING"),
+                    missing
+                );
+            }
+        }
+        if left_out_of_frame > 0 {
+            println!(
+                "{:>12} {} entities neither matched the --frame nor were embedded in an entity that did, and were left out",
+                yellow_bold.apply_to("WARNING"),
+                left_out_of_frame
+            );
+        }
+        // a --changed-only run only writes the entities that changed, whose hash isn't the dataset's, so the previous
+        // run's dataset hash is left as it was
+        if shared_opt.changed_only && !shared_opt.print {
+            println!(
+                "{:>12} writing the dataset hash, which a --changed-only run can't compute from the changed entities alone",
+                yellow_bold.apply_to("Skipped")
+            );
+        } else if dataset_hash.repeated_subjects() > 0 && !shared_opt.print {
+            // the hash of the previous run's data isn't this run's either
+            let _ = std::fs::remove_file(run_state::run_file_path(&shared_opt, MANIFEST_FILE));
+            println!(
+                "{:>12} writing the dataset hash, as {} subjects have statements in several data files (e.g. an _id fetched under several collections without --merge-collections), so the statements they repeat would be hashed twice",
+                yellow_bold.apply_to("Skipped"),
+                dataset_hash.repeated_subjects()
+            );
+        } else if !shared_opt.print {
+            let manifest =
+                DatasetManifest::new(&ledger_name, &parser.data_context_json(), &dataset_hash);
+            let manifest_path = run_state::run_file_path(&shared_opt, MANIFEST_FILE);
+            match manifest.write(&manifest_path) {
+                Ok(_) => println!(
+                    "{:>12} {} statements to {} (written to {})",
+                    green_bold.apply_to("Hashed"),
+                    manifest.statements,
+                    manifest.hash,
+                    manifest_path.display()
+                ),
+                Err(e) => println!(
+                    "{:>12} Could not write the dataset hash to {}: {}",
+                    yellow_bold.apply_to("WARNING"),
+                    manifest_path.display(),
+                    e
+                ),
+            }
+        }
+
+        if !report.dangling_references.is_empty() {
+            println!(
+                "{:>12} {} referenced entities were never migrated as subjects (e.g. {})",
+                yellow_bold.apply_to("WARNING"),
+                report.dangling_references.len(),
+                report
+                    .dangling_references
+                    .iter()
+                    .take(5)
+                    .map(|reference| reference.id.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+        }
+        report.pii_findings = pii_scanner.findings();
+        for finding in &report.pii_findings {
+            let mut reasons = vec![];
+            if let Some(kind) = &finding.name_suggests {
+                reasons.push(format!("its name suggests {}", kind));
+            }
+            for (kind, count) in &finding.matching_values {
+                reasons.push(format!(
+                    "{} of {} values look like {}",
+                    count, finding.scanned_values, kind
+                ));
+            }
+            println!(
+                "{:>12} {}/{} may hold PII ({})",
+                yellow_bold.apply_to("PII"),
+                finding.class,
+                finding.property,
+                reasons.join(", ")
+            );
+        }
+        if let Some(profile_path) = &shared_opt.profile {
+            let profile = data_profiler.data_profile();
+            for class in &profile.classes {
+                for property in class.properties.iter().filter(|p| p.values == 0) {
+                    println!(
+                        "{:>12} {}/{} has no values in any of its {} entities",
+                        yellow_bold.apply_to("PROFILE"),
+                        class.class,
+                        property.property,
+                        class.entities
+                    );
+                }
+            }
+            profile.write(profile_path);
+            println!(
+                "{:>12} Profile of {} classes to {}",
+                green_bold.apply_to("Wrote"),
+                profile.classes.len(),
+                profile_path.display()
+            );
+        }
+        report.save(&shared_opt);
+
         // let finish_line = match opt.print {
         //     false => format!("to {}/ ", opt.output.to_str().unwrap()),
         //     true => "".to_string(),
         // };
 
-        let finish_line = match (output, target) {
-            (_, Some(target)) => format!("to Target Ledger [{}] ", target),
-            (output, _) => match output {
-                Some(output) => format!("to {}/ ", output.to_str().unwrap()),
-                None => "".to_string(),
-            },
-        };
         println!(
-            "{:>12} v3 Migration {}in {}",
+            "{:>12} v3 Migration {}in {} (run {})",
             green_bold.apply_to("Finished"),
-            finish_line,
+            finish_line(&output, &target),
             HumanDuration(start.elapsed()),
+            trace::correlation_id(),
         );
+        report
+    }
+}
+
+/// Where the fetch tasks put the pages of entities they fetch: spooled to temp files, to be transformed once
+/// every class is fetched, or streamed to the transform through a channel of `FETCH_QUEUE_PAGES` pages,
+/// which holds the fetching back while the transform and the writer are behind
+#[derive(Clone)]
+enum PageSink {
+    Spool(Arc<Mutex<TempFile>>),
+    Stream(tokio::sync::mpsc::Sender<Page>),
+}
+
+impl PageSink {
+    async fn put(&self, class_name: &str, results: Vec<Value>) {
+        match self {
+            PageSink::Spool(temp_file) => temp_file
+                .lock()
+                .await
+                .write(class_name, &results)
+                .unwrap_or_else(|_| panic!("Issue writing file for {}", class_name)),
+            // the transform only stops taking pages when the run is ending anyway
+            PageSink::Stream(page_sender) => {
+                let _ = page_sender.send((class_name.to_string(), results)).await;
+            }
+        }
+    }
+}
+
+/// Starts the task that serializes and writes (or transacts) the data chunks sent to it, one after the other,
+/// so a chunk is written while the next is transformed. No chunk is written before the vocab writer has finished,
+/// whose target instance the chunks are transacted with. At most `WRITE_QUEUE_CHUNKS` chunks wait to be written.
+/// The writer records the content hashes of the entities of the chunks it wrote, and returns them (or the error it
+/// gave up on, after which it takes no more chunks)
+fn spawn_chunk_writer(
+    opt: &Arc<Opt>,
+    vocab_writer: tokio::task::JoinHandle<Result<Option<FlureeInstance>, String>>,
+    mut written_hashes: WrittenHashes,
+) -> (tokio::sync::mpsc::Sender<Chunk>, ChunkWriter) {
+    let (chunk_sender, mut chunk_receiver) =
+        tokio::sync::mpsc::channel::<Chunk>(WRITE_QUEUE_CHUNKS);
+    let opt = Arc::clone(opt);
+    let chunk_writer = tokio::spawn(async move {
+        let mut target_instance = vocab_writer.await.unwrap()?;
+        while let Some((file_name, document, hashes)) = chunk_receiver.recv().await {
+            let data = serde_json::to_string_pretty(&document).unwrap();
+            drop(document);
+            target_instance = opt.write_or_print(file_name, data, target_instance).await?;
+            written_hashes.record(hashes);
+        }
+        Ok(written_hashes)
+    });
+    (chunk_sender, chunk_writer)
+}
+
+/// Gives the migration up on a request that kept failing: the report gathered so far is written, and the run is
+/// stopped the way Ctrl-C stops it
+fn abort_migration(opt: &Opt, report: &MigrationReport, reason: &str) -> ! {
+    report.save(opt);
+    run_state::abort(opt, reason)
+}
+
+/// The `_id`s a fetch task fetched of its class, so a source that ignores the offset is noticed, and the entities
+/// fetched under several collections are found. With a --state-db, they're kept there rather than in memory
+enum FetchedIds {
+    Ranges(IdRanges),
+    Stored(String),
+}
+
+impl FetchedIds {
+    fn new(class_name: &str) -> Self {
+        match store::is_started() {
+            true => FetchedIds::Stored(class_name.to_string()),
+            false => FetchedIds::Ranges(IdRanges::default()),
+        }
+    }
+
+    /// Records the ids of a fetched page, answering whether any of them wasn't fetched before
+    fn insert_page(&mut self, ids: &[i64]) -> bool {
+        match self {
+            // every id is inserted, so no short-circuiting
+            FetchedIds::Ranges(ranges) => ids.iter().filter(|&&id| ranges.insert(id)).count() > 0,
+            FetchedIds::Stored(class_name) => store::record_fetched_ids(class_name, ids) > 0,
+        }
+    }
+}
+
+// the _ids fetched under more than one collection
+fn shared_ids(fetched_ids: &[FetchedIds]) -> IdRanges {
+    if store::is_started() {
+        return store::shared_fetched_ids();
+    }
+    let ranges = fetched_ids
+        .iter()
+        .filter_map(|ids| match ids {
+            FetchedIds::Ranges(ranges) => Some(ranges),
+            FetchedIds::Stored(_) => None,
+        })
+        .collect::<Vec<&IdRanges>>();
+    let mut shared = IdRanges::default();
+    for (index, ids) in ranges.iter().enumerate() {
+        for other_ids in &ranges[index + 1..] {
+            shared.extend(&ids.intersection(other_ids));
+        }
+    }
+    shared
+}
+
+// merges the transformed entities held back by --merge-collections into a subject each, as a page of the first
+// of their collections
+fn merge_held_back(held_back: Vec<(i64, String, Value)>) -> (String, u64, Vec<Value>) {
+    let mut subjects: BTreeMap<i64, Vec<(String, Value)>> = BTreeMap::new();
+    for (id, orig_class_name, node) in held_back {
+        subjects
+            .entry(id)
+            .or_default()
+            .push((orig_class_name, node));
+    }
+    let mut orig_class_names = vec![];
+    let merged = subjects
+        .into_values()
+        .map(|mut nodes| {
+            // the classes are listed in the same order whatever order the pages were transformed in
+            nodes.sort_by(|a, b| a.0.cmp(&b.0));
+            orig_class_names.push(nodes[0].0.to_owned());
+            merge_typed_nodes(nodes.into_iter().map(|(_, node)| node).collect())
+        })
+        .collect();
+    orig_class_names.sort();
+    let orig_class_name = orig_class_names.into_iter().next().unwrap_or_default();
+    (orig_class_name, 0, merged)
+}
+
+/// Applies `transform` to each of `items` on its own thread, returning the results in the items' order
+/// Tracks the refs of the transformed entities of every spooled page ahead of the data, so --dangling-refs knows
+/// which of them dangle before any entity is written
+fn track_spooled_references(
+    parser: &Parser,
+    files: &[PathBuf],
+    reference_tracker: &mut ReferenceTracker,
+) {
+    let transform_threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    for batch in files.chunks(transform_threads) {
+        let transformed = tokio::task::block_in_place(|| {
+            transform_in_parallel(batch, |file| {
+                let orig_class_name = TempFile::collection_name(file);
+                TempFile::read(file)
+                    .iter()
+                    .map(|entity| parser.transform_entity(&orig_class_name, entity))
+                    .collect::<Vec<Value>>()
+            })
+        });
+        for entity in transformed.iter().flatten() {
+            reference_tracker.track(entity);
+        }
+    }
+}
+
+// the refs that never resolve to a migrated subject, leaving out the subjects of the other shards
+fn dangling_references(
+    reference_tracker: &ReferenceTracker,
+    parser: &Parser,
+) -> Vec<DanglingReference> {
+    let mut dangling = reference_tracker.dangling();
+    dangling.retain(|reference| !parser.is_other_shards_subject(&reference.id));
+    dangling
+}
+
+fn transform_in_parallel<T, R, F>(items: &[T], transform: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    std::thread::scope(|scope| {
+        let handles = items
+            .iter()
+            .map(|item| scope.spawn(|| transform(item)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Could not transform page"))
+            .collect()
+    })
+}
+
+// whether two URLs have the same scheme, host, and port
+fn is_same_origin(url: &str, other_url: &str) -> bool {
+    match (reqwest::Url::parse(url), reqwest::Url::parse(other_url)) {
+        (Ok(url), Ok(other_url)) => url.origin() == other_url.origin(),
+        _ => false,
     }
 }
+
+// where the run's files went, for its closing line
+fn finish_line(output: &Option<PathBuf>, target: &Option<String>) -> String {
+    match (output, target) {
+        (_, Some(target)) => format!("to Target Ledger [{}] ", target),
+        (output, _) => match output {
+            Some(output) => format!("to {}/ ", output.to_str().unwrap()),
+            None => "".to_string(),
+        },
+    }
+}
+
+/// Transforms the v2 entity of `collection` read from stdin, and prints it with the data @context (`transform-one`)
+fn transform_one(parser: &Parser, collection: &str) {
+    let exit_with_error = |message: &str| -> ! {
+        pretty_print(message, ERROR_COLOR, true);
+        std::process::exit(1);
+    };
+    if !parser.classes.contains_key(collection) {
+        let mut collections = parser.classes.keys().cloned().collect::<Vec<String>>();
+        collections.sort();
+        exit_with_error(&format!(
+            "\"{}\" isn't a migrated v2 collection; expected one of {}",
+            collection,
+            collections.join(", ")
+        ));
+    }
+    let input = std::io::read_to_string(std::io::stdin())
+        .unwrap_or_else(|e| exit_with_error(&format!("Could not read stdin: {}", e)));
+    let entity = match serde_json::from_str::<Value>(&input) {
+        Ok(entity) if entity["_id"].is_number() => entity,
+        Ok(_) => exit_with_error(
+            "Expected a v2 entity as a JSON object with an _id, e.g. {\"_id\": 351843720888321, \"name\": \"Ada\"}",
+        ),
+        Err(e) => exit_with_error(&format!("The entity is not valid JSON: {}", e)),
+    };
+    let mut subject = parser.transform_entity(collection, &entity);
+    if let Some(subject) = subject.as_object_mut() {
+        subject.insert("@context".to_string(), parser.data_context_json());
+    }
+    println!("{}", serde_json::to_string_pretty(&subject).unwrap());
+}
+
+/// Counts the entities of each class and measures a small sample of them, to project the size, transaction count,
+/// and duration of a migration without transferring the data (`--estimate`)
+async fn print_estimate(source_instance: &mut FlureeInstance, parser: &Parser, opt: &Opt) {
+    let green_bold = Style::new().green().bold();
+    let cyan_bold = Style::new().cyan().bold();
+    let mut class_names = parser.classes.keys().cloned().collect::<Vec<String>>();
+    class_names.sort();
+
+    let (mut total_entities, mut total_source_bytes, mut total_output_bytes) = (0, 0, 0);
+    let (mut sampled_source_bytes, mut sampling_time) = (0, Duration::ZERO);
+    for class_name in &class_names {
+        let entity_count = source_instance
+            .collection_count(class_name)
+            .await
+            .unwrap_or_else(|e| run_state::abort(opt, &e));
+        let sampling_start = Instant::now();
+        let samples = source_instance
+            .sample_class(class_name, ESTIMATE_SAMPLE)
+            .await
+            .unwrap_or_else(|e| run_state::abort(opt, &e));
+        sampling_time += sampling_start.elapsed();
+
+        let (average_source_size, average_output_size) = match samples.len() {
+            0 => (0, 0),
+            sample_count => {
+                let source_size = samples
+                    .iter()
+                    .map(|sample| serde_json::to_string_pretty(sample).unwrap().len())
+                    .sum::<usize>();
+                let output_size = samples
+                    .iter()
+                    .map(|sample| {
+                        let entity = parser.transform_entity(class_name, sample);
+                        serde_json::to_string_pretty(&entity).unwrap().len()
+                    })
+                    .sum::<usize>();
+                sampled_source_bytes += source_size;
+                (source_size / sample_count, output_size / sample_count)
+            }
+        };
+        total_entities += entity_count;
+        total_source_bytes += entity_count * average_source_size;
+        total_output_bytes += entity_count * average_output_size;
+        opt.pb.println(format!(
+            "{:>12} {}: {} entities, ~{} each in v3",
+            cyan_bold.apply_to("Counted"),
+            class_name,
+            entity_count,
+            format_bytes(average_output_size)
+        ));
+    }
+
+    // data files are cut whenever the v2 data behind them passes DATA_CHUNK_SIZE, and the vocab is its own transaction
+    let transactions = (total_source_bytes as u64).div_ceil(DATA_CHUNK_SIZE).max(1) + 1;
+    let bytes_per_second = match sampling_time.as_secs_f64() {
+        seconds if seconds > 0.0 => sampled_source_bytes as f64 / seconds,
+        _ => f64::INFINITY,
+    };
+    // classes are fetched up to --fetch-concurrency at a time
+    let concurrency = class_names
+        .len()
+        .clamp(1, opt.fetch_concurrency.max(1) as usize) as f64;
+    let fetch_duration =
+        Duration::from_secs_f64(total_source_bytes as f64 / bytes_per_second / concurrency);
+
+    opt.pb.finish_and_clear();
+    println!(
+        "{:>12} {} entities in {} classes, ~{} of v3 JSON-LD in ~{} transactions (including the vocab)",
+        green_bold.apply_to("Estimated"),
+        total_entities,
+        class_names.len(),
+        format_bytes(total_output_bytes),
+        transactions
+    );
+    println!(
+        "{:>12} ~{} to fetch from the source, at the rate the samples were fetched",
+        green_bold.apply_to("Estimated"),
+        HumanDuration(fetch_duration)
+    );
+}
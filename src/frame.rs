@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::read_json_file;
+
+/// A JSON-LD frame (https://www.w3.org/TR/json-ld11-framing/) applied to the entities of each data file.
+/// Nodes are matched by `@type` (or `@id`), and a property whose frame is an object embeds the nodes it
+/// references, framed in turn. `@explicit` and `@embed` (`@once`, `@always`, `@never`) are supported.
+/// Terms are matched as they appear in the output, i.e. compacted with the data @context
+#[derive(Debug, Clone)]
+pub struct Frame {
+    frame: Map<String, Value>,
+}
+
+#[derive(Default)]
+struct FramingState {
+    embedded: HashSet<String>,
+    path: Vec<String>,
+}
+
+fn values(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(array) => array.iter().collect(),
+        value => vec![value],
+    }
+}
+
+fn node_id(node: &Map<String, Value>) -> Option<&str> {
+    node.get("@id").and_then(Value::as_str)
+}
+
+fn matches(node: &Map<String, Value>, frame: &Map<String, Value>) -> bool {
+    let type_matches = match frame.get("@type") {
+        Some(frame_types) => {
+            let node_types = node.get("@type").map(values).unwrap_or_default();
+            values(frame_types)
+                .iter()
+                .any(|frame_type| node_types.contains(frame_type))
+        }
+        None => true,
+    };
+    let id_matches = match frame.get("@id") {
+        Some(frame_ids) => node
+            .get("@id")
+            .is_some_and(|id| values(frame_ids).contains(&id)),
+        None => true,
+    };
+    type_matches && id_matches
+}
+
+impl Frame {
+    pub fn load(path: &Path) -> Self {
+        match read_json_file(path, "frame") {
+            Value::Object(frame) => Frame { frame },
+            _ => {
+                pretty_print(
+                    &format!("The frame in {} must be a JSON object", path.display()),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Frames the nodes of one data file. Returns the framed nodes, and how many nodes were left out
+    /// because they neither matched the frame nor were embedded in a node that did
+    pub fn apply(&self, nodes: &[Value]) -> (Vec<Value>, usize) {
+        let index: HashMap<&str, &Map<String, Value>> = nodes
+            .iter()
+            .filter_map(Value::as_object)
+            .filter_map(|node| Some((node_id(node)?, node)))
+            .collect();
+        let mut state = FramingState::default();
+        let mut framed = vec![];
+        let mut matched = HashSet::new();
+        for node in nodes.iter().filter_map(Value::as_object) {
+            if !matches(node, &self.frame) {
+                continue;
+            }
+            if let Some(id) = node_id(node) {
+                matched.insert(id.to_string());
+                state.path.push(id.to_string());
+            }
+            framed.push(Self::frame_node(node, &self.frame, &index, &mut state));
+            state.path.clear();
+        }
+        let left_out = nodes
+            .iter()
+            .filter_map(Value::as_object)
+            .filter(|node| match node_id(node) {
+                Some(id) => !matched.contains(id) && !state.embedded.contains(id),
+                None => !matches(node, &self.frame),
+            })
+            .count();
+        (framed, left_out)
+    }
+
+    /// Frames the `insert` of a data file in place, returning how many nodes were left out
+    pub fn apply_to_document(&self, document: &mut Map<String, Value>) -> usize {
+        let Some(Value::Array(nodes)) = document.get_mut("insert") else {
+            return 0;
+        };
+        let (framed, left_out) = self.apply(nodes);
+        *nodes = framed;
+        left_out
+    }
+
+    fn frame_node(
+        node: &Map<String, Value>,
+        frame: &Map<String, Value>,
+        index: &HashMap<&str, &Map<String, Value>>,
+        state: &mut FramingState,
+    ) -> Value {
+        let explicit = frame.get("@explicit") == Some(&Value::Bool(true));
+        let mut framed = Map::new();
+        for (key, value) in node {
+            let property_frame = frame.get(key);
+            if key.starts_with('@') || (!explicit && property_frame.is_none()) {
+                framed.insert(key.to_owned(), value.to_owned());
+                continue;
+            }
+            let property_frame = match property_frame {
+                Some(Value::Object(property_frame)) => property_frame,
+                Some(_) => {
+                    framed.insert(key.to_owned(), value.to_owned());
+                    continue;
+                }
+                None => continue,
+            };
+            let framed_value = match value {
+                Value::Array(array) => Value::Array(
+                    array
+                        .iter()
+                        .map(|value| Self::embed(value, property_frame, index, state))
+                        .collect(),
+                ),
+                value => Self::embed(value, property_frame, index, state),
+            };
+            framed.insert(key.to_owned(), framed_value);
+        }
+        Value::Object(framed)
+    }
+
+    // replaces a reference with the node it references, when that node is in the file and matches the frame
+    fn embed(
+        value: &Value,
+        frame: &Map<String, Value>,
+        index: &HashMap<&str, &Map<String, Value>>,
+        state: &mut FramingState,
+    ) -> Value {
+        let Some(id) = value
+            .as_object()
+            .filter(|reference| reference.len() == 1)
+            .and_then(node_id)
+        else {
+            return value.to_owned();
+        };
+        let Some(node) = index.get(id).filter(|node| matches(node, frame)) else {
+            return value.to_owned();
+        };
+        let embed = frame
+            .get("@embed")
+            .and_then(Value::as_str)
+            .unwrap_or("@once");
+        let is_embeddable = match embed {
+            "@never" => false,
+            "@always" => !state.path.iter().any(|ancestor| ancestor == id),
+            _ => !state.embedded.contains(id) && !state.path.iter().any(|ancestor| ancestor == id),
+        };
+        if !is_embeddable {
+            return value.to_owned();
+        }
+        state.embedded.insert(id.to_string());
+        state.path.push(id.to_string());
+        let framed = Self::frame_node(node, frame, index, state);
+        state.path.pop();
+        framed
+    }
+}
@@ -7,11 +7,14 @@ use log::{log_enabled, Level};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::io::stdout;
+use std::path::Path;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 
 use crate::cli::opt::Opt;
-use crate::console::ERROR_COLOR;
+use crate::console::{pretty_print, ERROR_COLOR};
 // use crate::cli::opt::Opt;
 use crate::fluree::FlureeInstance;
+use crate::redact::redact;
 
 // I have epoch instant values like 1693403567000 but want to convert them to ISO strings like "2023-08-30T13:52:47.000Z"
 pub fn instant_to_iso_string(epoch: i64) -> String {
@@ -21,6 +24,50 @@ pub fn instant_to_iso_string(epoch: i64) -> String {
     date_time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
 }
 
+pub fn instant_to_iso_date(epoch: i64) -> String {
+    let naive =
+        NaiveDateTime::from_timestamp_millis(epoch).expect("DateTime value is out of range");
+    naive.date().format("%Y-%m-%d").to_string()
+}
+
+// converts the epoch instants in a (possibly multi-valued) v2 value, leaving any other values as they are
+pub fn map_instants(value: &Value, convert: fn(i64) -> String) -> Value {
+    match value {
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| map_instants(value, convert))
+                .collect(),
+        ),
+        Value::Number(number) => match number.as_i64() {
+            Some(epoch) => Value::String(convert(epoch)),
+            None => value.to_owned(),
+        },
+        _ => value.to_owned(),
+    }
+}
+
+// matches text against a pattern where "*" stands for any run of characters
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts = parts.collect::<Vec<&str>>();
+    for (index, part) in parts.iter().enumerate() {
+        if index == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
 pub fn represent_fluree_value(value: &Value, ref_type: Option<String>) -> Value {
     match value {
         Value::Object(value) => {
@@ -45,6 +92,198 @@ pub fn represent_fluree_value(value: &Value, ref_type: Option<String>) -> Value
     }
 }
 
+// trims string values and collapses runs of control characters (other than newlines and tabs) into a single space
+pub fn clean_strings(value: &Value) -> Value {
+    match value {
+        Value::String(string) => {
+            let mut cleaned = String::with_capacity(string.len());
+            let mut in_control_run = false;
+            for character in string.chars() {
+                if character.is_control() && character != '\n' && character != '\t' {
+                    if !in_control_run {
+                        cleaned.push(' ');
+                    }
+                    in_control_run = true;
+                } else {
+                    cleaned.push(character);
+                    in_control_run = false;
+                }
+            }
+            Value::String(cleaned.trim().to_string())
+        }
+        Value::Array(values) => Value::Array(values.iter().map(clean_strings).collect()),
+        value => value.to_owned(),
+    }
+}
+
+// a stable 64-bit hash of a value under a seed (splitmix64), so seeded sampling is reproducible across runs and platforms
+pub fn seeded_hash(seed: u64, value: u64) -> u64 {
+    let mut hash = seed ^ value.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+/// The content hash a file is named and recognised by with --content-addressed: the first 16 hex digits
+/// of the SHA-256 of its contents
+pub fn content_hash(bytes: &[u8]) -> String {
+    sha256_hex(bytes)[..16].to_string()
+}
+
+// the lowercase hex SHA-256 digest of some bytes, as used by RDF canonicalization
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// the kind of PII a property name suggests it holds (e.g. "workEmail" or "person_ssn")
+pub fn pii_name_kind(property_name: &str) -> Option<&'static str> {
+    let name = property_name
+        .chars()
+        .filter(|character| character.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    let kinds: [(&str, &[&str]); 4] = [
+        ("email", &["email"]),
+        ("phone", &["phone", "mobile", "fax"]),
+        (
+            "nationalId",
+            &["ssn", "socialsecurity", "nationalid", "passport", "taxid"],
+        ),
+        ("creditCard", &["creditcard", "cardnumber", "ccnum"]),
+    ];
+    kinds
+        .iter()
+        .find(|(_, fragments)| fragments.iter().any(|fragment| name.contains(fragment)))
+        .map(|(kind, _)| *kind)
+}
+
+// the kind of PII a string value looks like, if any
+pub fn pii_value_kind(value: &str) -> Option<&'static str> {
+    let value = value.trim();
+    let digits = value
+        .chars()
+        .filter(|character| character.is_ascii_digit())
+        .collect::<Vec<char>>();
+    let is_digit_run = |separators: &str| {
+        value
+            .chars()
+            .all(|character| character.is_ascii_digit() || separators.contains(character))
+    };
+
+    if let Some((local, domain)) = value.split_once('@') {
+        let is_email = !local.is_empty()
+            && domain.contains('.')
+            && !domain.starts_with('.')
+            && !domain.ends_with('.')
+            && !value.contains(char::is_whitespace)
+            && !domain.contains('@');
+        return match is_email {
+            true => Some("email"),
+            false => None,
+        };
+    }
+    // e.g. 078-05-1120
+    let ssn_groups = value.split('-').map(str::len).collect::<Vec<usize>>();
+    if ssn_groups == [3, 2, 4] && digits.len() == 9 && is_digit_run("-") {
+        return Some("nationalId");
+    }
+    if (13..=19).contains(&digits.len()) && is_digit_run(" -") && passes_luhn(&digits) {
+        return Some("creditCard");
+    }
+    if (10..=15).contains(&digits.len())
+        && is_digit_run(" -.()+")
+        && value.find('+').unwrap_or(0) == 0
+    {
+        return Some("phone");
+    }
+    None
+}
+
+fn passes_luhn(digits: &[char]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| {
+            let digit = digit.to_digit(10).unwrap_or(0);
+            match index % 2 {
+                1 if digit * 2 > 9 => digit * 2 - 9,
+                1 => digit * 2,
+                _ => digit,
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+// removes null and empty-string values (or replaces them with the sentinel), returning None if nothing is left
+pub fn scrub_empty_values(value: Value, sentinel: Option<&Value>) -> Option<Value> {
+    match value {
+        Value::Null => sentinel.cloned(),
+        Value::String(string) if string.is_empty() => sentinel.cloned(),
+        Value::Array(values) => {
+            let values = values
+                .into_iter()
+                .filter_map(|value| scrub_empty_values(value, sentinel))
+                .collect::<Vec<Value>>();
+            match values.is_empty() {
+                true => None,
+                false => Some(Value::Array(values)),
+            }
+        }
+        value => Some(value),
+    }
+}
+
+// infers the xsd datatype shared by every sampled value, or None if the values are refs or mix datatypes
+pub fn infer_datatype(values: &[&Value]) -> Option<String> {
+    let mut datatypes = values.iter().map(|value| match value {
+        Value::Bool(_) => Some("xsd:boolean"),
+        Value::Number(_) => Some("xsd:decimal"),
+        Value::String(value) if DateTime::parse_from_rfc3339(value).is_ok() => Some("xsd:dateTime"),
+        Value::String(_) => Some("xsd:string"),
+        _ => None,
+    });
+    let first = datatypes.next()??;
+    let mut is_string = first == "xsd:string";
+    for datatype in datatypes {
+        let datatype = datatype?;
+        if datatype != first {
+            // date-looking strings mixed with free text are just strings
+            match (first, datatype) {
+                ("xsd:dateTime", "xsd:string") | ("xsd:string", "xsd:dateTime") => is_string = true,
+                _ => return None,
+            }
+        }
+    }
+    match is_string {
+        true => Some("xsd:string".to_string()),
+        false => Some(first.to_string()),
+    }
+}
+
+// wraps a literal value (or each value of an array) as a JSON-LD value object with the given datatype
+pub fn typed_literal(value: &Value, datatype: &str) -> Value {
+    match value {
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| typed_literal(value, datatype))
+                .collect(),
+        ),
+        Value::Object(_) | Value::Null => value.to_owned(),
+        // decimals keep their exact lexical form, rather than being read back as doubles
+        Value::Number(number) if datatype == "xsd:decimal" => {
+            serde_json::json!({ "@value": number.to_string(), "@type": datatype })
+        }
+        value => serde_json::json!({ "@value": value, "@type": datatype }),
+    }
+}
+
 // a function that expects strings. If the string has a pattern of substr:substr separated by ":", then it will return the second substr
 pub fn remove_namespace(string: &str) -> String {
     let mut split = string.split(":");
@@ -71,10 +310,7 @@ pub fn case_normalize(string: &str) -> String {
     let mut split = string.split("_");
     let mut result = String::new();
     let first = split.next();
-    let first = match first {
-        Some(first) => first,
-        None => "",
-    };
+    let first = first.unwrap_or_default();
     result.push_str(first);
     for part in split {
         result.push_str(&capitalize(part));
@@ -90,15 +326,81 @@ pub fn capitalize(string: &str) -> String {
     }
 }
 
+// the words of a term, title-cased, e.g. "Full Name" for "fullName", "Person Id" for "person_id", and "Home URL" for
+// "homeURL"; a run of capitals is kept as one word
+pub fn title_case_term(term: &str) -> String {
+    let chars = term.chars().collect::<Vec<char>>();
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    for (index, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' || c.is_whitespace() {
+            words.push(std::mem::take(&mut word));
+            continue;
+        }
+        let previous = index.checked_sub(1).map(|index| chars[index]);
+        let next = chars.get(index + 1);
+        let is_word_start = match previous {
+            Some(previous) if c.is_uppercase() => {
+                previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.is_some_and(|next| next.is_lowercase()))
+            }
+            Some(previous) => c.is_ascii_digit() && previous.is_alphabetic(),
+            None => false,
+        };
+        if is_word_start {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    words.push(word);
+    words
+        .iter()
+        .filter(|word| !word.is_empty())
+        .map(|word| capitalize(word))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
 pub fn standardize_class_name(string: &str) -> String {
     let string = remove_namespace(string);
     let string = capitalize(&string);
-    let string = case_normalize(&string);
-    string
+    case_normalize(&string).nfc().collect()
+}
+
+// the v3 class name of a v2 collection, from the `--rename-classes` map if it's given one, and otherwise
+// standardized, unless `--preserve-names` keeps it as it's spelled
+pub fn class_term(
+    orig_class_name: &str,
+    class_renames: &HashMap<String, String>,
+    preserve_names: bool,
+) -> String {
+    match (class_renames.get(orig_class_name), preserve_names) {
+        (Some(class_name), _) => class_name.to_owned(),
+        (None, true) => orig_class_name.to_owned(),
+        (None, false) => standardize_class_name(orig_class_name),
+    }
 }
 
 pub fn standardize_property_name(string: &str) -> String {
-    case_normalize(string)
+    case_normalize(string).nfc().collect()
+}
+
+// the v3 term of a v2 predicate that isn't renamed
+pub fn default_property_term(orig_property_name: &str, preserve_names: bool) -> String {
+    match preserve_names {
+        true => orig_property_name.to_owned(),
+        false => standardize_property_name(orig_property_name),
+    }
+}
+
+// normalizes string values to NFC, so visually identical values don't become distinct terms in v3
+pub fn nfc_strings(value: &Value) -> Value {
+    match value {
+        Value::String(string) if !is_nfc(string) => Value::String(string.nfc().collect()),
+        Value::Array(values) => Value::Array(values.iter().map(nfc_strings).collect()),
+        value => value.to_owned(),
+    }
 }
 
 pub fn parse_current_predicates(json: Value) -> Value {
@@ -256,29 +558,26 @@ pub fn parse_for_class_and_property_name(item: &Value) -> (String, String) {
     let item_id = item["_id"]
         .as_i64()
         .expect("An item in the JSON array does not have an _id");
-    let item_name = item["name"].as_str().expect(
-        format!(
+    let item_name = item["name"].as_str().unwrap_or_else(|| {
+        panic!(
             "An item in the JSON array does not have a name: {:?}",
             item_id
         )
-        .as_str(),
-    );
+    });
     let mut name_split = item_name.split("/");
     let name_parts: [&str; 2] = [
-        name_split.next().expect(
-            format!(
+        name_split.next().unwrap_or_else(|| {
+            panic!(
                 "{} does not have a collection and property name (e.g. collection/property)",
                 item_name
             )
-            .as_str(),
-        ),
-        name_split.next().expect(
-            format!(
+        }),
+        name_split.next().unwrap_or_else(|| {
+            panic!(
                 "{} does not have a collection and property name (e.g. collection/property)",
                 item_name
             )
-            .as_str(),
-        ),
+        }),
     ];
 
     let orig_class_name = name_parts[0].to_string();
@@ -286,6 +585,145 @@ pub fn parse_for_class_and_property_name(item: &Value) -> (String, String) {
     (orig_class_name, orig_property_name)
 }
 
+/// The collections a v2 ref predicate is restricted to: its `restrictCollection`, which names one collection,
+/// or a list of them for refs that legitimately target several
+pub fn restrict_collections(item: &Value) -> Vec<String> {
+    match &item["restrictCollection"] {
+        Value::String(collection) => vec![collection.to_string()],
+        Value::Array(collections) => collections
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Some Fluree deployments answer with a 200 status and an error payload (e.g. {"status": 400, "error": "db/invalid-query", "message": "..."}), so a successful status alone doesn't mean the request succeeded
+pub fn response_error_message(body: &str) -> Option<String> {
+    let json = serde_json::from_str::<Value>(body).ok()?;
+    let object = json.as_object()?;
+    let has_error = object.get("error").is_some_and(|error| !error.is_null());
+    let has_error_status = object
+        .get("status")
+        .and_then(|status| status.as_u64())
+        .is_some_and(|status| status >= 400);
+    if !has_error && !has_error_status {
+        return None;
+    }
+    let message = match (object.get("message"), object.get("error")) {
+        (Some(Value::String(message)), _) => message.to_string(),
+        (_, Some(Value::String(error))) => error.to_string(),
+        (_, Some(error)) if !error.is_null() => error.to_string(),
+        _ => body.to_string(),
+    };
+    Some(message)
+}
+
+// adds "f:defaultContext" to a txn that will create the ledger, unless the txn already sets one
+pub fn with_default_context(txn: &str, default_context: &Value) -> String {
+    match serde_json::from_str::<Value>(txn) {
+        Ok(Value::Object(mut txn_map)) => {
+            if !txn_map.contains_key("f:defaultContext") {
+                txn_map.insert("f:defaultContext".to_string(), default_context.to_owned());
+            }
+            serde_json::to_string(&txn_map).unwrap()
+        }
+        _ => txn.to_string(),
+    }
+}
+
+/// The base IRI of the subjects of the ledger a --ref-ledger points to: the given base IRI, or the default
+/// base IRI of a v2 ledger ("network/db") on the same server as the source ledger URL
+pub fn ref_ledger_base(source_url: &str, ledger: &str) -> String {
+    if ledger.contains("://") {
+        return ledger.to_string();
+    }
+    let mut url_parts = source_url.trim_end_matches('/').rsplitn(3, '/');
+    let server = url_parts.nth(2).unwrap_or_default();
+    format!("{}/{}/ids/", server, ledger.trim_matches('/'))
+}
+
+// a transaction read from --input gets the ledger --ledger-name or --ledger-map derive from the one it was written for
+pub fn with_target_ledger(txn: &str, opt: &Opt) -> String {
+    if opt.ledger_name.is_none() && opt.ledger_map.is_empty() {
+        return txn.to_string();
+    }
+    match serde_json::from_str::<Value>(txn) {
+        Ok(Value::Object(mut txn_map)) => {
+            let ledger = txn_map
+                .get("ledger")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let target_ledger = opt.target_ledger_name(ledger);
+            txn_map.insert("ledger".to_string(), Value::String(target_ledger));
+            serde_json::to_string(&txn_map).unwrap()
+        }
+        _ => txn.to_string(),
+    }
+}
+
+pub fn read_json_file(path: &Path, description: &str) -> Value {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            pretty_print(
+                &format!(
+                    "Could not read {} file {}: {}",
+                    description,
+                    path.display(),
+                    e
+                ),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    };
+    match serde_json::from_str::<Value>(&contents) {
+        Ok(json) => json,
+        Err(e) => {
+            pretty_print(
+                &format!(
+                    "Could not parse {} file {}: {}",
+                    description,
+                    path.display(),
+                    e
+                ),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+// wraps a policy document as a txn against the ledger. The document may already be a txn (with "insert"), a JSON-LD document (with "@graph"), or bare policy nodes
+pub fn policy_txn(policy: &Value, ledger_name: &str) -> Value {
+    let mut txn = match policy {
+        Value::Object(policy) if policy.contains_key("insert") => policy.to_owned(),
+        Value::Object(policy) if policy.contains_key("@graph") => {
+            let mut txn = serde_json::Map::new();
+            if let Some(context) = policy.get("@context") {
+                txn.insert("@context".to_string(), context.to_owned());
+            }
+            txn.insert("insert".to_string(), policy["@graph"].to_owned());
+            txn
+        }
+        _ => {
+            let mut txn = serde_json::Map::new();
+            let mut insert = policy.to_owned();
+            if let Some(context) = insert.as_object_mut().and_then(|p| p.remove("@context")) {
+                txn.insert("@context".to_string(), context);
+            }
+            txn.insert("insert".to_string(), insert);
+            txn
+        }
+    };
+    txn.insert("ledger".to_string(), Value::String(ledger_name.to_string()));
+    Value::Object(txn)
+}
+
 pub fn pretty_log(level: Level, pb: &mut ProgressBar, message: &str) {
     let info_color: Style = Style::new().green().bold();
     let warn_color: Style = Style::new().yellow().bold();
@@ -302,7 +740,7 @@ pub fn pretty_log(level: Level, pb: &mut ProgressBar, message: &str) {
                 Level::Debug => debug_color.apply_to("DEBUG"),
                 _ => debug_color.apply_to("DEBUG"),
             },
-            message
+            redact(message)
         ))
     }
 }
@@ -338,3 +776,30 @@ pub fn format_bytes(size: usize) -> String {
     let unit = units[exponent as usize];
     format!("{:.1} {}", pretty_size, unit)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the test vectors of FIPS 180-4
+    #[test]
+    fn hashes_with_sha256() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn content_hash_is_the_start_of_the_sha256() {
+        assert_eq!(content_hash(b"abc"), "ba7816bf8f01cfea");
+    }
+}
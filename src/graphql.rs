@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use reqwest::{Error, Response};
+use serde_json::{json, Map, Value};
+
+use crate::cassette::response;
+use crate::fluree::FlureeInstance;
+
+// the v2 schema, as the `_predicate` and `_collection` types of the GraphQL API; the fields are those the FlureeQL
+// schema multi-query's `*` would return (a GraphQL selection has to name each)
+const SCHEMA_QUERY: &str = "{ graph { \
+    _predicate { _id name doc type unique multi index upsert noHistory component fullText restrictCollection \
+        restrictTag specDoc spec { _id name doc code } } \
+    _collection { _id name doc version specDoc spec { _id name doc code } } \
+} }";
+
+// the fields of each v2 collection's GraphQL type, learned from the schema, and whether each is a ref (or tag),
+// whose values have to be selected as `{ _id }`
+static COLLECTION_FIELDS: Mutex<BTreeMap<String, Vec<(String, bool)>>> =
+    Mutex::new(BTreeMap::new());
+
+// FlureeQL's compact results leave out a predicate without a value, where GraphQL answers `null`
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+        value => value,
+    }
+}
+
+// the `data` of a GraphQL answer to a `graph` query, keyed by type, or the answer as an error response when it has
+// `errors`, or it's an error response to begin with
+async fn graph_data(
+    result: Result<Response, Error>,
+) -> Result<Result<Map<String, Value>, Response>, Error> {
+    let response = result?;
+    if !response.status().is_success() {
+        return Ok(Err(response));
+    }
+    let body = response.text().await.unwrap_or_default();
+    let answer = serde_json::from_str::<Value>(&body).unwrap_or_default();
+    if let Some(errors) = answer["errors"]
+        .as_array()
+        .filter(|errors| !errors.is_empty())
+    {
+        let message = errors
+            .iter()
+            .map(|error| {
+                error["message"]
+                    .as_str()
+                    .map_or(error.to_string(), str::to_string)
+            })
+            .collect::<Vec<String>>()
+            .join("; ");
+        return Ok(Err(response_with_error(400, &message)));
+    }
+    let data = match &answer["data"]["graph"] {
+        Value::Object(graph) => graph.to_owned(),
+        _ => answer["data"].as_object().cloned().unwrap_or_default(),
+    };
+    match answer["data"].is_object() {
+        true => Ok(Ok(data)),
+        false => Ok(Err(response_with_error(
+            502,
+            &format!("The GraphQL answer has no data: {}", body),
+        ))),
+    }
+}
+
+fn response_with_error(status: u16, message: &str) -> Response {
+    response(
+        status,
+        json!({ "status": status, "message": message }).to_string(),
+    )
+}
+
+/// The answer to the v2 schema multi-query from a `--graphql` source, whose `_predicate` and `_collection` types are
+/// queried instead. GraphQL can't be queried at the first block, so the initial predicates are taken to be those of
+/// the system collections (the `_`-prefixed ones)
+pub async fn schema_response(source_instance: &FlureeInstance) -> Result<Response, Error> {
+    let data = match graph_data(source_instance.issue_graphql_query(SCHEMA_QUERY).await).await? {
+        Ok(data) => data,
+        Err(response) => return Ok(response),
+    };
+    let predicates = data
+        .get("_predicate")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .map(without_nulls)
+        .collect::<Vec<Value>>();
+    let collections = data
+        .get("_collection")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .map(without_nulls)
+        .collect::<Vec<Value>>();
+
+    let mut collection_fields: BTreeMap<String, Vec<(String, bool)>> = BTreeMap::new();
+    for predicate in &predicates {
+        let Some((collection, field)) = predicate["name"]
+            .as_str()
+            .and_then(|name| name.split_once('/'))
+        else {
+            continue;
+        };
+        let is_ref = matches!(predicate["type"].as_str(), Some("ref") | Some("tag"));
+        collection_fields
+            .entry(collection.to_string())
+            .or_default()
+            .push((field.to_string(), is_ref));
+    }
+    *COLLECTION_FIELDS.lock().unwrap() = collection_fields;
+
+    let initial_predicates = predicates
+        .iter()
+        .filter(|predicate| {
+            predicate["name"]
+                .as_str()
+                .is_some_and(|name| name.starts_with('_'))
+        })
+        .map(|predicate| predicate["_id"].to_owned())
+        .collect::<Vec<Value>>();
+    let schema = json!({
+        "initial_predicates": initial_predicates,
+        "current_predicates": predicates,
+        "collections": collections,
+    });
+    Ok(response(200, schema.to_string()))
+}
+
+// a v2 data query of a collection's entities as a GraphQL `graph` query: its `limit`, `offset`, and `where` become
+// arguments, and its selection (`*` or `_id`) the collection's fields
+fn graph_query(query: &Value, collection: &str) -> String {
+    let fields = match query["select"] == json!(["_id"]) {
+        true => vec![],
+        false => COLLECTION_FIELDS
+            .lock()
+            .unwrap()
+            .get(collection)
+            .cloned()
+            .unwrap_or_default(),
+    };
+    let selection = std::iter::once("_id".to_string())
+        .chain(fields.iter().map(|(field, is_ref)| match is_ref {
+            true => format!("{} {{ _id }}", field),
+            false => field.to_string(),
+        }))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let mut arguments = vec![];
+    for option in ["limit", "offset"] {
+        if let Some(value) = query["opts"][option].as_u64() {
+            arguments.push(format!("{}: {}", option, value));
+        }
+    }
+    if let Some(where_clause) = query["where"].as_str() {
+        // a JSON string is a valid GraphQL string
+        arguments.push(format!(
+            "where: {}",
+            Value::String(where_clause.to_string())
+        ));
+    }
+    match arguments.is_empty() {
+        true => format!("{{ graph {{ {} {{ {} }} }} }}", collection, selection),
+        false => format!(
+            "{{ graph {{ {}({}) {{ {} }} }} }}",
+            collection,
+            arguments.join(", "),
+            selection
+        ),
+    }
+}
+
+/// The answer to a v2 data query from a `--graphql` source: the entities of its `from` collection, paged by its
+/// `offset` and `limit` and filtered by its `where`, as compact FlureeQL would answer them
+pub async fn query_response(
+    source_instance: &FlureeInstance,
+    query: &str,
+) -> Result<Response, Error> {
+    let query = serde_json::from_str::<Value>(query).unwrap_or_default();
+    let Some(collection) = query["from"].as_str() else {
+        return Ok(response_with_error(
+            400,
+            "A GraphQL source can only be queried for the entities of a collection",
+        ));
+    };
+    let graph_query = graph_query(&query, collection);
+    let data = match graph_data(source_instance.issue_graphql_query(&graph_query).await).await? {
+        Ok(data) => data,
+        Err(response) => return Ok(response),
+    };
+    let entities = match data.get(collection) {
+        Some(Value::Array(entities)) => entities.iter().cloned().map(without_nulls).collect(),
+        Some(Value::Object(entity)) => vec![without_nulls(Value::Object(entity.to_owned()))],
+        _ => vec![],
+    };
+    Ok(response(200, Value::Array(entities).to_string()))
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::functions::remove_namespace;
+
+/// The file, in the output directory (or the working directory), where a run records how to restore
+/// the v2 schema's search capabilities on the target
+pub const INDEX_HINTS_FILE: &str = "index-hints.json";
+
+/// The namespace (prefixed "fm") of the annotations the migration adds to the vocab, e.g. `fm:indexed`
+pub const MIGRATE_NAMESPACE: &str = "https://ns.flur.ee/migrate#";
+
+/// Target-side configuration for v2 predicates marked `fullText` or `index`, which the migrated data alone
+/// doesn't restore
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexHints {
+    pub ledger: String,
+    #[serde(rename = "@context")]
+    pub context: Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub full_text: Vec<FullTextHint>,
+    /// Properties to index on the target, for queries that relied on v2's `index`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub indexed: Vec<IndexedHint>,
+}
+
+/// The full-text properties of a class, and a BM25 virtual graph that searches its instances by them
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullTextHint {
+    pub class: String,
+    pub properties: Vec<String>,
+    pub v2_predicates: Vec<String>,
+    /// A node to insert into the ledger to create the virtual graph
+    pub virtual_graph: Value,
+}
+
+/// The properties of a class whose v2 predicates are marked `index`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedHint {
+    pub class: String,
+    pub properties: Vec<String>,
+    pub v2_predicates: Vec<String>,
+}
+
+impl FullTextHint {
+    /// `properties` are the (property, v2 predicate) pairs of the class's full-text properties
+    pub fn new(class: &str, properties: &[(String, String)], context: &Value) -> Self {
+        let label = remove_namespace(class);
+        let mut chars = label.chars();
+        let name = match chars.next() {
+            Some(first) => format!("{}{}Search", first.to_lowercase(), chars.as_str()),
+            None => "search".to_string(),
+        };
+        let selected = std::iter::once("@id".to_string())
+            .chain(properties.iter().map(|(property, _)| property.to_owned()))
+            .collect::<Vec<String>>();
+        FullTextHint {
+            class: class.to_string(),
+            properties: properties
+                .iter()
+                .map(|(property, _)| property.to_owned())
+                .collect(),
+            v2_predicates: properties
+                .iter()
+                .map(|(_, predicate)| predicate.to_owned())
+                .collect(),
+            virtual_graph: json!({
+                "@id": name,
+                "@type": ["f:VirtualGraph", "fidx:BM25"],
+                "f:virtualGraph": name,
+                "fidx:b": 0.75,
+                "fidx:k1": 1.2,
+                "f:query": {
+                    "@type": "@json",
+                    "@value": {
+                        "@context": context,
+                        "where": [{ "@id": "?x", "@type": class }],
+                        "select": { "?x": selected }
+                    }
+                }
+            }),
+        }
+    }
+}
+
+impl IndexHints {
+    /// Hints for the full-text and indexed properties of each class, given as class => [(property, v2 predicate)].
+    /// `context` is the data @context the class and property names are compacted with
+    pub fn new(
+        ledger: &str,
+        context: &Value,
+        full_text: &BTreeMap<String, Vec<(String, String)>>,
+        indexed: &BTreeMap<String, Vec<(String, String)>>,
+    ) -> Self {
+        let mut hints_context = context.as_object().cloned().unwrap_or_default();
+        hints_context.insert("f".to_string(), json!("https://ns.flur.ee/ledger#"));
+        hints_context.insert("fidx".to_string(), json!("https://ns.flur.ee/index#"));
+        IndexHints {
+            ledger: ledger.to_string(),
+            context: Value::Object(hints_context),
+            full_text: full_text
+                .iter()
+                .map(|(class, properties)| FullTextHint::new(class, properties, context))
+                .collect(),
+            indexed: indexed
+                .iter()
+                .map(|(class, properties)| IndexedHint {
+                    class: class.to_owned(),
+                    properties: properties
+                        .iter()
+                        .map(|(property, _)| property.to_owned())
+                        .collect(),
+                    v2_predicates: properties
+                        .iter()
+                        .map(|(_, predicate)| predicate.to_owned())
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+/// A set of v2 subject `_id`s kept as ranges of consecutive ids. The ids of a collection's subjects are mostly
+/// consecutive, so the ids seen paging through even hundreds of millions of them take a few ranges, one more
+/// for each gap left by a deleted subject
+#[derive(Debug, Clone, Default)]
+pub struct IdRanges {
+    // the first id of each range, and its last
+    ranges: BTreeMap<i64, i64>,
+    len: u64,
+}
+
+impl IdRanges {
+    /// The number of ids in the set
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        self.ranges
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, &last)| id <= last)
+    }
+
+    /// Adds an id, returning whether it's new to the set
+    pub fn insert(&mut self, id: i64) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.insert_range(id, id);
+        true
+    }
+
+    // adds the ids from `first` to `last`, merging the ranges they overlap or adjoin
+    fn insert_range(&mut self, first: i64, last: i64) {
+        let (mut first, mut last) = (first, last);
+        let touching = self
+            .ranges
+            .range(..=last.saturating_add(1))
+            .rev()
+            .take_while(|(_, &range_last)| range_last >= first.saturating_sub(1))
+            .map(|(&range_first, &range_last)| (range_first, range_last))
+            .collect::<Vec<(i64, i64)>>();
+        for (range_first, range_last) in touching {
+            self.ranges.remove(&range_first);
+            self.len -= (range_last - range_first) as u64 + 1;
+            first = first.min(range_first);
+            last = last.max(range_last);
+        }
+        self.ranges.insert(first, last);
+        self.len += (last - first) as u64 + 1;
+    }
+
+    /// The ids in both sets
+    pub fn intersection(&self, other: &IdRanges) -> IdRanges {
+        let mut shared = IdRanges::default();
+        for (&first, &last) in &self.ranges {
+            let start = other
+                .ranges
+                .range(..=first)
+                .next_back()
+                .map_or(first, |(&other_first, _)| other_first);
+            for (&other_first, &other_last) in other.ranges.range(start..=last) {
+                let (shared_first, shared_last) = (first.max(other_first), last.min(other_last));
+                if shared_first <= shared_last {
+                    shared.insert_range(shared_first, shared_last);
+                }
+            }
+        }
+        shared
+    }
+
+    /// Adds every id of another set
+    pub fn extend(&mut self, other: &IdRanges) {
+        for (&first, &last) in &other.ranges {
+            self.insert_range(first, last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[i64]) -> IdRanges {
+        let mut ranges = IdRanges::default();
+        for &id in ids {
+            ranges.insert(id);
+        }
+        ranges
+    }
+
+    #[test]
+    fn merges_consecutive_ids_into_a_range() {
+        let ranges = ids(&[3, 1, 2, 5]);
+        assert_eq!(ranges.ranges, BTreeMap::from([(1, 3), (5, 5)]));
+        assert_eq!(ranges.len(), 4);
+    }
+
+    #[test]
+    fn joins_the_ranges_either_side_of_a_gap() {
+        let mut ranges = ids(&[1, 2, 4, 5]);
+        assert!(ranges.insert(3));
+        assert_eq!(ranges.ranges, BTreeMap::from([(1, 5)]));
+        assert_eq!(ranges.len(), 5);
+    }
+
+    #[test]
+    fn inserts_an_id_once() {
+        let mut ranges = ids(&[1, 2, 3]);
+        assert!(!ranges.insert(2));
+        assert_eq!(ranges.len(), 3);
+    }
+
+    #[test]
+    fn contains_the_ids_of_its_ranges() {
+        let ranges = ids(&[1, 2, 3, 7]);
+        assert!(ranges.contains(1) && ranges.contains(3) && ranges.contains(7));
+        assert!(!ranges.contains(0) && !ranges.contains(4) && !ranges.contains(8));
+        assert!(!IdRanges::default().contains(0));
+    }
+
+    #[test]
+    fn intersects_and_extends_ranges() {
+        let (a, b) = (ids(&[1, 2, 3, 4, 8, 9]), ids(&[3, 4, 5, 9, 10]));
+        let shared = a.intersection(&b);
+        assert_eq!(shared.ranges, BTreeMap::from([(3, 4), (9, 9)]));
+        assert_eq!(shared.len(), 3);
+
+        let mut all = a.clone();
+        all.extend(&b);
+        assert_eq!(all.ranges, BTreeMap::from([(1, 5), (8, 10)]));
+        assert_eq!(all.len(), 8);
+    }
+}
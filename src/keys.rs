@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde_json::json;
+
+use crate::console::{pretty_print, ERROR_COLOR};
+
+/// An Ed25519 private key, read from a `--signing-key-file`, that transactions to the target are signed with
+#[derive(Clone)]
+pub struct SigningKey {
+    seed: [u8; 32],
+}
+
+// the seed is a secret, so it's never printed
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("SigningKey")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    pretty_print(message, ERROR_COLOR, true);
+    std::process::exit(1);
+}
+
+// base64url without padding, as JWS uses
+fn base64url(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The value of an `Authorization` header for HTTP Basic auth with "user:password" credentials
+pub fn basic_auth_header(credentials: &str) -> String {
+    format!("Basic {}", STANDARD.encode(credentials))
+}
+
+/// Reads a secret (an API key, or a hex private key) from a file, trimmed of surrounding whitespace. On Unix, a file
+/// that other users can read or write is refused, as `ssh` refuses such a private key
+pub fn read_secret_file(path: &Path, description: &str) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let mode = metadata.permissions().mode() & 0o777;
+            if mode & 0o077 != 0 {
+                exit_with_error(&format!(
+                    "The {} file {} can be accessed by other users (mode {:o}); restrict it with `chmod 600 {}`",
+                    description,
+                    path.display(),
+                    mode,
+                    path.display()
+                ));
+            }
+        }
+    }
+    match std::fs::read_to_string(path) {
+        Ok(contents) if !contents.trim().is_empty() => contents.trim().to_string(),
+        Ok(_) => exit_with_error(&format!(
+            "The {} file {} is empty",
+            description,
+            path.display()
+        )),
+        Err(e) => exit_with_error(&format!(
+            "Could not read {} file {}: {}",
+            description,
+            path.display(),
+            e
+        )),
+    }
+}
+
+impl SigningKey {
+    /// Reads a signing key file: the 32-byte Ed25519 private key (its seed) as 64 hex digits
+    pub fn read(path: &Path) -> Self {
+        let hex = read_secret_file(path, "signing key");
+        let hex = hex.strip_prefix("0x").unwrap_or(&hex);
+        let seed = (0..hex.len())
+            .step_by(2)
+            .map(|index| {
+                hex.get(index..index + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .unwrap_or_else(|| {
+                exit_with_error(&format!(
+                    "The signing key file {} must hold a 32-byte Ed25519 private key as 64 hex digits",
+                    path.display()
+                ))
+            });
+        SigningKey { seed }
+    }
+
+    /// The private key as hex, to be redacted wherever it could be written
+    pub fn secret(&self) -> String {
+        self.seed
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn key_pair(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.seed).unwrap()
+    }
+
+    /// The public key, base64url-encoded as in a JWK
+    pub fn public_key(&self) -> String {
+        base64url(self.key_pair().public_key().as_ref())
+    }
+
+    /// Signs a transaction as a compact JWS, whose header holds the public key (as a JWK) it's verified with
+    pub fn sign(&self, payload: &str) -> String {
+        let header = json!({
+            "alg": "EdDSA",
+            "jwk": {
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": self.public_key(),
+            }
+        });
+        let signing_input = format!(
+            "{}.{}",
+            base64url(header.to_string().as_bytes()),
+            base64url(payload.as_bytes())
+        );
+        let signature = self.key_pair().sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, base64url(signature.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    use super::*;
+
+    // the Ed25519 private key of RFC 8037, appendix A.1
+    fn signing_key() -> SigningKey {
+        let seed = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+        let seed = (0..seed.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&seed[index..index + 2], 16).unwrap())
+            .collect::<Vec<u8>>();
+        SigningKey {
+            seed: seed.try_into().unwrap(),
+        }
+    }
+
+    // the example of RFC 7617
+    #[test]
+    fn encodes_basic_auth_credentials() {
+        assert_eq!(
+            basic_auth_header("Aladdin:open sesame"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    // the public key of RFC 8037, appendix A.2
+    #[test]
+    fn encodes_the_public_key_as_in_a_jwk() {
+        assert_eq!(
+            signing_key().public_key(),
+            "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"
+        );
+    }
+
+    #[test]
+    fn signs_a_verifiable_compact_jws() {
+        let key = signing_key();
+        let jws = key.sign(r#"{"ledger":"demo/test"}"#);
+        let parts = jws.split('.').collect::<Vec<&str>>();
+        assert_eq!(parts.len(), 3);
+
+        let header: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[0]).unwrap()).unwrap();
+        assert_eq!(header["alg"], "EdDSA");
+        assert_eq!(header["jwk"]["x"], key.public_key().as_str());
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(parts[1]).unwrap(),
+            br#"{"ledger":"demo/test"}"#
+        );
+
+        let public_key = URL_SAFE_NO_PAD.decode(key.public_key()).unwrap();
+        let signature = URL_SAFE_NO_PAD.decode(parts[2]).unwrap();
+        let signing_input = format!("{}.{}", parts[0], parts[1]);
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(signing_input.as_bytes(), &signature)
+            .expect("the signature should verify with the public key");
+    }
+}
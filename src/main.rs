@@ -2,20 +2,105 @@ use clap::Parser;
 use cli::local_directory::LocalDirectory;
 use cli::source::Migrate;
 
+mod align;
+mod audit;
+mod batch;
+mod batching;
+mod cache;
+mod canonical;
+mod cassette;
+mod changes;
 mod cli;
 mod console;
+mod diagram;
+mod diff;
+mod docs;
+mod doctor;
+mod export;
+mod extract;
 mod fluree;
+mod frame;
 mod functions;
+mod graphql;
+mod hints;
+mod id_ranges;
+mod keys;
+mod mapping;
+mod mock;
+mod parquet;
+mod plan;
+mod probe;
+mod profile;
+mod redact;
+mod report;
+mod shapes;
+mod shard;
+mod spec;
+mod state;
+mod store;
+mod trace;
+mod transaction;
+mod uploads;
+mod verify;
 
-use cli::opt::Opt;
+use cli::opt::{Command, Opt};
 use fluree::FlureeInstance;
 
 #[tokio::main]
 async fn main() -> Result<(), reqwest::Error> {
-    env_logger::init();
-    let opt = Opt::parse();
+    Opt::check_short_vocab(&std::env::args().collect::<Vec<String>>());
+    let mut opt = Opt::parse();
+    opt.init_logger();
+    opt.check_output_format();
+    opt.check_changed_only();
+    opt.use_run_directory();
+    opt.use_shard();
+    opt.use_mock_source();
+    opt.use_cached_source();
+    opt.use_extracted_source();
+    opt.use_key_files();
+    redact::start(&opt);
+    cassette::start(&opt);
+    trace::start(&opt);
+    store::start(&opt);
+    state::install_pause_handlers();
+    let run_lock = opt.takes_run_lock().then(|| state::acquire_run_lock(&opt));
+    // only a run holding the lock on `.tmp` may remove it and write the run state when interrupted
+    if run_lock.is_some() {
+        state::install_interrupt_handler(&opt);
+    }
 
-    if opt.input.is_some() {
+    if let Some(Command::Verify { manifest }) = &opt.command {
+        verify::verify(&opt, manifest).await;
+    } else if let Some(Command::Shapes { path }) = &opt.command {
+        shapes::generate_shapes(&opt, path).await;
+    } else if let Some(Command::Audit { shapes }) = &opt.command {
+        audit::audit(&opt, shapes).await;
+    } else if let Some(Command::Diff {
+        target_a,
+        target_b,
+        ledger_a,
+        ledger_b,
+    }) = &opt.command
+    {
+        diff::diff(&opt, target_a, target_b, ledger_a, ledger_b).await;
+    } else if let Some(Command::Doctor) = &opt.command {
+        doctor::doctor(&opt).await;
+    } else if let Some(Command::Export { directory }) = &opt.command {
+        export::export(&opt, directory).await;
+    } else if let Some(Command::Extract) = &opt.command {
+        extract::extract(&opt).await;
+    } else if let Some(Command::Load { directory }) = &opt.command {
+        let mut load_opt = opt.clone();
+        load_opt.input = Some(directory.to_owned());
+        load_opt.command = None;
+        let mut source_directory = LocalDirectory::new(&load_opt);
+        source_directory.migrate().await;
+    } else if let Some(Command::MergeReports { reports }) = &opt.command {
+        report::merge_reports(&opt, reports);
+    } else if let Some(Command::Batch { manifest }) = &opt.command {
+        batch::migrate_batch(&opt, manifest).await;
+    } else if opt.input.is_some() {
         let mut source_directory = LocalDirectory::new(&opt);
         source_directory.migrate().await;
     } else {
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::{glob_match, read_json_file};
+
+/// User-provided rules (from `--mapping`) that customize how v2 entities are migrated
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MappingConfig {
+    pub same_as: Vec<SameAsRule>,
+    pub merge_entities: Vec<MergeRule>,
+    pub reverse: Vec<ReverseRule>,
+    /// v2 collections mapped to the only predicates of theirs that are migrated
+    pub allow_properties: HashMap<String, Vec<String>>,
+    /// v2 collections mapped to the IRI prefixes of their own, in place of the --base and --vocab every
+    /// other collection shares
+    pub namespaces: HashMap<String, ClassNamespace>,
+}
+
+/// The IRI prefix of a v2 collection's subjects (its `base`, e.g. `http://example.org/people/`) and of its class
+/// and properties (its `vocab`). Each is declared as a prefix of the @context, `{collection}-ids` and
+/// `{collection}-terms`, which the subjects' ids and the terms are written with
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ClassNamespace {
+    #[serde(alias = "@base")]
+    pub base: Option<String>,
+    #[serde(alias = "@vocab")]
+    pub vocab: Option<String>,
+}
+
+/// Links entities of a v2 collection to canonical external IRIs by the value of one of their predicates,
+/// either through an explicit `values` lookup or an `iriTemplate` such as `http://example.org/people/{value}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SameAsRule {
+    pub class: String,
+    pub property: String,
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+    #[serde(default)]
+    pub iri_template: Option<String>,
+}
+
+/// Entities of the listed v2 collections that share a value for the given predicates
+/// represent the same real-world thing, and are merged into a single subject
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeRule {
+    pub keys: Vec<MergeKey>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeKey {
+    pub class: String,
+    pub property: String,
+}
+
+/// Models a v2 ref predicate in the opposite direction (e.g. child `parent` becomes parent `hasChild`),
+/// through an `@reverse` term definition in the data @context
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReverseRule {
+    pub property: String,
+    pub inverse: String,
+}
+
+impl ReverseRule {
+    /// The v2 predicate name, without its collection
+    pub fn predicate(&self) -> &str {
+        self.property.rsplit('/').next().unwrap_or(&self.property)
+    }
+}
+
+/// Loads a JSON object of v2 names to v3 terms, such as the `--rename-properties` file
+pub fn load_renames(path: &Path, description: &str) -> HashMap<String, String> {
+    let json = read_json_file(path, description);
+    match serde_json::from_value(json) {
+        Ok(renames) => renames,
+        Err(e) => {
+            pretty_print(
+                &format!(
+                    "Invalid {} file {} (expected an object of names to terms): {}",
+                    description,
+                    path.display(),
+                    e
+                ),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+impl MappingConfig {
+    pub fn load(path: &Path) -> Self {
+        let json = read_json_file(path, "mapping");
+        let mapping: Self = match serde_json::from_value(json) {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                pretty_print(
+                    &format!("Invalid mapping file {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        };
+        // a namespace is only usable as a prefix of compact IRIs when it ends with a delimiter
+        let namespaces = mapping
+            .base_prefixes()
+            .into_iter()
+            .chain(mapping.vocab_prefixes());
+        for (prefix, namespace) in namespaces {
+            if !namespace.contains(':') || !namespace.ends_with(['/', '#']) {
+                pretty_print(
+                    &format!(
+                        "Invalid mapping file {}: the namespace of {}, \"{}\", must be an absolute IRI ending in \"/\" or \"#\"",
+                        path.display(),
+                        prefix,
+                        namespace
+                    ),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+        mapping
+    }
+
+    /// The @context prefix the IRIs of a v2 collection's subjects are written with, if it has a `base` of its own
+    pub fn base_prefix(&self, orig_class_name: &str) -> Option<String> {
+        self.namespaces.get(orig_class_name)?.base.as_ref()?;
+        Some(format!("{}-ids", orig_class_name))
+    }
+
+    /// The @context prefix a v2 collection's class and properties are named with, if it has a `vocab` of its own
+    pub fn vocab_prefix(&self, orig_class_name: &str) -> Option<String> {
+        self.namespaces.get(orig_class_name)?.vocab.as_ref()?;
+        Some(format!("{}-terms", orig_class_name))
+    }
+
+    /// The prefixes of the collections' own bases, and the namespaces they stand for
+    pub fn base_prefixes(&self) -> Vec<(String, String)> {
+        self.namespaces
+            .iter()
+            .filter_map(|(orig_class_name, namespace)| {
+                Some((self.base_prefix(orig_class_name)?, namespace.base.clone()?))
+            })
+            .collect()
+    }
+
+    /// The prefixes of the collections' own vocabs, and the namespaces they stand for
+    pub fn vocab_prefixes(&self) -> Vec<(String, String)> {
+        self.namespaces
+            .iter()
+            .filter_map(|(orig_class_name, namespace)| {
+                Some((
+                    self.vocab_prefix(orig_class_name)?,
+                    namespace.vocab.clone()?,
+                ))
+            })
+            .collect()
+    }
+
+    pub fn same_as_iris(&self, orig_class_name: &str, entity: &Value) -> Vec<String> {
+        self.same_as
+            .iter()
+            .filter(|rule| rule.class == orig_class_name)
+            .flat_map(|rule| rule.iris_for(entity))
+            .collect()
+    }
+
+    /// Whether the predicate is migrated under the `allowProperties` rules.
+    /// Collections without an allow-list migrate all of their predicates
+    pub fn is_allowed_property(&self, orig_class_name: &str, orig_property_name: &str) -> bool {
+        match self.allow_properties.get(orig_class_name) {
+            Some(allowed) => allowed.iter().any(|property| {
+                property.rsplit('/').next().unwrap_or(property) == orig_property_name
+            }),
+            None => true,
+        }
+    }
+
+    /// Returns the merge keys of an entity as (group key, key priority) pairs,
+    /// where the priority is the position of the matching key within its rule
+    pub fn merge_keys(&self, orig_class_name: &str, entity: &Value) -> Vec<(String, usize)> {
+        let mut merge_keys = vec![];
+        for (rule_index, rule) in self.merge_entities.iter().enumerate() {
+            for (key_index, key) in rule.keys.iter().enumerate() {
+                if key.class != orig_class_name {
+                    continue;
+                }
+                for value in property_values(entity, &key.property) {
+                    merge_keys.push((format!("{}|{}", rule_index, value), key_index));
+                }
+            }
+        }
+        merge_keys
+    }
+}
+
+// the property may be given as "collection/predicate" or just "predicate"
+fn property_values(entity: &Value, property: &str) -> Vec<String> {
+    let property = property.rsplit('/').next().unwrap_or(property);
+    let values = match &entity[property] {
+        Value::Array(values) => values.iter().collect::<Vec<&Value>>(),
+        Value::Null => vec![],
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .filter_map(|value| match value {
+            Value::String(value) => Some(value.nfc().collect()),
+            Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+impl SameAsRule {
+    fn iris_for(&self, entity: &Value) -> Vec<String> {
+        property_values(entity, &self.property)
+            .into_iter()
+            .filter_map(|value| match self.values.get(&value) {
+                Some(iri) => Some(iri.to_string()),
+                None => self
+                    .iri_template
+                    .as_ref()
+                    .map(|template| template.replace("{value}", &value)),
+            })
+            .map(|iri| iri.nfc().collect())
+            .collect()
+    }
+}
+
+/// Rules (from `--ledger-map`) that derive the target ledger from the v2 ledger, given as "from=to"
+/// (e.g. "old-network/old-db=new/alias") or as the path to a JSON file of them (e.g. {"old-network/*": "new/*"}).
+/// A `*` in `from` matches any text, which replaces the `*` in `to`
+#[derive(Debug, Clone, Default)]
+pub struct LedgerMap {
+    rules: Vec<(String, String)>,
+}
+
+impl FromStr for LedgerMap {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some((from, to)) = value.split_once('=') {
+            return Ok(LedgerMap {
+                rules: vec![(from.trim().to_string(), to.trim().to_string())],
+            });
+        }
+        let contents = std::fs::read_to_string(value).map_err(|e| {
+            format!(
+                "Expected a \"from=to\" rule or a file of rules, but could not read {}: {}",
+                value, e
+            )
+        })?;
+        let rules = serde_json::from_str::<serde_json::Map<String, Value>>(&contents)
+            .map_err(|e| format!("Invalid ledger map file {}: {}", value, e))?;
+        rules
+            .into_iter()
+            .map(|(from, to)| match to {
+                Value::String(to) => Ok((from, to)),
+                _ => Err(format!(
+                    "Invalid ledger map file {}: the ledger for {} must be a string",
+                    value, from
+                )),
+            })
+            .collect::<Result<Vec<(String, String)>, String>>()
+            .map(|rules| LedgerMap { rules })
+    }
+}
+
+impl LedgerMap {
+    /// The target ledger of the first rule that matches the v2 ledger (e.g. "network/db")
+    pub fn target_ledger(&self, ledger: &str) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|(from, _)| glob_match(from, ledger))
+            .map(|(from, to)| match from.split_once('*') {
+                Some((prefix, suffix)) if !suffix.contains('*') => {
+                    let matched = &ledger[prefix.len()..ledger.len() - suffix.len()];
+                    to.replace('*', matched)
+                }
+                _ => to.to_string(),
+            })
+    }
+}
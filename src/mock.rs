@@ -0,0 +1,62 @@
+use std::sync::LazyLock;
+
+use reqwest::Response;
+use serde_json::{json, Value};
+
+use crate::cassette::response;
+
+/// The v2 ledger `--mock` pretends to migrate; its URL only shapes the default IRI prefixes and ledger name
+pub const MOCK_SOURCE: &str = "http://localhost:8090/fdb/demo/mock";
+
+// a small v2 schema (as answered to the schema multi-query) and the entities of its collections
+static FIXTURES: LazyLock<Value> =
+    LazyLock::new(|| serde_json::from_str(include_str!("../fixtures/mock.json")).unwrap());
+
+/// The fixture response to a v2 schema multi-query
+pub fn schema_response() -> Response {
+    let schema = json!({
+        "initial_predicates": FIXTURES["initial_predicates"],
+        "current_predicates": FIXTURES["current_predicates"],
+        "collections": FIXTURES["collections"],
+    });
+    response(200, schema.to_string())
+}
+
+/// The fixture response to a v2 data query: the entities of its `from` collection (or with its `from` ids),
+/// paged by its `offset` and `limit`. Its `where` and `block` are ignored
+pub fn query_response(query: &str) -> Response {
+    let query = serde_json::from_str::<Value>(query).unwrap_or_default();
+    let collections = FIXTURES["data"].as_object().cloned().unwrap_or_default();
+    let entities = match &query["from"] {
+        Value::String(collection) => collections
+            .get(collection)
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default(),
+        Value::Array(ids) => collections
+            .values()
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter(|entity| ids.contains(&entity["_id"]))
+            .cloned()
+            .collect(),
+        _ => vec![],
+    };
+    page_response(&query, entities.into_iter())
+}
+
+/// A v2 data query's page of `entities`, by its `offset` and `limit`, holding only their `_id`s for a `select ["_id"]`
+pub fn page_response(query: &Value, entities: impl Iterator<Item = Value>) -> Response {
+    let offset = query["opts"]["offset"].as_u64().unwrap_or(0) as usize;
+    let limit = query["opts"]["limit"].as_u64().unwrap_or(u64::MAX) as usize;
+    let is_ids_only = query["select"] == json!(["_id"]);
+    let entities = entities
+        .skip(offset)
+        .take(limit)
+        .map(|entity| match is_ids_only {
+            true => json!({ "_id": entity["_id"] }),
+            false => entity,
+        })
+        .collect::<Vec<Value>>();
+    response(200, Value::Array(entities).to_string())
+}
@@ -0,0 +1,949 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use dialoguer::console::Style;
+use serde_json::{Map, Value};
+
+use crate::cli::file_template::file_name_safe;
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+
+/// The directory, in the output directory, where the rows of each class are kept until its Parquet file is written
+pub const PARQUET_ROWS_DIRECTORY: &str = ".parquet-rows";
+
+const MAGIC: &[u8] = b"PAR1";
+
+// the rows of a row group, so a class's Parquet file is written a row group at a time rather than from all its rows
+// held at once
+const ROW_GROUP_ROWS: usize = 10_000;
+
+// the class of the nodes without an @type
+const UNTYPED_CLASS: &str = "untyped";
+
+// a row group's number of rows, and the offset, number of levels, and size of each of its column chunks
+type RowGroup = (usize, Vec<(i64, usize, usize)>);
+
+/// Adds the nodes a data document inserts to the rows of their classes, one JSON object per line
+pub fn spool_rows(output: &Path, document: &Map<String, Value>) {
+    let directory = output.join(PARQUET_ROWS_DIRECTORY);
+    let Some(Value::Array(nodes)) = document.get("insert") else {
+        return;
+    };
+    let mut lines: BTreeMap<String, String> = BTreeMap::new();
+    for node in nodes.iter().filter(|node| node.is_object()) {
+        let class = match &node["@type"] {
+            Value::String(class) => class.as_str(),
+            Value::Array(types) => types
+                .first()
+                .and_then(Value::as_str)
+                .unwrap_or(UNTYPED_CLASS),
+            _ => UNTYPED_CLASS,
+        };
+        let rows = lines.entry(class.to_string()).or_default();
+        rows.push_str(&node.to_string());
+        rows.push('\n');
+    }
+    let written = std::fs::create_dir_all(&directory).and_then(|_| {
+        lines.iter().try_for_each(|(class, rows)| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(directory.join(format!("{}.jsonl", file_name_safe(class))))
+                .and_then(|mut file| file.write_all(rows.as_bytes()))
+        })
+    });
+    if let Err(e) = written {
+        pretty_print(
+            &format!("Could not write the rows of the Parquet files: {}", e),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Writes a `<class>.parquet` file to the --output directory for each class whose rows were spooled, with a column
+/// for the `@id` and one for each property of the class (references holding the `@id` they point to),
+/// and removes the spooled rows
+pub fn write_class_files(opt: &Opt) {
+    let Some(output) = &opt.output else {
+        return;
+    };
+    let directory = output.join(PARQUET_ROWS_DIRECTORY);
+    let Ok(entries) = std::fs::read_dir(&directory) else {
+        return;
+    };
+    let mut spooled = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "jsonl")
+        })
+        .collect::<Vec<_>>();
+    spooled.sort();
+    let (mut classes, mut total_rows) = (0, 0);
+    for path in spooled {
+        let file_name = path.with_extension("parquet");
+        let file_name = output.join(file_name.file_name().unwrap());
+        let written = File::create(&file_name).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            let rows = write_parquet(&mut writer, || spooled_rows(&path), ROW_GROUP_ROWS)?;
+            writer.flush()?;
+            Ok(rows)
+        });
+        match written {
+            Ok(rows) => total_rows += rows,
+            Err(e) => {
+                pretty_print(
+                    &format!("Could not write {}: {}", file_name.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+        classes += 1;
+    }
+    let _ = std::fs::remove_dir_all(&directory);
+    println!(
+        "{:>12} {} classes ({} rows) to Parquet files in {}/",
+        Style::new().green().bold().apply_to("Wrote"),
+        classes,
+        total_rows,
+        output.display()
+    );
+}
+
+// the rows spooled for a class, read one line at a time
+fn spooled_rows(path: &Path) -> io::Result<impl Iterator<Item = Map<String, Value>>> {
+    let rows = BufReader::new(File::open(path)?)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str::<Value>(&line) {
+            Ok(Value::Object(row)) => Some(row),
+            _ => None,
+        });
+    Ok(rows)
+}
+
+// the physical type a column's values are stored as
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Boolean,
+    Int64,
+    Double,
+    Utf8,
+}
+
+impl Kind {
+    fn of(value: &Value) -> Kind {
+        match value {
+            Value::Bool(_) => Kind::Boolean,
+            Value::Number(number) if number.is_i64() => Kind::Int64,
+            Value::Number(_) => Kind::Double,
+            _ => Kind::Utf8,
+        }
+    }
+
+    // the type that holds the values of both
+    fn join(self, other: Kind) -> Kind {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Kind::Int64, Kind::Double) | (Kind::Double, Kind::Int64) => Kind::Double,
+            _ => Kind::Utf8,
+        }
+    }
+
+    // the Parquet Type enum
+    fn physical_type(self) -> i32 {
+        match self {
+            Kind::Boolean => 0,
+            Kind::Int64 => 2,
+            Kind::Double => 5,
+            Kind::Utf8 => 6,
+        }
+    }
+}
+
+// a property of a class: the type that holds the values of all its rows, and whether any row has several, making it
+// a list column
+struct Column {
+    name: String,
+    kind: Kind,
+    is_list: bool,
+}
+
+// the value stored for a JSON-LD value: the @id of a reference, the @value of a value object,
+// and other objects as JSON
+fn cell(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => match (object.get("@id"), object.get("@value")) {
+            (Some(id), _) => id.to_owned(),
+            (None, Some(value)) => value.to_owned(),
+            _ => Value::String(value.to_string()),
+        },
+        Value::Array(_) => Value::String(value.to_string()),
+        _ => value.to_owned(),
+    }
+}
+
+// the values of a row's property (None when the row doesn't have it), and whether they're a list
+fn row_cell(row: &Map<String, Value>, name: &str) -> Option<(Vec<Value>, bool)> {
+    let value = row.get(name)?;
+    let values = match value {
+        Value::Array(values) => values,
+        Value::Object(object) if object.contains_key("@list") => object["@list"].as_array()?,
+        _ => return Some((vec![cell(value)], false)),
+    };
+    Some((values.iter().map(cell).collect(), true))
+}
+
+// the columns of the rows, from every row's properties: the @id, then the properties by name
+fn columns(rows: impl Iterator<Item = Map<String, Value>>) -> (Vec<Column>, usize) {
+    let mut columns: BTreeMap<String, (Option<Kind>, bool)> = BTreeMap::new();
+    let mut id_kind = None;
+    let mut row_count = 0;
+    for row in rows {
+        row_count += 1;
+        let names = row.keys().filter(|name| !name.starts_with('@'));
+        for name in std::iter::once("@id").chain(names.map(String::as_str)) {
+            let Some((values, is_list)) = row_cell(&row, name) else {
+                continue;
+            };
+            let (kind, column_is_list) = match name {
+                "@id" => (&mut id_kind, &mut false),
+                name => {
+                    let column = columns.entry(name.to_string()).or_default();
+                    (&mut column.0, &mut column.1)
+                }
+            };
+            *column_is_list |= is_list;
+            for value in &values {
+                let value_kind = Kind::of(value);
+                *kind = Some(kind.map_or(value_kind, |kind: Kind| kind.join(value_kind)));
+            }
+        }
+    }
+    let id_column = Column {
+        name: "@id".to_string(),
+        kind: id_kind.unwrap_or(Kind::Utf8),
+        is_list: false,
+    };
+    let columns = std::iter::once(id_column)
+        .chain(columns.into_iter().map(|(name, (kind, is_list))| Column {
+            name,
+            kind: kind.unwrap_or(Kind::Utf8),
+            is_list,
+        }))
+        .collect();
+    (columns, row_count)
+}
+
+/// Writes a Parquet file of the rows, with a row group per `row_group_rows` rows holding a single uncompressed,
+/// PLAIN-encoded data page per column. Multi-valued properties are LIST columns. The rows are read twice: once for
+/// the columns and their types, and once a row group at a time to write them. Returns the number of rows
+fn write_parquet<W, R>(
+    writer: &mut W,
+    rows: impl Fn() -> io::Result<R>,
+    row_group_rows: usize,
+) -> io::Result<usize>
+where
+    W: Write,
+    R: Iterator<Item = Map<String, Value>>,
+{
+    let (columns, row_count) = columns(rows()?);
+    writer.write_all(MAGIC)?;
+    let mut offset = MAGIC.len();
+    let mut row_groups = vec![];
+    let mut rows = rows()?;
+    loop {
+        let row_group = rows.by_ref().take(row_group_rows).collect::<Vec<_>>();
+        // a file without rows still has a row group, with no rows
+        if row_group.is_empty() && !row_groups.is_empty() {
+            break;
+        }
+        let mut chunks = vec![];
+        for column in &columns {
+            let cells = row_group
+                .iter()
+                .map(|row| row_cell(row, &column.name).map(|(values, _)| values))
+                .collect::<Vec<_>>();
+            let (page, levels) = data_page(column, &cells);
+            let mut header = Thrift::default();
+            header.begin();
+            header.i32(1, 0); // DATA_PAGE
+            header.i32(2, page.len() as i32);
+            header.i32(3, page.len() as i32);
+            header.struct_field(5);
+            header.i32(1, levels as i32);
+            header.i32(2, 0); // PLAIN
+            header.i32(3, 3); // RLE
+            header.i32(4, 3);
+            header.end();
+            header.end();
+            writer.write_all(&header.bytes)?;
+            writer.write_all(&page)?;
+            let size = header.bytes.len() + page.len();
+            chunks.push((offset as i64, levels, size));
+            offset += size;
+        }
+        let is_last = row_group.len() < row_group_rows;
+        row_groups.push((row_group.len(), chunks));
+        if is_last {
+            break;
+        }
+    }
+    let footer = footer(&columns, row_count, &row_groups);
+    writer.write_all(&footer)?;
+    writer.write_all(&(footer.len() as u32).to_le_bytes())?;
+    writer.write_all(MAGIC)?;
+    Ok(row_count)
+}
+
+// the file metadata: the schema, and the offset, levels, and size of each column chunk of each row group
+fn footer(columns: &[Column], row_count: usize, row_groups: &[RowGroup]) -> Vec<u8> {
+    let mut footer = Thrift::default();
+    footer.begin();
+    footer.i32(1, 1);
+    let schema_elements = 1 + columns
+        .iter()
+        .map(|column| if column.is_list { 3 } else { 1 })
+        .sum::<usize>();
+    footer.list(2, STRUCT, schema_elements);
+    footer.begin();
+    footer.string(4, "schema");
+    footer.i32(5, columns.len() as i32);
+    footer.end();
+    for column in columns {
+        let converted_type = (column.kind == Kind::Utf8).then_some(0); // UTF8
+        if column.is_list {
+            footer.begin();
+            footer.i32(3, 1); // OPTIONAL
+            footer.string(4, &column.name);
+            footer.i32(5, 1);
+            footer.i32(6, 3); // LIST
+            footer.end();
+            footer.begin();
+            footer.i32(3, 2); // REPEATED
+            footer.string(4, "list");
+            footer.i32(5, 1);
+            footer.end();
+            footer.leaf(column.kind, "element", converted_type);
+        } else {
+            footer.leaf(column.kind, &column.name, converted_type);
+        }
+    }
+    footer.i64(3, row_count as i64);
+    footer.list(4, STRUCT, row_groups.len());
+    for (rows, chunks) in row_groups {
+        footer.begin();
+        footer.list(1, STRUCT, columns.len());
+        for (column, (offset, levels, size)) in columns.iter().zip(chunks) {
+            footer.begin();
+            footer.i64(2, *offset);
+            footer.struct_field(3);
+            footer.i32(1, column.kind.physical_type());
+            footer.list(2, I32, 2);
+            footer.list_i32(&[0, 3]); // PLAIN, RLE
+            let path = match column.is_list {
+                true => vec![column.name.as_str(), "list", "element"],
+                false => vec![column.name.as_str()],
+            };
+            footer.list(3, BINARY, path.len());
+            for name in path {
+                footer.binary(name.as_bytes());
+            }
+            footer.i32(4, 0); // UNCOMPRESSED
+            footer.i64(5, *levels as i64);
+            footer.i64(6, *size as i64);
+            footer.i64(7, *size as i64);
+            footer.i64(9, *offset);
+            footer.end();
+            footer.end();
+        }
+        footer.i64(2, chunks.iter().map(|(_, _, size)| *size as i64).sum());
+        footer.i64(3, *rows as i64);
+        footer.end();
+    }
+    footer.string(6, "fluree-migrate");
+    footer.end();
+    footer.bytes
+}
+
+// the data page of a column's cells in a row group (its repetition levels for a list, definition levels, and
+// values), and the number of levels in it
+fn data_page(column: &Column, cells: &[Option<Vec<Value>>]) -> (Vec<u8>, usize) {
+    let (mut repetition_levels, mut definition_levels, mut values) = (vec![], vec![], vec![]);
+    for cell in cells {
+        match (cell, column.is_list) {
+            (None, _) => {
+                repetition_levels.push(0);
+                definition_levels.push(0);
+            }
+            (Some(cell), false) => {
+                repetition_levels.push(0);
+                definition_levels.push(1);
+                values.push(&cell[0]);
+            }
+            // an empty list is defined up to the list, and its elements up to the element
+            (Some(cell), true) if cell.is_empty() => {
+                repetition_levels.push(0);
+                definition_levels.push(1);
+            }
+            (Some(cell), true) => {
+                for (index, value) in cell.iter().enumerate() {
+                    repetition_levels.push(if index == 0 { 0 } else { 1 });
+                    definition_levels.push(3);
+                    values.push(value);
+                }
+            }
+        }
+    }
+    let mut page = vec![];
+    if column.is_list {
+        page.extend(rle(&repetition_levels));
+    }
+    page.extend(rle(&definition_levels));
+    match column.kind {
+        Kind::Boolean => {
+            let mut bits = vec![0u8; values.len().div_ceil(8)];
+            for (index, value) in values.iter().enumerate() {
+                if value.as_bool().unwrap_or_default() {
+                    bits[index / 8] |= 1 << (index % 8);
+                }
+            }
+            page.extend(bits);
+        }
+        Kind::Int64 => {
+            for value in values {
+                page.extend(value.as_i64().unwrap_or_default().to_le_bytes());
+            }
+        }
+        Kind::Double => {
+            for value in values {
+                page.extend(value.as_f64().unwrap_or_default().to_le_bytes());
+            }
+        }
+        Kind::Utf8 => {
+            for value in values {
+                let text = match value {
+                    Value::String(text) => text.to_owned(),
+                    value => value.to_string(),
+                };
+                page.extend((text.len() as u32).to_le_bytes());
+                page.extend(text.as_bytes());
+            }
+        }
+    }
+    (page, definition_levels.len())
+}
+
+// levels in the RLE/bit-packed hybrid encoding, as runs only (every level fits a byte), prefixed by their length
+fn rle(levels: &[u8]) -> Vec<u8> {
+    let mut runs = vec![];
+    let mut index = 0;
+    while index < levels.len() {
+        let level = levels[index];
+        let length = levels[index..]
+            .iter()
+            .take_while(|&&other| other == level)
+            .count();
+        varint(&mut runs, (length as u64) << 1);
+        runs.push(level);
+        index += length;
+    }
+    let mut encoded = (runs.len() as u32).to_le_bytes().to_vec();
+    encoded.extend(runs);
+    encoded
+}
+
+fn varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// the Thrift compact protocol types used by Parquet's metadata
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+// Parquet's page headers and file metadata, in the Thrift compact protocol
+#[derive(Default)]
+struct Thrift {
+    bytes: Vec<u8>,
+    // the id of the last field written in each open struct, which field headers are relative to
+    last_ids: Vec<i16>,
+}
+
+impl Thrift {
+    fn zigzag(&mut self, value: i64) {
+        varint(&mut self.bytes, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last_id = self.last_ids.last_mut().unwrap();
+        let delta = id - *last_id;
+        *last_id = id;
+        if (1..=15).contains(&delta) {
+            self.bytes.push(((delta as u8) << 4) | kind);
+        } else {
+            self.bytes.push(kind);
+            self.zigzag(id as i64);
+        }
+    }
+
+    fn begin(&mut self) {
+        self.last_ids.push(0);
+    }
+
+    fn end(&mut self) {
+        self.bytes.push(0);
+        self.last_ids.pop();
+    }
+
+    fn struct_field(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.begin();
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.zigzag(value as i64);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        self.zigzag(value);
+    }
+
+    fn binary(&mut self, value: &[u8]) {
+        varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend(value);
+    }
+
+    fn string(&mut self, id: i16, value: &str) {
+        self.field(id, BINARY);
+        self.binary(value.as_bytes());
+    }
+
+    fn list(&mut self, id: i16, kind: u8, length: usize) {
+        self.field(id, LIST);
+        if length < 15 {
+            self.bytes.push(((length as u8) << 4) | kind);
+        } else {
+            self.bytes.push(0xf0 | kind);
+            varint(&mut self.bytes, length as u64);
+        }
+    }
+
+    fn list_i32(&mut self, values: &[i32]) {
+        for value in values {
+            self.zigzag(*value as i64);
+        }
+    }
+
+    // the SchemaElement of an optional column
+    fn leaf(&mut self, kind: Kind, name: &str, converted_type: Option<i32>) {
+        self.begin();
+        self.i32(1, kind.physical_type());
+        self.i32(3, 1); // OPTIONAL
+        self.string(4, name);
+        if let Some(converted_type) = converted_type {
+            self.i32(6, converted_type);
+        }
+        self.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // A reader of the Parquet format as the specification describes it (https://parquet.apache.org/docs/file-format/),
+    // kept apart from the writer: it decodes any Thrift compact struct generically, and reads the levels in both
+    // run-length and bit-packed form
+
+    #[derive(Debug, Clone)]
+    enum Thrifted {
+        Bool(bool),
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Thrifted>),
+        Struct(BTreeMap<i16, Thrifted>),
+    }
+
+    impl Thrifted {
+        fn field(&self, id: i16) -> &Thrifted {
+            match self {
+                Thrifted::Struct(fields) => &fields[&id],
+                value => panic!("not a struct: {:?}", value),
+            }
+        }
+
+        fn get(&self, id: i16) -> Option<&Thrifted> {
+            match self {
+                Thrifted::Struct(fields) => fields.get(&id),
+                _ => None,
+            }
+        }
+
+        fn int(&self) -> i64 {
+            match self {
+                Thrifted::Int(value) => *value,
+                Thrifted::Bool(value) => *value as i64,
+                value => panic!("not an integer: {:?}", value),
+            }
+        }
+
+        fn text(&self) -> String {
+            match self {
+                Thrifted::Binary(bytes) => String::from_utf8(bytes.to_owned()).unwrap(),
+                value => panic!("not a binary: {:?}", value),
+            }
+        }
+
+        fn list(&self) -> &[Thrifted] {
+            match self {
+                Thrifted::List(values) => values,
+                value => panic!("not a list: {:?}", value),
+            }
+        }
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.position += 1;
+            self.bytes[self.position - 1]
+        }
+
+        fn take(&mut self, length: usize) -> &[u8] {
+            self.position += length;
+            &self.bytes[self.position - length..self.position]
+        }
+
+        fn varint(&mut self) -> u64 {
+            let (mut value, mut shift) = (0, 0);
+            loop {
+                let byte = self.byte();
+                value |= ((byte & 0x7f) as u64) << shift;
+                if byte & 0x80 == 0 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+
+        fn zigzag(&mut self) -> i64 {
+            let value = self.varint();
+            (value >> 1) as i64 ^ -((value & 1) as i64)
+        }
+
+        fn value(&mut self, kind: u8) -> Thrifted {
+            match kind {
+                1 => Thrifted::Bool(true),
+                2 => Thrifted::Bool(false),
+                3 => Thrifted::Int(self.byte() as i8 as i64),
+                4..=6 => Thrifted::Int(self.zigzag()),
+                7 => Thrifted::Binary(self.take(8).to_vec()),
+                8 => {
+                    let length = self.varint() as usize;
+                    Thrifted::Binary(self.take(length).to_vec())
+                }
+                9 | 10 => {
+                    let header = self.byte();
+                    let length = match header >> 4 {
+                        15 => self.varint() as usize,
+                        length => length as usize,
+                    };
+                    let element_kind = header & 0x0f;
+                    let values = (0..length)
+                        .map(|_| match element_kind {
+                            1 | 2 => Thrifted::Bool(self.byte() == 1),
+                            kind => self.value(kind),
+                        })
+                        .collect();
+                    Thrifted::List(values)
+                }
+                12 => self.structure(),
+                kind => panic!("unexpected Thrift type {}", kind),
+            }
+        }
+
+        fn structure(&mut self) -> Thrifted {
+            let mut fields = BTreeMap::new();
+            let mut last_id = 0;
+            loop {
+                let header = self.byte();
+                if header == 0 {
+                    return Thrifted::Struct(fields);
+                }
+                let id = match header >> 4 {
+                    0 => self.zigzag() as i16,
+                    delta => last_id + delta as i16,
+                };
+                last_id = id;
+                fields.insert(id, self.value(header & 0x0f));
+            }
+        }
+
+        // levels in the RLE/bit-packed hybrid encoding, prefixed by their length
+        fn levels(&mut self, count: usize, bit_width: u32) -> Vec<u8> {
+            let length = u32::from_le_bytes(self.take(4).try_into().unwrap()) as usize;
+            let end = self.position + length;
+            let mut levels = vec![];
+            while levels.len() < count && self.position < end {
+                let header = self.varint();
+                match header & 1 {
+                    0 => {
+                        let level = self.take(bit_width.div_ceil(8) as usize)[0];
+                        levels.extend(std::iter::repeat_n(level, (header >> 1) as usize));
+                    }
+                    _ => {
+                        let values = (header >> 1) as usize * 8;
+                        let packed = self.take(values * bit_width as usize / 8).to_vec();
+                        for index in 0..values {
+                            let mut level = 0;
+                            for bit in 0..bit_width as usize {
+                                let position = index * bit_width as usize + bit;
+                                level |= ((packed[position / 8] >> (position % 8)) & 1) << bit;
+                            }
+                            levels.push(level);
+                        }
+                    }
+                }
+            }
+            self.position = end;
+            levels.truncate(count);
+            levels
+        }
+
+        fn plain(&mut self, physical_type: i64, count: usize) -> Vec<Value> {
+            match physical_type {
+                0 => {
+                    let bits = self.take(count.div_ceil(8)).to_vec();
+                    (0..count)
+                        .map(|index| json!(bits[index / 8] >> (index % 8) & 1 == 1))
+                        .collect()
+                }
+                2 => (0..count)
+                    .map(|_| json!(i64::from_le_bytes(self.take(8).try_into().unwrap())))
+                    .collect(),
+                5 => (0..count)
+                    .map(|_| json!(f64::from_le_bytes(self.take(8).try_into().unwrap())))
+                    .collect(),
+                6 => (0..count)
+                    .map(|_| {
+                        let length = u32::from_le_bytes(self.take(4).try_into().unwrap());
+                        json!(String::from_utf8(self.take(length as usize).to_vec()).unwrap())
+                    })
+                    .collect(),
+                physical_type => panic!("unexpected physical type {}", physical_type),
+            }
+        }
+    }
+
+    // the file's row groups, its number of rows, and its columns by name, each a value per row (null for a row
+    // without one, and an array for a LIST column)
+    fn read_parquet(bytes: &[u8]) -> (usize, i64, Vec<(String, Vec<Value>)>) {
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(&bytes[bytes.len() - 4..], MAGIC);
+        let footer_length =
+            u32::from_le_bytes(bytes[bytes.len() - 8..bytes.len() - 4].try_into().unwrap());
+        let mut reader = Reader {
+            bytes,
+            position: bytes.len() - 8 - footer_length as usize,
+        };
+        let metadata = reader.structure();
+
+        // the root is followed by its children: a leaf, or a LIST group with its repeated group and element
+        let schema = metadata.field(2).list();
+        let mut leaves = vec![];
+        let mut index = 1;
+        while index < schema.len() {
+            let element = &schema[index];
+            let is_list = element.get(6).is_some_and(|converted| converted.int() == 3);
+            leaves.push((element.field(4).text(), is_list));
+            index += if is_list { 3 } else { 1 };
+        }
+        assert_eq!(leaves.len() as i64, schema[0].field(5).int());
+
+        let mut columns = leaves
+            .iter()
+            .map(|(name, _)| (name.to_owned(), vec![]))
+            .collect::<Vec<(String, Vec<Value>)>>();
+        let row_groups = metadata.field(4).list();
+        for row_group in row_groups {
+            let mut row_group_rows = None;
+            for (chunk, ((_, is_list), (_, column))) in row_group
+                .field(1)
+                .list()
+                .iter()
+                .zip(leaves.iter().zip(columns.iter_mut()))
+            {
+                let chunk_metadata = chunk.field(3);
+                let mut reader = Reader {
+                    bytes,
+                    position: chunk_metadata.field(9).int() as usize,
+                };
+                let page_header = reader.structure();
+                assert_eq!(page_header.field(1).int(), 0);
+                let levels = page_header.field(5).field(1).int() as usize;
+                assert_eq!(levels as i64, chunk_metadata.field(5).int());
+
+                let max_definition = if *is_list { 3 } else { 1 };
+                let repetition_levels = match is_list {
+                    true => reader.levels(levels, 1),
+                    false => vec![0; levels],
+                };
+                let definition_levels = reader.levels(levels, if *is_list { 2 } else { 1 });
+                let defined = definition_levels
+                    .iter()
+                    .filter(|&&level| level == max_definition)
+                    .count();
+                let mut values = reader
+                    .plain(chunk_metadata.field(1).int(), defined)
+                    .into_iter();
+
+                let mut rows = vec![];
+                for (repetition, definition) in repetition_levels.into_iter().zip(definition_levels)
+                {
+                    match (is_list, repetition, definition) {
+                        (_, _, 0) => rows.push(Value::Null),
+                        (false, _, _) => rows.push(values.next().unwrap()),
+                        (true, 0, 1) => rows.push(json!([])),
+                        (true, 0, _) => rows.push(json!([values.next().unwrap()])),
+                        (true, _, _) => rows
+                            .last_mut()
+                            .and_then(Value::as_array_mut)
+                            .unwrap()
+                            .push(values.next().unwrap()),
+                    }
+                }
+                assert_eq!(*row_group_rows.get_or_insert(rows.len()), rows.len());
+                column.extend(rows);
+            }
+            assert_eq!(
+                row_group_rows.unwrap_or_default() as i64,
+                row_group.field(3).int()
+            );
+        }
+        (row_groups.len(), metadata.field(3).int(), columns)
+    }
+
+    fn write(rows: &[Value], row_group_rows: usize) -> Vec<u8> {
+        let rows = || {
+            Ok(rows
+                .iter()
+                .map(|row| row.as_object().unwrap().to_owned())
+                .collect::<Vec<_>>()
+                .into_iter())
+        };
+        let mut bytes = vec![];
+        write_parquet(&mut bytes, rows, row_group_rows).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips_rows_through_row_groups() {
+        let rows = [
+            json!({
+                "@id": "ex:ann",
+                "@type": "ex:Person",
+                "ex:name": "Ann",
+                "ex:age": 30,
+                "ex:active": true,
+                "ex:knows": {"@id": "ex:bob"},
+                "ex:tags": ["a", "b"]
+            }),
+            json!({
+                "@id": "ex:bob",
+                "@type": "ex:Person",
+                "ex:name": "Bob",
+                "ex:age": 41.5,
+                "ex:active": false,
+                "ex:tags": []
+            }),
+            json!({
+                "@id": "ex:cy",
+                "@type": "ex:Person",
+                "ex:name": {"@value": "Cy", "@language": "en"},
+                "ex:tags": "c"
+            }),
+        ];
+        let (row_groups, row_count, columns) = read_parquet(&write(&rows, 2));
+        assert_eq!(row_groups, 2);
+        assert_eq!(row_count, 3);
+        assert_eq!(
+            columns,
+            vec![
+                (
+                    "@id".to_string(),
+                    vec![json!("ex:ann"), json!("ex:bob"), json!("ex:cy")]
+                ),
+                (
+                    "ex:active".to_string(),
+                    vec![json!(true), json!(false), Value::Null]
+                ),
+                (
+                    "ex:age".to_string(),
+                    vec![json!(30.0), json!(41.5), Value::Null]
+                ),
+                (
+                    "ex:knows".to_string(),
+                    vec![json!("ex:bob"), Value::Null, Value::Null]
+                ),
+                (
+                    "ex:name".to_string(),
+                    vec![json!("Ann"), json!("Bob"), json!("Cy")]
+                ),
+                (
+                    "ex:tags".to_string(),
+                    vec![json!(["a", "b"]), json!([]), json!(["c"])]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_a_row_group_per_batch_of_rows() {
+        let rows = (0..25)
+            .map(|index| json!({"@id": format!("ex:{}", index), "ex:index": index}))
+            .collect::<Vec<_>>();
+        let (row_groups, row_count, columns) = read_parquet(&write(&rows, 10));
+        assert_eq!(row_groups, 3);
+        assert_eq!(row_count, 25);
+        assert_eq!(
+            columns[1],
+            (
+                "ex:index".to_string(),
+                (0..25).map(|index| json!(index)).collect()
+            )
+        );
+    }
+
+    #[test]
+    fn writes_a_file_without_rows() {
+        let (row_groups, row_count, columns) = read_parquet(&write(&[], 10));
+        assert_eq!(row_groups, 1);
+        assert_eq!(row_count, 0);
+        assert_eq!(columns, vec![("@id".to_string(), vec![])]);
+    }
+}
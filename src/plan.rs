@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::read_json_file;
+
+/// A reviewable description of a migration, written by `fluree-migrate plan`
+/// and executed exactly as written by `fluree-migrate migrate --plan`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationPlan {
+    pub source: String,
+    pub ledger: String,
+    /// Data files are cut whenever the v2 data behind them passes this many bytes
+    pub chunk_size: u64,
+    /// Only the listed collections are migrated, and only the listed predicates of each
+    pub classes: Vec<PlannedClass>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedClass {
+    pub collection: String,
+    pub class: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_count: Option<usize>,
+    pub properties: Vec<PlannedProperty>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedProperty {
+    pub predicate: String,
+    pub term: String,
+    /// The types the predicate has in v2, as xsd datatypes where they have one (e.g. "xsd:dateTime" for instants)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datatype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ref_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<u32>,
+}
+
+impl MigrationPlan {
+    pub fn load(path: &Path) -> Self {
+        let json = read_json_file(path, "plan");
+        match serde_json::from_value(json) {
+            Ok(plan) => plan,
+            Err(e) => {
+                pretty_print(
+                    &format!("Invalid plan file {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) {
+        let contents = serde_json::to_string_pretty(self).unwrap();
+        if let Err(e) = std::fs::write(path, contents) {
+            pretty_print(
+                &format!("Could not write plan to {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+
+    pub fn class(&self, orig_class_name: &str) -> Option<&PlannedClass> {
+        self.classes
+            .iter()
+            .find(|class| class.collection == orig_class_name)
+    }
+}
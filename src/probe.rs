@@ -0,0 +1,189 @@
+use reqwest::Url;
+use serde_json::{json, Value};
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+
+// the URL of a v2 ledger, as the suggested fixes show it
+const EXAMPLE_SOURCE: &str = "http://localhost:8090/fdb/network/ledger";
+
+/// What a `--source` that didn't answer the v2 schema query turned out to be, told apart by probing its server
+#[derive(Debug)]
+pub enum SourceKind {
+    /// A v2 ledger, which answers v2 queries, so the schema query failed for some other reason
+    V2Ledger,
+    /// A v2 server, whose URL is missing a ledger's network and name; its ledgers, as `network/ledger`
+    V2Server { ledgers: Vec<String> },
+    /// A v3 server, which is a --target rather than a --source
+    V3Server,
+    /// A server that doesn't speak either Fluree API, with the status and content type it answered a v2 query with
+    NotFluree { status: u16, content_type: String },
+    /// A server that couldn't be reached, or refused the probes, so nothing is known of it
+    Unknown,
+}
+
+/// Whether a response to the v2 schema multi-query is one
+pub fn is_v2_schema(body: &str) -> bool {
+    serde_json::from_str::<Value>(body).is_ok_and(|schema| {
+        schema["current_predicates"].is_array()
+            && schema["initial_predicates"].is_array()
+            && schema["collections"].is_array()
+    })
+}
+
+// a probe's status, content type, and body as JSON (or null, when it isn't), or None if there was no answer
+async fn probe(
+    source_instance: &FlureeInstance,
+    url: String,
+    body: Value,
+) -> Option<(u16, String, Value)> {
+    let response = source_instance
+        .issue_probe(url, body.to_string())
+        .await
+        .ok()?;
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or("no content type")
+        .to_string();
+    let body = response.text().await.unwrap_or_default();
+    let body = serde_json::from_str::<Value>(&body).unwrap_or_default();
+    Some((status, content_type, body))
+}
+
+// the scheme, host, and port of a URL, where both a v2 and a v3 server answer their API
+fn origin(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    Some(url.origin().ascii_serialization())
+}
+
+/// Probes the --source's server to tell what it is: it's a v2 ledger if it answers a v2 query with JSON, a v2
+/// server if its origin lists v2 ledgers, and a v3 server if its origin answers a v3 query with JSON
+pub async fn detect(source_instance: &FlureeInstance) -> SourceKind {
+    // the smallest v2 query there is: one collection's _id
+    let v2_query = json!({
+        "select": ["_id"],
+        "from": "_collection",
+        "opts": { "limit": 1 }
+    });
+    let Some((status, content_type, body)) = probe(
+        source_instance,
+        format!("{}/query", source_instance.url),
+        v2_query,
+    )
+    .await
+    else {
+        return SourceKind::Unknown;
+    };
+    match status {
+        200 if body.is_array() => return SourceKind::V2Ledger,
+        401 | 403 => return SourceKind::Unknown,
+        _ => {}
+    }
+    let Some(origin) = origin(&source_instance.url) else {
+        return SourceKind::Unknown;
+    };
+
+    if let Some((200, _, Value::Array(ledgers))) =
+        probe(source_instance, format!("{}/fdb/dbs", origin), json!({})).await
+    {
+        // each ledger is listed as `[network, ledger]`, or as `network/ledger`
+        let ledgers = ledgers
+            .iter()
+            .filter_map(|ledger| match ledger {
+                Value::Array(parts) => Some(
+                    parts
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<&str>>()
+                        .join("/"),
+                ),
+                Value::String(ledger) => Some(ledger.to_string()),
+                _ => None,
+            })
+            .collect();
+        return SourceKind::V2Server { ledgers };
+    }
+
+    // a v3 server answers a query of a missing ledger with a JSON error, which is enough to tell it apart
+    let v3_query = json!({
+        "from": "fluree-migrate/probe",
+        "select": ["?s"],
+        "where": { "@id": "?s" },
+        "limit": 1
+    });
+    if let Some((_, _, body)) = probe(
+        source_instance,
+        format!("{}/fluree/query", origin),
+        v3_query,
+    )
+    .await
+    {
+        if !body.is_null() {
+            return SourceKind::V3Server;
+        }
+    }
+    SourceKind::NotFluree {
+        status,
+        content_type,
+    }
+}
+
+/// What's wrong with a --source of this kind, and how to fix it, or None if it's a v2 ledger or unknown
+pub fn explain(kind: &SourceKind, url: &str) -> Option<String> {
+    let origin = origin(url).unwrap_or(url.to_string());
+    match kind {
+        SourceKind::V2Ledger | SourceKind::Unknown => None,
+        SourceKind::V2Server { ledgers } => Some(match ledgers.first() {
+            Some(ledger) => format!(
+                "The --source {} is a Fluree v2 server, but not the URL of one of its ledgers. Add the ledger's \
+                 network and name, e.g. --source {}/fdb/{} (its ledgers are {})",
+                url,
+                origin,
+                ledger,
+                ledgers.join(", ")
+            ),
+            None => format!(
+                "The --source {} is a Fluree v2 server, but not the URL of one of its ledgers, and it has no \
+                 ledgers. Add the ledger's network and name, e.g. --source {}/fdb/network/ledger",
+                url, origin
+            ),
+        }),
+        SourceKind::V3Server => Some(format!(
+            "The --source {} is a Fluree v3 server, but the data is migrated from a v2 ledger. Give the v2 \
+             ledger as the --source, e.g. {}, and {} as the --target",
+            url, EXAMPLE_SOURCE, origin
+        )),
+        SourceKind::NotFluree {
+            status,
+            content_type,
+        } => Some(format!(
+            "The --source {} answered a v2 query with {} ({}), so it isn't a Fluree v2 ledger. Check its host and \
+             port, and that it's the URL of a ledger, e.g. {}",
+            url, status, content_type, EXAMPLE_SOURCE
+        )),
+    }
+}
+
+/// Exits with what's wrong with the --source, and how to fix it, when its server isn't a v2 ledger. Called once
+/// the schema query has failed, or answered with something other than a v2 schema, so a source that works is never
+/// probed. Sources that aren't a v2 server's `/query` (a --graphql endpoint, a replay, or a cache) aren't probed
+pub async fn exit_unless_v2(source_instance: &FlureeInstance) {
+    let opt = &source_instance.opt;
+    if opt.mock
+        || opt.graphql
+        || opt.extract_directory().is_some()
+        || opt.replay.is_some()
+        || opt.from_cache.is_some()
+    {
+        return;
+    }
+    let kind = detect(source_instance).await;
+    if let Some(message) = explain(&kind, &source_instance.url) {
+        opt.pb.finish_and_clear();
+        pretty_print(&message, ERROR_COLOR, true);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::redact::redact;
+
+// distinct values are counted exactly up to this many per property, beyond which the count is a lower bound
+const MAX_DISTINCT_VALUES: usize = 100_000;
+const MAX_SAMPLES: usize = 5;
+
+/// Per-property statistics of the transformed entities of each class, written as JSON when `--profile` is given
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataProfile {
+    pub classes: Vec<ClassProfile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassProfile {
+    pub class: String,
+    pub entities: u64,
+    pub properties: Vec<PropertyProfile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PropertyProfile {
+    pub property: String,
+    /// The share of the class's entities with no value (or only nulls and empty strings) for the property
+    pub null_rate: f64,
+    pub values: u64,
+    pub distinct_values: usize,
+    /// Whether there were more distinct values than were counted, so `distinctValues` is a lower bound
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub distinct_values_capped: bool,
+    /// The lengths, in characters, of the shortest and longest string values (other than refs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// The first few distinct values, as migrated (refs as the `@id` they point to)
+    pub samples: Vec<Value>,
+}
+
+#[derive(Debug, Default)]
+struct PropertyStats {
+    entities_with_values: u64,
+    values: u64,
+    distinct: HashSet<u64>,
+    is_distinct_capped: bool,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    samples: Vec<Value>,
+}
+
+#[derive(Debug, Default)]
+struct ClassStats {
+    entities: u64,
+    properties: BTreeMap<String, PropertyStats>,
+}
+
+/// Gathers a `DataProfile` from the transformed entities, when `--profile` is given
+#[derive(Debug, Default)]
+pub struct DataProfiler {
+    classes: BTreeMap<String, ClassStats>,
+}
+
+// a property value as profiled: the @id of a ref, the @value of a typed literal, or the value itself, and whether
+// it's a ref. Nulls and empty strings aren't values
+fn profiled_value(value: &Value) -> Option<(&Value, bool)> {
+    let (value, is_ref) = match value {
+        Value::Object(object) if object.contains_key("@value") => (&object["@value"], false),
+        Value::Object(object) if object.contains_key("@id") => (&object["@id"], true),
+        value => (value, false),
+    };
+    match value {
+        Value::Null => None,
+        Value::String(string) if string.is_empty() => None,
+        value => Some((value, is_ref)),
+    }
+}
+
+impl DataProfiler {
+    /// A profiler that expects the given properties of each class, so a property no entity has a value for is
+    /// profiled too, with a null rate of 1
+    pub fn with_properties(class_properties: BTreeMap<String, Vec<String>>) -> Self {
+        DataProfiler {
+            classes: class_properties
+                .into_iter()
+                .map(|(class, properties)| {
+                    let properties = properties
+                        .into_iter()
+                        .map(|property| (property, PropertyStats::default()))
+                        .collect();
+                    (
+                        class,
+                        ClassStats {
+                            entities: 0,
+                            properties,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn profile(&mut self, entity: &Value) {
+        let class_name = match &entity["@type"] {
+            Value::String(class_name) => class_name.to_string(),
+            Value::Array(types) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<&str>>()
+                .join(", "),
+            _ => return,
+        };
+        let object = match entity.as_object() {
+            Some(object) => object,
+            None => return,
+        };
+        let class_stats = self.classes.entry(class_name).or_default();
+        class_stats.entities += 1;
+        for (property, value) in object {
+            if property.starts_with('@') {
+                continue;
+            }
+            let stats = class_stats
+                .properties
+                .entry(property.to_owned())
+                .or_default();
+            let values = match value {
+                Value::Array(values) => values.iter().filter_map(profiled_value).collect(),
+                value => profiled_value(value)
+                    .into_iter()
+                    .collect::<Vec<(&Value, bool)>>(),
+            };
+            if !values.is_empty() {
+                stats.entities_with_values += 1;
+            }
+            for (value, is_ref) in values {
+                stats.values += 1;
+                if let (Value::String(string), false) = (value, is_ref) {
+                    let length = string.chars().count();
+                    stats.min_length = Some(stats.min_length.map_or(length, |min| min.min(length)));
+                    stats.max_length = Some(stats.max_length.map_or(length, |max| max.max(length)));
+                }
+                let mut hasher = DefaultHasher::new();
+                value.to_string().hash(&mut hasher);
+                let hash = hasher.finish();
+                if stats.distinct.contains(&hash) {
+                    continue;
+                }
+                match stats.distinct.len() < MAX_DISTINCT_VALUES {
+                    true => {
+                        stats.distinct.insert(hash);
+                    }
+                    false => stats.is_distinct_capped = true,
+                }
+                if stats.samples.len() < MAX_SAMPLES {
+                    stats.samples.push(value.to_owned());
+                }
+            }
+        }
+    }
+
+    pub fn data_profile(&self) -> DataProfile {
+        DataProfile {
+            classes: self
+                .classes
+                .iter()
+                .map(|(class, class_stats)| ClassProfile {
+                    class: class.to_owned(),
+                    entities: class_stats.entities,
+                    properties: class_stats
+                        .properties
+                        .iter()
+                        .map(|(property, stats)| PropertyProfile {
+                            property: property.to_owned(),
+                            null_rate: match class_stats.entities {
+                                0 => 1.0,
+                                entities => {
+                                    1.0 - stats.entities_with_values as f64 / entities as f64
+                                }
+                            },
+                            values: stats.values,
+                            distinct_values: stats.distinct.len(),
+                            distinct_values_capped: stats.is_distinct_capped,
+                            min_length: stats.min_length,
+                            max_length: stats.max_length,
+                            samples: stats.samples.to_owned(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl DataProfile {
+    pub fn write(&self, path: &Path) {
+        let contents = redact(&serde_json::to_string_pretty(self).unwrap());
+        if let Err(e) = std::fs::write(path, contents) {
+            pretty_print(
+                &format!("Could not write profile to {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use dialoguer::console::Style;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::functions::{pii_name_kind, pii_value_kind};
+use crate::redact::redact;
+use crate::store;
+
+/// Findings gathered over a migration run, written as JSON when `--report` is given
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MigrationReport {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dangling_references: Vec<DanglingReference>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cardinality_changes: Vec<CardinalityChange>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub datatype_inferences: Vec<DatatypeInference>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pii_findings: Vec<PiiFinding>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsupported_features: Vec<UnsupportedFeature>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schema_statistics: Vec<ClassStatistics>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_files: Vec<SkippedFile>,
+}
+
+/// A referenced `@id` that was never emitted as a subject (e.g. a deleted entity or an excluded class)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DanglingReference {
+    pub id: String,
+    pub reference_count: u64,
+    pub properties: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub types: BTreeSet<String>,
+    pub example_subjects: Vec<String>,
+}
+
+/// A property whose `sh:maxCount` was changed because sampled data disagreed with the v2 `multi` setting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardinalityChange {
+    pub class: String,
+    pub property: String,
+    pub declared_max_count: Option<u32>,
+    pub inferred_max_count: Option<u32>,
+    pub observed_max_values: usize,
+    pub sampled_entities: usize,
+}
+
+/// An xsd datatype inferred from sampled values for a property whose v2 type doesn't map to one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatatypeInference {
+    pub class: String,
+    pub property: String,
+    pub v2_types: Vec<String>,
+    pub datatype: String,
+    pub sampled_values: usize,
+}
+
+/// How cleanly the predicates of a v2 collection map to the properties of its v3 class
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassStatistics {
+    pub collection: String,
+    pub class: String,
+    /// Whether the class was given a name other than the one derived from the collection
+    pub renamed: bool,
+    /// The collection's v2 predicates
+    pub predicates: usize,
+    /// The predicates migrated as properties of the class
+    pub properties: usize,
+    /// The migrated predicates of type `ref`
+    pub refs: usize,
+    /// The migrated predicates whose property is shared with predicates of other datatypes, so it has no
+    /// `sh:datatype`
+    pub datatype_conflicts: usize,
+    /// The predicates that weren't migrated, e.g. with `--drop-properties`
+    pub dropped: usize,
+    /// The migrated predicates given a term other than the one derived from the predicate
+    pub renamed_properties: usize,
+}
+
+/// A v2 schema feature (e.g. a spec function) that has no v3 equivalent and wasn't migrated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsupportedFeature {
+    /// What kind of feature it is, e.g. "collectionSpec" or "predicateSpec"
+    pub feature: String,
+    pub class: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub property: Option<String>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+/// A file of --input (or `load`) that wasn't transacted because the target didn't answer it, with
+/// `--on-upload-failure skip`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedFile {
+    pub file: String,
+    pub attempts: u32,
+}
+
+/// A property whose name or values look like personally identifiable information
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PiiFinding {
+    pub class: String,
+    pub property: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_suggests: Option<String>,
+    /// PII kinds mapped to the number of scanned values that look like them
+    pub matching_values: BTreeMap<String, u64>,
+    pub scanned_values: u64,
+}
+
+const MAX_EXAMPLE_SUBJECTS: usize = 5;
+
+impl DanglingReference {
+    /// A minimal subject standing in for the missing entity, typed by the refs pointing at it
+    pub fn stub(&self) -> Value {
+        let mut stub = json!({ "@id": self.id });
+        match self.types.len() {
+            0 => {}
+            1 => stub["@type"] = json!(self.types.iter().next().unwrap()),
+            _ => stub["@type"] = json!(self.types),
+        }
+        stub
+    }
+}
+
+impl MigrationReport {
+    /// Writes the report to the --report file, and records its findings in the --state-db, if either is given
+    pub fn save(&self, opt: &Opt) {
+        store::record_findings(self);
+        if let Some(report_path) = &opt.report {
+            self.write(report_path);
+        }
+    }
+
+    pub fn read(path: &Path) -> Self {
+        let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            pretty_print(
+                &format!("Could not read report {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        });
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            pretty_print(
+                &format!("Invalid report {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        })
+    }
+
+    /// Combines the reports of the shards of a --shard run: a ref dangling in several shards is one dangling
+    /// reference, the PII scans of a property add up, and the findings about the schema, which every shard
+    /// makes alike, are kept once
+    pub fn merge(reports: Vec<MigrationReport>) -> MigrationReport {
+        let mut dangling_references: BTreeMap<String, DanglingReference> = BTreeMap::new();
+        let mut pii_findings: BTreeMap<(String, String), PiiFinding> = BTreeMap::new();
+        let mut merged = MigrationReport::default();
+        fn extend_distinct<T: PartialEq>(merged: &mut Vec<T>, findings: Vec<T>) {
+            for finding in findings {
+                if !merged.contains(&finding) {
+                    merged.push(finding);
+                }
+            }
+        }
+        for report in reports {
+            for reference in report.dangling_references {
+                match dangling_references.get_mut(&reference.id) {
+                    Some(merged_reference) => {
+                        merged_reference.reference_count += reference.reference_count;
+                        merged_reference.properties.extend(reference.properties);
+                        merged_reference.types.extend(reference.types);
+                        for subject in reference.example_subjects {
+                            if merged_reference.example_subjects.len() < MAX_EXAMPLE_SUBJECTS {
+                                merged_reference.example_subjects.push(subject);
+                            }
+                        }
+                    }
+                    None => {
+                        dangling_references.insert(reference.id.to_owned(), reference);
+                    }
+                }
+            }
+            for finding in report.pii_findings {
+                let key = (finding.class.to_owned(), finding.property.to_owned());
+                match pii_findings.get_mut(&key) {
+                    Some(merged_finding) => {
+                        merged_finding.scanned_values += finding.scanned_values;
+                        for (kind, count) in finding.matching_values {
+                            *merged_finding.matching_values.entry(kind).or_default() += count;
+                        }
+                    }
+                    None => {
+                        pii_findings.insert(key, finding);
+                    }
+                }
+            }
+            extend_distinct(&mut merged.cardinality_changes, report.cardinality_changes);
+            extend_distinct(&mut merged.datatype_inferences, report.datatype_inferences);
+            extend_distinct(
+                &mut merged.unsupported_features,
+                report.unsupported_features,
+            );
+            extend_distinct(&mut merged.schema_statistics, report.schema_statistics);
+            merged.skipped_files.extend(report.skipped_files);
+        }
+        merged.dangling_references = dangling_references.into_values().collect();
+        merged.pii_findings = pii_findings.into_values().collect();
+        merged
+    }
+
+    pub fn write(&self, path: &Path) {
+        let contents = redact(&serde_json::to_string_pretty(self).unwrap());
+        if let Err(e) = std::fs::write(path, contents) {
+            pretty_print(
+                &format!("Could not write report to {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+        }
+    }
+}
+
+/// Records the subjects and `@id` references of every transformed entity,
+/// so references that never resolve to a migrated subject can be reported
+#[derive(Debug, Default)]
+pub struct ReferenceTracker {
+    subjects: HashSet<String>,
+    references: HashMap<String, DanglingReference>,
+    external_bases: Vec<String>,
+}
+
+impl ReferenceTracker {
+    /// A tracker that ignores refs under the base IRIs of other ledgers (from `--ref-ledger`),
+    /// whose subjects are migrated there
+    pub fn with_external_bases(external_bases: Vec<String>) -> Self {
+        ReferenceTracker {
+            external_bases,
+            ..Default::default()
+        }
+    }
+
+    pub fn track(&mut self, entity: &Value) {
+        let subject = match entity["@id"].as_str() {
+            Some(subject) => subject.to_string(),
+            None => return,
+        };
+        if let Some(object) = entity.as_object() {
+            for (property, value) in object {
+                // owl:sameAs points outside the migrated graph by design
+                if property.starts_with('@') || property == "owl:sameAs" {
+                    continue;
+                }
+                self.track_value(&subject, property, value);
+            }
+        }
+        self.subjects.insert(subject);
+    }
+
+    fn track_value(&mut self, subject: &str, property: &str, value: &Value) {
+        match value {
+            Value::Array(values) => {
+                for value in values {
+                    self.track_value(subject, property, value);
+                }
+            }
+            Value::Object(object) => {
+                if let Some(Value::String(id)) = object.get("@id") {
+                    if self.external_bases.iter().any(|base| id.starts_with(base)) {
+                        return;
+                    }
+                    let reference = self.references.entry(id.to_string()).or_insert_with(|| {
+                        DanglingReference {
+                            id: id.to_string(),
+                            reference_count: 0,
+                            properties: BTreeSet::new(),
+                            types: BTreeSet::new(),
+                            example_subjects: vec![],
+                        }
+                    });
+                    reference.reference_count += 1;
+                    reference.properties.insert(property.to_string());
+                    if let Some(Value::String(ref_type)) = object.get("@type") {
+                        reference.types.insert(ref_type.to_string());
+                    }
+                    if reference.example_subjects.len() < MAX_EXAMPLE_SUBJECTS {
+                        reference.example_subjects.push(subject.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn dangling(&self) -> Vec<DanglingReference> {
+        let mut dangling = self
+            .references
+            .values()
+            .filter(|reference| !self.subjects.contains(&reference.id))
+            .cloned()
+            .collect::<Vec<DanglingReference>>();
+        dangling.sort_by(|a, b| a.id.cmp(&b.id));
+        dangling
+    }
+}
+
+/// Scans the string values of transformed entities for PII, when `--scan-pii` is given
+#[derive(Debug, Default)]
+pub struct PiiScanner {
+    findings: HashMap<(String, String), PiiFinding>,
+}
+
+impl PiiScanner {
+    pub fn scan(&mut self, entity: &Value) {
+        let class_name = match &entity["@type"] {
+            Value::String(class_name) => class_name.to_string(),
+            Value::Array(types) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<&str>>()
+                .join(", "),
+            _ => return,
+        };
+        let object = match entity.as_object() {
+            Some(object) => object,
+            None => return,
+        };
+        for (property, value) in object {
+            if property.starts_with('@') {
+                continue;
+            }
+            let finding = self
+                .findings
+                .entry((class_name.to_owned(), property.to_owned()))
+                .or_insert_with(|| PiiFinding {
+                    class: class_name.to_owned(),
+                    property: property.to_owned(),
+                    name_suggests: pii_name_kind(property).map(str::to_string),
+                    ..Default::default()
+                });
+            let values = match value {
+                Value::Array(values) => values.iter().collect::<Vec<&Value>>(),
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    Value::String(value) => value,
+                    _ => continue,
+                };
+                finding.scanned_values += 1;
+                if let Some(kind) = pii_value_kind(value) {
+                    *finding.matching_values.entry(kind.to_string()).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    pub fn findings(&self) -> Vec<PiiFinding> {
+        let mut findings = self
+            .findings
+            .values()
+            .filter(|finding| {
+                finding.name_suggests.is_some() || !finding.matching_values.is_empty()
+            })
+            .cloned()
+            .collect::<Vec<PiiFinding>>();
+        findings.sort_by(|a, b| (&a.class, &a.property).cmp(&(&b.class, &b.property)));
+        findings
+    }
+}
+
+/// Merges the reports of the shards of a --shard run into the --report file
+pub fn merge_reports(opt: &Opt, reports: &[PathBuf]) {
+    let Some(report_path) = &opt.report else {
+        pretty_print(
+            "merge-reports writes the merged report to --report, which wasn't given",
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    };
+    let merged = MigrationReport::merge(
+        reports
+            .iter()
+            .map(|path| MigrationReport::read(path))
+            .collect(),
+    );
+    merged.save(opt);
+    println!(
+        "{:>12} {} reports into {} ({} dangling references, {} PII findings, {} skipped files)",
+        Style::new().green().bold().apply_to("Merged"),
+        reports.len(),
+        report_path.display(),
+        merged.dangling_references.len(),
+        merged.pii_findings.len(),
+        merged.skipped_files.len()
+    );
+}
@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use dialoguer::console::Style;
+use serde_json::{json, Value};
+
+use crate::cli::opt::Opt;
+use crate::cli::parser::jsonld::{ShaclProperty, ShaclShape};
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+use crate::state;
+
+const SHAPES_PAGE_SIZE: usize = 10_000;
+
+pub const INTEGER_DATATYPES: [&str; 6] = [
+    "xsd:integer",
+    "xsd:long",
+    "xsd:int",
+    "xsd:short",
+    "xsd:byte",
+    "xsd:nonNegativeInteger",
+];
+
+// classes in these namespaces describe the vocab and shapes rather than data
+const META_PREFIXES: [&str; 5] = ["rdf:", "rdfs:", "sh:", "f:", "xsd:"];
+
+/// What the sampled instances of a class hold for one property
+#[derive(Debug, Default)]
+struct PropertyStats {
+    datatypes: BTreeSet<String>,
+    has_refs: bool,
+    max_values: usize,
+}
+
+fn query_context() -> Value {
+    json!({
+        "rdf": "http://www.w3.org/1999/02/22-rdf-syntax-ns#",
+        "rdfs": "http://www.w3.org/2000/01/rdf-schema#",
+        "sh": "http://www.w3.org/ns/shacl#",
+        "xsd": "http://www.w3.org/2001/XMLSchema#",
+        "f": "https://ns.flur.ee/ledger#"
+    })
+}
+
+fn column(row: &Value, index: usize) -> Option<&str> {
+    match row {
+        Value::Array(columns) => columns.get(index).and_then(Value::as_str),
+        row if index == 0 => row.as_str(),
+        _ => None,
+    }
+}
+
+// the datatype all of a property's literals share, with integer types of different widths counted as one
+fn common_datatype(datatypes: &BTreeSet<String>) -> Option<String> {
+    match datatypes.len() {
+        0 => None,
+        1 => datatypes.iter().next().cloned(),
+        _ if datatypes
+            .iter()
+            .all(|datatype| INTEGER_DATATYPES.contains(&datatype.as_str())) =>
+        {
+            Some("xsd:integer".to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Samples the instances and vocab of an already-migrated v3 ledger (the --target with --ledger-name)
+/// and writes SHACL NodeShapes for its classes, e.g. for data migrated without --shacl
+pub async fn generate_shapes(opt: &Opt, path: &Path) {
+    let green_bold = Style::new().green().bold();
+    let yellow_bold = Style::new().yellow().bold();
+    let ledger = match &opt.ledger_name {
+        Some(ledger) => ledger.to_owned(),
+        None => {
+            pretty_print(
+                "Generating shapes needs the --ledger-name of the v3 ledger to sample",
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    };
+    let context = query_context();
+    let mut target_instance = FlureeInstance::new_target(opt);
+
+    let mut classes = BTreeSet::new();
+    for pattern in [
+        json!({ "@id": "?s", "@type": "?class" }),
+        json!({ "@id": "?class", "@type": "rdfs:Class" }),
+    ] {
+        let query = json!({
+            "@context": context,
+            "from": ledger,
+            "selectDistinct": ["?class"],
+            "where": [pattern]
+        });
+        for row in target_instance
+            .v3_query_array(&query)
+            .await
+            .unwrap_or_else(|e| state::abort(opt, &e))
+        {
+            if let Some(class) = column(&row, 0) {
+                if !META_PREFIXES.iter().any(|prefix| class.starts_with(prefix)) {
+                    classes.insert(class.to_string());
+                }
+            }
+        }
+    }
+
+    // properties the vocab declares for a class, which are shaped even when no sampled instance holds them
+    let domain_query = json!({
+        "@context": context,
+        "from": ledger,
+        "select": ["?p", "?class"],
+        "where": [{ "@id": "?p", "rdfs:domain": "?class" }]
+    });
+    let mut vocab_properties: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for row in target_instance
+        .v3_query_array(&domain_query)
+        .await
+        .unwrap_or_else(|e| state::abort(opt, &e))
+    {
+        if let (Some(property), Some(class)) = (column(&row, 0), column(&row, 1)) {
+            vocab_properties
+                .entry(class.to_string())
+                .or_default()
+                .insert(property.to_string());
+        }
+    }
+
+    opt.pb.set_length(classes.len() as u64);
+    opt.pb.set_prefix("Sampling");
+    let mut shapes = vec![];
+    let mut warnings = vec![];
+    for class in &classes {
+        opt.pb.inc(1);
+        opt.pb.set_message(class.to_owned());
+        let mut properties: BTreeMap<String, PropertyStats> = BTreeMap::new();
+        for property in vocab_properties.get(class).into_iter().flatten() {
+            properties.entry(property.to_owned()).or_default();
+        }
+
+        // statements are paged in subject order until the sample is complete
+        let mut value_counts: HashMap<(String, String), usize> = HashMap::new();
+        let mut subjects = BTreeSet::new();
+        let mut offset = 0;
+        'paging: loop {
+            let query = json!({
+                "@context": context,
+                "from": ledger,
+                "select": ["?s", "?p", "?o", "?dt"],
+                "where": [
+                    { "@id": "?s", "@type": class },
+                    { "@id": "?s", "?p": "?o" },
+                    ["bind", "?dt", "(datatype ?o)"]
+                ],
+                "orderBy": ["?s", "?p"],
+                "limit": SHAPES_PAGE_SIZE,
+                "offset": offset
+            });
+            let rows = target_instance
+                .v3_query_array(&query)
+                .await
+                .unwrap_or_else(|e| state::abort(opt, &e));
+            for row in &rows {
+                let (Some(subject), Some(property)) = (column(row, 0), column(row, 1)) else {
+                    continue;
+                };
+                if !subjects.contains(subject) {
+                    if subjects.len() == opt.inference_sample {
+                        break 'paging;
+                    }
+                    subjects.insert(subject.to_string());
+                }
+                if ["@type", "rdf:type"].contains(&property) {
+                    continue;
+                }
+                let stats = properties.entry(property.to_string()).or_default();
+                match column(row, 3).unwrap_or_default() {
+                    "@id" | "id" => stats.has_refs = true,
+                    "" => {}
+                    datatype => {
+                        stats.datatypes.insert(datatype.to_string());
+                    }
+                }
+                *value_counts
+                    .entry((subject.to_string(), property.to_string()))
+                    .or_default() += 1;
+            }
+            if rows.len() < SHAPES_PAGE_SIZE {
+                break;
+            }
+            offset += SHAPES_PAGE_SIZE;
+        }
+        for ((_, property), count) in value_counts {
+            if let Some(stats) = properties.get_mut(&property) {
+                stats.max_values = stats.max_values.max(count);
+            }
+        }
+
+        let mut shape = ShaclShape::new(class, opt.closed_shapes);
+        for (property, stats) in &properties {
+            let mut shacl_property = ShaclProperty::new(property);
+            if stats.max_values == 1 {
+                shacl_property.max_count = Some(1);
+            }
+            match (stats.has_refs, common_datatype(&stats.datatypes)) {
+                (false, Some(datatype)) => {
+                    shacl_property.datatype = Some(HashMap::from([("@id".to_string(), datatype)]));
+                }
+                (true, None) if stats.datatypes.is_empty() => {
+                    let ref_query = json!({
+                        "@context": context,
+                        "from": ledger,
+                        "selectDistinct": ["?class"],
+                        "where": [
+                            { "@id": "?s", "@type": class },
+                            { "@id": "?s", property: "?o" },
+                            { "@id": "?o", "@type": "?class" }
+                        ]
+                    });
+                    let ref_classes = target_instance.v3_query_array(&ref_query).await.unwrap_or_else(|e| state::abort(opt, &e));
+                    match ref_classes.as_slice() {
+                        [ref_class] if column(ref_class, 0).is_some() => {
+                            shacl_property.class = Some(HashMap::from([(
+                                "@id".to_string(),
+                                column(ref_class, 0).unwrap().to_string(),
+                            )]));
+                        }
+                        _ => shacl_property.node_kind = "sh:IRI".to_string(),
+                    }
+                }
+                (false, None) if stats.datatypes.is_empty() => {}
+                _ => warnings.push(format!(
+                    "Property, \"{}\", of class, \"{}\", holds values of several types [{}{}], so its shape has no datatype",
+                    property,
+                    class,
+                    stats.datatypes.iter().cloned().collect::<Vec<String>>().join(", "),
+                    if stats.has_refs { ", refs" } else { "" }
+                )),
+            }
+            shape.property.push(shacl_property);
+        }
+        shapes.push(serde_json::to_value(shape).unwrap());
+    }
+    opt.pb.finish_and_clear();
+
+    for warning in &warnings {
+        println!("{:>12} {}", yellow_bold.apply_to("WARNING"), warning);
+    }
+    let document = json!({
+        "ledger": ledger,
+        "@context": context,
+        "insert": shapes
+    });
+    let contents = serde_json::to_string_pretty(&document).unwrap();
+    if opt.print {
+        println!("{}", contents);
+        return;
+    }
+    if let Err(e) = std::fs::write(path, contents) {
+        pretty_print(
+            &format!("Could not write shapes to {}: {}", path.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+        std::process::exit(1);
+    }
+    println!(
+        "{:>12} SHACL shapes for {} classes of {} to {}",
+        green_bold.apply_to("Wrote"),
+        shapes.len(),
+        ledger,
+        path.display()
+    );
+}
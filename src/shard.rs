@@ -0,0 +1,74 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// with --shard-by ids, the _ids are dealt to the shards in blocks of this many consecutive ids
+const SHARD_ID_BLOCK: i64 = 1000;
+
+/// The slice of a migration a run takes with `--shard`, e.g. "2/8" for the second of eight shards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// The shard's number, from 1 to `count`
+    pub index: u32,
+    pub count: u32,
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    /// "i/n", where i is the shard's number, from 1 to n
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Expected a shard like 2/8 (the second of eight), not \"{}\"",
+                value
+            )
+        };
+        let (index, count) = value.trim().split_once('/').ok_or_else(invalid)?;
+        let index = index.trim().parse::<u32>().map_err(|_| invalid())?;
+        let count = count.trim().parse::<u32>().map_err(|_| invalid())?;
+        match (1..=count).contains(&index) {
+            true => Ok(Shard { index, count }),
+            false => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl Shard {
+    /// The first shard writes (or transacts) what the shards share, e.g. the vocab
+    pub fn is_first(&self) -> bool {
+        self.index == 1
+    }
+
+    /// The name of the shard's subdirectory of --output, e.g. "shard-2-of-8"
+    pub fn name(&self) -> String {
+        format!("shard-{}-of-{}", self.index, self.count)
+    }
+
+    /// The path with the shard's name before its extension, e.g. "report.shard-2-of-8.json", so the shards'
+    /// files don't clobber each other
+    pub fn file_name(&self, path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match path.extension() {
+            Some(extension) => format!("{}.{}.{}", stem, self.name(), extension.to_string_lossy()),
+            None => format!("{}.{}", stem, self.name()),
+        };
+        path.with_file_name(file_name)
+    }
+
+    /// Whether the shard takes the `key`th of the things (e.g. classes) dealt to the shards in turn
+    pub fn owns(&self, key: u64) -> bool {
+        key % self.count as u64 == (self.index - 1) as u64
+    }
+
+    /// Whether the v2 subject `_id` falls in one of this shard's blocks of ids
+    pub fn owns_id(&self, id: i64) -> bool {
+        self.owns((id / SHARD_ID_BLOCK) as u64)
+    }
+}
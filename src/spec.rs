@@ -0,0 +1,266 @@
+use serde_json::Value;
+
+// `valid-email?` as a pattern: something, an @, and a domain with a dot
+const EMAIL_PATTERN: &str = r"^[^@\s]+@[^@\s]+\.[^@\s]+$";
+
+/// A form of a v2 smart function's code, e.g. `(>= (?o) 0)`
+#[derive(Debug, Clone, PartialEq)]
+enum Form {
+    List(Vec<Form>),
+    Symbol(String),
+    Str(String),
+}
+
+fn parse_forms(code: &str) -> Option<Vec<Form>> {
+    let mut stack: Vec<Vec<Form>> = vec![vec![]];
+    let mut chars = code.chars().peekable();
+    while let Some(character) = chars.next() {
+        match character {
+            '(' => stack.push(vec![]),
+            ')' => {
+                let list = stack.pop()?;
+                stack.last_mut()?.push(Form::List(list));
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => string.push(chars.next()?),
+                        character => string.push(character),
+                    }
+                }
+                stack.last_mut()?.push(Form::Str(string));
+            }
+            character if character.is_whitespace() || character == ',' => {}
+            character => {
+                let mut symbol = character.to_string();
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || ['(', ')', '"', ','].contains(next) {
+                        break;
+                    }
+                    symbol.push(chars.next()?);
+                }
+                stack.last_mut()?.push(Form::Symbol(symbol));
+            }
+        }
+    }
+    match stack.as_slice() {
+        [forms] => Some(forms.to_owned()),
+        _ => None,
+    }
+}
+
+// what a comparison's operand measures: the value itself, its length, or a constant
+enum Operand {
+    Value,
+    Length,
+    Number(serde_json::Number),
+}
+
+fn operand(form: &Form) -> Option<Operand> {
+    let is_value = |form: &Form| match form {
+        Form::Symbol(symbol) => symbol == "?o",
+        Form::List(list) => list.as_slice() == [Form::Symbol("?o".to_string())],
+        _ => false,
+    };
+    match form {
+        form if is_value(form) => Some(Operand::Value),
+        Form::List(list) => match list.as_slice() {
+            [Form::Symbol(function), argument] if function == "count" && is_value(argument) => {
+                Some(Operand::Length)
+            }
+            _ => None,
+        },
+        Form::Symbol(symbol) => match serde_json::from_str::<Value>(symbol) {
+            Ok(Value::Number(number)) => Some(Operand::Number(number)),
+            _ => None,
+        },
+        Form::Str(_) => None,
+    }
+}
+
+// the constraint `value <operator> number` (or `length <operator> number`) stands for
+fn comparison(
+    operator: &str,
+    measure: &Operand,
+    number: &serde_json::Number,
+) -> Option<(String, Value)> {
+    match measure {
+        Operand::Value => {
+            let constraint = match operator {
+                ">" => "sh:minExclusive",
+                ">=" => "sh:minInclusive",
+                "<" => "sh:maxExclusive",
+                "<=" => "sh:maxInclusive",
+                _ => return None,
+            };
+            Some((constraint.to_string(), Value::Number(number.to_owned())))
+        }
+        Operand::Length => {
+            let length = number.as_i64()?;
+            let (constraint, length) = match operator {
+                ">" => ("sh:minLength", length + 1),
+                ">=" => ("sh:minLength", length),
+                "<" => ("sh:maxLength", length - 1),
+                "<=" => ("sh:maxLength", length),
+                _ => return None,
+            };
+            Some((constraint.to_string(), Value::from(length.max(0))))
+        }
+        Operand::Number(_) => None,
+    }
+}
+
+fn flipped(operator: &str) -> &str {
+    match operator {
+        ">" => "<",
+        ">=" => "<=",
+        "<" => ">",
+        "<=" => ">=",
+        operator => operator,
+    }
+}
+
+fn form_constraints(form: &Form) -> Option<Vec<(String, Value)>> {
+    let Form::List(list) = form else {
+        return None;
+    };
+    let (Form::Symbol(function), arguments) = list.split_first()? else {
+        return None;
+    };
+    match (function.as_str(), arguments) {
+        ("and", arguments) => {
+            let constraints = arguments
+                .iter()
+                .map(form_constraints)
+                .collect::<Option<Vec<Vec<(String, Value)>>>>()?;
+            Some(constraints.into_iter().flatten().collect())
+        }
+        // a chained comparison like (< 0 (?o) 100) holds when each adjacent pair does
+        (">" | ">=" | "<" | "<=", arguments) if arguments.len() >= 2 => {
+            let mut constraints = vec![];
+            for pair in arguments.windows(2) {
+                let constraint = match (operand(&pair[0])?, operand(&pair[1])?) {
+                    (Operand::Number(_), Operand::Number(_)) => return None,
+                    (measure, Operand::Number(number)) => comparison(function, &measure, &number)?,
+                    (Operand::Number(number), measure) => {
+                        comparison(flipped(function), &measure, &number)?
+                    }
+                    _ => return None,
+                };
+                constraints.push(constraint);
+            }
+            Some(constraints)
+        }
+        ("re-find" | "re-matches", [Form::Str(pattern), argument]) => match operand(argument)? {
+            Operand::Value if function == "re-matches" => Some(vec![(
+                "sh:pattern".to_string(),
+                Value::String(format!("^(?:{})$", pattern)),
+            )]),
+            Operand::Value => Some(vec![(
+                "sh:pattern".to_string(),
+                Value::String(pattern.to_owned()),
+            )]),
+            _ => None,
+        },
+        ("valid-email?", [argument]) => match operand(argument)? {
+            Operand::Value => Some(vec![(
+                "sh:pattern".to_string(),
+                Value::String(EMAIL_PATTERN.to_string()),
+            )]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The SHACL constraints (e.g. `sh:minInclusive`, `sh:maxLength`, or `sh:pattern`) a v2 predicate spec's code
+/// stands for, when it's a recognizable built-in comparison of the value or its length, a regex, or an email check.
+/// Returns None when any part of the code isn't recognized, so a spec is never migrated as a weaker constraint
+pub fn spec_constraints(code: &str) -> Option<Vec<(String, Value)>> {
+    match parse_forms(code)?.as_slice() {
+        [form] => form_constraints(form),
+        _ => None,
+    }
+}
+
+/// What a v2 collection spec requires of each entity: predicates it must hold, in combination
+#[derive(Debug, Clone, PartialEq)]
+pub enum Requirement {
+    Has(String),
+    All(Vec<Requirement>),
+    Any(Vec<Requirement>),
+}
+
+// the predicate a form reads from the subject, e.g. (get (?s) "person/handle") or (?s "person/handle")
+fn subject_predicate(form: &Form) -> Option<String> {
+    let is_subject = |form: &Form| match form {
+        Form::Symbol(symbol) => symbol == "?s",
+        Form::List(list) => list.as_slice() == [Form::Symbol("?s".to_string())],
+        _ => false,
+    };
+    let Form::List(list) = form else {
+        return None;
+    };
+    match list.as_slice() {
+        [Form::Symbol(function), subject, Form::Str(predicate)]
+            if ["get", "contains?"].contains(&function.as_str()) && is_subject(subject) =>
+        {
+            Some(predicate.to_owned())
+        }
+        [Form::Symbol(subject), Form::Str(predicate)] if subject == "?s" => {
+            Some(predicate.to_owned())
+        }
+        _ => None,
+    }
+}
+
+fn requirement(form: &Form) -> Option<Requirement> {
+    if let Some(predicate) = subject_predicate(form) {
+        return Some(Requirement::Has(predicate));
+    }
+    let Form::List(list) = form else {
+        return None;
+    };
+    let (Form::Symbol(function), arguments) = list.split_first()? else {
+        return None;
+    };
+    match (function.as_str(), arguments) {
+        ("and" | "or", arguments) if !arguments.is_empty() => {
+            let requirements = arguments
+                .iter()
+                .map(requirement)
+                .collect::<Option<Vec<Requirement>>>()?;
+            match function.as_str() {
+                "and" => Some(Requirement::All(requirements)),
+                _ => Some(Requirement::Any(requirements)),
+            }
+        }
+        ("boolean" | "some?", [argument]) => requirement(argument),
+        ("not", [Form::List(negated)]) => match negated.as_slice() {
+            [Form::Symbol(function), argument] if function == "nil?" => requirement(argument),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The predicates a v2 collection spec's code requires each entity to hold, when it's a recognizable
+/// combination (`and`/`or`) of checks that the subject has a value for a predicate, e.g.
+/// `(or (get (?s) "person/email") (get (?s) "person/phone"))`
+pub fn collection_requirement(code: &str) -> Option<Requirement> {
+    match parse_forms(code)?.as_slice() {
+        [form] => requirement(form),
+        _ => None,
+    }
+}
+
+/// The `_fn` records of a v2 `spec`, which is a single ref or a list of them
+pub fn spec_functions(spec: &Value) -> Vec<Value> {
+    match spec {
+        Value::Array(functions) => functions.to_owned(),
+        Value::Null => vec![],
+        function => vec![function.to_owned()],
+    }
+}
@@ -0,0 +1,354 @@
+use std::fs::{File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use dialoguer::console::Style;
+use indicatif::ProgressBar;
+use serde::Serialize;
+
+use crate::cli::opt::Opt;
+use crate::cli::temp_files::TEMP_DIRECTORY;
+use crate::functions::content_hash;
+use crate::store;
+
+/// The file, in the output directory (or the working directory), where an interrupted run records its progress
+pub const STATE_FILE: &str = ".fluree-migrate-state.json";
+
+/// The lock file guarding the working directory's `.tmp`
+pub const LOCK_FILE: &str = ".fluree-migrate.lock";
+
+/// While this file exists in the working directory, the load loop pauses before its next transaction
+pub const PAUSE_FILE: &str = ".fluree-migrate-pause";
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Progress of the current run, so an interrupted run can report exactly what it already wrote or transacted
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunState {
+    pub started_at: String,
+    pub interrupted: bool,
+    pub transformed_entities: u64,
+    pub completed: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_flight: Option<String>,
+}
+
+static RUN_STATE: LazyLock<Mutex<RunState>> = LazyLock::new(|| {
+    Mutex::new(RunState {
+        started_at: chrono::Utc::now().to_rfc3339(),
+        ..Default::default()
+    })
+});
+
+/// Marks a file as being written (or transacted)
+pub fn begin(file_name: &str) {
+    RUN_STATE.lock().unwrap().in_flight = Some(file_name.to_string());
+    store::begin_file(file_name);
+}
+
+/// Marks the file that was being written (or transacted) as done
+pub fn complete(file_name: &str) {
+    let mut state = RUN_STATE.lock().unwrap();
+    state.in_flight = None;
+    state.completed.push(file_name.to_string());
+    store::complete_file(file_name);
+}
+
+pub fn add_transformed_entities(count: u64) {
+    RUN_STATE.lock().unwrap().transformed_entities += count;
+    store::add_transformed_entities(count);
+}
+
+/// Where a file about the run (e.g. its state) goes: the output directory, or the working directory
+/// when printing or transacting to a target, where a --shard names it for the shard
+pub fn run_file_path(opt: &Opt, file_name: &str) -> PathBuf {
+    match (&opt.output, opt.print, &opt.target, opt.shard) {
+        (Some(output), false, None, _) if output.is_dir() => output.join(file_name),
+        (_, _, _, Some(shard)) => shard.file_name(Path::new(file_name)),
+        _ => PathBuf::from(file_name),
+    }
+}
+
+/// On Ctrl-C, removes the temp files, writes the run state, prints what was already written, and exits,
+/// rather than leaving `.tmp` debris and an unknown amount of committed data behind. Only installed once the run
+/// holds its locks, so interrupting a read-only command leaves another run's `.tmp` and state alone
+pub fn install_interrupt_handler(opt: &Opt) {
+    let opt = opt.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        stop_run(&opt, "Interrupted");
+        std::process::exit(130);
+    });
+}
+
+/// Gives a run up on an error it can't retry past (e.g. a request that kept failing), stopping it the way Ctrl-C
+/// does when it holds its locks
+pub fn abort(opt: &Opt, reason: &str) -> ! {
+    let red_bold = Style::new().red().bold();
+    opt.pb.finish_and_clear();
+    println!("{:>12} {}", red_bold.apply_to("ERROR"), reason);
+    if !RUN_LOCKS.lock().unwrap().is_empty() {
+        stop_run(opt, "Aborted");
+    }
+    std::process::exit(1);
+}
+
+// removes the temp files, releases the locks, writes the run state, and prints what was already written
+fn stop_run(opt: &Opt, heading: &str) {
+    let yellow_bold = Style::new().yellow().bold();
+    let state = {
+        let mut state = RUN_STATE.lock().unwrap();
+        state.interrupted = true;
+        state.clone()
+    };
+    opt.pb.finish_and_clear();
+
+    let _ = std::fs::remove_dir_all(TEMP_DIRECTORY);
+    release_locks();
+    // the --state-db already holds the run's state, which is only marked interrupted
+    let (state_path, state_written) = match &opt.state_db {
+        Some(state_db) => {
+            store::interrupt();
+            (state_db.to_owned(), true)
+        }
+        None => {
+            let state_path = run_file_path(opt, STATE_FILE);
+            let state_written =
+                std::fs::write(&state_path, serde_json::to_string_pretty(&state).unwrap()).is_ok();
+            (state_path, state_written)
+        }
+    };
+
+    let destination = match opt.target.is_some() {
+        true => "transacted",
+        false => "written",
+    };
+    println!(
+        "{:>12} after {} entities were transformed and {} files were {} (last: {})",
+        yellow_bold.apply_to(heading),
+        state.transformed_entities,
+        state.completed.len(),
+        destination,
+        state.completed.last().map_or("none", String::as_str)
+    );
+    if let Some(in_flight) = &state.in_flight {
+        println!(
+            "{:>12} {} was being {} and may be incomplete",
+            yellow_bold.apply_to("WARNING"),
+            in_flight,
+            destination
+        );
+    }
+    if state_written {
+        println!(
+            "{:>12} Run state to {}",
+            yellow_bold.apply_to("Wrote"),
+            state_path.display()
+        );
+    }
+}
+
+/// SIGUSR1 pauses the load loop before its next transaction and SIGUSR2 resumes it,
+/// so a shared target can be relieved of pressure without killing the run
+#[cfg(unix)]
+pub fn install_pause_handlers() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    for (kind, paused) in [
+        (SignalKind::user_defined1(), true),
+        (SignalKind::user_defined2(), false),
+    ] {
+        let mut stream = match signal(kind) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        tokio::spawn(async move {
+            while stream.recv().await.is_some() {
+                PAUSED.store(paused, Ordering::SeqCst);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_pause_handlers() {}
+
+fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst) || Path::new(PAUSE_FILE).exists()
+}
+
+/// Waits while the run is paused by SIGUSR1 or the pause file
+pub async fn wait_while_paused(pb: &ProgressBar) {
+    if !is_paused() {
+        return;
+    }
+    let yellow_bold = Style::new().yellow().bold();
+    pb.println(format!(
+        "{:>12} before the next transaction (send SIGUSR2 or remove {} to resume)",
+        yellow_bold.apply_to("Paused"),
+        PAUSE_FILE
+    ));
+    while is_paused() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    pb.println(format!("{:>12}", yellow_bold.apply_to("Resumed")));
+}
+
+static RUN_LOCKS: Mutex<Vec<(PathBuf, File)>> = Mutex::new(vec![]);
+
+/// Holds the run's locks until it's dropped, so two simultaneous runs can't interleave transactions into the same
+/// target ledger, write into the same output directory, or clobber each other's `.tmp` files. The locks are OS
+/// advisory locks on the lock files, which the OS also releases when the process exits some other way
+pub struct RunLock;
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        release_locks();
+    }
+}
+
+// removes the lock files while they're still locked, so no other run can lock a file that's about to go away
+fn release_locks() {
+    for (path, lock_file) in RUN_LOCKS.lock().unwrap().drain(..) {
+        let _ = std::fs::remove_file(path);
+        drop(lock_file);
+    }
+}
+
+// the lock files of a run: one in the working directory for `.tmp`, and one in the system temp directory
+// keyed on the output directory or target ledger
+fn lock_paths(opt: &Opt) -> Vec<(PathBuf, String)> {
+    let working_directory = std::env::current_dir().unwrap_or_default();
+    let mut lock_paths = vec![(
+        PathBuf::from(LOCK_FILE),
+        format!("{}", working_directory.join(TEMP_DIRECTORY).display()),
+    )];
+    let destination = match (&opt.target, &opt.output, opt.print) {
+        (Some(target), _, _) => {
+            let ledger = opt
+                .ledger_name
+                .clone()
+                .or(opt.source.clone())
+                .or(opt.input.as_ref().map(|input| input.display().to_string()))
+                .unwrap_or_default();
+            // the shards of a --shard run transact into the same ledger at once, each under its own lock
+            match opt.shard {
+                Some(shard) => Some(format!(
+                    "{} {} (shard {})",
+                    target.trim_end_matches('/'),
+                    ledger,
+                    shard
+                )),
+                None => Some(format!("{} {}", target.trim_end_matches('/'), ledger)),
+            }
+        }
+        (None, Some(output), false) => {
+            Some(format!("{}", working_directory.join(output).display()))
+        }
+        _ => None,
+    };
+    if let Some(destination) = destination {
+        // named by the destination's hash, so every run derives the same lock file name from the same destination
+        let lock_path = std::env::temp_dir().join(format!(
+            "fluree-migrate-{}.lock",
+            content_hash(destination.as_bytes())
+        ));
+        lock_paths.push((lock_path, destination));
+    }
+    lock_paths
+}
+
+// whether the locked file is still the one at the lock path, rather than one another run removed (on releasing its
+// lock) after this run opened it
+#[cfg(unix)]
+fn is_lock_path_of(lock_file: &File, lock_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (lock_file.metadata(), std::fs::metadata(lock_path)) {
+        (Ok(locked), Ok(current)) => locked.dev() == current.dev() && locked.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_lock_path_of(_lock_file: &File, lock_path: &Path) -> bool {
+    lock_path.exists()
+}
+
+/// Takes the run's locks, exiting if another run holds one of them (unless `--force-unlock` is given)
+pub fn acquire_run_lock(opt: &Opt) -> RunLock {
+    let red_bold = Style::new().red().bold();
+    let yellow_bold = Style::new().yellow().bold();
+    for (lock_path, key) in lock_paths(opt) {
+        if lock_path.exists() && opt.force_unlock {
+            println!(
+                "{:>12} Removing the lock on {}",
+                yellow_bold.apply_to("WARNING"),
+                key
+            );
+            let _ = std::fs::remove_file(&lock_path);
+        }
+        let lock_file = loop {
+            let lock_file = match std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_path)
+            {
+                Ok(lock_file) => lock_file,
+                Err(e) => {
+                    println!(
+                        "{:>12} Could not open the lock file {}: {}",
+                        red_bold.apply_to("ERROR"),
+                        lock_path.display(),
+                        e
+                    );
+                    release_locks();
+                    std::process::exit(1);
+                }
+            };
+            match lock_file.try_lock() {
+                Ok(()) if is_lock_path_of(&lock_file, &lock_path) => break lock_file,
+                // the run that held the lock removed the file on releasing it, so lock the new one
+                Ok(()) => continue,
+                Err(TryLockError::WouldBlock) => {
+                    let holder = std::fs::read_to_string(&lock_path).unwrap_or_default();
+                    println!(
+                        "{:>12} Another migration holds the lock on {} ({}). If no other run is active, rerun with --force-unlock",
+                        red_bold.apply_to("ERROR"),
+                        key,
+                        holder.trim()
+                    );
+                    release_locks();
+                    std::process::exit(1);
+                }
+                Err(TryLockError::Error(e)) => {
+                    println!(
+                        "{:>12} Could not lock {}: {}",
+                        red_bold.apply_to("ERROR"),
+                        lock_path.display(),
+                        e
+                    );
+                    release_locks();
+                    std::process::exit(1);
+                }
+            }
+        };
+        // the lock file names the run holding it, for the error of a run that can't take it
+        let contents = serde_json::json!({
+            "pid": std::process::id(),
+            "startedAt": chrono::Utc::now().to_rfc3339(),
+            "lock": key,
+        });
+        let _ = lock_file.set_len(0);
+        let _ = (&lock_file).write_all(contents.to_string().as_bytes());
+        RUN_LOCKS.lock().unwrap().push((lock_path, lock_file));
+    }
+    RunLock
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::extract::CollectionCheckpoint;
+use crate::id_ranges::IdRanges;
+use crate::redact::redact;
+use crate::trace;
+
+// the tables of the state database, created when it's opened. Every run is keyed by its correlation ID, and
+// records the --shard it migrated, if any
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    correlation_id TEXT PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    shard TEXT,
+    interrupted INTEGER NOT NULL DEFAULT 0,
+    transformed_entities INTEGER NOT NULL DEFAULT 0,
+    in_flight TEXT
+);
+CREATE TABLE IF NOT EXISTS run_files (
+    correlation_id TEXT NOT NULL,
+    file TEXT NOT NULL,
+    completed_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS uploads (
+    ledger TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    uploaded_at TEXT NOT NULL,
+    PRIMARY KEY (ledger, hash)
+);
+CREATE TABLE IF NOT EXISTS extract_snapshots (
+    directory TEXT PRIMARY KEY,
+    snapshot TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS extract_collections (
+    directory TEXT NOT NULL,
+    collection TEXT NOT NULL,
+    next_offset INTEGER NOT NULL,
+    entities INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    complete INTEGER NOT NULL,
+    PRIMARY KEY (directory, collection)
+);
+CREATE TABLE IF NOT EXISTS extracted_ids (
+    directory TEXT NOT NULL,
+    collection TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    PRIMARY KEY (directory, collection, id)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS entity_hashes (
+    destination TEXT NOT NULL,
+    id TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    PRIMARY KEY (destination, id)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS findings (
+    correlation_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    finding TEXT NOT NULL
+);
+";
+
+// the tables of the run itself, which aren't kept in the database: the `_id`s the fetch tasks fetched of each
+// collection, and the entities a --changed-only run found unchanged or wrote, by destination
+const RUN_SCHEMA: &str = "
+CREATE TEMP TABLE IF NOT EXISTS fetched_ids (
+    collection TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    PRIMARY KEY (collection, id)
+) WITHOUT ROWID;
+CREATE TEMP TABLE IF NOT EXISTS seen_entities (
+    destination TEXT NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (destination, id)
+) WITHOUT ROWID;
+";
+
+static STORE: Mutex<Option<Connection>> = Mutex::new(None);
+
+/// With `--state-db`, opens (or creates) the SQLite database the run keeps its state in, exiting if it can't be
+pub fn start(opt: &Opt) {
+    let Some(path) = &opt.state_db else {
+        return;
+    };
+    let opened = Connection::open(path).and_then(|connection| {
+        // the shards of a --shard run may share the database, so a write waits for another's to finish
+        connection.busy_timeout(Duration::from_secs(30))?;
+        // a write-ahead log lets the database be read (e.g. with the sqlite3 shell) while a run writes to it
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch(RUN_SCHEMA)?;
+        connection.execute(
+            "INSERT OR IGNORE INTO runs (correlation_id, started_at, shard) VALUES (?1, ?2, ?3)",
+            params![
+                trace::correlation_id(),
+                chrono::Utc::now().to_rfc3339(),
+                opt.shard.map(|shard| shard.to_string())
+            ],
+        )?;
+        Ok(connection)
+    });
+    match opened {
+        Ok(connection) => *STORE.lock().unwrap() = Some(connection),
+        Err(e) => {
+            pretty_print(
+                &format!("Could not open state database {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Whether the run keeps its state in a `--state-db`
+pub fn is_started() -> bool {
+    STORE.lock().unwrap().is_some()
+}
+
+// runs a statement (or several, in a transaction) against the database, if it's open. A failed write is reported
+// rather than ending the run, as the ad-hoc state files were
+fn with_store<T: Default>(
+    description: &str,
+    f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>,
+) -> T {
+    let mut store = STORE.lock().unwrap();
+    let Some(connection) = store.as_mut() else {
+        return T::default();
+    };
+    f(connection).unwrap_or_else(|e| {
+        pretty_print(
+            &format!("Could not {} in the state database: {}", description, e),
+            ERROR_COLOR,
+            true,
+        );
+        T::default()
+    })
+}
+
+/// Records the file being written (or transacted) by the run
+pub fn begin_file(file_name: &str) {
+    with_store("record the file in flight", |connection| {
+        connection.execute(
+            "UPDATE runs SET in_flight = ?2 WHERE correlation_id = ?1",
+            params![trace::correlation_id(), file_name],
+        )?;
+        Ok(())
+    })
+}
+
+/// Records a file the run finished writing (or transacting)
+pub fn complete_file(file_name: &str) {
+    with_store("record the completed file", |connection| {
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "UPDATE runs SET in_flight = NULL WHERE correlation_id = ?1",
+            params![trace::correlation_id()],
+        )?;
+        transaction.execute(
+            "INSERT INTO run_files (correlation_id, file, completed_at) VALUES (?1, ?2, ?3)",
+            params![
+                trace::correlation_id(),
+                file_name,
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )?;
+        transaction.commit()
+    })
+}
+
+pub fn add_transformed_entities(count: u64) {
+    with_store("count the transformed entities", |connection| {
+        connection.execute(
+            "UPDATE runs SET transformed_entities = transformed_entities + ?2 WHERE correlation_id = ?1",
+            params![trace::correlation_id(), count as i64],
+        )?;
+        Ok(())
+    })
+}
+
+/// Marks the run as interrupted
+pub fn interrupt() {
+    with_store("record the interruption", |connection| {
+        connection.execute(
+            "UPDATE runs SET interrupted = 1 WHERE correlation_id = ?1",
+            params![trace::correlation_id()],
+        )?;
+        Ok(())
+    })
+}
+
+/// Whether a file with this content hash was already transacted to the ledger (keyed by its target URL)
+pub fn is_uploaded(ledger: &str, hash: &str) -> bool {
+    with_store("look up the upload", |connection| {
+        connection
+            .query_row(
+                "SELECT 1 FROM uploads WHERE ledger = ?1 AND hash = ?2",
+                params![ledger, hash],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|row| row.is_some())
+    })
+}
+
+pub fn record_upload(ledger: &str, hash: &str) {
+    with_store("record the upload", |connection| {
+        connection.execute(
+            "INSERT OR IGNORE INTO uploads (ledger, hash, uploaded_at) VALUES (?1, ?2, ?3)",
+            params![ledger, hash, chrono::Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    })
+}
+
+pub fn forget_uploads(ledger: &str) {
+    with_store("forget the uploads", |connection| {
+        connection.execute("DELETE FROM uploads WHERE ledger = ?1", params![ledger])?;
+        Ok(())
+    })
+}
+
+fn directory_key(directory: &Path) -> String {
+    std::path::absolute(directory)
+        .unwrap_or_else(|_| directory.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// The snapshot (source, --block or --as-of, and --where) of the extract the database has progress of for the
+/// directory, if any
+pub fn extract_snapshot(directory: &Path) -> Option<Value> {
+    with_store("read the extract's snapshot", |connection| {
+        connection
+            .query_row(
+                "SELECT snapshot FROM extract_snapshots WHERE directory = ?1",
+                params![directory_key(directory)],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map(|snapshot| snapshot.and_then(|snapshot| serde_json::from_str(&snapshot).ok()))
+    })
+}
+
+/// Starts recording the progress of an extract of the snapshot into the directory, forgetting any earlier one's
+pub fn start_extract(directory: &Path, snapshot: &Value) {
+    let directory = directory_key(directory);
+    with_store("start the extract", |connection| {
+        let transaction = connection.transaction()?;
+        for table in ["extract_snapshots", "extract_collections", "extracted_ids"] {
+            transaction.execute(
+                &format!("DELETE FROM {} WHERE directory = ?1", table),
+                params![directory],
+            )?;
+        }
+        transaction.execute(
+            "INSERT INTO extract_snapshots (directory, snapshot) VALUES (?1, ?2)",
+            params![directory, snapshot.to_string()],
+        )?;
+        transaction.commit()
+    })
+}
+
+/// How far the extract into the directory got in fetching each collection
+pub fn extract_progress(directory: &Path) -> Vec<(String, CollectionCheckpoint)> {
+    with_store("read the extract's progress", |connection| {
+        let mut statement = connection.prepare(
+            "SELECT collection, next_offset, entities, bytes, complete FROM extract_collections
+             WHERE directory = ?1",
+        )?;
+        let rows = statement.query_map(params![directory_key(directory)], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                CollectionCheckpoint {
+                    offset: row.get::<_, i64>(1)? as u64,
+                    entities: row.get::<_, i64>(2)? as u64,
+                    bytes: row.get::<_, i64>(3)? as u64,
+                    complete: row.get(4)?,
+                },
+            ))
+        })?;
+        rows.collect()
+    })
+}
+
+/// The ids of a collection's entities the extract into the directory already wrote
+pub fn extracted_ids(directory: &Path, collection: &str) -> IdRanges {
+    with_store("read the extracted ids", |connection| {
+        let mut statement = connection.prepare(
+            "SELECT id FROM extracted_ids WHERE directory = ?1 AND collection = ?2 ORDER BY id",
+        )?;
+        let mut ids = IdRanges::default();
+        for id in statement.query_map(params![directory_key(directory), collection], |row| {
+            row.get::<_, i64>(0)
+        })? {
+            ids.insert(id?);
+        }
+        Ok(ids)
+    })
+}
+
+/// Records the ids of the entities of a page the extract wrote, and how far it got, at once, so the progress
+/// and the ids can't disagree
+pub fn save_extract_page(
+    directory: &Path,
+    collection: &str,
+    ids: &[i64],
+    progress: &CollectionCheckpoint,
+) {
+    let directory = directory_key(directory);
+    with_store("save the extract's progress", |connection| {
+        let transaction = connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO extracted_ids (directory, collection, id) VALUES (?1, ?2, ?3)",
+            )?;
+            for id in ids {
+                insert.execute(params![directory, collection, id])?;
+            }
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO extract_collections
+             (directory, collection, next_offset, entities, bytes, complete) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                directory,
+                collection,
+                progress.offset as i64,
+                progress.entities as i64,
+                progress.bytes as i64,
+                progress.complete
+            ],
+        )?;
+        transaction.commit()
+    })
+}
+
+/// Forgets the progress of the extract into the directory, once it completes
+pub fn finish_extract(directory: &Path) {
+    let directory = directory_key(directory);
+    with_store("finish the extract", |connection| {
+        let transaction = connection.transaction()?;
+        for table in ["extract_snapshots", "extract_collections", "extracted_ids"] {
+            transaction.execute(
+                &format!("DELETE FROM {} WHERE directory = ?1", table),
+                params![directory],
+            )?;
+        }
+        transaction.commit()
+    })
+}
+
+/// The content hash of the entity the previous `--changed-only` runs migrated to the destination, if any
+pub fn entity_hash(destination: &str, id: &str) -> Option<String> {
+    with_store("read the entity hash", |connection| {
+        connection
+            .prepare_cached("SELECT hash FROM entity_hashes WHERE destination = ?1 AND id = ?2")?
+            .query_row(params![destination, id], |row| row.get(0))
+            .optional()
+    })
+}
+
+/// Records that the run found the entity unchanged since the previous run, so it isn't counted as missing
+pub fn see_entity(destination: &str, id: &str) {
+    with_store("record the unchanged entity", |connection| {
+        connection
+            .prepare_cached(
+                "INSERT OR IGNORE INTO temp.seen_entities (destination, id) VALUES (?1, ?2)",
+            )?
+            .execute(params![destination, id])
+            .map(|_| ())
+    })
+}
+
+/// Records the content hashes of the entities of a chunk that was written (or transacted), in place of the
+/// previous runs' hashes of the same entities. The hashes of other entities are left as they are
+pub fn save_entity_hashes(destination: &str, hashes: &HashMap<String, String>) {
+    with_store("save the entity hashes", |connection| {
+        let transaction = connection.transaction()?;
+        {
+            let mut upsert = transaction.prepare_cached(
+                "INSERT OR REPLACE INTO entity_hashes (destination, id, hash) VALUES (?1, ?2, ?3)",
+            )?;
+            let mut see = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO temp.seen_entities (destination, id) VALUES (?1, ?2)",
+            )?;
+            for (id, hash) in hashes {
+                upsert.execute(params![destination, id, hash])?;
+                see.execute(params![destination, id])?;
+            }
+        }
+        transaction.commit()
+    })
+}
+
+/// The number of entities previous runs migrated to the destination that this run neither found unchanged nor wrote
+pub fn missing_entities(destination: &str) -> usize {
+    with_store("count the missing entities", |connection| {
+        connection.query_row(
+            "SELECT COUNT(*) FROM entity_hashes WHERE destination = ?1
+             AND id NOT IN (SELECT id FROM temp.seen_entities WHERE destination = ?1)",
+            params![destination],
+            |row| row.get::<_, i64>(0),
+        )
+    }) as usize
+}
+
+/// Forgets the `_id`s an earlier migration of the run (e.g. of another ledger of a batch) fetched
+pub fn clear_fetched_ids() {
+    with_store("clear the fetched ids", |connection| {
+        connection
+            .execute("DELETE FROM temp.fetched_ids", [])
+            .map(|_| ())
+    })
+}
+
+/// Records the `_id`s of a page fetched of the collection, returning how many of them weren't fetched before
+pub fn record_fetched_ids(collection: &str, ids: &[i64]) -> usize {
+    with_store("record the fetched ids", |connection| {
+        let transaction = connection.transaction()?;
+        let mut recorded = 0;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO temp.fetched_ids (collection, id) VALUES (?1, ?2)",
+            )?;
+            for id in ids {
+                recorded += insert.execute(params![collection, id])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(recorded)
+    })
+}
+
+/// The `_id`s fetched under more than one collection
+pub fn shared_fetched_ids() -> IdRanges {
+    with_store("read the shared fetched ids", |connection| {
+        let mut statement = connection.prepare(
+            "SELECT id FROM temp.fetched_ids GROUP BY id HAVING COUNT(*) > 1 ORDER BY id",
+        )?;
+        let mut ids = IdRanges::default();
+        for id in statement.query_map([], |row| row.get::<_, i64>(0))? {
+            ids.insert(id?);
+        }
+        Ok(ids)
+    })
+}
+
+/// Records each finding of the run's report (e.g. a dangling reference or an unsupported feature) as a row of
+/// its kind, holding the finding as JSON, with any secrets redacted
+pub fn record_findings<T: Serialize>(report: &T) {
+    if !is_started() {
+        return;
+    }
+    let Ok(Value::Object(report)) = serde_json::to_value(report) else {
+        return;
+    };
+    with_store("record the findings", |connection| {
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM findings WHERE correlation_id = ?1",
+            params![trace::correlation_id()],
+        )?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO findings (correlation_id, kind, finding) VALUES (?1, ?2, ?3)",
+            )?;
+            for (kind, findings) in &report {
+                for finding in findings.as_array().into_iter().flatten() {
+                    insert.execute(params![
+                        trace::correlation_id(),
+                        kind,
+                        redact(&finding.to_string())
+                    ])?;
+                }
+            }
+        }
+        transaction.commit()
+    })
+}
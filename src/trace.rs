@@ -0,0 +1,250 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Local, NaiveDate};
+
+use reqwest::header::HeaderMap;
+use reqwest::{Error, Response};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::cassette;
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::redact::{redact, redact_header};
+
+/// The log file in the working directory when --log-file isn't given
+pub const LOG_FILE: &str = "fluree-migrate.log";
+
+/// The User-Agent every request is sent with
+pub const USER_AGENT: &str = concat!("fluree-migrate/", env!("CARGO_PKG_VERSION"));
+
+/// The header every request carries the run's correlation ID in, so the server's logs of a run can be found
+pub const CORRELATION_ID_HEADER: &str = "X-Correlation-ID";
+
+static CORRELATION_ID: OnceLock<String> = OnceLock::new();
+
+// request and response bodies longer than this are cut in the log, e.g. a 10MB data transaction
+const MAX_BODY_LENGTH: usize = 2000;
+
+/// When `--log-rotate` starts a new log file: once the log reaches a number of bytes, or on a new day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogRotation {
+    Size(u64),
+    Daily,
+}
+
+impl FromStr for LogRotation {
+    type Err = String;
+
+    /// "daily", or a size such as "500KB", "100MB", or "1GB" (a bare number is bytes)
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let rotation = value.trim().to_uppercase();
+        if rotation == "DAILY" {
+            return Ok(LogRotation::Daily);
+        }
+        let digits = rotation
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rotation.len());
+        let multiplier = match rotation[digits..].trim() {
+            "" | "B" => 1,
+            "KB" => 1024,
+            "MB" => 1024 * 1024,
+            "GB" => 1024 * 1024 * 1024,
+            _ => {
+                return Err(format!(
+                    "Expected \"daily\" or a size like 100MB, not \"{}\"",
+                    value
+                ))
+            }
+        };
+        match rotation[..digits].parse::<u64>() {
+            Ok(size) if size > 0 => Ok(LogRotation::Size(size * multiplier)),
+            _ => Err(format!(
+                "Expected \"daily\" or a size like 100MB, not \"{}\"",
+                value
+            )),
+        }
+    }
+}
+
+// the open log file, and what --log-rotate needs to know to rotate it
+struct Log {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    day: NaiveDate,
+    rotation: Option<LogRotation>,
+    keep: usize,
+}
+
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+
+/// With `--trace-http`, opens the log file (appending to it), exiting if it can't be
+pub fn start(opt: &Opt) {
+    if !opt.trace_http {
+        return;
+    }
+    let path = opt.log_file.to_owned().unwrap_or_else(|| LOG_FILE.into());
+    match open(&path) {
+        Ok(file) => {
+            let metadata = file.metadata().ok();
+            // a log last written to on an earlier day is rotated by the first line of a daily-rotated run
+            let day = metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok())
+                .map(|modified| DateTime::<Local>::from(modified).date_naive())
+                .unwrap_or_else(|| Local::now().date_naive());
+            *LOG.lock().unwrap() = Some(Log {
+                path,
+                file,
+                size: metadata.map(|metadata| metadata.len()).unwrap_or(0),
+                day,
+                rotation: opt.log_rotate,
+                keep: opt.log_keep,
+            })
+        }
+        Err(e) => {
+            pretty_print(
+                &format!("Could not open log file {}: {}", path.display(), e),
+                ERROR_COLOR,
+                true,
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The run's correlation ID, a random UUID, which is the same for every request of the run (and every ledger of a
+/// batch) and is printed when it finishes
+pub fn correlation_id() -> &'static str {
+    CORRELATION_ID.get_or_init(|| {
+        let mut bytes = [0u8; 16];
+        SystemRandom::new().fill(&mut bytes).unwrap();
+        // version 4, variant 1
+        bytes[6] = bytes[6] & 0x0f | 0x40;
+        bytes[8] = bytes[8] & 0x3f | 0x80;
+        let hex = bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    })
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Logs an outgoing request, with the values of its credentials headers and any secrets redacted
+pub fn request(method: &str, url: &str, headers: &HeaderMap, body: &str) {
+    if LOG.lock().unwrap().is_none() {
+        return;
+    }
+    let headers = headers
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}: {}",
+                name,
+                redact_header(name.as_str(), value.to_str().unwrap_or("[binary]"))
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    log(&format!(
+        "--> {} {} [{}]\n{}",
+        method,
+        url,
+        headers,
+        truncate_body(body)
+    ));
+}
+
+/// Logs the response to a request to `url` (or the error it got), returning an equivalent result
+pub async fn response(url: &str, result: Result<Response, Error>) -> Result<Response, Error> {
+    if LOG.lock().unwrap().is_none() {
+        return result;
+    }
+    let traced = match result {
+        Ok(traced) => traced,
+        Err(e) => {
+            log(&format!("<-- {} failed: {}", url, e));
+            return Err(e);
+        }
+    };
+    let status = traced.status().as_u16();
+    let text = traced.text().await?;
+    log(&format!("<-- {} {}\n{}", status, url, truncate_body(&text)));
+    Ok(cassette::response(status, text))
+}
+
+fn truncate_body(body: &str) -> String {
+    match body.char_indices().nth(MAX_BODY_LENGTH) {
+        Some((index, _)) => format!(
+            "{}... ({} bytes truncated)",
+            &body[..index],
+            body.len() - index
+        ),
+        None => body.to_string(),
+    }
+}
+
+fn log(message: &str) {
+    let mut log = LOG.lock().unwrap();
+    let Some(log) = log.as_mut() else {
+        return;
+    };
+    let now = Local::now();
+    let line = format!(
+        "[{}] {}\n",
+        now.format("%Y-%m-%dT%H:%M:%S%.3f"),
+        redact(message)
+    );
+    let is_due = match log.rotation {
+        Some(LogRotation::Size(size)) => log.size > 0 && log.size + line.len() as u64 > size,
+        Some(LogRotation::Daily) => log.size > 0 && log.day != now.date_naive(),
+        None => false,
+    };
+    if is_due {
+        log.rotate();
+    }
+    log.day = now.date_naive();
+    if log.file.write_all(line.as_bytes()).is_ok() {
+        log.size += line.len() as u64;
+    }
+}
+
+impl Log {
+    // renames the log to <log>.1, shifting earlier rotations up to <log>.<keep> (deleting the oldest), and
+    // starts a new one. If the log can't be reopened, logging carries on in the renamed file
+    fn rotate(&mut self) {
+        let rotated = |index: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", index));
+            PathBuf::from(path)
+        };
+        if self.keep == 0 {
+            let _ = std::fs::remove_file(&self.path);
+        } else {
+            let _ = std::fs::remove_file(rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let _ = std::fs::rename(rotated(index), rotated(index + 1));
+            }
+            let _ = std::fs::rename(&self.path, rotated(1));
+        }
+        if let Ok(file) = open(&self.path) {
+            self.file = file;
+            self.size = 0;
+        }
+    }
+}
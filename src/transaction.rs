@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{json, Map, Value};
+
+/// Turns the bare `insert` of a data transaction into the full `where`/`delete`/`insert` form, which first
+/// retracts the current values of every property it writes, so a re-run replaces values instead of adding to them
+pub fn replace_existing_values(transaction: &mut Map<String, Value>) {
+    replace_values_of(transaction, |_| true);
+}
+
+/// Like `replace_existing_values`, but only for the nodes of the given classes (e.g. ones keyed by v2 `upsert`
+/// predicates), so their values are replaced the way a v2 upsert replaced them
+pub fn upsert_nodes(transaction: &mut Map<String, Value>, classes: &HashSet<String>) {
+    replace_values_of(transaction, |node| is_of_classes(node, classes));
+}
+
+/// Merges the nodes of one subject fetched under several v2 collections into a node with each of their `@type`s,
+/// and the `@id` of the first. A property several of them hold gets all of their values
+pub fn merge_typed_nodes(nodes: Vec<Value>) -> Value {
+    let values_of = |value: Value| match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    let mut merged = Map::new();
+    let mut types: Vec<Value> = vec![];
+    for node in nodes {
+        let Value::Object(node) = node else {
+            continue;
+        };
+        for (key, value) in node {
+            if key == "@id" && merged.contains_key("@id") {
+                continue;
+            }
+            if key == "@type" {
+                for type_ in values_of(value) {
+                    if !types.contains(&type_) {
+                        types.push(type_);
+                    }
+                }
+                continue;
+            }
+            let value = match merged.remove(&key) {
+                None => value,
+                Some(existing) if existing == value => existing,
+                Some(existing) => {
+                    let mut values = values_of(existing);
+                    for value in values_of(value) {
+                        if !values.contains(&value) {
+                            values.push(value);
+                        }
+                    }
+                    Value::Array(values)
+                }
+            };
+            merged.insert(key, value);
+        }
+    }
+    let types = match types.len() {
+        1 => types.remove(0),
+        _ => Value::Array(types),
+    };
+    merged.insert("@type".to_string(), types);
+    Value::Object(merged)
+}
+
+/// Merges the nodes of the given classes that share an `@id` (e.g. entities deduplicated by a v2 `upsert` key)
+/// into the first of them, a later node's values replacing the earlier's
+pub fn merge_upserted_nodes(transaction: &mut Map<String, Value>, classes: &HashSet<String>) {
+    let Some(Value::Array(nodes)) = transaction.get_mut("insert") else {
+        return;
+    };
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut merged: Vec<Value> = vec![];
+    for node in nodes.drain(..) {
+        let id = match (node["@id"].as_str(), is_of_classes(&node, classes)) {
+            (Some(id), true) => id.to_string(),
+            _ => {
+                merged.push(node);
+                continue;
+            }
+        };
+        match positions.get(&id) {
+            Some(&position) => {
+                if let (Value::Object(first), Value::Object(node)) = (&mut merged[position], node) {
+                    first.extend(node);
+                }
+            }
+            None => {
+                positions.insert(id, merged.len());
+                merged.push(node);
+            }
+        }
+    }
+    *nodes = merged;
+}
+
+fn is_of_classes(node: &Value, classes: &HashSet<String>) -> bool {
+    match &node["@type"] {
+        Value::String(class) => classes.contains(class),
+        Value::Array(types) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|class| classes.contains(class)),
+        _ => false,
+    }
+}
+
+fn replace_values_of(transaction: &mut Map<String, Value>, is_replaced: impl Fn(&Value) -> bool) {
+    let Some(Value::Array(nodes)) = transaction.get("insert") else {
+        return;
+    };
+    // values of @reverse terms belong to other subjects, whose own transactions replace them
+    let is_reverse_term = |term: &str| {
+        transaction
+            .get("@context")
+            .and_then(|context| context.get(term))
+            .is_some_and(|definition| definition.get("@reverse").is_some())
+    };
+    let mut patterns = vec![];
+    let mut retractions = vec![];
+    for node in nodes
+        .iter()
+        .filter(|node| is_replaced(node))
+        .filter_map(Value::as_object)
+    {
+        let Some(id) = node.get("@id") else {
+            continue;
+        };
+        let mut retraction = Map::new();
+        for term in node
+            .keys()
+            .filter(|term| !term.starts_with('@') && !is_reverse_term(term))
+        {
+            let variable = format!("?v{}", patterns.len());
+            patterns.push(json!({ "@id": id, term: variable }));
+            retraction.insert(term.to_owned(), Value::String(variable));
+        }
+        if !retraction.is_empty() {
+            retraction.insert("@id".to_string(), id.to_owned());
+            retractions.push(Value::Object(retraction));
+        }
+    }
+    if patterns.is_empty() {
+        return;
+    }
+    transaction.insert("where".to_string(), retraction_where(patterns));
+    transaction.insert("delete".to_string(), Value::Array(retractions));
+}
+
+/// The `where` matching the current values of each `{ "@id": <subject>, <term>: <variable> }` pattern. They're
+/// matched as the branches of a union, so every value is its own solution instead of each pattern's values being
+/// joined with every other's (a multi-valued property of many subjects would otherwise make a cartesian product),
+/// and the union is optional so a transaction of new subjects still has the one solution its insert needs
+fn retraction_where(mut patterns: Vec<Value>) -> Value {
+    let matched = match patterns.len() {
+        1 => patterns.remove(0),
+        _ => {
+            let mut union = vec![Value::from("union")];
+            union.extend(patterns);
+            json!([union])
+        }
+    };
+    json!([["optional", matched]])
+}
+
+/// The patterns of a `where` made by `retraction_where`
+fn retraction_patterns(where_: &Value) -> Vec<Value> {
+    match &where_[0][1] {
+        Value::Array(matched) => matched[0]
+            .as_array()
+            .map(|union| union[1..].to_vec())
+            .unwrap_or_default(),
+        pattern => vec![pattern.to_owned()],
+    }
+}
+
+/// Merges `--txn-opts` into a transaction's `opts`, e.g. so its commit carries a message and author
+pub fn merge_txn_opts(transaction: &mut Map<String, Value>, txn_opts: &Map<String, Value>) {
+    let opts = transaction
+        .entry("opts".to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(opts) = opts {
+        for (key, value) in txn_opts {
+            opts.insert(key.to_owned(), value.to_owned());
+        }
+    }
+}
+
+/// Splits a transaction into two, each inserting half of its nodes and retracting the values of its own subjects
+/// (the `where`/`delete` of `replace_existing_values`). Transactions whose nodes can't be halved without a
+/// subject's values being retracted by both halves aren't split
+pub fn split_transaction(transaction: &Map<String, Value>) -> Option<(Value, Value)> {
+    let nodes = transaction.get("insert")?.as_array()?;
+    if nodes.len() < 2 {
+        return None;
+    }
+    let (first, second) = nodes.split_at(nodes.len() / 2);
+    let ids_of = |nodes: &[Value]| {
+        nodes
+            .iter()
+            .filter_map(|node| node["@id"].as_str().map(str::to_string))
+            .collect::<HashSet<String>>()
+    };
+    let (first_ids, second_ids) = (ids_of(first), ids_of(second));
+    let is_retracted = |id: &str| {
+        transaction
+            .get("delete")
+            .and_then(Value::as_array)
+            .is_some_and(|retractions| retractions.iter().any(|retraction| retraction["@id"] == id))
+    };
+    if first_ids
+        .intersection(&second_ids)
+        .any(|id| is_retracted(id))
+    {
+        return None;
+    }
+    let half = |nodes: &[Value], ids: &HashSet<String>| {
+        let is_own = |id: &Value| id.as_str().is_some_and(|id| ids.contains(id));
+        let mut half = transaction.to_owned();
+        half.insert("insert".to_string(), Value::Array(nodes.to_vec()));
+        if let Some(where_) = half.remove("where") {
+            let patterns: Vec<Value> = retraction_patterns(&where_)
+                .into_iter()
+                .filter(|pattern| is_own(&pattern["@id"]))
+                .collect();
+            if patterns.is_empty() {
+                half.remove("delete");
+            } else {
+                half.insert("where".to_string(), retraction_where(patterns));
+            }
+        }
+        if let Some(Value::Array(retractions)) = half.get_mut("delete") {
+            retractions.retain(|retraction| is_own(&retraction["@id"]));
+        }
+        Value::Object(half)
+    };
+    Some((half(first, &first_ids), half(second, &second_ids)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(insert: Value) -> Map<String, Value> {
+        let Value::Object(transaction) = json!({ "ledger": "test/ledger", "insert": insert })
+        else {
+            unreachable!()
+        };
+        transaction
+    }
+
+    #[test]
+    fn replaces_a_single_value() {
+        let mut txn =
+            transaction(json!([{ "@id": "person/1", "@type": "Person", "name": "Jane" }]));
+        replace_existing_values(&mut txn);
+        assert_eq!(
+            txn["where"],
+            json!([["optional", { "@id": "person/1", "name": "?v0" }]])
+        );
+        assert_eq!(txn["delete"], json!([{ "@id": "person/1", "name": "?v0" }]));
+    }
+
+    #[test]
+    fn matches_each_value_of_multi_valued_properties_separately() {
+        let mut txn = transaction(json!([
+            { "@id": "person/1", "name": "Jane", "nickname": ["J", "Janie"] },
+            { "@id": "person/2", "name": "John", "nickname": ["Jo", "Johnny"] }
+        ]));
+        replace_existing_values(&mut txn);
+        assert_eq!(
+            txn["where"],
+            json!([["optional", [["union",
+                { "@id": "person/1", "name": "?v0" },
+                { "@id": "person/1", "nickname": "?v1" },
+                { "@id": "person/2", "name": "?v2" },
+                { "@id": "person/2", "nickname": "?v3" }
+            ]]]])
+        );
+        assert_eq!(
+            txn["delete"],
+            json!([
+                { "@id": "person/1", "name": "?v0", "nickname": "?v1" },
+                { "@id": "person/2", "name": "?v2", "nickname": "?v3" }
+            ])
+        );
+    }
+
+    #[test]
+    fn splits_the_retractions_with_their_subjects() {
+        let mut txn = transaction(json!([
+            { "@id": "person/1", "name": "Jane", "nickname": ["J", "Janie"] },
+            { "@id": "person/2", "name": "John" }
+        ]));
+        replace_existing_values(&mut txn);
+        let (first, second) = split_transaction(&txn).unwrap();
+        assert_eq!(
+            first["where"],
+            json!([["optional", [["union",
+                { "@id": "person/1", "name": "?v0" },
+                { "@id": "person/1", "nickname": "?v1" }
+            ]]]])
+        );
+        assert_eq!(
+            first["delete"],
+            json!([{ "@id": "person/1", "name": "?v0", "nickname": "?v1" }])
+        );
+        assert_eq!(
+            second["where"],
+            json!([["optional", { "@id": "person/2", "name": "?v2" }]])
+        );
+        assert_eq!(
+            second["delete"],
+            json!([{ "@id": "person/2", "name": "?v2" }])
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::state as run_state;
+use crate::store;
+
+/// The file, in the working directory, where `--content-addressed` runs record the content hashes of the files
+/// transacted to each target ledger (unless they're recorded in the --state-db)
+pub const UPLOADS_FILE: &str = ".fluree-migrate-uploads.json";
+
+// the hashes of the files transacted to each ledger, keyed by the target URL and ledger name
+type Uploads = BTreeMap<String, BTreeSet<String>>;
+
+fn key(opt: &Opt, ledger: &str) -> String {
+    format!("{} {}", opt.target.as_deref().unwrap_or_default(), ledger)
+}
+
+fn read(opt: &Opt) -> Uploads {
+    std::fs::read_to_string(run_state::run_file_path(opt, UPLOADS_FILE))
+        .ok()
+        .and_then(|uploads| serde_json::from_str(&uploads).ok())
+        .unwrap_or_default()
+}
+
+fn write(opt: &Opt, uploads: &Uploads) {
+    let path = run_state::run_file_path(opt, UPLOADS_FILE);
+    if let Err(e) = std::fs::write(&path, serde_json::to_string_pretty(uploads).unwrap()) {
+        pretty_print(
+            &format!("Could not record the upload in {}: {}", path.display(), e),
+            ERROR_COLOR,
+            true,
+        );
+    }
+}
+
+/// Whether a file with this content hash was already transacted to the ledger
+pub fn is_uploaded(opt: &Opt, ledger: &str, hash: &str) -> bool {
+    if store::is_started() {
+        return store::is_uploaded(&key(opt, ledger), hash);
+    }
+    read(opt)
+        .get(&key(opt, ledger))
+        .is_some_and(|hashes| hashes.contains(hash))
+}
+
+/// Records that a file with this content hash was transacted to the ledger
+pub fn record(opt: &Opt, ledger: &str, hash: &str) {
+    if store::is_started() {
+        return store::record_upload(&key(opt, ledger), hash);
+    }
+    let mut uploads = read(opt);
+    uploads
+        .entry(key(opt, ledger))
+        .or_default()
+        .insert(hash.to_string());
+    write(opt, &uploads);
+}
+
+/// Forgets the files transacted to a ledger, when it's created anew
+pub fn forget(opt: &Opt, ledger: &str) {
+    if store::is_started() {
+        return store::forget_uploads(&key(opt, ledger));
+    }
+    let mut uploads = read(opt);
+    if uploads.remove(&key(opt, ledger)).is_some() {
+        write(opt, &uploads);
+    }
+}
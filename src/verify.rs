@@ -0,0 +1,315 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+use dialoguer::console::Style;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::canonical::{document_quads, Quad, Term, RDF_TYPE};
+use crate::cli::opt::Opt;
+use crate::console::{pretty_print, ERROR_COLOR};
+use crate::fluree::FlureeInstance;
+use crate::functions::{read_json_file, sha256_hex};
+use crate::state;
+
+/// The file, in the output directory (or the working directory), where a run records the hash of the data it produced
+pub const MANIFEST_FILE: &str = "dataset-hash.json";
+
+const VERIFY_PAGE_SIZE: usize = 10_000;
+
+const NUMERIC_DATATYPES: [&str; 7] = ["integer", "long", "int", "short", "byte", "double", "float"];
+
+/// An order-independent hash of a set of RDF statements: the sum, modulo 2^256, of the SHA-256 of each
+/// statement's N-Quads line, so data hashed chunk by chunk as it's produced matches data hashed page by page
+/// from the target
+#[derive(Debug, Default, Clone)]
+pub struct DatasetHash {
+    // little-endian 64-bit limbs
+    sum: [u64; 4],
+    statements: u64,
+    skipped_statements: u64,
+    classes: BTreeSet<String>,
+    // the subjects of the documents added so far, when they're tracked
+    subjects: Option<HashSet<String>>,
+    repeated_subjects: u64,
+}
+
+impl DatasetHash {
+    /// A hash of a run's output documents, which tracks their subjects: a statement is only deduplicated within
+    /// its document, so one repeated in another (e.g. of an `_id` fetched under several collections without
+    /// --merge-collections) would be added twice to the sum, though the target holds it once
+    pub fn of_documents() -> Self {
+        DatasetHash {
+            subjects: Some(HashSet::new()),
+            ..Default::default()
+        }
+    }
+
+    /// Adds the statements of an output document. Statements about blank nodes can't be matched with the
+    /// target's, so they're counted but not hashed
+    pub fn add_document(&mut self, document: &Map<String, Value>) {
+        let quads = document_quads(document);
+        let mut seen = HashSet::new();
+        for quad in quads.iter().filter(|quad| seen.insert(*quad)) {
+            self.add(quad);
+        }
+        if let Some(subjects) = &mut self.subjects {
+            let document_subjects = quads
+                .iter()
+                .filter_map(|quad| match &quad.subject {
+                    Term::Iri(subject) => Some(subject),
+                    _ => None,
+                })
+                .collect::<HashSet<&String>>();
+            for subject in document_subjects {
+                if !subjects.insert(subject.to_owned()) {
+                    self.repeated_subjects += 1;
+                }
+            }
+        }
+    }
+
+    /// The number of subjects with statements in more than one of the added documents, whose statements may have
+    /// been added more than once, so the hash may not match the target's
+    pub fn repeated_subjects(&self) -> u64 {
+        self.repeated_subjects
+    }
+
+    fn add(&mut self, quad: &Quad) {
+        if quad.has_blank_nodes() {
+            self.skipped_statements += 1;
+            return;
+        }
+        if let (RDF_TYPE, Term::Iri(class)) = (quad.predicate.as_str(), &quad.object) {
+            self.classes.insert(class.to_owned());
+        }
+        let digest = sha256_hex(quad.to_nquad(&|label| format!("_:{}", label)).as_bytes());
+        let mut carry = 0u128;
+        for (index, limb) in self.sum.iter_mut().enumerate() {
+            // the digest is big-endian, so its last 16 hex digits are the lowest limb
+            let start = (3 - index) * 16;
+            let word = u64::from_str_radix(&digest[start..start + 16], 16).unwrap();
+            let total = *limb as u128 + word as u128 + carry;
+            *limb = total as u64;
+            carry = total >> 64;
+        }
+        self.statements += 1;
+    }
+
+    pub fn hex(&self) -> String {
+        self.sum
+            .iter()
+            .rev()
+            .map(|limb| format!("{:016x}", limb))
+            .collect()
+    }
+}
+
+/// What `verify` needs to re-derive a run's dataset hash from the target: the ledger, the data @context,
+/// and the classes whose instances were hashed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetManifest {
+    pub ledger: String,
+    #[serde(rename = "@context")]
+    pub context: Value,
+    pub classes: Vec<String>,
+    pub statements: u64,
+    pub skipped_statements: u64,
+    pub hash: String,
+}
+
+impl DatasetManifest {
+    pub fn new(ledger: &str, context: &Value, dataset_hash: &DatasetHash) -> Self {
+        DatasetManifest {
+            ledger: ledger.to_string(),
+            context: context.to_owned(),
+            classes: dataset_hash.classes.iter().cloned().collect(),
+            statements: dataset_hash.statements,
+            skipped_statements: dataset_hash.skipped_statements,
+            hash: dataset_hash.hex(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Self {
+        let json = read_json_file(path, "dataset hash");
+        match serde_json::from_value(json) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                pretty_print(
+                    &format!("Invalid dataset hash file {}: {}", path.display(), e),
+                    ERROR_COLOR,
+                    true,
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+// the target compacts results with the query's @context, and `@reverse` terms would turn its statements around
+pub fn without_reverse_terms(context: &Value) -> Value {
+    match context {
+        Value::Object(context) => Value::Object(
+            context
+                .iter()
+                .filter(|(_, definition)| definition.get("@reverse").is_none())
+                .map(|(term, definition)| (term.to_owned(), definition.to_owned()))
+                .collect(),
+        ),
+        context => context.to_owned(),
+    }
+}
+
+// a [subject, predicate, object, datatype] row as a JSON-LD node shaped the way the migration wrote it,
+// i.e. refs as {"@id": ...}, JSON numbers and booleans as they are, and other literals typed
+pub fn row_node(row: &Value) -> Option<Value> {
+    let [subject, predicate, object, datatype] = row.as_array()?.as_slice() else {
+        return None;
+    };
+    let (subject, predicate) = (subject.as_str()?, predicate.as_str()?);
+    if ["@type", "rdf:type", RDF_TYPE].contains(&predicate) {
+        return Some(json!({ "@id": subject, "@type": object }));
+    }
+    let datatype = datatype.as_str().unwrap_or_default();
+    let datatype_name = datatype.rsplit([':', '#']).next().unwrap_or_default();
+    let value = match object {
+        Value::Object(_) => object.to_owned(),
+        _ if datatype == "@id" || datatype == "id" => json!({ "@id": object }),
+        Value::Number(_) if NUMERIC_DATATYPES.contains(&datatype_name) => object.to_owned(),
+        Value::Bool(_) => object.to_owned(),
+        Value::String(_) if datatype.is_empty() || datatype_name == "string" => object.to_owned(),
+        _ => json!({ "@value": object, "@type": datatype }),
+    };
+    Some(json!({ "@id": subject, predicate: value }))
+}
+
+/// Re-derives the dataset hash of a run from the target ledger, by querying every statement about the
+/// instances of the hashed classes, and compares it with the hash the run recorded
+pub async fn verify(opt: &Opt, manifest_path: &Path) {
+    let green_bold = Style::new().green().bold();
+    let red_bold = Style::new().red().bold();
+    let manifest = DatasetManifest::load(manifest_path);
+    let ledger = opt.ledger_name.clone().unwrap_or(manifest.ledger.clone());
+    let context = without_reverse_terms(&manifest.context);
+    let mut target_instance = FlureeInstance::new_target(opt);
+
+    opt.pb.set_length(manifest.classes.len() as u64);
+    opt.pb.set_prefix("Verifying");
+    let mut target_hash = DatasetHash::default();
+    let mut hashed_subjects: HashSet<String> = HashSet::new();
+    for class in &manifest.classes {
+        opt.pb.inc(1);
+        let mut class_subjects = HashSet::new();
+        let mut offset = 0;
+        loop {
+            let query = json!({
+                "@context": context,
+                "from": ledger,
+                "select": ["?s", "?p", "?o", "?dt"],
+                "where": [
+                    { "@id": "?s", "@type": class },
+                    { "@id": "?s", "?p": "?o" },
+                    ["bind", "?dt", "(datatype ?o)"]
+                ],
+                "orderBy": ["?s", "?p"],
+                "limit": VERIFY_PAGE_SIZE,
+                "offset": offset
+            });
+            let rows = target_instance
+                .v3_query_array(&query)
+                .await
+                .unwrap_or_else(|e| state::abort(opt, &e));
+            // an instance of several classes is only hashed with the first of them
+            let nodes = rows
+                .iter()
+                .filter(|row| {
+                    let subject = row[0].as_str().unwrap_or_default();
+                    class_subjects.insert(subject.to_string());
+                    !hashed_subjects.contains(subject)
+                })
+                .filter_map(row_node)
+                .collect::<Vec<Value>>();
+            let mut document = Map::new();
+            document.insert("@context".to_string(), context.to_owned());
+            document.insert("insert".to_string(), Value::Array(nodes));
+            target_hash.add_document(&document);
+            if rows.len() < VERIFY_PAGE_SIZE {
+                break;
+            }
+            offset += VERIFY_PAGE_SIZE;
+        }
+        hashed_subjects.extend(class_subjects);
+    }
+    opt.pb.finish_and_clear();
+
+    if manifest.skipped_statements > 0 {
+        println!(
+            "{:>12} {} statements about blank nodes can't be matched with the ledger's, and weren't compared",
+            Style::new().yellow().bold().apply_to("WARNING"),
+            manifest.skipped_statements
+        );
+    }
+    if target_hash.hex() == manifest.hash && target_hash.statements == manifest.statements {
+        println!(
+            "{:>12} {} statements in {} match the migrated data (hash {})",
+            green_bold.apply_to("Verified"),
+            manifest.statements,
+            ledger,
+            manifest.hash
+        );
+        return;
+    }
+    println!(
+        "{:>12} {} differs from the migrated data: {} statements hash to {} in the ledger, but {} statements hashed to {} when they were produced",
+        red_bold.apply_to("ERROR"),
+        ledger,
+        target_hash.statements,
+        target_hash.hex(),
+        manifest.statements,
+        manifest.hash
+    );
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(nodes: Value) -> Map<String, Value> {
+        let Value::Object(document) = json!({
+            "@context": { "ex": "http://example.org/" },
+            "insert": nodes
+        }) else {
+            unreachable!()
+        };
+        document
+    }
+
+    #[test]
+    fn deduplicates_statements_within_a_document() {
+        let mut once = DatasetHash::of_documents();
+        once.add_document(&document(json!([{ "@id": "ex:1", "ex:name": "Jane" }])));
+        let mut twice = DatasetHash::of_documents();
+        twice.add_document(&document(json!([
+            { "@id": "ex:1", "ex:name": "Jane" },
+            { "@id": "ex:1", "ex:name": "Jane" }
+        ])));
+        assert_eq!(once.hex(), twice.hex());
+        assert_eq!(twice.repeated_subjects(), 0);
+    }
+
+    #[test]
+    fn counts_subjects_repeated_across_documents() {
+        let mut hash = DatasetHash::of_documents();
+        hash.add_document(&document(json!([{ "@id": "ex:1", "ex:name": "Jane" }])));
+        hash.add_document(&document(json!([{ "@id": "ex:2", "ex:name": "John" }])));
+        assert_eq!(hash.repeated_subjects(), 0);
+        hash.add_document(&document(json!([{ "@id": "ex:1", "ex:name": "Jane" }])));
+        assert_eq!(hash.repeated_subjects(), 1);
+    }
+}